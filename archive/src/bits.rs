@@ -0,0 +1,148 @@
+use crate::error::ArchiveError;
+
+// BitWriter packs values into bytes starting from the least significant bit,
+// as deflate does.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // writes the n low bits of value, n is at most 32
+    pub fn write(&mut self, value: u32, n: u32) {
+        self.bits |= (value as u64 & ((1 << n) - 1)) << self.count;
+        self.count += n;
+        while self.count >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    // writes a huffman code, whose most significant bit comes first
+    pub fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.write(reversed as u32, length as u32);
+    }
+
+    // pads the current byte with zeros
+    pub fn align(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.align();
+        self.out.extend_from_slice(bytes);
+    }
+
+    // the bytes completed so far, the bits of an incomplete byte are kept
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+// BitReader reads values packed by a BitWriter.
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    input: &'a [u8],
+    // position in bits
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        Self { input, position: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    pub fn seek(&mut self, position: usize) {
+        self.position = position;
+    }
+
+    pub fn bit(&mut self) -> Result<u32, ArchiveError> {
+        let byte = self
+            .input
+            .get(self.position / 8)
+            .ok_or(ArchiveError::UnexpectedEnd)?;
+        let bit = (byte >> (self.position % 8)) & 1;
+        self.position += 1;
+        Ok(bit as u32)
+    }
+
+    // reads n bits, n is at most 32
+    pub fn bits(&mut self, n: u32) -> Result<u32, ArchiveError> {
+        if self.position + n as usize > self.input.len() * 8 {
+            return Err(ArchiveError::UnexpectedEnd);
+        }
+        let mut value = 0;
+        for i in 0..n {
+            value |= self.bit()? << i;
+        }
+        Ok(value)
+    }
+
+    // skips to the start of the next byte
+    pub fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    // whole bytes left to read, the reader must be aligned
+    pub fn available(&self) -> usize {
+        self.input.len().saturating_sub(self.position / 8)
+    }
+
+    // reads whole bytes, the reader must be aligned
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], ArchiveError> {
+        let start = self.position / 8;
+        let bytes = self
+            .input
+            .get(start..start + n)
+            .ok_or(ArchiveError::UnexpectedEnd)?;
+        self.position += n * 8;
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits() {
+        let mut writer = BitWriter::new();
+        writer.write(1, 1);
+        writer.write(0b10, 2);
+        writer.write_code(0b110, 3);
+        writer.write(0x1234, 16);
+        writer.write_bytes(&[0xab]);
+        let out = writer.finish();
+
+        let mut reader = BitReader::new(&out);
+        assert_eq!(reader.bits(1), Ok(1));
+        assert_eq!(reader.bits(2), Ok(0b10));
+        // codes are read one bit at a time, most significant first
+        assert_eq!(reader.bit(), Ok(1));
+        assert_eq!(reader.bit(), Ok(1));
+        assert_eq!(reader.bit(), Ok(0));
+        assert_eq!(reader.bits(16), Ok(0x1234));
+        reader.align();
+        assert_eq!(reader.bytes(1), Ok(&[0xab][..]));
+        assert_eq!(reader.bits(1), Err(ArchiveError::UnexpectedEnd));
+    }
+}
@@ -1,30 +1,752 @@
+use std::sync::OnceLock;
+
+use crate::{
+    bits::{BitReader, BitWriter},
+    error::ArchiveError,
+    huffman::{canonical_codes, code_lengths, Decoder as HuffmanDecoder, MAX_CODE_LENGTH},
+    lz77::{tokens, Token, WINDOW_SIZE},
+};
+
+// input compressed in a block, the most a stored block holds
+pub const BLOCK_SIZE: usize = 65535;
+// input a decoder buffers while waiting for the rest of a symbol
+pub const MAX_PENDING: usize = 1024 * 1024;
+
+const END_OF_BLOCK: usize = 256;
+const LITERAL_CODES: usize = 286;
+const DISTANCE_CODES: usize = 30;
+// longest code of the code lengths code
+const MAX_CODE_LENGTH_LENGTH: u8 = 7;
+
+// base length and extra bits of length symbols 257 to 285
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+// order in which the lengths of the code lengths code are sent
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
 // https://datatracker.ietf.org/doc/html/rfc1951#section-Abstract
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Method {
-    NoCompression { length: usize },
+    NoCompression,
+    FixedHuffman,
+    DynamicHuffman,
 }
 
-#[derive(Debug)]
+// Block is the header of a deflate block.
+#[derive(Debug, PartialEq)]
 pub struct Block {
     last: bool,
     method: Method,
 }
 
+impl Block {
+    fn read(reader: &mut BitReader) -> Result<Self, ArchiveError> {
+        let last = reader.bits(1)? == 1;
+        let method = match reader.bits(2)? {
+            0 => Method::NoCompression,
+            1 => Method::FixedHuffman,
+            2 => Method::DynamicHuffman,
+            _ => return Err(ArchiveError::InvalidBlockType),
+        };
+        Ok(Self { last, method })
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write(self.last as u32, 1);
+        let method = match self.method {
+            Method::NoCompression => 0,
+            Method::FixedHuffman => 1,
+            Method::DynamicHuffman => 2,
+        };
+        writer.write(method, 2);
+    }
+}
+
+// symbol, extra bits and their value of a match length
+fn length_symbol(length: u16) -> (usize, u8, u16) {
+    let i = LENGTH_BASE.partition_point(|&base| base <= length) - 1;
+    (257 + i, LENGTH_EXTRA[i], length - LENGTH_BASE[i])
+}
+
+fn distance_symbol(distance: u16) -> (usize, u8, u16) {
+    let i = DISTANCE_BASE.partition_point(|&base| base <= distance) - 1;
+    (i, DISTANCE_EXTRA[i], distance - DISTANCE_BASE[i])
+}
+
+fn fixed_lengths() -> (Vec<u8>, Vec<u8>) {
+    let mut literals = vec![8u8; 288];
+    literals[144..256].fill(9);
+    literals[256..280].fill(7);
+    (literals, vec![5u8; DISTANCE_CODES])
+}
+
+fn fixed_decoders() -> &'static (HuffmanDecoder, HuffmanDecoder) {
+    static DECODERS: OnceLock<(HuffmanDecoder, HuffmanDecoder)> = OnceLock::new();
+    DECODERS.get_or_init(|| {
+        let (literals, distances) = fixed_lengths();
+        (
+            HuffmanDecoder::new(&literals).expect("fixed code is valid"),
+            HuffmanDecoder::new(&distances).expect("fixed code is valid"),
+        )
+    })
+}
+
+// Code holds the huffman codes of a block.
+struct Code {
+    literal_lengths: Vec<u8>,
+    literal_codes: Vec<u16>,
+    distance_lengths: Vec<u8>,
+    distance_codes: Vec<u16>,
+}
+
+impl Code {
+    fn new(literal_lengths: Vec<u8>, distance_lengths: Vec<u8>) -> Self {
+        Self {
+            literal_codes: canonical_codes(&literal_lengths),
+            literal_lengths,
+            distance_codes: canonical_codes(&distance_lengths),
+            distance_lengths,
+        }
+    }
+
+    // size in bits of the tokens and the end of the block
+    fn cost(&self, tokens: &[Token]) -> usize {
+        let mut bits = self.literal_lengths[END_OF_BLOCK] as usize;
+        for token in tokens {
+            bits += match *token {
+                Token::Literal(b) => self.literal_lengths[b as usize] as usize,
+                Token::Match { length, distance } => {
+                    let (symbol, extra, _) = length_symbol(length);
+                    let (dsymbol, dextra, _) = distance_symbol(distance);
+                    self.literal_lengths[symbol] as usize
+                        + extra as usize
+                        + self.distance_lengths[dsymbol] as usize
+                        + dextra as usize
+                }
+            };
+        }
+        bits
+    }
+
+    fn write(&self, writer: &mut BitWriter, tokens: &[Token]) {
+        for token in tokens {
+            match *token {
+                Token::Literal(b) => writer.write_code(
+                    self.literal_codes[b as usize],
+                    self.literal_lengths[b as usize],
+                ),
+                Token::Match { length, distance } => {
+                    let (symbol, extra, value) = length_symbol(length);
+                    writer.write_code(self.literal_codes[symbol], self.literal_lengths[symbol]);
+                    writer.write(value as u32, extra as u32);
+                    let (symbol, extra, value) = distance_symbol(distance);
+                    writer.write_code(self.distance_codes[symbol], self.distance_lengths[symbol]);
+                    writer.write(value as u32, extra as u32);
+                }
+            }
+        }
+        writer.write_code(
+            self.literal_codes[END_OF_BLOCK],
+            self.literal_lengths[END_OF_BLOCK],
+        );
+    }
+}
+
+// DynamicHeader describes the codes of a dynamic block, the code lengths are
+// run length encoded then huffman coded themselves.
+struct DynamicHeader {
+    literals: usize,
+    distances: usize,
+    // code length symbols with the value of their extra bits
+    symbols: Vec<(u8, u8)>,
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+    // number of code length code lengths sent
+    count: usize,
+}
+
+impl DynamicHeader {
+    fn new(code: &Code) -> Self {
+        let literals = LITERAL_CODES.min(
+            code.literal_lengths
+                .iter()
+                .rposition(|&l| l > 0)
+                .map_or(257, |i| (i + 1).max(257)),
+        );
+        let distances = code
+            .distance_lengths
+            .iter()
+            .rposition(|&l| l > 0)
+            .map_or(1, |i| i + 1);
+        let all: Vec<u8> = code.literal_lengths[..literals]
+            .iter()
+            .chain(&code.distance_lengths[..distances])
+            .copied()
+            .collect();
+
+        let mut symbols = Vec::new();
+        let mut i = 0;
+        while i < all.len() {
+            let length = all[i];
+            let run = all[i..].iter().take_while(|&&l| l == length).count();
+            let mut left = run;
+            if length == 0 {
+                while left >= 11 {
+                    let n = left.min(138);
+                    symbols.push((18, (n - 11) as u8));
+                    left -= n;
+                }
+                if left >= 3 {
+                    symbols.push((17, (left - 3) as u8));
+                    left = 0;
+                }
+            } else {
+                symbols.push((length, 0));
+                left -= 1;
+                while left >= 3 {
+                    let n = left.min(6);
+                    symbols.push((16, (n - 3) as u8));
+                    left -= n;
+                }
+            }
+            symbols.extend(std::iter::repeat_n((length, 0), left));
+            i += run;
+        }
+
+        let mut frequencies = [0usize; 19];
+        for (symbol, _) in symbols.iter() {
+            frequencies[*symbol as usize] += 1;
+        }
+        // a code of a single symbol would be incomplete, which decoders
+        // reject for the code lengths code
+        if frequencies.iter().filter(|&&f| f > 0).count() < 2 {
+            let unused = frequencies
+                .iter()
+                .position(|&f| f == 0)
+                .expect("19 symbols");
+            frequencies[unused] = 1;
+        }
+        let lengths = code_lengths(&frequencies, MAX_CODE_LENGTH_LENGTH);
+        let codes = canonical_codes(&lengths);
+        let count = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&s| lengths[s] > 0)
+            .map_or(4, |i| (i + 1).max(4));
+
+        Self {
+            literals,
+            distances,
+            symbols,
+            lengths,
+            codes,
+            count,
+        }
+    }
+
+    fn cost(&self) -> usize {
+        let symbols: usize = self
+            .symbols
+            .iter()
+            .map(|&(symbol, _)| self.lengths[symbol as usize] as usize + extra_bits(symbol))
+            .sum();
+        5 + 5 + 4 + 3 * self.count + symbols
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write((self.literals - 257) as u32, 5);
+        writer.write((self.distances - 1) as u32, 5);
+        writer.write((self.count - 4) as u32, 4);
+        for &symbol in CODE_LENGTH_ORDER.iter().take(self.count) {
+            writer.write(self.lengths[symbol] as u32, 3);
+        }
+        for &(symbol, extra) in self.symbols.iter() {
+            writer.write_code(self.codes[symbol as usize], self.lengths[symbol as usize]);
+            writer.write(extra as u32, extra_bits(symbol) as u32);
+        }
+    }
+}
+
+// extra bits of a code length symbol
+fn extra_bits(symbol: u8) -> usize {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+// Encoder compresses data written in pieces, each block of BLOCK_SIZE bytes
+// is sent in whichever of the three methods is the smallest.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    // last bytes already compressed, which matches may refer to
+    history: Vec<u8>,
+    pending: Vec<u8>,
+    writer: BitWriter,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns the compressed bytes available so far
+    pub fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(data);
+        while self.pending.len() >= BLOCK_SIZE {
+            let block: Vec<u8> = self.pending.drain(..BLOCK_SIZE).collect();
+            self.block(&block, false);
+        }
+        self.writer.take()
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let block = std::mem::take(&mut self.pending);
+        self.block(&block, true);
+        self.writer.finish()
+    }
+
+    fn block(&mut self, input: &[u8], last: bool) {
+        let start = self.history.len();
+        let mut data = std::mem::take(&mut self.history);
+        data.extend_from_slice(input);
+        let tokens = tokens(&data, start);
+
+        let mut literals = vec![0usize; LITERAL_CODES];
+        let mut distances = vec![0usize; DISTANCE_CODES];
+        literals[END_OF_BLOCK] = 1;
+        for token in tokens.iter() {
+            match *token {
+                Token::Literal(b) => literals[b as usize] += 1,
+                Token::Match { length, distance } => {
+                    literals[length_symbol(length).0] += 1;
+                    distances[distance_symbol(distance).0] += 1;
+                }
+            }
+        }
+        let dynamic = Code::new(
+            code_lengths(&literals, MAX_CODE_LENGTH),
+            code_lengths(&distances, MAX_CODE_LENGTH),
+        );
+        let header = DynamicHeader::new(&dynamic);
+        let (literals, distances) = fixed_lengths();
+        let fixed = Code::new(literals, distances);
+
+        // a stored block is aligned, then starts with its length
+        let stored = 3 + 7 + 32 + input.len() * 8;
+        let fixed_cost = 3 + fixed.cost(&tokens);
+        let dynamic_cost = 3 + header.cost() + dynamic.cost(&tokens);
+        if stored <= fixed_cost && stored <= dynamic_cost {
+            Block {
+                last,
+                method: Method::NoCompression,
+            }
+            .write(&mut self.writer);
+            self.writer.align();
+            self.writer.write(input.len() as u32, 16);
+            self.writer.write(!(input.len() as u32), 16);
+            self.writer.write_bytes(input);
+        } else if fixed_cost <= dynamic_cost {
+            Block {
+                last,
+                method: Method::FixedHuffman,
+            }
+            .write(&mut self.writer);
+            fixed.write(&mut self.writer, &tokens);
+        } else {
+            Block {
+                last,
+                method: Method::DynamicHuffman,
+            }
+            .write(&mut self.writer);
+            header.write(&mut self.writer);
+            dynamic.write(&mut self.writer, &tokens);
+        }
+
+        self.history = data[data.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+    }
+}
+
+// Decoder decompresses data received in pieces. Blocks are decoded as they
+// are received, a symbol split across pieces is decoded once whole.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    input: Vec<u8>,
+    // position in bits of the next symbol or block in input
+    position: usize,
+    // last bytes decoded, which matches may refer to
+    window: Vec<u8>,
+    // block being decoded and whether it is the last one
+    block: Option<(Inflate, bool)>,
+    done: bool,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns the bytes decoded so far
+    pub fn write(&mut self, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        self.input.extend_from_slice(data);
+        let start = self.window.len();
+        let mut reader = BitReader::new(&self.input);
+        reader.seek(self.position);
+        while !self.done {
+            let mark = reader.position();
+            let step = match &mut self.block {
+                Some((inflate, _)) => inflate_step(inflate, &mut reader, &mut self.window),
+                None => read_block(&mut reader).map(|block| {
+                    self.block = Some(block);
+                    false
+                }),
+            };
+            match step {
+                Ok(false) => {}
+                Ok(true) => self.done = self.block.take().is_some_and(|(_, last)| last),
+                Err(ArchiveError::UnexpectedEnd) => {
+                    reader.seek(mark);
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        self.position = reader.position();
+
+        let out = self.window[start..].to_vec();
+        let excess = self.window.len().saturating_sub(WINDOW_SIZE);
+        self.window.drain(..excess);
+        if self.input.len() - self.position / 8 > MAX_PENDING {
+            return Err(ArchiveError::BlockTooLarge);
+        }
+        let consumed = self.position / 8;
+        self.input.drain(..consumed);
+        self.position -= consumed * 8;
+        Ok(out)
+    }
+
+    // whether the last block was decoded
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    // input received after the last block
+    pub fn rest(&self) -> &[u8] {
+        match self.done {
+            true => &self.input[self.position.div_ceil(8)..],
+            false => &[],
+        }
+    }
+
+    pub fn finish(&self) -> Result<(), ArchiveError> {
+        match self.done {
+            true => Ok(()),
+            false => Err(ArchiveError::UnexpectedEnd),
+        }
+    }
+}
+
+// Inflate is what is left to decode of a block.
+#[derive(Debug)]
+enum Inflate {
+    Stored { remaining: usize },
+    Fixed,
+    Dynamic(HuffmanDecoder, HuffmanDecoder),
+}
+
+// reads the header of a block, returns whether it is the last one
+fn read_block(reader: &mut BitReader) -> Result<(Inflate, bool), ArchiveError> {
+    let block = Block::read(reader)?;
+    let inflate = match block.method {
+        Method::NoCompression => {
+            reader.align();
+            let length = reader.bits(16)?;
+            if reader.bits(16)? != !length & 0xffff {
+                return Err(ArchiveError::InvalidStoredLength);
+            }
+            Inflate::Stored {
+                remaining: length as usize,
+            }
+        }
+        Method::FixedHuffman => Inflate::Fixed,
+        Method::DynamicHuffman => {
+            let (literals, distances) = read_dynamic(reader)?;
+            Inflate::Dynamic(literals, distances)
+        }
+    };
+    Ok((inflate, block.last))
+}
+
+// decodes the bytes of a stored block received so far, or a single symbol,
+// after the bytes of out, returns whether the block is over
+fn inflate_step(
+    inflate: &mut Inflate,
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+) -> Result<bool, ArchiveError> {
+    match inflate {
+        Inflate::Stored { remaining } => {
+            let n = (*remaining).min(reader.available());
+            if n == 0 && *remaining > 0 {
+                return Err(ArchiveError::UnexpectedEnd);
+            }
+            out.extend_from_slice(reader.bytes(n)?);
+            *remaining -= n;
+            Ok(*remaining == 0)
+        }
+        Inflate::Fixed => {
+            let (literals, distances) = fixed_decoders();
+            inflate_symbol(reader, out, literals, distances)
+        }
+        Inflate::Dynamic(literals, distances) => inflate_symbol(reader, out, literals, distances),
+    }
+}
+
+fn read_dynamic(reader: &mut BitReader) -> Result<(HuffmanDecoder, HuffmanDecoder), ArchiveError> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let count = reader.bits(4)? as usize + 4;
+    if literals > LITERAL_CODES || distances > DISTANCE_CODES {
+        return Err(ArchiveError::InvalidCodeLengths);
+    }
+
+    let mut lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER.iter().take(count) {
+        lengths[symbol] = reader.bits(3)? as u8;
+    }
+    let decoder = HuffmanDecoder::new(&lengths)?;
+
+    let mut all = Vec::with_capacity(literals + distances);
+    while all.len() < literals + distances {
+        let (length, repeat) = match decoder.decode(reader)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => match all.last() {
+                Some(&previous) => (previous, 3 + reader.bits(2)? as usize),
+                None => return Err(ArchiveError::InvalidCodeLengths),
+            },
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if all.len() + repeat > literals + distances {
+            return Err(ArchiveError::InvalidCodeLengths);
+        }
+        all.extend(std::iter::repeat_n(length, repeat));
+    }
+    if all[END_OF_BLOCK] == 0 {
+        return Err(ArchiveError::InvalidCodeLengths);
+    }
+
+    Ok((
+        HuffmanDecoder::new(&all[..literals])?,
+        HuffmanDecoder::new(&all[literals..])?,
+    ))
+}
+
+// decodes a literal or a match, out is only changed once the whole symbol
+// is read, returns whether it is the end of the block
+fn inflate_symbol(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    literals: &HuffmanDecoder,
+    distances: &HuffmanDecoder,
+) -> Result<bool, ArchiveError> {
+    let symbol = literals.decode(reader)?;
+    match symbol as usize {
+        b @ 0..=255 => out.push(b as u8),
+        END_OF_BLOCK => return Ok(true),
+        s @ 257..=285 => {
+            let i = s - 257;
+            let length = LENGTH_BASE[i] as usize + reader.bits(LENGTH_EXTRA[i] as u32)? as usize;
+            let d = distances.decode(reader)? as usize;
+            if d >= DISTANCE_CODES {
+                return Err(ArchiveError::InvalidSymbol { symbol: d as u16 });
+            }
+            let distance =
+                DISTANCE_BASE[d] as usize + reader.bits(DISTANCE_EXTRA[d] as u32)? as usize;
+            if distance > out.len() {
+                return Err(ArchiveError::InvalidDistance { distance });
+            }
+            let start = out.len() - distance;
+            for i in start..start + length {
+                out.push(out[i]);
+            }
+        }
+        _ => return Err(ArchiveError::InvalidSymbol { symbol }),
+    }
+    Ok(false)
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut out = encoder.write(input);
+    out.extend(encoder.finish());
+    out
+}
+
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut decoder = Decoder::new();
+    let out = decoder.write(input)?;
+    decoder.finish()?;
+    Ok(out)
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn les_miserables() -> Vec<u8> {
+        std::fs::read("benches/testdata/les_miserables.txt").unwrap()
+    }
+
+    // pseudo random bytes, which do not compress
+    fn noise(n: usize) -> Vec<u8> {
+        let mut x = 0x2545f491u32;
+        (0..n)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                x as u8
+            })
+            .collect()
+    }
 
     #[test]
     fn generate_fixed_huffman_decoder() {
-        let n = 288;
-        let mut bits = [0u8; 288];
-        for i in 0..n {
-            match i {
-                0..=143 => bits[i] = 8,
-                144..=255 => bits[i] = 9,
-                256..=279 => bits[i] = 7,
-                280..=287 => bits[i] = 8,
-                _ => {}
-            }
+        let (literals, distances) = fixed_lengths();
+        assert_eq!(literals.len(), 288);
+        assert_eq!(canonical_codes(&literals)[0], 0b00110000);
+        assert_eq!(canonical_codes(&literals)[END_OF_BLOCK], 0);
+        assert!(HuffmanDecoder::new(&literals).is_ok());
+        assert!(HuffmanDecoder::new(&distances).is_ok());
+    }
+
+    #[rstest]
+    #[case(3, (257, 0, 0))]
+    #[case(10, (264, 0, 0))]
+    #[case(12, (265, 1, 1))]
+    #[case(257, (284, 5, 30))]
+    #[case(258, (285, 0, 0))]
+    fn test_length_symbol(#[case] length: u16, #[case] expected: (usize, u8, u16)) {
+        assert_eq!(length_symbol(length), expected);
+    }
+
+    #[rstest]
+    #[case(1, (0, 0, 0))]
+    #[case(6, (4, 1, 1))]
+    #[case(32768, (29, 13, 8191))]
+    fn test_distance_symbol(#[case] distance: u16, #[case] expected: (usize, u8, u16)) {
+        assert_eq!(distance_symbol(distance), expected);
+    }
+
+    // streams produced by zlib
+    #[rstest]
+    // empty, fixed
+    #[case(&[0x03, 0x00], b"")]
+    // fixed
+    #[case(&[0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00], b"hello")]
+    // stored
+    #[case(&[0x01, 0x05, 0x00, 0xfa, 0xff, 0x68, 0x65, 0x6c, 0x6c, 0x6f], b"hello")]
+    // fixed, with a match overlapping the bytes it copies
+    #[case(&[0x4b, 0x4c, 0x84, 0x01, 0x00], b"aaaaaaaaaa")]
+    fn test_decompress_zlib(#[case] input: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(decompress(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(Vec::new())]
+    #[case(b"hello".to_vec())]
+    #[case(b"abc".repeat(10_000))]
+    #[case(noise(200_000))]
+    #[case(les_miserables())]
+    fn test_round_trip(#[case] input: Vec<u8>) {
+        let compressed = compress(&input);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+    }
+
+    #[test]
+    fn test_methods() {
+        // noise is stored, short text fixed and long text dynamic
+        let stored = compress(&noise(1000));
+        assert_eq!(stored[0] & 0b111, 0b001);
+        assert_eq!(stored.len(), 1005);
+        assert_eq!(compress(b"hello")[0] & 0b111, 0b011);
+        let text = les_miserables();
+        let compressed = compress(&text);
+        assert_eq!(compressed[0] & 0b111, 0b100);
+        assert!(compressed.len() < text.len() / 2);
+    }
+
+    #[test]
+    fn test_streaming() {
+        let input = les_miserables();
+        let mut encoder = Encoder::new();
+        let mut compressed = Vec::new();
+        for piece in input.chunks(10_000) {
+            compressed.extend(encoder.write(piece));
         }
+        compressed.extend(encoder.finish());
+        assert_eq!(compressed, compress(&input));
+
+        // blocks are decoded as soon as they are received
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        for piece in compressed.chunks(777) {
+            out.extend(decoder.write(piece).unwrap());
+        }
+        assert!(decoder.done());
+        decoder.finish().unwrap();
+        assert_eq!(out, input);
+
+        // blocks received a byte at a time are not decoded again from their start
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        for b in compressed.iter() {
+            out.extend(decoder.write(&[*b]).unwrap());
+        }
+        assert!(decoder.done());
+        assert_eq!(out, input);
+
+        // stored bytes are returned before the end of their block
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.write(&[0x01, 0x03, 0x00, 0xfc, 0xff]).unwrap(), b"");
+        assert_eq!(decoder.write(b"ab").unwrap(), b"ab");
+        assert_eq!(decoder.write(b"c").unwrap(), b"c");
+        assert!(decoder.done());
+
+        let mut decoder = Decoder::new();
+        decoder.write(&compressed[..compressed.len() - 1]).unwrap();
+        assert_eq!(decoder.finish(), Err(ArchiveError::UnexpectedEnd));
+
+        // input after the last block is left over
+        let mut decoder = Decoder::new();
+        decoder.write(&[0x03, 0x00, 0xaa, 0xbb]).unwrap();
+        assert_eq!(decoder.rest(), &[0xaa, 0xbb]);
+    }
+
+    #[rstest]
+    #[case(&[0x07], ArchiveError::InvalidBlockType)]
+    #[case(&[0x01, 0x05, 0x00, 0x00, 0x00], ArchiveError::InvalidStoredLength)]
+    // fixed block with a distance past the start of the output
+    #[case(&[0x03, 0x02, 0x00], ArchiveError::InvalidDistance { distance: 1 })]
+    fn test_decompress_invalid(#[case] input: &[u8], #[case] expected: ArchiveError) {
+        assert_eq!(decompress(input), Err(expected));
     }
 }
@@ -0,0 +1,38 @@
+use std::error::Error;
+
+#[derive(Debug, PartialEq)]
+pub enum ArchiveError {
+    // the input ended in the middle of a block or member
+    UnexpectedEnd,
+    // a block longer than a decoder buffers before decoding it
+    BlockTooLarge,
+    InvalidBlockType,
+    InvalidStoredLength,
+    InvalidCodeLengths,
+    InvalidSymbol { symbol: u16 },
+    InvalidDistance { distance: usize },
+    InvalidHeader { reason: &'static str },
+    ChecksumMismatch,
+    LengthMismatch,
+}
+
+impl std::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ArchiveError::UnexpectedEnd => write!(f, "unexpected end of input"),
+            ArchiveError::BlockTooLarge => write!(f, "block too large"),
+            ArchiveError::InvalidBlockType => write!(f, "invalid block type"),
+            ArchiveError::InvalidStoredLength => write!(f, "invalid stored block length"),
+            ArchiveError::InvalidCodeLengths => write!(f, "invalid huffman code lengths"),
+            ArchiveError::InvalidSymbol { symbol } => write!(f, "invalid symbol {}", symbol),
+            ArchiveError::InvalidDistance { distance } => {
+                write!(f, "invalid distance {}", distance)
+            }
+            ArchiveError::InvalidHeader { reason } => write!(f, "invalid header: {}", reason),
+            ArchiveError::ChecksumMismatch => write!(f, "checksum mismatch"),
+            ArchiveError::LengthMismatch => write!(f, "length mismatch"),
+        }
+    }
+}
+
+impl Error for ArchiveError {}
@@ -1,23 +1,40 @@
-#[derive(Debug)]
-pub struct Member {
-    id_1: u16,
-    id_2: u16,
+use encoding::crc32::{update, TABLE_IEEE};
 
-    cm: u16,
-    fl: Flags,
-    mtime: u128,
-    xfl: ExtraFlags,
-    os: Os,
+use crate::{deflate, error::ArchiveError};
 
-    extra: Option<(u32, String)>,
-    name: Option<String>,
-    comment: Option<String>,
+const ID: [u8; 2] = [0x1f, 0x8b];
+// compression method of deflate, the only one defined
+const CM_DEFLATE: u8 = 8;
 
-    crc: u128,
-    isize: u128,
+// https://datatracker.ietf.org/doc/html/rfc1952#section-2.3
+#[derive(Debug, Clone, PartialEq)]
+pub struct Member {
+    pub text: bool,
+    pub mtime: u32,
+    pub xfl: Option<ExtraFlags>,
+    pub os: Os,
+
+    pub extra: Option<Vec<u8>>,
+    pub name: Option<String>,
+    pub comment: Option<String>,
 }
 
-#[derive(Debug)]
+impl Default for Member {
+    fn default() -> Self {
+        Self {
+            text: false,
+            mtime: 0,
+            xfl: None,
+            os: Os::Unknown,
+            extra: None,
+            name: None,
+            comment: None,
+        }
+    }
+}
+
+// bit positions of the flags byte
+#[derive(Debug, Clone, Copy)]
 pub enum Flags {
     FTEXT = 0,
     FHCRC = 1,
@@ -26,13 +43,19 @@ pub enum Flags {
     FCOMMENT = 4,
 }
 
-#[derive(Debug)]
+impl Flags {
+    fn set(self, flags: u8) -> bool {
+        flags & (1 << self as u8) != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ExtraFlags {
     MaxCompression = 2,
     Fastest = 4,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Os {
     FAT = 0,
     Amiga = 1,
@@ -48,4 +71,380 @@ pub enum Os {
     NTFS = 11,
     QDOS = 12,
     Acorn = 13,
+    Unknown = 255,
+}
+
+impl TryFrom<u8> for Os {
+    type Error = ArchiveError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Os::FAT,
+            1 => Os::Amiga,
+            2 => Os::VMS,
+            3 => Os::Unix,
+            4 => Os::VMCMS,
+            5 => Os::Atari,
+            6 => Os::HPFS,
+            7 => Os::Mac,
+            8 => Os::Z,
+            9 => Os::CP,
+            10 => Os::TOPS,
+            11 => Os::NTFS,
+            12 => Os::QDOS,
+            13 => Os::Acorn,
+            255 => Os::Unknown,
+            _ => return Err(ArchiveError::InvalidHeader { reason: "os" }),
+        })
+    }
+}
+
+impl Member {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut flags = 0u8;
+        if self.text {
+            flags |= 1 << Flags::FTEXT as u8;
+        }
+        if self.extra.is_some() {
+            flags |= 1 << Flags::FEXTRA as u8;
+        }
+        if self.name.is_some() {
+            flags |= 1 << Flags::FNAME as u8;
+        }
+        if self.comment.is_some() {
+            flags |= 1 << Flags::FCOMMENT as u8;
+        }
+        out.extend_from_slice(&ID);
+        out.push(CM_DEFLATE);
+        out.push(flags);
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        out.push(self.xfl.map_or(0, |xfl| xfl as u8));
+        out.push(self.os as u8);
+        if let Some(extra) = self.extra.as_ref() {
+            out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            out.extend_from_slice(extra);
+        }
+        for field in [self.name.as_ref(), self.comment.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            out.extend_from_slice(field.as_bytes());
+            out.push(0);
+        }
+    }
+
+    // parses a header at the start of input, returns it with its length
+    fn read(input: &[u8]) -> Result<(Self, usize), ArchiveError> {
+        let fixed = input.get(..10).ok_or(ArchiveError::UnexpectedEnd)?;
+        if fixed[..2] != ID {
+            return Err(ArchiveError::InvalidHeader { reason: "id" });
+        }
+        if fixed[2] != CM_DEFLATE {
+            return Err(ArchiveError::InvalidHeader {
+                reason: "compression method",
+            });
+        }
+        let flags = fixed[3];
+        let xfl = match fixed[8] {
+            2 => Some(ExtraFlags::MaxCompression),
+            4 => Some(ExtraFlags::Fastest),
+            _ => None,
+        };
+        let mut member = Member {
+            text: Flags::FTEXT.set(flags),
+            mtime: u32::from_le_bytes(fixed[4..8].try_into().expect("4 bytes")),
+            xfl,
+            os: Os::try_from(fixed[9])?,
+            ..Default::default()
+        };
+
+        let mut position = 10;
+        if Flags::FEXTRA.set(flags) {
+            let length = input
+                .get(position..position + 2)
+                .ok_or(ArchiveError::UnexpectedEnd)?;
+            let length = u16::from_le_bytes([length[0], length[1]]) as usize;
+            let extra = input
+                .get(position + 2..position + 2 + length)
+                .ok_or(ArchiveError::UnexpectedEnd)?;
+            member.extra = Some(extra.to_vec());
+            position += 2 + length;
+        }
+        for (flag, field) in [
+            (Flags::FNAME, &mut member.name),
+            (Flags::FCOMMENT, &mut member.comment),
+        ] {
+            if !flag.set(flags) {
+                continue;
+            }
+            let rest = input.get(position..).unwrap_or_default();
+            let end = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(ArchiveError::UnexpectedEnd)?;
+            // fields are latin-1, which maps to the first code points
+            *field = Some(rest[..end].iter().map(|&b| b as char).collect());
+            position += end + 1;
+        }
+        if Flags::FHCRC.set(flags) {
+            let crc = input
+                .get(position..position + 2)
+                .ok_or(ArchiveError::UnexpectedEnd)?;
+            let expected = update(0, &input[..position], &TABLE_IEEE) as u16;
+            if u16::from_le_bytes([crc[0], crc[1]]) != expected {
+                return Err(ArchiveError::ChecksumMismatch);
+            }
+            position += 2;
+        }
+        Ok((member, position))
+    }
+}
+
+// Encoder writes a single member compressing data written in pieces.
+#[derive(Debug)]
+pub struct Encoder {
+    header: Option<Member>,
+    deflate: deflate::Encoder,
+    crc: u128,
+    size: u32,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new(Member::default())
+    }
+}
+
+impl Encoder {
+    pub fn new(member: Member) -> Self {
+        Self {
+            header: Some(member),
+            deflate: deflate::Encoder::new(),
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    // returns the bytes of the member available so far
+    pub fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(member) = self.header.take() {
+            member.write(&mut out);
+        }
+        self.crc = update(self.crc, data, &TABLE_IEEE);
+        self.size = self.size.wrapping_add(data.len() as u32);
+        out.extend(self.deflate.write(data));
+        out
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut out = self.write(&[]);
+        out.extend(self.deflate.finish());
+        out.extend_from_slice(&(self.crc as u32).to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+        out
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    Header,
+    Body {
+        deflate: deflate::Decoder,
+        crc: u128,
+        size: u32,
+    },
+    Trailer {
+        crc: u128,
+        size: u32,
+    },
+}
+
+// Decoder decompresses members received in pieces, the data of members
+// following each other is concatenated.
+#[derive(Debug)]
+pub struct Decoder {
+    state: State,
+    input: Vec<u8>,
+    members: Vec<Member>,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            state: State::Header,
+            input: Vec::new(),
+            members: Vec::new(),
+        }
+    }
+
+    // headers of the members decoded so far
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+
+    // returns the bytes decoded so far
+    pub fn write(&mut self, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        self.input.extend_from_slice(data);
+        let mut out = Vec::new();
+        loop {
+            match &mut self.state {
+                State::Header => {
+                    if self.input.is_empty() {
+                        break;
+                    }
+                    let (member, length) = match Member::read(&self.input) {
+                        Err(ArchiveError::UnexpectedEnd) => break,
+                        res => res?,
+                    };
+                    self.members.push(member);
+                    self.input.drain(..length);
+                    self.state = State::Body {
+                        deflate: deflate::Decoder::new(),
+                        crc: 0,
+                        size: 0,
+                    };
+                }
+                State::Body { deflate, crc, size } => {
+                    let decoded = deflate.write(&self.input)?;
+                    *crc = update(*crc, &decoded, &TABLE_IEEE);
+                    *size = size.wrapping_add(decoded.len() as u32);
+                    out.extend(decoded);
+                    if !deflate.done() {
+                        self.input.clear();
+                        break;
+                    }
+                    self.input = deflate.rest().to_vec();
+                    self.state = State::Trailer {
+                        crc: *crc,
+                        size: *size,
+                    };
+                }
+                State::Trailer { crc, size } => {
+                    let Some(trailer) = self.input.get(..8) else {
+                        break;
+                    };
+                    if u32::from_le_bytes(trailer[..4].try_into().expect("4 bytes")) != *crc as u32
+                    {
+                        return Err(ArchiveError::ChecksumMismatch);
+                    }
+                    if u32::from_le_bytes(trailer[4..].try_into().expect("4 bytes")) != *size {
+                        return Err(ArchiveError::LengthMismatch);
+                    }
+                    self.input.drain(..8);
+                    self.state = State::Header;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    pub fn finish(&self) -> Result<(), ArchiveError> {
+        match (&self.state, self.members.is_empty(), self.input.is_empty()) {
+            (State::Header, false, true) => Ok(()),
+            _ => Err(ArchiveError::UnexpectedEnd),
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::default();
+    let mut out = encoder.write(input);
+    out.extend(encoder.finish());
+    out
+}
+
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut decoder = Decoder::new();
+    let out = decoder.write(input)?;
+    decoder.finish()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    // members produced by gzip
+    #[rstest]
+    // gzip.compress(b"hello", mtime=0)
+    #[case(&[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+        0xc9, 0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+    ], b"hello")]
+    // two members
+    #[case(&[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+        0xc9, 0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9,
+        0xc9, 0x07, 0x00, 0x86, 0xa6, 0x10, 0x36, 0x05, 0x00, 0x00, 0x00,
+    ], b"hellohello")]
+    fn test_decompress_gzip(#[case] input: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(decompress(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(b"".to_vec())]
+    #[case(b"hello".to_vec())]
+    #[case(b"gzip ".repeat(50_000))]
+    fn test_round_trip(#[case] input: Vec<u8>) {
+        let compressed = compress(&input);
+        assert_eq!(&compressed[..2], &ID);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        for piece in compressed.chunks(3) {
+            out.extend(decoder.write(piece).unwrap());
+        }
+        decoder.finish().unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[test]
+    fn test_header() {
+        let member = Member {
+            text: true,
+            mtime: 1_700_000_000,
+            xfl: Some(ExtraFlags::MaxCompression),
+            os: Os::Unix,
+            extra: Some(vec![1, 2, 3]),
+            name: Some("file.txt".to_string()),
+            comment: Some("a comment".to_string()),
+        };
+        let mut encoder = Encoder::new(member.clone());
+        let mut compressed = encoder.write(b"data");
+        compressed.extend(encoder.finish());
+
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.write(&compressed).unwrap(), b"data");
+        assert_eq!(decoder.members(), &[member]);
+    }
+
+    #[test]
+    fn test_decompress_invalid() {
+        let mut compressed = compress(b"hello");
+        assert_eq!(
+            decompress(&compressed[1..]),
+            Err(ArchiveError::InvalidHeader { reason: "id" })
+        );
+        assert_eq!(
+            decompress(&compressed[..compressed.len() - 1]),
+            Err(ArchiveError::UnexpectedEnd)
+        );
+        let n = compressed.len();
+        compressed[n - 8] ^= 1;
+        assert_eq!(decompress(&compressed), Err(ArchiveError::ChecksumMismatch));
+        compressed[n - 8] ^= 1;
+        compressed[n - 4] ^= 1;
+        assert_eq!(decompress(&compressed), Err(ArchiveError::LengthMismatch));
+        assert_eq!(decompress(&[]), Err(ArchiveError::UnexpectedEnd));
+    }
 }
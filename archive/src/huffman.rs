@@ -7,6 +7,11 @@ use std::{
 
 use itertools::Itertools;
 
+use crate::{bits::BitReader, error::ArchiveError};
+
+// longest code of deflate
+pub const MAX_CODE_LENGTH: u8 = 15;
+
 pub struct Node<T> {
     count: usize,
     value: Option<T>,
@@ -66,7 +71,7 @@ pub struct Tree<T> {
 }
 
 impl<T: Eq + Hash + Copy> Tree<T> {
-    fn new(input: &[T]) -> Tree<T> {
+    pub fn new(input: &[T]) -> Tree<T> {
         let characters: HashMap<&T, usize> = input.iter().counts();
         let n = characters.len();
 
@@ -100,7 +105,7 @@ impl<T: Eq + Hash + Copy> Tree<T> {
         Tree { root, lookup }
     }
 
-    fn encode(&self, input: &[T]) -> String {
+    pub fn encode(&self, input: &[T]) -> String {
         let mut res = String::new();
 
         for ch in input.iter() {
@@ -110,7 +115,7 @@ impl<T: Eq + Hash + Copy> Tree<T> {
         res
     }
 
-    fn decode(&self, input: &str) -> Vec<T> {
+    pub fn decode(&self, input: &str) -> Vec<T> {
         let mut res = Vec::<T>::new();
         let mut cur = &self.root;
 
@@ -138,9 +143,170 @@ impl<T: Eq + Hash + Copy> Tree<T> {
     }
 }
 
+// length of the code of each symbol of a length limited huffman code, 0 for
+// the symbols that do not occur
+pub fn code_lengths(frequencies: &[usize], max_length: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; frequencies.len()];
+    let mut symbols: Vec<usize> = (0..frequencies.len())
+        .filter(|&s| frequencies[s] > 0)
+        .collect();
+    match symbols.len() {
+        0 => return lengths,
+        // a code needs at least one bit
+        1 => {
+            lengths[symbols[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    // depths in a huffman tree, nodes past the symbols are internal
+    let mut parents = vec![0usize; symbols.len() * 2 - 1];
+    let mut nodes: BinaryHeap<Reverse<(usize, usize)>> = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, &s)| Reverse((frequencies[s], i)))
+        .collect();
+    let mut next = symbols.len();
+    while nodes.len() > 1 {
+        let Reverse((a, left)) = nodes.pop().expect("at least two nodes");
+        let Reverse((b, right)) = nodes.pop().expect("at least two nodes");
+        parents[left] = next;
+        parents[right] = next;
+        nodes.push(Reverse((a + b, next)));
+        next += 1;
+    }
+    let root = next - 1;
+    let mut depths = vec![0usize; parents.len()];
+    for node in (0..root).rev() {
+        depths[node] = depths[parents[node]] + 1;
+    }
+
+    // codes past the limit are shortened, then the shorter codes lengthened
+    // until the code is complete again
+    let max = max_length as usize;
+    let mut counts = vec![0usize; max + 1];
+    for depth in depths.iter().take(symbols.len()) {
+        counts[(*depth).min(max)] += 1;
+    }
+    let mut total: usize = (1..=max).map(|l| counts[l] << (max - l)).sum();
+    while total > 1 << max {
+        counts[max] -= 1;
+        for l in (1..max).rev() {
+            if counts[l] > 0 {
+                counts[l] -= 1;
+                counts[l + 1] += 2;
+                break;
+            }
+        }
+        total -= 1;
+    }
+
+    // the most frequent symbols get the shortest codes
+    symbols.sort_by_key(|&s| Reverse(frequencies[s]));
+    let mut symbols = symbols.into_iter();
+    for (length, count) in counts.iter().enumerate().skip(1) {
+        for symbol in symbols.by_ref().take(*count) {
+            lengths[symbol] = length as u8;
+        }
+    }
+    lengths
+}
+
+// canonical codes of the given code lengths, as defined by deflate
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+
+    let mut next = [0u16; MAX_CODE_LENGTH as usize + 1];
+    let mut code = 0u16;
+    for bits in 1..=MAX_CODE_LENGTH as usize {
+        code = (code + counts[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&length| match length {
+            0 => 0,
+            length => {
+                let code = next[length as usize];
+                next[length as usize] += 1;
+                code
+            }
+        })
+        .collect()
+}
+
+// Decoder reads the symbols of a canonical huffman code one bit at a time.
+#[derive(Debug)]
+pub struct Decoder {
+    // number of codes of each length
+    counts: [u16; MAX_CODE_LENGTH as usize + 1],
+    // symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Decoder {
+    pub fn new(lengths: &[u8]) -> Result<Self, ArchiveError> {
+        let mut counts = [0u16; MAX_CODE_LENGTH as usize + 1];
+        for &length in lengths {
+            if length > MAX_CODE_LENGTH {
+                return Err(ArchiveError::InvalidCodeLengths);
+            }
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // more codes of a length than there is room for
+        let mut left = 1i32;
+        for count in counts.iter().skip(1) {
+            left = (left << 1) - *count as i32;
+            if left < 0 {
+                return Err(ArchiveError::InvalidCodeLengths);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_LENGTH as usize + 2];
+        for length in 1..=MAX_CODE_LENGTH as usize {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; offsets[MAX_CODE_LENGTH as usize + 1] as usize];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length > 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    pub fn decode(&self, reader: &mut BitReader) -> Result<u16, ArchiveError> {
+        // first code of the current length, and index of its symbol
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_CODE_LENGTH as usize {
+            code |= reader.bit()? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        // only an incomplete code has unused codes
+        Err(ArchiveError::InvalidCodeLengths)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bits::BitWriter;
+    use rstest::*;
 
     #[test]
     fn test_something() {
@@ -150,4 +316,66 @@ mod tests {
         let encoded = tree.encode(input.as_bytes());
         assert_eq!(input, String::from_utf8(tree.decode(&encoded)).unwrap());
     }
+
+    #[test]
+    fn test_canonical_codes() {
+        // example of rfc 1951 section 3.2.2
+        let lengths = [3, 3, 3, 3, 3, 2, 4, 4];
+        assert_eq!(
+            canonical_codes(&lengths),
+            [0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]
+        );
+    }
+
+    #[rstest]
+    #[case(&[], 15, &[])]
+    #[case(&[0, 5, 0], 15, &[0, 1, 0])]
+    #[case(&[1, 1, 2, 4], 15, &[3, 3, 2, 1])]
+    // fibonacci frequencies make the deepest trees
+    #[case(&[1, 1, 2, 3, 5, 8, 13, 21], 7, &[7, 7, 6, 5, 4, 3, 2, 1])]
+    #[case(&[1, 1, 2, 3, 5, 8, 13, 21], 4, &[4, 4, 4, 4, 4, 4, 3, 1])]
+    fn test_code_lengths(
+        #[case] frequencies: &[usize],
+        #[case] max_length: u8,
+        #[case] expected: &[u8],
+    ) {
+        let lengths = code_lengths(frequencies, max_length);
+        assert_eq!(lengths, expected);
+        // the code is complete
+        let kraft: f64 = lengths
+            .iter()
+            .filter(|&&l| l > 0)
+            .map(|&l| 0.5f64.powi(l as i32))
+            .sum();
+        assert!(lengths.iter().filter(|&&l| l > 0).count() < 2 || kraft == 1.0);
+    }
+
+    #[test]
+    fn test_decoder() {
+        let input = b"this is an example of a huffman tree";
+        let mut frequencies = vec![0; 256];
+        for &b in input {
+            frequencies[b as usize] += 1;
+        }
+        let lengths = code_lengths(&frequencies, MAX_CODE_LENGTH);
+        let codes = canonical_codes(&lengths);
+
+        let mut writer = BitWriter::new();
+        for &b in input {
+            writer.write_code(codes[b as usize], lengths[b as usize]);
+        }
+        let out = writer.finish();
+
+        let decoder = Decoder::new(&lengths).unwrap();
+        let mut reader = BitReader::new(&out);
+        let decoded: Vec<u8> = (0..input.len())
+            .map(|_| decoder.decode(&mut reader).unwrap() as u8)
+            .collect();
+        assert_eq!(decoded, input);
+
+        assert_eq!(
+            Decoder::new(&[1, 1, 1]).unwrap_err(),
+            ArchiveError::InvalidCodeLengths
+        );
+    }
 }
@@ -1,4 +1,7 @@
+pub mod bits;
 pub mod deflate;
+pub mod error;
 pub mod gzip;
 pub mod huffman;
 pub mod lz77;
+pub mod zlib;
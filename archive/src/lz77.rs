@@ -5,6 +5,115 @@ use std::{
     slice::Windows,
 };
 
+// farthest back a deflate match may refer to
+pub const WINDOW_SIZE: usize = 32 * 1024;
+pub const MIN_MATCH: usize = 3;
+pub const MAX_MATCH: usize = 258;
+// candidates tried for each position, longer chains find longer matches
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+// Token is a literal byte or a copy of earlier bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+const NONE: usize = usize::MAX;
+
+// Chains links the positions of data sharing the hash of their next three
+// bytes, the most recent first.
+struct Chains<'a> {
+    data: &'a [u8],
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl<'a> Chains<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; data.len()],
+        }
+    }
+
+    #[inline]
+    fn hash(&self, i: usize) -> usize {
+        let data = self.data;
+        let h = (data[i] as usize) << 10 ^ (data[i + 1] as usize) << 5 ^ data[i + 2] as usize;
+        h & ((1 << HASH_BITS) - 1)
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH <= self.data.len() {
+            let h = self.hash(i);
+            self.prev[i] = self.head[h];
+            self.head[h] = i;
+        }
+    }
+
+    // longest match of data[i..i + max] along the chain, as length and
+    // distance
+    fn longest(&self, i: usize, max: usize) -> (usize, usize) {
+        let (mut best, mut distance) = (0, 0);
+        let mut candidate = self.head[self.hash(i)];
+        let mut chain = 0;
+        while candidate != NONE && i - candidate <= WINDOW_SIZE && chain < MAX_CHAIN {
+            let length = self.data[candidate..]
+                .iter()
+                .zip(&self.data[i..i + max])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best {
+                (best, distance) = (length, i - candidate);
+                if length == max {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate];
+            chain += 1;
+        }
+        (best, distance)
+    }
+}
+
+// tokens of data[start..], with matches that may refer to data[..start] as
+// long as it is within the window
+pub fn tokens(data: &[u8], start: usize) -> Vec<Token> {
+    let mut chains = Chains::new(data);
+    for i in start.saturating_sub(WINDOW_SIZE)..start {
+        chains.insert(i);
+    }
+
+    let mut res = Vec::new();
+    let mut i = start;
+    while i < data.len() {
+        let max = (data.len() - i).min(MAX_MATCH);
+        let (best, distance) = match max >= MIN_MATCH {
+            true => chains.longest(i, max),
+            false => (0, 0),
+        };
+
+        if best >= MIN_MATCH {
+            res.push(Token::Match {
+                length: best as u16,
+                distance: distance as u16,
+            });
+            for j in i..i + best {
+                chains.insert(j);
+            }
+            i += best;
+        } else {
+            res.push(Token::Literal(data[i]));
+            chains.insert(i);
+            i += 1;
+        }
+    }
+    res
+}
+
 pub struct Match {
     offset: usize,
     length: usize,
@@ -93,7 +202,7 @@ impl<'a> Buffer<'a> {
         }
     }
 
-    fn decompress<R, W>(
+    pub fn decompress<R, W>(
         &mut self,
         reader: &mut BufReader<R>,
         writer: &mut BufWriter<W>,
@@ -166,14 +275,11 @@ impl<'a> Buffer<'a> {
                 continue;
             }
 
-            match self.find_longest_match(&search_buffer.data, lookahead) {
-                Some(m) => {
-                    if m.length > 0 {
-                        hop += m.length;
-                    }
-                    self.write(writer, m);
+            if let Some(m) = self.find_longest_match(&search_buffer.data, lookahead) {
+                if m.length > 0 {
+                    hop += m.length;
                 }
-                None => {}
+                self.write(writer, m);
             }
 
             search_buffer.insert(lookahead[0]);
@@ -187,14 +293,11 @@ impl<'a> Buffer<'a> {
                 continue;
             }
 
-            match self.find_longest_match(&search_buffer.data, lookahead) {
-                Some(m) => {
-                    if m.length > 0 {
-                        hop += m.length;
-                    }
-                    self.write(writer, m);
+            if let Some(m) = self.find_longest_match(&search_buffer.data, lookahead) {
+                if m.length > 0 {
+                    hop += m.length;
                 }
-                None => {}
+                self.write(writer, m);
             }
             search_buffer.insert(lookahead[0]);
         }
@@ -238,9 +341,66 @@ impl<'a> Buffer<'a> {
 #[cfg(test)]
 mod tests {
 
-    use super::find;
+    use super::*;
     use rstest::*;
 
+    // bytes described by the tokens following data[..start]
+    fn expand(data: &[u8], start: usize, tokens: &[Token]) -> Vec<u8> {
+        let mut res = data[..start].to_vec();
+        for token in tokens {
+            match *token {
+                Token::Literal(b) => res.push(b),
+                Token::Match { length, distance } => {
+                    for _ in 0..length {
+                        res.push(res[res.len() - distance as usize]);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    #[rstest]
+    #[case(b"", 0)]
+    #[case(b"abcabcabcabc", 0)]
+    #[case(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa", 0)]
+    #[case(b"abcdefabcdef", 6)]
+    fn test_tokens(#[case] data: &[u8], #[case] start: usize) {
+        let tokens = tokens(data, start);
+        assert_eq!(expand(data, start, &tokens), data);
+    }
+
+    #[test]
+    fn test_tokens_matches() {
+        assert_eq!(
+            tokens(b"abcabcabcx", 0),
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                // overlapping the bytes it copies
+                Token::Match {
+                    length: 6,
+                    distance: 3
+                },
+                Token::Literal(b'x'),
+            ]
+        );
+        // the history is referred to
+        assert_eq!(
+            tokens(b"abcdabcd", 4),
+            [Token::Match {
+                length: 4,
+                distance: 4
+            }]
+        );
+
+        let data: Vec<u8> = (0..100_000).map(|i| (i % 251) as u8).collect();
+        let tokens = tokens(&data, 0);
+        assert_eq!(expand(&data, 0, &tokens), data);
+        assert!(tokens.len() < 1000);
+    }
+
     #[rstest]
     #[case(vec![0, 1, 2, 1, 1, 2, 1], &[2, 1, 1], Some(2))]
     #[case(vec![2, 2, 2], &[2, 2, 2], Some(0))]
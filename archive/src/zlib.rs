@@ -0,0 +1,209 @@
+use crate::{deflate, error::ArchiveError};
+
+// deflate with a 32K window at the default level, the header most encoders
+// write
+const HEADER: [u8; 2] = [0x78, 0x9c];
+const CM_DEFLATE: u8 = 8;
+// flag of a preset dictionary, which is not supported
+const FDICT: u8 = 1 << 5;
+
+const ADLER_MOD: u32 = 65521;
+// bytes summed before a modulo is needed to avoid an overflow
+const ADLER_CHUNK: usize = 5552;
+
+// https://datatracker.ietf.org/doc/html/rfc1950#section-8.2
+pub fn adler32(adler: u32, input: &[u8]) -> u32 {
+    let mut a = adler & 0xffff;
+    let mut b = adler >> 16;
+    for chunk in input.chunks(ADLER_CHUNK) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= ADLER_MOD;
+        b %= ADLER_MOD;
+    }
+    (b << 16) | a
+}
+
+// Encoder writes a zlib stream compressing data written in pieces.
+#[derive(Debug)]
+pub struct Encoder {
+    started: bool,
+    deflate: deflate::Encoder,
+    adler: u32,
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self {
+            started: false,
+            deflate: deflate::Encoder::new(),
+            adler: 1,
+        }
+    }
+
+    // returns the bytes of the stream available so far
+    pub fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        if !self.started {
+            out.extend_from_slice(&HEADER);
+            self.started = true;
+        }
+        self.adler = adler32(self.adler, data);
+        out.extend(self.deflate.write(data));
+        out
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut out = self.write(&[]);
+        out.extend(self.deflate.finish());
+        out.extend_from_slice(&self.adler.to_be_bytes());
+        out
+    }
+}
+
+// Decoder decompresses a zlib stream received in pieces.
+#[derive(Debug)]
+pub struct Decoder {
+    header: Vec<u8>,
+    deflate: deflate::Decoder,
+    adler: u32,
+    done: bool,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self {
+            header: Vec::new(),
+            deflate: deflate::Decoder::new(),
+            adler: 1,
+            done: false,
+        }
+    }
+
+    // returns the bytes decoded so far
+    pub fn write(&mut self, mut data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        if self.header.len() < 2 {
+            let n = data.len().min(2 - self.header.len());
+            self.header.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.header.len() < 2 {
+                return Ok(Vec::new());
+            }
+            let (cmf, flg) = (self.header[0], self.header[1]);
+            if cmf & 0x0f != CM_DEFLATE || cmf >> 4 > 7 {
+                return Err(ArchiveError::InvalidHeader {
+                    reason: "compression method",
+                });
+            }
+            if !(((cmf as u16) << 8) | flg as u16).is_multiple_of(31) {
+                return Err(ArchiveError::InvalidHeader { reason: "check" });
+            }
+            if flg & FDICT != 0 {
+                return Err(ArchiveError::InvalidHeader {
+                    reason: "preset dictionary",
+                });
+            }
+        }
+
+        let out = self.deflate.write(data)?;
+        self.adler = adler32(self.adler, &out);
+        if self.deflate.done() && !self.done {
+            let Some(trailer) = self.deflate.rest().get(..4) else {
+                return Ok(out);
+            };
+            if u32::from_be_bytes(trailer.try_into().expect("4 bytes")) != self.adler {
+                return Err(ArchiveError::ChecksumMismatch);
+            }
+            self.done = true;
+        }
+        Ok(out)
+    }
+
+    pub fn finish(&self) -> Result<(), ArchiveError> {
+        match self.done {
+            true => Ok(()),
+            false => Err(ArchiveError::UnexpectedEnd),
+        }
+    }
+}
+
+pub fn compress(input: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    let mut out = encoder.write(input);
+    out.extend(encoder.finish());
+    out
+}
+
+pub fn decompress(input: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut decoder = Decoder::new();
+    let out = decoder.write(input)?;
+    decoder.finish()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(b"", 1)]
+    #[case(b"Wikipedia", 0x11e60398)]
+    fn test_adler32(#[case] input: &[u8], #[case] expected: u32) {
+        assert_eq!(adler32(1, input), expected);
+    }
+
+    #[test]
+    fn test_adler32_long() {
+        let input = vec![0xff; 100_000];
+        let split = adler32(adler32(1, &input[..33_333]), &input[33_333..]);
+        assert_eq!(adler32(1, &input), split);
+        assert_eq!(split, 0x149a_302c);
+    }
+
+    // streams produced by zlib
+    #[rstest]
+    #[case(&[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01], b"")]
+    #[case(&[
+        0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00, 0x06, 0x2c, 0x02, 0x15,
+    ], b"hello")]
+    fn test_decompress_zlib(#[case] input: &[u8], #[case] expected: &[u8]) {
+        assert_eq!(decompress(input).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case(b"".to_vec())]
+    #[case(b"zlib ".repeat(50_000))]
+    fn test_round_trip(#[case] input: Vec<u8>) {
+        let compressed = compress(&input);
+        let mut decoder = Decoder::new();
+        let mut out = Vec::new();
+        for piece in compressed.chunks(1) {
+            out.extend(decoder.write(piece).unwrap());
+        }
+        decoder.finish().unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[rstest]
+    #[case(&[0x78, 0x9d, 0x03, 0x00], ArchiveError::InvalidHeader { reason: "check" })]
+    #[case(&[0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x02], ArchiveError::ChecksumMismatch)]
+    #[case(&[0x78, 0x9c, 0x03, 0x00, 0x00], ArchiveError::UnexpectedEnd)]
+    fn test_decompress_invalid(#[case] input: &[u8], #[case] expected: ArchiveError) {
+        assert_eq!(decompress(input), Err(expected));
+    }
+}
@@ -1,3 +1,29 @@
+use std::{borrow::Borrow, collections::HashMap, hash::Hash, sync::Arc};
+
+// Weighted values tell how much of the capacity of a cache they use,
+// typically their size in bytes.
+pub trait Weighted {
+    fn weight(&self) -> usize;
+}
+
+impl Weighted for Vec<u8> {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl Weighted for String {
+    fn weight(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<T: Weighted + ?Sized> Weighted for Arc<T> {
+    fn weight(&self) -> usize {
+        self.as_ref().weight()
+    }
+}
+
 #[derive(Debug)]
 pub struct Entry<T> {
     value: Option<T>,
@@ -6,76 +32,117 @@ pub struct Entry<T> {
     next: usize,
 }
 
+// LinkedList is a doubly linked list whose nodes live in a vector, so that a
+// node can be found, moved or removed in O(1) from its index. The node at
+// index 0 is a sentinel: its next is the front of the list, its prev the back.
+// Indexes of removed nodes are reused.
 #[derive(Debug)]
 pub struct LinkedList<T> {
     entries: Vec<Entry<T>>,
+    free: Vec<usize>,
     length: usize,
-    capacity: usize,
+}
+
+impl<T> Default for LinkedList<T> {
+    fn default() -> Self {
+        Self::with_capacity(0)
+    }
 }
 
 impl<T> LinkedList<T> {
     pub fn with_capacity(capacity: usize) -> Self {
-        let mut entries = Vec::with_capacity(capacity);
+        let mut entries = Vec::with_capacity(capacity + 1);
         entries.push(Entry {
             value: None,
             next: 0,
             prev: 0,
         });
-        entries.push(Entry {
-            value: None,
-            next: 1,
-            prev: 1,
-        });
         Self {
             entries,
+            free: Vec::new(),
             length: 0,
-            capacity,
         }
     }
 
-    pub fn unlink(&mut self, index: usize) {
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    fn unlink(&mut self, index: usize) {
         let prev = self.entries[index].prev;
         let next = self.entries[index].next;
         self.entries[prev].next = next;
         self.entries[next].prev = prev;
     }
 
-    pub fn link_after(&mut self, index: usize, prev: usize) {
+    fn link_after(&mut self, index: usize, prev: usize) {
         let next = self.entries[prev].next;
-        self.entries[next].prev = prev;
-        self.entries[next].next = next;
+        self.entries[index].prev = prev;
+        self.entries[index].next = next;
         self.entries[prev].next = index;
         self.entries[next].prev = index;
     }
 
+    // inserts a value at the front, returns the index of its node
     pub fn push_front(&mut self, value: T) -> usize {
-        if self.entries[0].next == 0 {
-            self.entries.push(Entry {
-                value: None,
-                next: 0,
-                prev: 0,
-            });
-            self.entries[0].next = self.entries.len() - 1;
-        }
-        let index = self.entries[0].next;
-        self.entries[index].value = Some(value);
-        self.unlink(index);
-        self.link_after(index, 1);
+        let entry = Entry {
+            value: Some(value),
+            next: 0,
+            prev: 0,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.entries[index] = entry;
+                index
+            }
+            None => {
+                self.entries.push(entry);
+                self.entries.len() - 1
+            }
+        };
+        self.link_after(index, 0);
+        self.length += 1;
         index
     }
 
-    pub fn back(&self) -> usize {
-        self.entries[1].prev
+    pub fn move_to_front(&mut self, index: usize) {
+        self.unlink(index);
+        self.link_after(index, 0);
+    }
+
+    pub fn remove(&mut self, index: usize) -> T {
+        let value = self.entries[index].value.take().expect("invalid index");
+        self.unlink(index);
+        self.free.push(index);
+        self.length -= 1;
+        value
+    }
+
+    pub fn front(&self) -> Option<usize> {
+        Some(self.entries[0].next).filter(|index| *index != 0)
+    }
+
+    pub fn back(&self) -> Option<usize> {
+        Some(self.entries[0].prev).filter(|index| *index != 0)
     }
 
     pub fn get(&self, index: usize) -> &T {
         self.entries[index].value.as_ref().expect("invalid index")
     }
 
-    pub fn iter(&self) -> LinkedListIterator<T> {
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        self.entries[index].value.as_mut().expect("invalid index")
+    }
+
+    // values from the front to the back of the list
+    pub fn iter(&self) -> LinkedListIterator<'_, T> {
         LinkedListIterator::<T> {
             list: self,
-            index: 1,
+            index: 0,
         }
     }
 }
@@ -94,34 +161,226 @@ impl<'a, T> Iterator for LinkedListIterator<'a, T> {
         if next == 0 {
             None
         } else {
-            let value = self.list.entries[next].value.as_ref();
             self.index = next;
-            value
+            self.list.entries[next].value.as_ref()
+        }
+    }
+}
+
+// Lru maps keys to values, evicting the least recently used values once the
+// total weight of the values exceeds its capacity.
+#[derive(Debug)]
+pub struct Lru<K, V> {
+    map: HashMap<K, usize>,
+    list: LinkedList<(K, V)>,
+    weight: usize,
+    capacity: usize,
+}
+
+impl<K: Hash + Eq + Clone, V: Weighted> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            list: LinkedList::default(),
+            weight: 0,
+            capacity,
         }
     }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    // total weight of the values held
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // returns the value of the key, marking it as the most recently used
+    pub fn get<Q>(&mut self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        self.list.move_to_front(index);
+        Some(&self.list.get(index).1)
+    }
+
+    // returns the value of the key without marking it as used
+    pub fn peek<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        Some(&self.list.get(index).1)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    // inserts a value, evicting the least recently used values to make room
+    // for it. A value heavier than the capacity is not inserted. Returns the
+    // evicted values, along with the value replaced for the same key.
+    pub fn put(&mut self, key: K, value: V) -> Vec<(K, V)> {
+        let mut evicted = Vec::new();
+        if let Some(old) = self.remove(&key) {
+            evicted.push((key.clone(), old));
+        }
+        let weight = value.weight();
+        if weight > self.capacity {
+            return evicted;
+        }
+
+        while self.weight + weight > self.capacity {
+            let back = self.list.back().expect("weight of an empty list is 0");
+            let (k, v) = self.list.remove(back);
+            self.map.remove(&k);
+            self.weight -= v.weight();
+            evicted.push((k, v));
+        }
+
+        let index = self.list.push_front((key.clone(), value));
+        self.map.insert(key, index);
+        self.weight += weight;
+        evicted
+    }
+
+    // changes the capacity, evicting the least recently used values that no
+    // longer fit. Returns the evicted values.
+    pub fn resize(&mut self, capacity: usize) -> Vec<(K, V)> {
+        self.capacity = capacity;
+        let mut evicted = Vec::new();
+        while self.weight > self.capacity {
+            let back = self.list.back().expect("weight of an empty list is 0");
+            let (k, v) = self.list.remove(back);
+            self.map.remove(&k);
+            self.weight -= v.weight();
+            evicted.push((k, v));
+        }
+        evicted
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let index = self.map.remove(key)?;
+        let (_, value) = self.list.remove(index);
+        self.weight -= value.weight();
+        Some(value)
+    }
+
+    // entries from the most to the least recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.list.iter().map(|(k, v)| (k, v))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::LinkedList;
+    use super::*;
+
+    #[test]
+    fn test_linked_list() {
+        let mut list = LinkedList::<&str>::with_capacity(10);
+        assert_eq!(list.back(), None);
+
+        let holla = list.push_front("Holla");
+        let quetal = list.push_front("Quetal");
+        list.push_front("Comom");
+        assert_eq!(
+            list.iter().copied().collect::<Vec<_>>(),
+            ["Comom", "Quetal", "Holla"]
+        );
+        assert_eq!(list.get(list.back().unwrap()), &"Holla");
+
+        list.move_to_front(holla);
+        assert_eq!(
+            list.iter().copied().collect::<Vec<_>>(),
+            ["Holla", "Comom", "Quetal"]
+        );
+
+        assert_eq!(list.remove(quetal), "Quetal");
+        assert_eq!(list.len(), 2);
+        // the node of a removed value is reused
+        assert_eq!(list.push_front("Adios"), quetal);
+        assert_eq!(
+            list.iter().copied().collect::<Vec<_>>(),
+            ["Adios", "Holla", "Comom"]
+        );
+    }
 
     #[test]
     fn test_lru() {
-        let mut lru = LinkedList::<&str>::with_capacity(10);
-        println!("{:?}", lru.entries);
+        let mut lru = Lru::<&str, String>::new(10);
+        assert!(lru.put("a", "aaa".to_string()).is_empty());
+        assert!(lru.put("b", "bbb".to_string()).is_empty());
+        assert!(lru.put("c", "ccc".to_string()).is_empty());
+        assert_eq!(lru.weight(), 9);
 
-        lru.push_front("Holla");
-        println!("{:?}", lru.entries);
+        // a is used, b is now the least recently used
+        assert_eq!(lru.get("a"), Some(&"aaa".to_string()));
+        assert_eq!(
+            lru.put("d", "dd".to_string()),
+            vec![("b", "bbb".to_string())]
+        );
+        assert_eq!(lru.peek("b"), None);
+        assert_eq!(lru.weight(), 8);
+        assert_eq!(
+            lru.iter().map(|(k, _)| *k).collect::<Vec<_>>(),
+            ["d", "a", "c"]
+        );
 
-        lru.push_front("Quetal");
-        println!("{:?}", lru.entries);
+        // replacing a value may evict others to make room
+        assert_eq!(
+            lru.put("d", "dddddd".to_string()),
+            vec![("d", "dd".to_string()), ("c", "ccc".to_string())]
+        );
+        assert_eq!(lru.weight(), 9);
 
-        lru.push_front("Comom");
-        println!("{:?}", lru.entries);
+        // values heavier than the capacity are not kept
+        assert_eq!(lru.put("e", "e".repeat(11)), vec![]);
+        assert!(!lru.contains_key("e"));
 
-        lru.push_front("Holla");
-        println!("{:?}", lru.entries);
+        assert_eq!(lru.remove("a"), Some("aaa".to_string()));
+        assert_eq!(lru.len(), 1);
+        assert_eq!(lru.weight(), 6);
+    }
+
+    #[test]
+    fn test_lru_resize() {
+        let mut lru = Lru::<&str, String>::new(10);
+        lru.put("a", "aaa".to_string());
+        lru.put("b", "bbb".to_string());
+        lru.put("c", "ccc".to_string());
+        lru.get("a");
 
-        println!("{:?}", lru.get(lru.back()));
+        assert!(lru.resize(20).is_empty());
+        assert_eq!(
+            lru.resize(4),
+            vec![("b", "bbb".to_string()), ("c", "ccc".to_string())]
+        );
+        assert_eq!(lru.capacity(), 4);
+        assert_eq!(lru.weight(), 3);
+        assert_eq!(
+            lru.put("d", "dd".to_string()),
+            vec![("a", "aaa".to_string())]
+        );
     }
 }
@@ -26,9 +26,11 @@ fn inverse(ch: char, alphabet: &[char; 64]) -> Result<u8, EncodingError> {
     }
 }
 
+// decodes to the raw bytes, each of them is a char of the string returned by
+// decode
 #[inline]
-pub fn decode(input: &str, alphabet: &[char; 64]) -> Result<String, EncodingError> {
-    let mut res = String::with_capacity((input.len() / 4) * 3);
+pub fn decode_bytes(input: &str, alphabet: &[char; 64]) -> Result<Vec<u8>, EncodingError> {
+    let mut res = Vec::with_capacity((input.len() / 4) * 3);
 
     for window in input.trim_end_matches('=').as_bytes().chunks(4) {
         let n = window.len();
@@ -43,16 +45,16 @@ pub fn decode(input: &str, alphabet: &[char; 64]) -> Result<String, EncodingErro
 
         match n {
             2 => {
-                res.push(((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4)) as char);
+                res.push((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4));
             }
             3 => {
-                res.push(((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4)) as char);
-                res.push((((indexes[1] & 0xF) << 4) | ((indexes[2] & 0x3C) >> 2)) as char);
+                res.push((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4));
+                res.push(((indexes[1] & 0xF) << 4) | ((indexes[2] & 0x3C) >> 2));
             }
             4 => {
-                res.push(((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4)) as char);
-                res.push((((indexes[1] & 0xF) << 4) | ((indexes[2] & 0x3C) >> 2)) as char);
-                res.push((((indexes[2]) << 6) | (indexes[3] & 0x3F)) as char);
+                res.push((indexes[0] << 2) | ((indexes[1] & 0x30) >> 4));
+                res.push(((indexes[1] & 0xF) << 4) | ((indexes[2] & 0x3C) >> 2));
+                res.push(((indexes[2]) << 6) | (indexes[3] & 0x3F));
             }
             _ => {}
         }
@@ -61,11 +63,19 @@ pub fn decode(input: &str, alphabet: &[char; 64]) -> Result<String, EncodingErro
 }
 
 #[inline]
-pub fn encode(input: &str, alphabet: &[char; 64]) -> String {
+pub fn decode(input: &str, alphabet: &[char; 64]) -> Result<String, EncodingError> {
+    Ok(decode_bytes(input, alphabet)?
+        .into_iter()
+        .map(|b| b as char)
+        .collect())
+}
+
+#[inline]
+pub fn encode_bytes(input: &[u8], alphabet: &[char; 64]) -> String {
     let encoded_length = ((4 * input.len() / 3) + 3) & !3;
     let mut res = String::with_capacity(encoded_length);
 
-    for window in input.as_bytes().chunks(3) {
+    for window in input.chunks(3) {
         let n = window.len();
         let mut encoded: [char; 4] = ['=', '=', '=', '='];
 
@@ -96,6 +106,11 @@ pub fn encode(input: &str, alphabet: &[char; 64]) -> String {
     res
 }
 
+#[inline]
+pub fn encode(input: &str, alphabet: &[char; 64]) -> String {
+    encode_bytes(input.as_bytes(), alphabet)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -126,4 +141,12 @@ mod tests {
 
         assert_eq!(decode(&encoded, STD_ALPHABET).unwrap(), input);
     }
+
+    #[test]
+    fn test_bytes_encoding() {
+        let input = [0u8, 0xff, 0x80, 0x7f, 0xc3];
+        let encoded = encode_bytes(&input, STD_ALPHABET);
+        assert_eq!(encoded, "AP+Af8M=");
+        assert_eq!(decode_bytes(&encoded, STD_ALPHABET).unwrap(), input);
+    }
 }
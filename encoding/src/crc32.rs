@@ -120,16 +120,17 @@ fn generate_table(polynomial: u128) -> [u128; 256] {
 }
 
 pub fn checksum(input: &str, table: [u128; 256]) -> u128 {
-    let mut crc = 0u128;
+    update(0, input.as_bytes(), &table)
+}
 
-    for b in input.as_bytes() {
-        let mut c = crc ^ 0xffffffff;
+// continues the checksum of the bytes seen so far with the following ones,
+// starting from 0
+pub fn update(crc: u128, input: &[u8], table: &[u128; 256]) -> u128 {
+    let mut c = crc ^ 0xffffffff;
+    for b in input {
         c = table[((c ^ *b as u128) & 0xff) as usize] ^ (c >> 8);
-        c ^= 0xffffffff;
-        crc = c;
     }
-
-    crc
+    c ^ 0xffffffff
 }
 
 #[cfg(test)]
@@ -161,4 +162,10 @@ mod tests {
     fn test_checksum(#[case] input: &str, #[case] table: [u128; 256], #[case] expected: u128) {
         assert_eq!(checksum(input, table), expected);
     }
+
+    #[test]
+    fn test_update() {
+        let crc = update(0, b"a", &TABLE_IEEE);
+        assert_eq!(update(crc, b"bc", &TABLE_IEEE), 891568578);
+    }
 }
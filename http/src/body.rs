@@ -0,0 +1,327 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use super::{
+    chunked,
+    error::frame::FrameError,
+    header::{HeaderKind, HeaderMap},
+    method::Method,
+    statuscode::StatusCode,
+};
+
+// largest piece of body read or written at once, which bounds the memory
+// used to relay a body whatever its size
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+// Framing tells how the end of a message body is found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    Empty,
+    Length(usize),
+    Chunked,
+    // the body ends when the connection is closed, only valid for responses
+    Close,
+}
+
+impl Framing {
+    pub fn request(method: &Method, headers: &HeaderMap) -> Result<Self, FrameError> {
+        // a body framed both ways could be read differently by the next hop,
+        // which would smuggle a request in the body of another
+        if chunked::is_chunked(headers)? {
+            if headers.raw.contains_key("content-length") {
+                return Err(FrameError::Invalid {
+                    reason: "content-length and transfer-encoding are both set",
+                    subject: "request",
+                });
+            }
+            return Ok(Framing::Chunked);
+        }
+        if let Some(n) = content_length(headers)? {
+            return Ok(Framing::Length(n));
+        }
+        if *method == Method::POST {
+            return Err(FrameError::RequiredParam {
+                subject: "content-length header is required",
+            });
+        }
+        Ok(Framing::Empty)
+    }
+
+    pub fn response(
+        method: &Method,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Result<Self, FrameError> {
+        if *method == Method::HEAD
+            || status.code() < 200
+            || status == StatusCode::NoContent
+            || status == StatusCode::NotModified
+        {
+            return Ok(Framing::Empty);
+        }
+        if chunked::is_chunked(headers)? {
+            return Ok(Framing::Chunked);
+        }
+        match content_length(headers)? {
+            Some(n) => Ok(Framing::Length(n)),
+            None => Ok(Framing::Close),
+        }
+    }
+
+    // whether the end of the body is known without closing the connection
+    pub fn delimited(&self) -> bool {
+        *self != Framing::Close
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, FrameError> {
+    if !headers.raw.contains_key("content-length") {
+        return Ok(None);
+    }
+    match headers.get("content-length")? {
+        HeaderKind::ContentLength(n) => Ok(Some(n)),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Debug)]
+enum State {
+    // bytes left in the body, or in the current chunk
+    Data(usize),
+    // expecting the size line of the next chunk
+    Size,
+    Done,
+}
+
+// BodyReader reads a body piece by piece from a buffered stream, leaving the
+// bytes following it in the buffer.
+#[derive(Debug)]
+pub struct BodyReader {
+    framing: Framing,
+    state: State,
+    trailers: Option<HeaderMap>,
+}
+
+impl BodyReader {
+    pub fn new(framing: Framing) -> Self {
+        let state = match framing {
+            Framing::Empty | Framing::Length(0) => State::Done,
+            Framing::Length(n) => State::Data(n),
+            Framing::Chunked => State::Size,
+            Framing::Close => State::Data(usize::MAX),
+        };
+        Self {
+            framing,
+            state,
+            trailers: None,
+        }
+    }
+
+    pub fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    // trailer fields of a chunked body, available once it was read entirely
+    pub fn trailers(&mut self) -> Option<HeaderMap> {
+        self.trailers.take()
+    }
+
+    // returns the next piece of the body, at most MAX_CHUNK_SIZE long, or
+    // None once the body was read entirely
+    pub async fn next<R: AsyncBufRead + Unpin>(
+        &mut self,
+        buffer: &mut R,
+    ) -> Result<Option<Vec<u8>>, FrameError> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Size => match chunked::read_size(buffer).await? {
+                    0 => {
+                        self.trailers = chunked::read_trailers(buffer).await?;
+                        self.state = State::Done;
+                    }
+                    n => self.state = State::Data(n),
+                },
+                State::Data(remaining) => {
+                    let available = buffer.fill_buf().await?;
+                    if available.is_empty() {
+                        if self.framing == Framing::Close {
+                            self.state = State::Done;
+                            return Ok(None);
+                        }
+                        return Err(FrameError::IOError(std::io::Error::from(
+                            std::io::ErrorKind::UnexpectedEof,
+                        )));
+                    }
+
+                    let n = available.len().min(remaining).min(MAX_CHUNK_SIZE);
+                    let data = available[..n].to_vec();
+                    buffer.consume(n);
+
+                    self.state = match (self.framing, remaining - n) {
+                        (Framing::Close, _) => State::Data(remaining),
+                        (Framing::Chunked, 0) => {
+                            chunked::read_end(buffer).await?;
+                            State::Size
+                        }
+                        (_, 0) => State::Done,
+                        (_, left) => State::Data(left),
+                    };
+                    return Ok(Some(data));
+                }
+            }
+        }
+    }
+
+    // reads the whole body in memory
+    pub async fn collect<R: AsyncBufRead + Unpin>(
+        mut self,
+        buffer: &mut R,
+    ) -> Result<(Vec<u8>, Option<HeaderMap>), FrameError> {
+        let mut body = Vec::new();
+        while let Some(data) = self.next(buffer).await? {
+            body.extend_from_slice(&data);
+        }
+        Ok((body, self.trailers()))
+    }
+}
+
+// BodyWriter writes a body piece by piece with the given framing, encoding
+// the pieces as chunks when needed.
+#[derive(Debug)]
+pub struct BodyWriter {
+    chunked: bool,
+}
+
+impl BodyWriter {
+    pub fn new(framing: Framing) -> Self {
+        Self {
+            chunked: framing == Framing::Chunked,
+        }
+    }
+
+    pub async fn write<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut W,
+        data: &[u8],
+    ) -> Result<(), FrameError> {
+        if !self.chunked {
+            stream.write_all(data).await?;
+            return Ok(());
+        }
+        // an empty chunk would mark the end of the body
+        if !data.is_empty() {
+            stream
+                .write_all(format!("{:x}\r\n", data.len()).as_bytes())
+                .await?;
+            stream.write_all(data).await?;
+            stream.write_all(b"\r\n").await?;
+        }
+        Ok(())
+    }
+
+    pub async fn finish<W: AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut W,
+        trailers: Option<&HeaderMap>,
+    ) -> Result<(), FrameError> {
+        if self.chunked {
+            stream.write_all(b"0\r\n").await?;
+            if let Some(trailers) = trailers {
+                stream
+                    .write_all(String::try_from(trailers.clone())?.as_bytes())
+                    .await?;
+            }
+            stream.write_all(b"\r\n").await?;
+        }
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use tokio::io::BufReader;
+
+    #[rstest]
+    #[case(Method::GET, "", Some(Framing::Empty))]
+    #[case(Method::POST, "", None)]
+    #[case(Method::PUT, "content-length: 3", Some(Framing::Length(3)))]
+    #[case(Method::POST, "transfer-encoding: chunked", Some(Framing::Chunked))]
+    #[case(Method::POST, "content-length: abc", None)]
+    #[case(Method::POST, "content-length: 3\r\ntransfer-encoding: chunked", None)]
+    fn test_framing_request(
+        #[case] method: Method,
+        #[case] header: &str,
+        #[case] expected: Option<Framing>,
+    ) {
+        let mut headers = HeaderMap::default();
+        for line in header.split("\r\n") {
+            headers.parse(line).unwrap();
+        }
+        assert_eq!(Framing::request(&method, &headers).ok(), expected);
+    }
+
+    #[rstest]
+    #[case(Method::GET, StatusCode::Ok, "", Framing::Close)]
+    #[case(Method::GET, StatusCode::Ok, "content-length: 3", Framing::Length(3))]
+    #[case(Method::HEAD, StatusCode::Ok, "content-length: 3", Framing::Empty)]
+    #[case(Method::GET, StatusCode::NoContent, "", Framing::Empty)]
+    #[case(Method::GET, StatusCode::NotModified, "", Framing::Empty)]
+    #[case(
+        Method::GET,
+        StatusCode::Ok,
+        "transfer-encoding: chunked",
+        Framing::Chunked
+    )]
+    fn test_framing_response(
+        #[case] method: Method,
+        #[case] status: StatusCode,
+        #[case] header: &str,
+        #[case] expected: Framing,
+    ) {
+        let mut headers = HeaderMap::default();
+        headers.parse(header).unwrap();
+        assert_eq!(
+            Framing::response(&method, status, &headers).unwrap(),
+            expected
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_bounded_pieces() {
+        let body = vec![7u8; MAX_CHUNK_SIZE * 2 + 10];
+        let mut input = body.clone();
+        input.extend_from_slice(b"next");
+
+        let mut buffer = BufReader::with_capacity(MAX_CHUNK_SIZE * 4, input.as_slice());
+        let mut reader = BodyReader::new(Framing::Length(body.len()));
+        let mut sizes = Vec::new();
+        while let Some(data) = reader.next(&mut buffer).await.unwrap() {
+            sizes.push(data.len());
+        }
+        assert_eq!(sizes, vec![MAX_CHUNK_SIZE, MAX_CHUNK_SIZE, 10]);
+        assert_eq!(buffer.buffer(), b"next");
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_close() {
+        let mut buffer: &[u8] = b"until the end";
+        let (body, trailers) = BodyReader::new(Framing::Close)
+            .collect(&mut buffer)
+            .await
+            .unwrap();
+        assert_eq!(body, b"until the end");
+        assert_eq!(trailers, None);
+    }
+
+    #[tokio::test]
+    async fn test_body_reader_truncated() {
+        let mut buffer: &[u8] = b"abc";
+        let res = BodyReader::new(Framing::Length(4))
+            .collect(&mut buffer)
+            .await;
+        assert!(res.is_err());
+    }
+}
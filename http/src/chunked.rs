@@ -0,0 +1,209 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt};
+
+use super::{
+    body::{BodyReader, Framing},
+    error::frame::FrameError,
+    header::HeaderMap,
+};
+
+const MAX_CHUNK_LINE_SIZE: usize = 1024;
+
+// whether the body of a message with these headers is chunked, which is only
+// the case when chunked is the last transfer coding applied
+pub fn is_chunked(headers: &HeaderMap) -> Result<bool, FrameError> {
+    let codings = match headers.raw.get("transfer-encoding") {
+        Some(codings) => codings,
+        None => return Ok(false),
+    };
+
+    let codings: Vec<&str> = codings.split(',').map(|c| c.trim()).collect();
+    match codings.as_slice() {
+        [] => Ok(false),
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(true),
+        _ => Err(FrameError::NotImplemented {
+            subject: format!(
+                "transfer-encoding not implemented for {}",
+                codings.join(", ")
+            ),
+        }),
+    }
+}
+
+async fn read_line<R: AsyncBufRead + Unpin>(
+    buffer: &mut R,
+    line: &mut String,
+) -> Result<(), FrameError> {
+    line.clear();
+    let n = buffer
+        .take(MAX_CHUNK_LINE_SIZE as u64)
+        .read_line(line)
+        .await?;
+    if n == 0 || !line.ends_with("\r\n") {
+        return Err(FrameError::Invalid {
+            reason: "chunk line is truncated or too long",
+            subject: "transfer-encoding",
+        });
+    }
+    line.truncate(line.len() - 2);
+    Ok(())
+}
+
+// reads the size line of the next chunk, chunk extensions are ignored
+pub async fn read_size<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<usize, FrameError> {
+    let mut line = String::new();
+    read_line(buffer, &mut line).await?;
+
+    let size = line.split(';').next().unwrap_or_default().trim();
+    match usize::from_str_radix(size, 16) {
+        Ok(size) => Ok(size),
+        Err(_) => Err(FrameError::Invalid {
+            reason: "invalid chunk size",
+            subject: "transfer-encoding",
+        }),
+    }
+}
+
+// reads the line break ending the data of a chunk
+pub async fn read_end<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<(), FrameError> {
+    let mut line = String::new();
+    read_line(buffer, &mut line).await?;
+    if !line.is_empty() {
+        return Err(FrameError::Invalid {
+            reason: "chunk data is longer than its size",
+            subject: "transfer-encoding",
+        });
+    }
+    Ok(())
+}
+
+// reads the trailer fields following the last chunk
+pub async fn read_trailers<R: AsyncBufRead + Unpin>(
+    buffer: &mut R,
+) -> Result<Option<HeaderMap>, FrameError> {
+    let mut line = String::new();
+    let mut trailers = HeaderMap::default();
+    loop {
+        read_line(buffer, &mut line).await?;
+        if line.is_empty() {
+            break;
+        }
+        trailers.parse(&line)?;
+    }
+
+    if trailers.raw.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(trailers))
+    }
+}
+
+// decodes a chunked body, the trailer fields following the last chunk are
+// returned along with it
+pub async fn read<R: AsyncBufRead + Unpin>(
+    buffer: &mut R,
+) -> Result<(Vec<u8>, Option<HeaderMap>), FrameError> {
+    BodyReader::new(Framing::Chunked).collect(buffer).await
+}
+
+// encodes a body as a single chunk followed by the last chunk and trailers
+pub fn encode(body: &[u8], trailers: Option<&HeaderMap>) -> Result<Vec<u8>, FrameError> {
+    let mut res = Vec::with_capacity(body.len() + 16);
+    if !body.is_empty() {
+        res.extend_from_slice(format!("{:x}\r\n", body.len()).as_bytes());
+        res.extend_from_slice(body);
+        res.extend_from_slice(b"\r\n");
+    }
+    res.extend_from_slice(b"0\r\n");
+    if let Some(trailers) = trailers {
+        res.extend_from_slice(String::try_from(trailers.clone())?.as_bytes());
+    }
+    res.extend_from_slice(b"\r\n");
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("0\r\n\r\n", "", None)]
+    #[case("4\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n", "Wikipedia", None)]
+    #[case(
+        "4;name=value\r\nWiki\r\nB\r\n in\r\nchunks\r\n0\r\n\r\n",
+        "Wiki in\r\nchunks",
+        None
+    )]
+    #[case(
+        "3\r\nabc\r\n0\r\nExpires: never\r\nX-Checksum: 42\r\n\r\n",
+        "abc",
+        Some(vec![("expires", "never"), ("x-checksum", "42")])
+    )]
+    #[tokio::test]
+    async fn test_chunked_read(
+        #[case] input: &str,
+        #[case] expected: &str,
+        #[case] trailers: Option<Vec<(&str, &str)>>,
+    ) {
+        let mut buffer = input.as_bytes();
+        let (body, res) = read(&mut buffer).await.unwrap();
+        assert_eq!(body, expected.as_bytes());
+        assert!(buffer.is_empty());
+
+        let res: Option<Vec<(String, String)>> = res.map(|t| {
+            let mut fields: Vec<(String, String)> = t.raw.into_iter().collect();
+            fields.sort();
+            fields
+        });
+        let trailers = trailers.map(|t| {
+            t.into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        });
+        assert_eq!(res, trailers);
+    }
+
+    #[rstest]
+    #[case("4\r\nWiki\r\n")]
+    #[case("z\r\nWiki\r\n0\r\n\r\n")]
+    #[case("2\r\nWiki\r\n0\r\n\r\n")]
+    #[case("4\r\nWiki\r\n0\r\n")]
+    #[tokio::test]
+    async fn test_chunked_read_invalid(#[case] input: &str) {
+        let mut buffer = input.as_bytes();
+        assert!(read(&mut buffer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chunked_roundtrip() {
+        let mut trailers = HeaderMap::default();
+        trailers.parse("x-checksum: 42").unwrap();
+
+        let encoded = encode(b"hello world", Some(&trailers)).unwrap();
+        assert_eq!(
+            encoded,
+            b"b\r\nhello world\r\n0\r\nx-checksum:42\r\n\r\n".to_vec()
+        );
+
+        let (body, res) = read(&mut encoded.as_slice()).await.unwrap();
+        assert_eq!(body, b"hello world");
+        assert_eq!(res, Some(trailers));
+
+        assert_eq!(encode(b"", None).unwrap(), b"0\r\n\r\n".to_vec());
+    }
+
+    #[rstest]
+    #[case(None, Some(false))]
+    #[case(Some("chunked"), Some(true))]
+    #[case(Some("Chunked"), Some(true))]
+    #[case(Some("gzip, chunked"), None)]
+    fn test_is_chunked(#[case] value: Option<&str>, #[case] expected: Option<bool>) {
+        let mut headers = HeaderMap::default();
+        if let Some(value) = value {
+            headers
+                .parse(&format!("transfer-encoding: {}", value))
+                .unwrap();
+        }
+        assert_eq!(is_chunked(&headers).ok(), expected);
+    }
+}
@@ -1,40 +1,387 @@
-use std::net::Ipv4Addr;
+use std::{
+    collections::HashMap,
+    net::{Ipv4Addr, SocketAddr},
+    ops::Range,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
-use tokio::net::TcpStream;
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    net::TcpStream,
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::Instant,
+};
 
 use super::{
-    error::frame::FrameError, request::Request, response::Response, uri::authority::Authority,
+    body::Framing,
+    error::frame::FrameError,
+    request::{Parts, Request},
+    response::Response,
+    uri::authority::Authority,
 };
 use dns::resolver::Resolver;
 
-pub struct Client {}
+pub const MAX_IDLE_PER_HOST: usize = 32;
+pub const MAX_PER_HOST: usize = 256;
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+#[derive(Debug)]
+struct Idle {
+    stream: TcpStream,
+    since: Instant,
+}
+
+#[derive(Debug)]
+struct Host {
+    idle: Vec<Idle>,
+    permits: Arc<Semaphore>,
+}
+
+// Client keeps the connections it opened alive and reuses them for later
+// requests to the same authority. Clones share the same pool.
+#[derive(Debug, Clone)]
+pub struct Client {
+    dns_ip: Vec<Ipv4Addr>,
+    max_idle_per_host: usize,
+    max_per_host: usize,
+    idle_timeout: Duration,
+    decompress: bool,
+
+    hosts: Arc<Mutex<HashMap<Authority, Host>>>,
+}
 
 impl Client {
-    pub async fn perform(request: Request, dns_ip: &[Ipv4Addr]) -> Result<Response, FrameError> {
-        let mut stream: TcpStream;
-        let resolver = Resolver::new();
+    pub fn new(dns_ip: &[Ipv4Addr]) -> Self {
+        Self {
+            dns_ip: dns_ip.to_vec(),
+            max_idle_per_host: MAX_IDLE_PER_HOST,
+            max_per_host: MAX_PER_HOST,
+            idle_timeout: IDLE_TIMEOUT,
+            decompress: false,
+            hosts: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
 
-        match request.parts.url.authority {
-            Authority::Domain { ref host, port } => {
-                let hosts: Vec<Ipv4Addr> = resolver.lookup_a(host, dns_ip).await?;
-                let host = hosts.first().unwrap();
-                stream = TcpStream::connect((*host, port as u16)).await?;
+    pub fn max_idle_per_host(mut self, n: usize) -> Self {
+        self.max_idle_per_host = n;
+        self
+    }
+
+    // limit of requests in flight, hence of connections in use, to the same
+    // authority. Requests wait for one to complete once it is reached.
+    pub fn max_per_host(mut self, n: usize) -> Self {
+        self.max_per_host = n.max(1);
+        self
+    }
+
+    pub fn idle_timeout(mut self, d: Duration) -> Self {
+        self.idle_timeout = d;
+        self
+    }
+
+    // asks servers for gzip or deflate bodies when the request does not tell
+    // which codings it accepts, and decodes the bodies of responses
+    // performed, for callers wanting plain bodies
+    pub fn decompress(mut self, enabled: bool) -> Self {
+        self.decompress = enabled;
+        self
+    }
+
+    // number of idle connections currently kept for the authority
+    pub fn idle(&self, authority: &Authority) -> usize {
+        self.hosts()
+            .get(authority)
+            .map(|host| host.idle.len())
+            .unwrap_or_default()
+    }
+
+    fn hosts(&self) -> std::sync::MutexGuard<'_, HashMap<Authority, Host>> {
+        self.hosts.lock().expect("client pool lock poisoned")
+    }
+
+    // returns a connection to the authority, an idle one when available,
+    // for the caller to write a request and read its response on
+    pub async fn connect(&self, authority: &Authority) -> Result<Connection, FrameError> {
+        self.connection(authority, true).await
+    }
+
+    // same as connect, but always opens a new connection
+    pub async fn connect_new(&self, authority: &Authority) -> Result<Connection, FrameError> {
+        self.connection(authority, false).await
+    }
+
+    async fn connection(
+        &self,
+        authority: &Authority,
+        reuse: bool,
+    ) -> Result<Connection, FrameError> {
+        let permits = {
+            let mut hosts = self.hosts();
+            self.prune(&mut hosts);
+            hosts
+                .entry(authority.clone())
+                .or_insert_with(|| Host {
+                    idle: Vec::new(),
+                    permits: Arc::new(Semaphore::new(self.max_per_host)),
+                })
+                .permits
+                .clone()
+        };
+        let permit = match permits.acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => {
+                return Err(FrameError::Invalid {
+                    reason: "connection pool closed",
+                    subject: "client",
+                })
+            }
+        };
+
+        let idle = if reuse {
+            self.checkout(authority)
+        } else {
+            None
+        };
+        let (stream, dial) = match idle {
+            Some(stream) => (stream, None),
+            None => {
+                let (stream, dial) = self.open(authority).await?;
+                (stream, Some(dial))
+            }
+        };
+        Ok(Connection {
+            stream: BufReader::new(stream),
+            dial,
+            authority: authority.clone(),
+            client: self.clone(),
+            _permit: permit,
+        })
+    }
+
+    pub async fn perform(&self, mut request: Request) -> Result<Response, FrameError> {
+        if self.decompress {
+            request
+                .parts
+                .headers
+                .raw
+                .entry("accept-encoding".to_string())
+                .or_insert_with(|| "gzip, deflate".to_string());
+        }
+        let mut resp = self.exchange(request).await?;
+        if self.decompress {
+            resp.decode()?;
+        }
+        Ok(resp)
+    }
+
+    async fn exchange(&self, request: Request) -> Result<Response, FrameError> {
+        let authority = request.parts.url.authority.clone();
+
+        let conn = self.connect(&authority).await?;
+        if conn.reused() && request.parts.method.idempotent() {
+            // the server may close an idle connection at any time, a request
+            // it got no answer to on a reused connection is sent again on a
+            // new one
+            let retry = Request {
+                parts: request.parts.clone(),
+                hasbody: request.hasbody,
+                body: request.body.clone(),
+                trailers: request.trailers.clone(),
+            };
+            match conn.send(request).await {
+                Ok(resp) => return Ok(resp),
+                Err(Failure::Unanswered(_)) => {
+                    let conn = self.connect_new(&authority).await?;
+                    return conn.send(retry).await.map_err(FrameError::from);
+                }
+                Err(Failure::Failed(e)) => return Err(e),
             }
-            Authority::IPv4 { ip, port } => {
-                stream = TcpStream::connect((ip, port as u16)).await?;
+        }
+
+        conn.send(request).await.map_err(FrameError::from)
+    }
+
+    // forgets the authorities with neither connections in use nor idle ones
+    // left, so that the pool does not grow with every authority requested.
+    // Connections in use and requests waiting for one hold the permits.
+    fn prune(&self, hosts: &mut HashMap<Authority, Host>) {
+        let idle_timeout = self.idle_timeout;
+        hosts.retain(|_, host| {
+            host.idle.retain(|idle| idle.since.elapsed() < idle_timeout);
+            !host.idle.is_empty() || Arc::strong_count(&host.permits) > 1
+        });
+    }
+
+    fn checkout(&self, authority: &Authority) -> Option<TcpStream> {
+        let mut hosts = self.hosts();
+        let host = hosts.get_mut(authority)?;
+
+        while let Some(idle) = host.idle.pop() {
+            if idle.since.elapsed() >= self.idle_timeout || closed(&idle.stream) {
+                continue;
             }
-            Authority::IPv6 { ip, port } => {
-                stream = TcpStream::connect((ip, port as u16)).await?;
+            return Some(idle.stream);
+        }
+        None
+    }
+
+    fn checkin(&self, authority: &Authority, stream: TcpStream) {
+        let mut hosts = self.hosts();
+        let host = match hosts.get_mut(authority) {
+            Some(host) => host,
+            None => return,
+        };
+
+        let idle_timeout = self.idle_timeout;
+        host.idle
+            .retain(|idle| idle.since.elapsed() < idle_timeout && !closed(&idle.stream));
+        if host.idle.len() < self.max_idle_per_host {
+            host.idle.push(Idle {
+                stream,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    async fn open(&self, authority: &Authority) -> Result<(TcpStream, Dial), FrameError> {
+        let start = SystemTime::now();
+        let (lookup, addr) = match authority {
+            Authority::Domain { ref host, port } => {
+                let resolver = Resolver::new();
+                let hosts: Vec<Ipv4Addr> = resolver.lookup_a(host, &self.dns_ip).await?;
+                let host = match hosts.first() {
+                    Some(host) => host,
+                    None => {
+                        return Err(FrameError::Invalid {
+                            reason: "no address found for host",
+                            subject: "authority",
+                        })
+                    }
+                };
+                let lookup = start..SystemTime::now();
+                (Some(lookup), SocketAddr::from((*host, *port as u16)))
             }
+            Authority::IPv4 { ip, port } => (None, SocketAddr::from((*ip, *port as u16))),
+            Authority::IPv6 { ip, port } => (None, SocketAddr::from((*ip, *port as u16))),
             _ => {
                 return Err(FrameError::Invalid {
                     reason: "unable to resolve authority",
                     subject: "authority",
-                });
+                })
             }
+        };
+        let connecting = SystemTime::now();
+        let stream = TcpStream::connect(addr).await?;
+        let dial = Dial {
+            lookup,
+            connect: connecting..SystemTime::now(),
+        };
+        Ok((stream, dial))
+    }
+}
+
+// Dial is when a new connection was opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dial {
+    // resolving the host, not done for authorities holding an address
+    pub lookup: Option<Range<SystemTime>>,
+    pub connect: Range<SystemTime>,
+}
+
+// Connection is a connection to an upstream checked out of the client pool.
+// It holds one of the per host permits until dropped, and goes back to the
+// pool when released after a complete exchange.
+#[derive(Debug)]
+pub struct Connection {
+    pub stream: BufReader<TcpStream>,
+    // not set for connections taken from the pool
+    dial: Option<Dial>,
+    authority: Authority,
+    client: Client,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Connection {
+    // whether the connection already served a previous request
+    pub fn reused(&self) -> bool {
+        self.dial.is_none()
+    }
+
+    pub fn dial(&self) -> Option<&Dial> {
+        self.dial.as_ref()
+    }
+
+    // waits for the server to start answering. False when it closed or reset
+    // the connection without sending a byte, as servers do with connections
+    // left idle too long, the request can then be sent again if idempotent.
+    pub async fn answering(&mut self) -> bool {
+        matches!(self.stream.fill_buf().await, Ok(buf) if !buf.is_empty())
+    }
+
+    async fn send(mut self, request: Request) -> Result<Response, Failure> {
+        let parts = request.parts.clone();
+        request
+            .write(self.stream.get_mut())
+            .await
+            .map_err(Failure::Unanswered)?;
+        if !self.answering().await {
+            return Err(Failure::Unanswered(FrameError::Invalid {
+                reason: "connection closed before any response",
+                subject: "response",
+            }));
+        }
+        let resp = Response::read_for(&mut self.stream, &parts.method)
+            .await
+            .map_err(Failure::Failed)?;
+        self.release(&parts, &resp);
+        Ok(resp)
+    }
+
+    // returns the connection to the pool once the response to the request was
+    // read entirely. It is only kept when the end of the response body is
+    // known and neither side asked to close it.
+    pub fn release(self, request: &Parts, resp: &Response) {
+        let framed = match Framing::response(&request.method, resp.status, &resp.headers) {
+            Ok(framing) => framing.delimited(),
+            Err(_) => false,
+        };
+        if framed
+            && self.stream.buffer().is_empty()
+            && request.headers.keep_alive(&request.standard)
+            && resp.headers.keep_alive(&resp.standard)
+        {
+            self.client
+                .checkin(&self.authority, self.stream.into_inner());
+        }
+    }
+}
+
+// Failure of a request sent on a connection.
+#[derive(Debug)]
+enum Failure {
+    // the request could not be written, or the server closed the connection
+    // without answering
+    Unanswered(FrameError),
+    // the server started answering
+    Failed(FrameError),
+}
+
+impl From<Failure> for FrameError {
+    fn from(src: Failure) -> Self {
+        match src {
+            Failure::Unanswered(e) | Failure::Failed(e) => e,
         }
+    }
+}
 
-        request.call(&mut stream).await
+// whether the server closed its side of an idle connection, or sent data
+// nobody asked for, either way the connection cannot be reused
+fn closed(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    match stream.try_read(&mut buf) {
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+        Ok(_) => true,
     }
 }
 
@@ -48,6 +395,276 @@ mod tests {
     };
     use dns::resolver::DNS_IP_LOCAL;
     use rstest::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    #[derive(Clone, Copy)]
+    enum Mode {
+        KeepAlive,
+        // answers with connection: close and closes the connection
+        Close,
+        // closes the connection after the response without telling the client
+        HalfClose,
+        // answers the first request on a connection, then closes it on
+        // reading the next one without answering
+        Unanswered,
+        // answers the first request on a connection, then closes it in the
+        // middle of the next response body
+        Truncated,
+    }
+
+    // starts a server answering "ok" to every request, returns its url and
+    // the number of connections it accepted
+    async fn server(mode: Mode) -> (Url, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::from_str(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    let mut line = String::new();
+                    let mut served = 0;
+                    loop {
+                        line.clear();
+                        match stream.read_line(&mut line).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(_) if line != "\r\n" => continue,
+                            Ok(_) => {}
+                        }
+
+                        tokio::time::sleep(Duration::from_millis(5)).await;
+                        served += 1;
+                        let resp = match mode {
+                            Mode::Unanswered if served > 1 => return,
+                            Mode::Truncated if served > 1 => {
+                                "HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nok"
+                            }
+                            Mode::Close => {
+                                "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok"
+                            }
+                            _ => "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok",
+                        };
+                        if stream.write_all(resp.as_bytes()).await.is_err() {
+                            return;
+                        }
+                        if let Mode::Close | Mode::HalfClose = mode {
+                            return;
+                        }
+                        if let (Mode::Truncated, 2..) = (mode, served) {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        (url, accepted)
+    }
+
+    fn request(url: &Url) -> Request {
+        Builder::new().method(Method::GET).url(url.clone()).build()
+    }
+
+    #[tokio::test]
+    async fn test_client_reuses_connections() {
+        let (url, accepted) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        for _ in 0..3 {
+            let resp = client.perform(request(&url)).await.unwrap();
+            assert_eq!(resp.body, Some(b"ok".to_vec()));
+        }
+        // clones share the pool
+        client.clone().perform(request(&url)).await.unwrap();
+
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+        assert_eq!(client.idle(&url.authority), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_dial() {
+        let (url, _) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        let conn = client.connect(&url.authority).await.unwrap();
+        let dial = conn.dial().unwrap();
+        // the url holds an address, nothing is resolved
+        assert_eq!(dial.lookup, None);
+        assert!(dial.connect.start <= dial.connect.end);
+        assert!(!conn.reused());
+        drop(conn);
+
+        client.perform(request(&url)).await.unwrap();
+        let conn = client.connect(&url.authority).await.unwrap();
+        assert!(conn.reused());
+        assert_eq!(conn.dial(), None);
+    }
+
+    #[tokio::test]
+    async fn test_client_connection_close() {
+        let (url, accepted) = server(Mode::Close).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        for _ in 0..3 {
+            client.perform(request(&url)).await.unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 3);
+        assert_eq!(client.idle(&url.authority), 0);
+
+        // the client asking to close is honoured as well
+        let (url, accepted) = server(Mode::KeepAlive).await;
+        for _ in 0..2 {
+            let mut req = request(&url);
+            req.parts.headers.parse("connection: close").unwrap();
+            client.perform(req).await.unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_evicts_half_closed() {
+        let (url, accepted) = server(Mode::HalfClose).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        client.perform(request(&url)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(client.idle(&url.authority), 1);
+
+        let resp = client.perform(request(&url)).await.unwrap();
+        assert_eq!(resp.body, Some(b"ok".to_vec()));
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+
+    #[rstest]
+    #[case(Mode::Unanswered, Method::GET, true)]
+    #[case(Mode::Unanswered, Method::POST, false)]
+    #[case(Mode::Truncated, Method::GET, false)]
+    #[tokio::test]
+    async fn test_client_resend(#[case] mode: Mode, #[case] method: Method, #[case] resent: bool) {
+        let (url, accepted) = server(mode).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        client.perform(request(&url)).await.unwrap();
+        assert_eq!(client.idle(&url.authority), 1);
+
+        // only idempotent requests the server did not start answering are
+        // sent again on a new connection
+        let mut req = request(&url);
+        req.parts.method = method;
+        let res = client.perform(req).await;
+        assert_eq!(res.is_ok(), resent);
+        let expected = if resent { 2 } else { 1 };
+        assert_eq!(accepted.load(Ordering::Relaxed), expected);
+    }
+
+    #[tokio::test]
+    async fn test_client_prunes_hosts() {
+        let (url, _) = server(Mode::Close).await;
+        let (other, _) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL);
+
+        client.perform(request(&url)).await.unwrap();
+        assert_eq!(client.hosts().len(), 1);
+
+        // the first authority has no connection left to keep
+        client.perform(request(&other)).await.unwrap();
+        assert_eq!(client.hosts().len(), 1);
+        assert_eq!(client.idle(&other.authority), 1);
+
+        let conn = client.connect(&url.authority).await.unwrap();
+        assert_eq!(client.hosts().len(), 2);
+        drop(conn);
+        let conn = client.connect_new(&other.authority).await.unwrap();
+        assert_eq!(client.hosts().len(), 1);
+        drop(conn);
+    }
+
+    #[tokio::test]
+    async fn test_client_idle_timeout() {
+        let (url, accepted) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL).idle_timeout(Duration::from_millis(20));
+
+        client.perform(request(&url)).await.unwrap();
+        client.perform(request(&url)).await.unwrap();
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        client.perform(request(&url)).await.unwrap();
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_decompress() {
+        // answers with a gzip body to requests accepting it
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::from_str(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let req = Request::read(&mut BufReader::new(&mut stream))
+                    .await
+                    .unwrap();
+                let mut resp = b"HTTP/1.1 200 OK\r\nconnection: close\r\n".to_vec();
+                match req.parts.headers.raw.get("accept-encoding") {
+                    Some(_) => {
+                        let body = archive::gzip::compress(b"plain");
+                        resp.extend(
+                            format!(
+                                "content-encoding: gzip\r\ncontent-length: {}\r\n\r\n",
+                                body.len()
+                            )
+                            .bytes(),
+                        );
+                        resp.extend(body);
+                    }
+                    None => resp.extend(b"content-length: 5\r\n\r\nplain"),
+                }
+                stream.write_all(&resp).await.unwrap();
+            }
+        });
+
+        let resp = Client::new(DNS_IP_LOCAL)
+            .perform(request(&url))
+            .await
+            .unwrap();
+        assert_eq!(resp.body, Some(b"plain".to_vec()));
+        assert!(!resp.headers.raw.contains_key("content-encoding"));
+
+        let client = Client::new(DNS_IP_LOCAL).decompress(true);
+        let resp = client.perform(request(&url)).await.unwrap();
+        assert_eq!(resp.body, Some(b"plain".to_vec()));
+        assert!(!resp.headers.raw.contains_key("content-encoding"));
+        assert_eq!(resp.headers.raw["content-length"], "5");
+    }
+
+    #[tokio::test]
+    async fn test_client_limits() {
+        let (url, accepted) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL).max_per_host(1);
+
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..4 {
+            let client = client.clone();
+            let req = request(&url);
+            set.spawn(async move { client.perform(req).await.unwrap() });
+        }
+        while set.join_next().await.is_some() {}
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        let (url, accepted) = server(Mode::KeepAlive).await;
+        let client = Client::new(DNS_IP_LOCAL).max_idle_per_host(0);
+        for _ in 0..2 {
+            client.perform(request(&url)).await.unwrap();
+        }
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+        assert_eq!(client.idle(&url.authority), 0);
+    }
 
     #[ignore]
     #[tokio::test]
@@ -57,7 +674,7 @@ mod tests {
             .url(Url::from_str("http://httpbin.org/robots.txt").unwrap())
             .headers(HeaderMap::default())
             .build();
-        let resp = Client::perform(request, DNS_IP_LOCAL).await.unwrap();
+        let resp = Client::new(DNS_IP_LOCAL).perform(request).await.unwrap();
         assert_eq!(resp.status, StatusCode::Ok);
     }
 
@@ -104,7 +721,7 @@ mod tests {
             .headers(HeaderMap::default())
             .basic_auth(user, password)
             .build();
-        let resp = Client::perform(request, DNS_IP_LOCAL).await.unwrap();
+        let resp = Client::new(DNS_IP_LOCAL).perform(request).await.unwrap();
         assert_eq!(resp.status, expected);
     }
 }
@@ -0,0 +1,184 @@
+use std::str::FromStr;
+
+use archive::{deflate, error::ArchiveError, gzip, zlib};
+
+use super::header::HeaderMap;
+
+// Coding is a content coding of a message body. Deflate is the zlib format,
+// though some servers send raw deflate under its name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Coding {
+    Gzip,
+    Deflate,
+}
+
+impl FromStr for Coding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "deflate" => Ok(Coding::Deflate),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Coding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Coding::Gzip => "gzip",
+            Coding::Deflate => "deflate",
+        }
+    }
+
+    // the coding of a message body, when it has a single supported one
+    pub fn of(headers: &HeaderMap) -> Option<Self> {
+        Coding::from_str(headers.raw.get("content-encoding")?).ok()
+    }
+
+    // updates the headers of a message whose body gets encoded
+    pub fn encode_headers(self, headers: &mut HeaderMap) {
+        headers
+            .raw
+            .insert("content-encoding".to_string(), self.as_str().to_string());
+        changed(headers);
+    }
+
+    // updates the headers of a message whose body gets decoded
+    pub fn decode_headers(self, headers: &mut HeaderMap) {
+        headers.raw.remove("content-encoding");
+        changed(headers);
+    }
+}
+
+// the length is the one of the body before the change, and the entity tag no
+// longer identifies the same bytes
+fn changed(headers: &mut HeaderMap) {
+    headers.raw.remove("content-length");
+    if let Some(etag) = headers.raw.get_mut("etag") {
+        if !etag.starts_with("W/") {
+            etag.insert_str(0, "W/");
+        }
+    }
+}
+
+// Decoder decodes a body received one piece at a time.
+#[derive(Debug)]
+pub struct Decoder {
+    coding: Coding,
+    inner: Inner,
+}
+
+#[derive(Debug)]
+enum Inner {
+    Gzip(gzip::Decoder),
+    Zlib(zlib::Decoder),
+    Raw(deflate::Decoder),
+    // first bytes of a deflate body, until they tell whether it is wrapped
+    Undetected(Vec<u8>),
+}
+
+impl Decoder {
+    pub fn new(coding: Coding) -> Self {
+        let inner = match coding {
+            Coding::Gzip => Inner::Gzip(gzip::Decoder::new()),
+            Coding::Deflate => Inner::Undetected(Vec::new()),
+        };
+        Self { coding, inner }
+    }
+
+    pub fn coding(&self) -> Coding {
+        self.coding
+    }
+
+    // returns the bytes decoded so far
+    pub fn write(&mut self, data: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+        match &mut self.inner {
+            Inner::Gzip(decoder) => decoder.write(data),
+            Inner::Zlib(decoder) => decoder.write(data),
+            Inner::Raw(decoder) => decoder.write(data),
+            Inner::Undetected(head) => {
+                head.extend_from_slice(data);
+                if head.len() < 2 {
+                    return Ok(Vec::new());
+                }
+                let head = std::mem::take(head);
+                self.detect(&head);
+                self.write(&head)
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Result<(), ArchiveError> {
+        match &mut self.inner {
+            Inner::Gzip(decoder) => decoder.finish(),
+            Inner::Zlib(decoder) => decoder.finish(),
+            Inner::Raw(decoder) => decoder.finish(),
+            Inner::Undetected(_) => Err(ArchiveError::UnexpectedEnd),
+        }
+    }
+
+    // a zlib header is a deflate method byte along with a check byte
+    fn detect(&mut self, head: &[u8]) {
+        let (cmf, flg) = (head[0] as u16, head[1] as u16);
+        self.inner = match cmf & 0x0f == 8 && ((cmf << 8) | flg).is_multiple_of(31) {
+            true => Inner::Zlib(zlib::Decoder::new()),
+            false => Inner::Raw(deflate::Decoder::new()),
+        };
+    }
+}
+
+pub fn decode(coding: Coding, body: &[u8]) -> Result<Vec<u8>, ArchiveError> {
+    let mut decoder = Decoder::new(coding);
+    let out = decoder.write(body)?;
+    decoder.finish()?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(Coding::Gzip, gzip::compress(b"plain body"))]
+    #[case(Coding::Deflate, zlib::compress(b"plain body"))]
+    #[case(Coding::Deflate, deflate::compress(b"plain body"))]
+    fn test_decode(#[case] coding: Coding, #[case] body: Vec<u8>) {
+        assert_eq!(decode(coding, &body).unwrap(), b"plain body");
+
+        let mut decoder = Decoder::new(coding);
+        let mut out = Vec::new();
+        for piece in body.chunks(1) {
+            out.extend(decoder.write(piece).unwrap());
+        }
+        decoder.finish().unwrap();
+        assert_eq!(out, b"plain body");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let body = gzip::compress(b"plain body");
+        assert_eq!(
+            decode(Coding::Gzip, &body[..body.len() - 2]),
+            Err(ArchiveError::UnexpectedEnd)
+        );
+        assert!(decode(Coding::Deflate, b"not deflate").is_err());
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = HeaderMap::default();
+        headers.parse("content-length: 10").unwrap();
+        headers.parse("etag: \"a\"").unwrap();
+        Coding::Gzip.encode_headers(&mut headers);
+        assert_eq!(Coding::of(&headers), Some(Coding::Gzip));
+        assert!(!headers.raw.contains_key("content-length"));
+        assert_eq!(headers.raw["etag"], "W/\"a\"");
+
+        Coding::Gzip.decode_headers(&mut headers);
+        assert_eq!(Coding::of(&headers), None);
+        assert_eq!(headers.raw["etag"], "W/\"a\"");
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// days since the epoch of a date of the proleptic gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// parses an http date in the IMF-fixdate format, e.g.
+// "Sun, 06 Nov 1994 08:49:37 GMT". The obsolete formats are not supported.
+pub fn parse(s: &str) -> Option<SystemTime> {
+    let (_, rest) = s.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as i64 + 1;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|n| n.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || parts.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    let seconds = u64::try_from(seconds).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+pub fn format(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let days = seconds / 86400;
+    let (year, month, day) = civil_from_days(days);
+    let rest = seconds % 86400;
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+// formats a time in the RFC 3339 profile used by logs, in UTC with
+// milliseconds, e.g. "1994-11-06T08:49:37.000Z"
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since.as_secs() as i64;
+    let (year, month, day) = civil_from_days(seconds / 86400);
+    let rest = seconds % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("Sun, 06 Nov 1994 08:49:37 GMT", Some(784111777))]
+    #[case("Thu, 01 Jan 1970 00:00:00 GMT", Some(0))]
+    #[case("Tue, 29 Feb 2028 23:59:59 GMT", Some(1835481599))]
+    #[case("Sunday, 06-Nov-94 08:49:37 GMT", None)]
+    #[case("Sun, 06 Nov 1994 08:49:37 CET", None)]
+    #[case("Sun, 06 Nov 1994 25:49:37 GMT", None)]
+    #[case("0", None)]
+    fn test_date_parse(#[case] input: &str, #[case] expected: Option<u64>) {
+        assert_eq!(
+            parse(input),
+            expected.map(|s| UNIX_EPOCH + Duration::from_secs(s))
+        );
+    }
+
+    #[rstest]
+    #[case(784111777, "Sun, 06 Nov 1994 08:49:37 GMT")]
+    #[case(0, "Thu, 01 Jan 1970 00:00:00 GMT")]
+    #[case(1835481599, "Tue, 29 Feb 2028 23:59:59 GMT")]
+    fn test_date_format(#[case] seconds: u64, #[case] expected: &str) {
+        assert_eq!(format(UNIX_EPOCH + Duration::from_secs(seconds)), expected);
+    }
+
+    #[rstest]
+    #[case(784_111_777_000, "1994-11-06T08:49:37.000Z")]
+    #[case(0, "1970-01-01T00:00:00.000Z")]
+    #[case(1_835_481_599_042, "2028-02-29T23:59:59.042Z")]
+    fn test_date_format_rfc3339(#[case] millis: u64, #[case] expected: &str) {
+        assert_eq!(
+            format_rfc3339(UNIX_EPOCH + Duration::from_millis(millis)),
+            expected
+        );
+    }
+}
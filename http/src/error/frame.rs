@@ -1,5 +1,6 @@
 use std::{error::Error, net::AddrParseError, num::ParseIntError};
 
+use archive::error::ArchiveError;
 use dns::error::LookupError;
 use encoding::error::EncodingError;
 
@@ -20,6 +21,8 @@ pub enum FrameError {
     ConversionError(String),
     AuthorizationError(AuthorizationError),
     EncodingError(EncodingError),
+    // a body could not be decoded from its content coding
+    ArchiveError(ArchiveError),
     ContentTooLarge {
         subject: String,
     },
@@ -48,6 +51,12 @@ impl From<EncodingError> for FrameError {
     }
 }
 
+impl From<ArchiveError> for FrameError {
+    fn from(src: ArchiveError) -> Self {
+        Self::ArchiveError(src)
+    }
+}
+
 impl From<AddrParseError> for FrameError {
     fn from(src: AddrParseError) -> Self {
         Self::ConversionError(src.to_string())
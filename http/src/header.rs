@@ -4,6 +4,7 @@ use super::{
     error::frame::FrameError,
     method::Method,
     mimetype::MimeType,
+    standard::Standard,
     uri::{authority::Authority, url::Url},
     useragent::UserAgent,
 };
@@ -88,10 +89,40 @@ impl HeaderMap {
         }
     }
 
+    // whether a comma separated header, such as connection, holds the token
+    pub fn contains_token(&self, k: &str, token: &str) -> bool {
+        match self.raw.get(&k.to_lowercase()) {
+            Some(v) => v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)),
+            None => false,
+        }
+    }
+
+    // whether the connection a message was sent on may be kept open
+    pub fn keep_alive(&self, standard: &Standard) -> bool {
+        if self.contains_token("connection", "close") {
+            return false;
+        }
+        self.contains_token("connection", "keep-alive") || standard.persistent()
+    }
+
     pub fn put(&mut self, k: &str, v: HeaderKind) -> Result<(), FrameError> {
         self.raw.insert(k.to_string(), String::try_from(v)?);
         Ok(())
     }
+
+    // adds a value to a comma separated header, after the ones already there
+    pub fn append(&mut self, k: &str, v: &str) {
+        let lk = k.to_lowercase();
+        match self.raw.get_mut(&lk) {
+            Some(existing) if !existing.trim().is_empty() => {
+                existing.push_str(", ");
+                existing.push_str(v);
+            }
+            _ => {
+                self.raw.insert(lk, v.to_string());
+            }
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -263,4 +294,33 @@ mod tests {
 
         println!("{:?}", String::try_from(headers).unwrap());
     }
+
+    #[test]
+    fn test_headers_keep_alive() {
+        let http10 = Standard::from_str("HTTP/1.0").unwrap();
+        let http11 = Standard::default();
+
+        let mut headers = HeaderMap::default();
+        assert!(headers.keep_alive(&http11));
+        assert!(!headers.keep_alive(&http10));
+
+        headers.parse("Connection: Keep-Alive").unwrap();
+        assert!(headers.keep_alive(&http10));
+
+        headers.parse("Connection: upgrade, close").unwrap();
+        assert!(headers.contains_token("connection", "upgrade"));
+        assert!(!headers.keep_alive(&http11));
+    }
+
+    #[test]
+    fn test_headers_append() {
+        let mut headers = HeaderMap::default();
+        headers.append("Via", "1.0 cdn");
+        headers.append("via", "1.1 proxy");
+        assert_eq!(headers.raw["via"], "1.0 cdn, 1.1 proxy");
+
+        headers.parse("Vary:").unwrap();
+        headers.append("vary", "Accept-Encoding");
+        assert_eq!(headers.raw["vary"], "Accept-Encoding");
+    }
 }
@@ -1,7 +1,11 @@
 #![feature(try_trait_v2)]
 pub mod auth;
+pub mod body;
 pub mod builder;
+pub mod chunked;
 pub mod client;
+pub mod coding;
+pub mod date;
 pub mod error;
 pub mod header;
 pub mod method;
@@ -10,6 +14,7 @@ pub mod request;
 pub mod response;
 pub mod standard;
 pub mod statuscode;
+pub mod trace;
 pub mod uri;
 pub mod useragent;
 pub mod version;
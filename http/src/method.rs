@@ -15,6 +15,21 @@ pub enum Method {
     UNDEFINED,
 }
 
+impl Method {
+    // whether sending the request several times has the same effect as once
+    pub fn idempotent(&self) -> bool {
+        matches!(
+            self,
+            Method::GET
+                | Method::HEAD
+                | Method::PUT
+                | Method::DELETE
+                | Method::OPTIONS
+                | Method::TRACE
+        )
+    }
+}
+
 impl FromStr for Method {
     type Err = FrameError;
 
@@ -1,3 +1,5 @@
+use crate::body::{BodyReader, Framing};
+use crate::chunked;
 use crate::error::frame::FrameError;
 use crate::header::HeaderKind;
 use crate::method::Method;
@@ -5,8 +7,7 @@ use crate::standard::Standard;
 
 use std::fmt::Debug;
 use std::str::FromStr;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 use super::header::HeaderMap;
 use super::response::Response;
@@ -47,6 +48,8 @@ pub struct Request {
     pub hasbody: bool,
 
     pub body: Option<Vec<u8>>,
+    // fields sent after a chunked body
+    pub trailers: Option<HeaderMap>,
 }
 
 impl Debug for Request {
@@ -59,7 +62,7 @@ impl Debug for Request {
 
 impl PartialEq for Request {
     fn eq(&self, other: &Self) -> bool {
-        self.parts == other.parts && self.body == other.body
+        self.parts == other.parts && self.body == other.body && self.trailers == other.trailers
     }
 }
 
@@ -74,30 +77,76 @@ impl Default for Request {
             },
             body: None,
             hasbody: false,
+            trailers: None,
         }
     }
 }
 
 impl Request {
-    pub async fn write(self, stream: &mut TcpStream) -> Result<(), FrameError> {
-        let req = String::try_from(self.parts)?;
+    // writes the request line and headers, the body is left to the caller
+    pub async fn write_head<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+    ) -> Result<(), FrameError> {
+        let req = String::try_from(self.parts.clone())?;
         stream.write_all(req.as_bytes()).await?;
+        Ok(())
+    }
 
-        if let Some(body) = self.body {
-            stream.write_all(b"\r\n").await?;
+    pub async fn write<W: AsyncWrite + Unpin>(self, stream: &mut W) -> Result<(), FrameError> {
+        self.write_head(stream).await?;
+
+        let chunked = matches!(chunked::is_chunked(&self.parts.headers), Ok(true));
+        if chunked {
+            let body = self.body.unwrap_or_default();
+            stream
+                .write_all(&chunked::encode(&body, self.trailers.as_ref())?)
+                .await?;
+        } else if let Some(body) = self.body {
             stream.write_all(&body).await?;
         }
+        // buffered transports only send what was flushed
+        stream.flush().await?;
 
         Ok(())
     }
 
-    pub async fn call(self, stream: &mut TcpStream) -> Result<Response, FrameError> {
+    // sends the request on a stream, which may be any transport carrying
+    // http, and reads the response to it
+    pub async fn call<S: AsyncRead + AsyncWrite + Unpin>(
+        self,
+        stream: &mut S,
+    ) -> Result<Response, FrameError> {
+        let method = self.parts.method.clone();
         self.write(stream).await?;
-        Response::parse(stream).await
+        let mut buffer = BufReader::new(stream);
+        Response::read_for(&mut buffer, &method).await
     }
 
-    pub async fn parse(stream: &mut TcpStream) -> Result<Self, FrameError> {
+    pub async fn parse<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Self, FrameError> {
         let mut buffer = BufReader::new(stream);
+        Request::read(&mut buffer).await
+    }
+
+    // reads a request from a buffered stream, the bytes following it are left
+    // in the buffer so that pipelined requests can be read afterwards
+    pub async fn read<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<Self, FrameError> {
+        let mut request = Request::read_head(buffer).await?;
+
+        let framing = Framing::request(&request.parts.method, &request.parts.headers)?;
+        if framing != Framing::Empty {
+            let (body, trailers) = BodyReader::new(framing).collect(buffer).await?;
+            request.hasbody = true;
+            request.body = Some(body);
+            request.trailers = trailers;
+        }
+
+        Ok(request)
+    }
+
+    // reads the request line and headers only, leaving the body in the buffer
+    // so that it can be relayed piece by piece with a BodyReader
+    pub async fn read_head<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<Self, FrameError> {
         let mut request = Request::default();
         let mut line = String::with_capacity(MAX_REQUEST_LINE_SIZE);
         let mut state: u8 = 0;
@@ -131,10 +180,6 @@ impl Request {
                     1 => {
                         if line == "\r\n" {
                             line.clear();
-
-                            if request.parts.method == Method::POST {
-                                request.hasbody = true;
-                            }
                             break;
                         }
                         let _ = request.parts.headers.parse(&line);
@@ -147,24 +192,15 @@ impl Request {
             }
         }
 
-        if let Ok(HeaderKind::Host(authority)) = request.parts.headers.get("host") {
-            request.parts.url.authority = authority;
+        if state == 0 {
+            return Err(FrameError::Invalid {
+                reason: "connection closed before the request line",
+                subject: "request",
+            });
         }
 
-        if request.hasbody {
-            match request.parts.headers.get("content-length")? {
-                HeaderKind::ContentLength(n) => {
-                    let mut body = vec![0u8; n];
-                    buffer.read_exact(&mut body).await?;
-                    request.body = Some(body);
-                    return Ok(request);
-                }
-                _ => {
-                    return Err(FrameError::RequiredParam {
-                        subject: "content-length header is required",
-                    })
-                }
-            }
+        if let Ok(HeaderKind::Host(authority)) = request.parts.headers.get("host") {
+            request.parts.url.authority = authority;
         }
 
         Ok(request)
@@ -176,17 +212,25 @@ mod tests {
     use crate::uri::path::Path;
     use crate::{uri::authority::Authority, version::Version};
 
+    use crate::statuscode::StatusCode;
     use std::collections::HashMap;
-    use std::env;
-    use tokio::net::TcpStream;
 
     use super::*;
+    use rstest::*;
 
     #[tokio::test]
     async fn test_request_call() {
-        let mut stream = TcpStream::connect(env::var("HTTPBIN_HOST").unwrap())
-            .await
-            .unwrap();
+        let (mut stream, mut server) = tokio::io::duplex(1024);
+        let upstream = tokio::spawn(async move {
+            let req = Request::parse(&mut server).await.unwrap();
+            let mut resp = Response::new(StatusCode::Ok);
+            resp.headers
+                .put("content-length", HeaderKind::ContentLength(5))
+                .unwrap();
+            resp.body = Some(b"hello".to_vec());
+            resp.write(&mut server).await.unwrap();
+            req
+        });
 
         let req: Request = Request {
             parts: Parts {
@@ -218,8 +262,55 @@ mod tests {
             },
             body: None,
             hasbody: false,
+            trailers: None,
         };
 
-        let _ = req.call(&mut stream).await.unwrap();
+        let expected = req.parts.clone();
+        let resp = req.call(&mut stream).await.unwrap();
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(resp.body, Some(b"hello".to_vec()));
+
+        let received = upstream.await.unwrap();
+        assert_eq!(received.parts.method, expected.method);
+        assert_eq!(received.parts.url.path, expected.url.path);
+        assert_eq!(received.parts.headers.raw, expected.headers.raw);
+    }
+
+    #[rstest]
+    #[case(
+        "POST /upload HTTP/1.1\r\nhost: localhost:9090\r\ncontent-length: 5\r\n\r\nhello",
+        Some("hello"),
+        None
+    )]
+    #[case(
+        "POST /upload HTTP/1.1\r\nhost: localhost:9090\r\ntransfer-encoding: chunked\r\n\r\n\
+        2\r\nhe\r\n3\r\nllo\r\n0\r\nx-checksum: 42\r\n\r\n",
+        Some("hello"),
+        Some(("x-checksum", "42"))
+    )]
+    #[case(
+        "PUT /upload HTTP/1.1\r\nhost: localhost:9090\r\ncontent-length: 2\r\n\r\nhi",
+        Some("hi"),
+        None
+    )]
+    #[case("GET / HTTP/1.1\r\nhost: localhost:9090\r\n\r\n", None, None)]
+    #[tokio::test]
+    async fn test_request_read_body(
+        #[case] input: &str,
+        #[case] body: Option<&str>,
+        #[case] trailer: Option<(&str, &str)>,
+    ) {
+        // a pipelined request following the first one is left untouched
+        let input = format!("{}GET / HTTP/1.1\r\n\r\n", input);
+        let mut buffer = input.as_bytes();
+
+        let req = Request::read(&mut buffer).await.unwrap();
+        assert_eq!(req.body, body.map(|b| b.as_bytes().to_vec()));
+        assert_eq!(
+            req.trailers
+                .map(|t| t.raw.get(trailer.unwrap().0).cloned().unwrap()),
+            trailer.map(|(_, v)| v.to_string())
+        );
+        assert_eq!(buffer, b"GET / HTTP/1.1\r\n\r\n");
     }
 }
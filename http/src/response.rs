@@ -1,15 +1,16 @@
 use std::{fmt::Debug, str::FromStr};
 
-use tokio::{
-    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
-    net::TcpStream,
-};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 
 use crate::standard::Standard;
 
 use super::{
+    body::{BodyReader, Framing},
+    chunked,
+    coding::{decode, Coding},
     error::frame::FrameError,
     header::{HeaderKind, HeaderMap},
+    method::Method,
     statuscode::StatusCode,
 };
 const MAX_RESPONSE_LINE_SIZE: usize = 8096 * 4;
@@ -21,6 +22,8 @@ pub struct Response {
 
     pub hasbody: bool,
     pub body: Option<Vec<u8>>,
+    // fields sent after a chunked body
+    pub trailers: Option<HeaderMap>,
 }
 
 impl Debug for Response {
@@ -34,35 +37,112 @@ impl Debug for Response {
 }
 
 impl Response {
-    pub async fn write(self, stream: &mut TcpStream) -> Result<(), FrameError> {
-        let standard = String::try_from(self.standard)?;
+    // response without body, mostly used to report errors
+    pub fn new(status: StatusCode) -> Self {
+        let mut headers = HeaderMap::default();
+        let _ = headers.put("content-length", HeaderKind::ContentLength(0));
+        Self {
+            standard: Standard::default(),
+            status,
+            headers,
+            hasbody: false,
+            body: None,
+            trailers: None,
+        }
+    }
+
+    // decodes a body sent with a gzip or deflate content coding, so that it
+    // holds the bytes the server meant
+    pub fn decode(&mut self) -> Result<(), FrameError> {
+        let (Some(coding), Some(body)) = (Coding::of(&self.headers), &self.body) else {
+            return Ok(());
+        };
+        let body = decode(coding, body)?;
+        coding.decode_headers(&mut self.headers);
+        if !chunked::is_chunked(&self.headers)? {
+            self.headers
+                .put("content-length", HeaderKind::ContentLength(body.len()))?;
+        }
+        self.body = Some(body);
+        Ok(())
+    }
+
+    // writes the status line and headers, the body is left to the caller
+    pub async fn write_head<W: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut W,
+    ) -> Result<(), FrameError> {
+        let standard = String::try_from(self.standard.clone())?;
         let status = String::try_from(self.status)?;
         let mut res = String::new();
         res.push_str(&standard);
         res.push(' ');
         res.push_str(&status);
         res.push_str("\r\n");
+        res.push_str(&String::try_from(self.headers.clone())?);
+        res.push_str("\r\n");
         stream.write_all(res.as_bytes()).await?;
+        Ok(())
+    }
 
-        stream
-            .write_all(String::try_from(self.headers)?.as_bytes())
-            .await?;
-        stream.write_all(b"\r\n").await?;
+    pub async fn write<W: AsyncWrite + Unpin>(self, stream: &mut W) -> Result<(), FrameError> {
+        self.write_head(stream).await?;
 
-        if let Some(body) = self.body {
+        let chunked = matches!(chunked::is_chunked(&self.headers), Ok(true));
+        if chunked {
+            let body = self.body.unwrap_or_default();
+            stream
+                .write_all(&chunked::encode(&body, self.trailers.as_ref())?)
+                .await?;
+        } else if let Some(body) = self.body {
             stream.write_all(&body).await?;
         }
+        // buffered transports only send what was flushed
+        stream.flush().await?;
         Ok(())
     }
 
-    pub async fn parse(stream: &mut TcpStream) -> Result<Self, FrameError> {
+    pub async fn parse<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Self, FrameError> {
         let mut buffer = BufReader::new(stream);
+        Response::read(&mut buffer).await
+    }
+
+    // reads a response from a buffered stream, the bytes following it are
+    // left in the buffer
+    pub async fn read<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<Self, FrameError> {
+        Response::read_for(buffer, &Method::GET).await
+    }
+
+    // reads the response to a request made with the given method, which
+    // tells whether a body follows the headers
+    pub async fn read_for<R: AsyncBufRead + Unpin>(
+        buffer: &mut R,
+        method: &Method,
+    ) -> Result<Self, FrameError> {
+        let mut response = Response::read_head(buffer).await?;
+        if !response.hasbody {
+            return Ok(response);
+        }
+
+        let framing = Framing::response(method, response.status, &response.headers)?;
+        if framing != Framing::Empty {
+            let (body, trailers) = BodyReader::new(framing).collect(buffer).await?;
+            response.body = Some(body);
+            response.trailers = trailers;
+        }
+        Ok(response)
+    }
+
+    // reads the status line and headers only, leaving the body in the buffer
+    // so that it can be relayed piece by piece with a BodyReader
+    pub async fn read_head<R: AsyncBufRead + Unpin>(buffer: &mut R) -> Result<Self, FrameError> {
         let mut response: Response = Response {
             status: StatusCode::Accepted,
             standard: Standard::default(),
             headers: HeaderMap::default(),
             hasbody: false,
             body: None,
+            trailers: None,
         };
 
         let mut line = String::with_capacity(MAX_RESPONSE_LINE_SIZE);
@@ -107,15 +187,91 @@ impl Response {
             }
         }
 
-        if response.hasbody && response.status != StatusCode::NoContent {
-            if let HeaderKind::ContentLength(n) = response.headers.get("content-length")? {
-                let mut body = vec![0; n];
-                buffer.read_exact(&mut body).await?;
-                response.body = Some(body);
-                return Ok(response);
-            }
+        if state == 0 {
+            return Err(FrameError::Invalid {
+                reason: "connection closed before the status line",
+                subject: "response",
+            });
         }
 
         Ok(response)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case(
+        "HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nhello",
+        Method::GET,
+        Some("hello")
+    )]
+    #[case(
+        "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        Method::GET,
+        Some("hello")
+    )]
+    #[case(
+        "HTTP/1.1 200 OK\r\n\r\nuntil the end",
+        Method::GET,
+        Some("until the end")
+    )]
+    #[case("HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\n", Method::HEAD, None)]
+    #[case("HTTP/1.1 204 No Content\r\n\r\n", Method::GET, None)]
+    #[case(
+        "HTTP/1.1 304 Not Modified\r\ncontent-length: 5\r\n\r\n",
+        Method::GET,
+        None
+    )]
+    #[tokio::test]
+    async fn test_response_read_body(
+        #[case] input: &str,
+        #[case] method: Method,
+        #[case] body: Option<&str>,
+    ) {
+        let mut buffer = input.as_bytes();
+        let resp = Response::read_for(&mut buffer, &method).await.unwrap();
+        assert_eq!(resp.body, body.map(|b| b.as_bytes().to_vec()));
+        assert!(buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_response_read_trailers() {
+        let input = "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+            3\r\nabc\r\n0\r\nx-checksum: 42\r\n\r\nHTTP/1.1 200 OK\r\n";
+        let mut buffer = input.as_bytes();
+
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body, Some(b"abc".to_vec()));
+        assert_eq!(
+            resp.trailers.unwrap().raw.get("x-checksum"),
+            Some(&"42".to_string())
+        );
+        assert_eq!(buffer, b"HTTP/1.1 200 OK\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_response_read_closed() {
+        let mut buffer: &[u8] = b"";
+        assert!(Response::read(&mut buffer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_response_write_parse_duplex() {
+        let (mut client, mut server) = tokio::io::duplex(16);
+
+        let mut resp = Response::new(StatusCode::Ok);
+        resp.headers.parse("transfer-encoding: chunked").unwrap();
+        resp.headers.raw.remove("content-length");
+        resp.body = Some(b"streamed through a small pipe".to_vec());
+        let writer = tokio::spawn(async move { resp.write(&mut server).await });
+
+        let res = Response::parse(&mut client).await.unwrap();
+        writer.await.unwrap().unwrap();
+        assert_eq!(res.status, StatusCode::Ok);
+        assert_eq!(res.body, Some(b"streamed through a small pipe".to_vec()));
+    }
+}
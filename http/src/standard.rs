@@ -21,6 +21,14 @@ impl Default for Standard {
     }
 }
 
+impl Standard {
+    // whether connections are persistent unless a connection header says
+    // otherwise, which is the default starting with HTTP/1.1
+    pub fn persistent(&self) -> bool {
+        self.version.major > 1 || (self.version.major == 1 && self.version.minor.unwrap_or(0) >= 1)
+    }
+}
+
 impl TryFrom<Standard> for String {
     type Error = FrameError;
 
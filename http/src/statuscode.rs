@@ -2,7 +2,7 @@ use std::{fmt::Debug, str::FromStr};
 
 use super::error::frame::FrameError;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum StatusCode {
     Continue = 100,
     SwitchingProtocol = 101,
@@ -73,6 +73,12 @@ pub enum StatusCode {
     NetworkAuthenticationRequired = 511,
 }
 
+impl StatusCode {
+    pub fn code(&self) -> u16 {
+        *self as u16
+    }
+}
+
 impl TryFrom<StatusCode> for String {
     type Error = FrameError;
 
@@ -175,9 +181,10 @@ impl FromStr for StatusCode {
             300 => Ok(StatusCode::MultipleChoice),
             301 => Ok(StatusCode::MovedPermanently),
             302 => Ok(StatusCode::Found),
-            304 => Ok(StatusCode::SeeOther),
-            305 => Ok(StatusCode::NotModified),
-            306 => Ok(StatusCode::UseProxy),
+            303 => Ok(StatusCode::SeeOther),
+            304 => Ok(StatusCode::NotModified),
+            305 => Ok(StatusCode::UseProxy),
+            306 => Ok(StatusCode::SwitchProxy),
             307 => Ok(StatusCode::TemporaryRedirect),
             308 => Ok(StatusCode::PermanentRedirect),
 
@@ -228,3 +235,23 @@ impl FromStr for StatusCode {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("200", StatusCode::Ok)]
+    #[case("302", StatusCode::Found)]
+    #[case("303", StatusCode::SeeOther)]
+    #[case("304", StatusCode::NotModified)]
+    #[case("305", StatusCode::UseProxy)]
+    #[case("306", StatusCode::SwitchProxy)]
+    #[case("503", StatusCode::ServiceUnavailable)]
+    fn test_status_code_from_str(#[case] input: &str, #[case] expected: StatusCode) {
+        let status = StatusCode::from_str(input).unwrap();
+        assert_eq!(status, expected);
+        assert_eq!(status.code().to_string(), input);
+    }
+}
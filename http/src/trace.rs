@@ -0,0 +1,258 @@
+use std::str::FromStr;
+
+use super::{error::frame::FrameError, header::HeaderMap};
+
+pub const TRACEPARENT: &str = "traceparent";
+pub const TRACESTATE: &str = "tracestate";
+
+const VERSION: u8 = 0;
+const SAMPLED: u8 = 0x01;
+// list members a tracestate may hold, the ones after are dropped
+const MAX_STATE_MEMBERS: usize = 32;
+
+// TraceContext is the position of a request in a distributed trace, as told
+// by the W3C traceparent and tracestate headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    // span the request was sent from
+    pub parent_id: u64,
+    pub flags: u8,
+    // vendor specific entries, passed along as received
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    // starts a new trace
+    pub fn new(sampled: bool) -> Self {
+        Self {
+            trace_id: nonzero(|| fastrand::u128(..)),
+            parent_id: span_id(),
+            flags: if sampled { SAMPLED } else { 0 },
+            state: None,
+        }
+    }
+
+    // context of a request sent from a new span of the same trace
+    pub fn child(&self) -> Self {
+        Self {
+            parent_id: span_id(),
+            ..self.clone()
+        }
+    }
+
+    pub fn sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn parent_id_hex(&self) -> String {
+        format!("{:016x}", self.parent_id)
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "{:02x}-{}-{}-{:02x}",
+            VERSION,
+            self.trace_id_hex(),
+            self.parent_id_hex(),
+            self.flags
+        )
+    }
+
+    // reads the context of a request, a missing or invalid traceparent
+    // discards the tracestate as well
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut context = Self::from_str(headers.raw.get(TRACEPARENT)?).ok()?;
+        context.state = headers.raw.get(TRACESTATE).and_then(|s| state(s));
+        Some(context)
+    }
+
+    // writes the context to the headers of a request, replacing any
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        headers
+            .raw
+            .insert(TRACEPARENT.to_string(), self.traceparent());
+        match &self.state {
+            Some(state) => {
+                headers.raw.insert(TRACESTATE.to_string(), state.clone());
+            }
+            None => {
+                headers.raw.remove(TRACESTATE);
+            }
+        }
+    }
+}
+
+impl TryFrom<TraceContext> for String {
+    type Error = FrameError;
+
+    fn try_from(context: TraceContext) -> Result<Self, Self::Error> {
+        Ok(context.traceparent())
+    }
+}
+
+impl FromStr for TraceContext {
+    type Err = FrameError;
+
+    // parses a traceparent, e.g.
+    // "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01". Fields added
+    // by later versions are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| FrameError::Invalid {
+            reason,
+            subject: TRACEPARENT,
+        };
+        let mut fields = s.trim().split('-');
+        let mut field = |len: usize| match fields.next() {
+            Some(f)
+                if f.len() == len && f.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) =>
+            {
+                Ok(f)
+            }
+            _ => Err(invalid("expected lowercase hex fields")),
+        };
+
+        let version = u8::from_str_radix(field(2)?, 16)?;
+        let trace_id = u128::from_str_radix(field(32)?, 16)?;
+        let parent_id = u64::from_str_radix(field(16)?, 16)?;
+        let flags = u8::from_str_radix(field(2)?, 16)?;
+        if version == 0xff {
+            return Err(invalid("version ff is forbidden"));
+        }
+        if version == VERSION && fields.next().is_some() {
+            return Err(invalid("unexpected field after the flags"));
+        }
+        if trace_id == 0 || parent_id == 0 {
+            return Err(invalid("ids should not be all zeroes"));
+        }
+        Ok(Self {
+            trace_id,
+            parent_id,
+            flags,
+            state: None,
+        })
+    }
+}
+
+// keeps the members of a tracestate that look like key=value, up to the
+// allowed number
+fn state(s: &str) -> Option<String> {
+    let members: Vec<&str> = s
+        .split(',')
+        .map(str::trim)
+        .filter(|m| matches!(m.split_once('='), Some((k, v)) if !k.is_empty() && !v.is_empty()))
+        .take(MAX_STATE_MEMBERS)
+        .collect();
+    match members.is_empty() {
+        true => None,
+        false => Some(members.join(",")),
+    }
+}
+
+pub fn span_id() -> u64 {
+    nonzero(|| fastrand::u64(..))
+}
+
+// all zeroes ids are invalid
+fn nonzero<T: Default + PartialEq>(f: impl Fn() -> T) -> T {
+    loop {
+        let id = f();
+        if id != T::default() {
+            return id;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[test]
+    fn test_traceparent() {
+        let context =
+            TraceContext::from_str("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+                .unwrap();
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.parent_id, 0x00f067aa0ba902b7);
+        assert!(context.sampled());
+        assert_eq!(
+            String::try_from(context).unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+
+        // later versions may add fields
+        let context =
+            TraceContext::from_str("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-x")
+                .unwrap();
+        assert!(!context.sampled());
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7")]
+    #[case("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x")]
+    #[case("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01")]
+    #[case("00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01")]
+    #[case("00-+bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")]
+    #[case("00-00000000000000000000000000000000-00f067aa0ba902b7-01")]
+    #[case("00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01")]
+    #[case("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")]
+    fn test_traceparent_invalid(#[case] input: &str) {
+        assert!(TraceContext::from_str(input).is_err());
+    }
+
+    #[test]
+    fn test_trace_context_headers() {
+        let mut headers = HeaderMap::default();
+        headers.raw.insert(
+            TRACEPARENT.to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        );
+        headers.raw.insert(
+            TRACESTATE.to_string(),
+            "congo=t61rcWkgMzE, ,bad".to_string(),
+        );
+        let context = TraceContext::from_headers(&headers).unwrap();
+        assert_eq!(context.state, Some("congo=t61rcWkgMzE".to_string()));
+
+        let child = context.child();
+        assert_eq!(child.trace_id, context.trace_id);
+        assert_ne!(child.parent_id, context.parent_id);
+        assert_eq!(child.state, context.state);
+
+        let mut outbound = HeaderMap::default();
+        child.inject(&mut outbound);
+        assert_eq!(
+            outbound.raw[TRACEPARENT],
+            format!(
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-{:016x}-01",
+                child.parent_id
+            )
+        );
+        assert_eq!(outbound.raw[TRACESTATE], "congo=t61rcWkgMzE");
+
+        // the tracestate is not trusted without a valid traceparent
+        headers
+            .raw
+            .insert(TRACEPARENT.to_string(), "garbage".to_string());
+        assert_eq!(TraceContext::from_headers(&headers), None);
+    }
+
+    #[test]
+    fn test_trace_context_new() {
+        let context = TraceContext::new(true);
+        assert_ne!(context.trace_id, 0);
+        assert_ne!(context.parent_id, 0);
+        assert!(context.sampled());
+        assert_eq!(
+            TraceContext::from_str(&context.traceparent()).unwrap(),
+            context
+        );
+        assert!(!TraceContext::new(false).sampled());
+    }
+}
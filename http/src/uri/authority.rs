@@ -5,7 +5,7 @@ use std::{
 
 use crate::error::frame::FrameError;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum Authority {
     Domain { host: String, port: usize },
     IPv4 { ip: Ipv4Addr, port: usize },
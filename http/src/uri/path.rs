@@ -41,6 +41,18 @@ impl TryFrom<Query> for String {
     }
 }
 
+impl Query {
+    // query as received, the order of its parameters is kept
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    // values of a parameter, in the order they were received
+    pub fn get(&self, key: &str) -> Option<&[String]> {
+        self.lookup.get(key).map(Vec::as_slice)
+    }
+}
+
 impl FromStr for Query {
     type Err = FrameError;
 
@@ -203,6 +215,17 @@ mod tests {
         assert_eq!(double.lookup, query.lookup);
     }
 
+    #[test]
+    fn test_query_get() {
+        let query = Query::from_str("a=1&b=%26&a=2").unwrap();
+        assert_eq!(
+            query.get("a"),
+            Some(&["1".to_string(), "2".to_string()][..])
+        );
+        assert_eq!(query.get("b"), Some(&["&".to_string()][..]));
+        assert_eq!(query.get("c"), None);
+    }
+
     #[rstest]
     #[case("%zzzz=a")]
     #[case("%=a")]
@@ -25,6 +25,24 @@ impl Debug for Token {
     }
 }
 
+impl Token {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn start(&self) -> usize {
+        self.start
+    }
+
+    pub fn end(&self) -> usize {
+        self.end
+    }
+
+    pub fn kind(&self) -> &TokenKind {
+        &self.kind
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub enum TokenKind {
     BraceOpen,
@@ -189,9 +207,14 @@ pub fn take_string<I: Iterator<Item = (usize, char)>>(
                             }
                         }
                     }
-                    '"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' => {
+                    '"' | '\\' | '/' => {
                         res.push(ch);
                     }
+                    'b' => res.push('\u{8}'),
+                    'f' => res.push('\u{c}'),
+                    'n' => res.push('\n'),
+                    'r' => res.push('\r'),
+                    't' => res.push('\t'),
                     _ => {
                         return Err(ParserError {
                             token: Some(Token {
@@ -557,12 +580,60 @@ pub fn parse_object<'a, I: Iterator<Item = &'a Token>>(
 }
 
 impl Node {
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match self {
+            Node::Object(mapping) => mapping.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Node::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Node::Number(NumberNode::I64(i)) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Node::Number(NumberNode::F64(f)) => Some(*f),
+            Node::Number(NumberNode::I64(i)) => Some(*i as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Node::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Node>> {
+        match self {
+            Node::Array(nodes) => Some(nodes),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(&self) -> Option<&HashMap<String, Node>> {
+        match self {
+            Node::Object(mapping) => Some(mapping),
+            _ => None,
+        }
+    }
+
     pub fn write<W: Write>(&self, writer: &mut BufWriter<W>) -> Result<(), ParserError> {
         match self {
             Node::String(s) => {
-                writer.write_all(&[b'"'])?;
-                writer.write_all(s.as_bytes())?;
-                writer.write_all(&[b'"'])?;
+                write_string(writer, s)?;
             }
             Node::Boolean(b) => {
                 writer.write_all(b.to_string().as_bytes())?;
@@ -591,9 +662,8 @@ impl Node {
                 writer.write_all(&[b'{'])?;
                 let n = mapping.len();
                 for (i, (k, v)) in mapping.iter().enumerate() {
-                    writer.write_all(&[b'"'])?;
-                    writer.write_all(k.as_bytes())?;
-                    writer.write_all(&[b'"', b':'])?;
+                    write_string(writer, k)?;
+                    writer.write_all(&[b':'])?;
                     v.write(writer)?;
 
                     if i != n - 1 {
@@ -608,6 +678,31 @@ impl Node {
     }
 }
 
+// writes a quoted string, escaping the characters a string cannot contain
+fn write_string<W: Write>(writer: &mut BufWriter<W>, s: &str) -> Result<(), ParserError> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, ch) in s.char_indices() {
+        let escaped = match ch {
+            '"' => "\\\"".to_string(),
+            '\\' => "\\\\".to_string(),
+            '\n' => "\\n".to_string(),
+            '\r' => "\\r".to_string(),
+            '\t' => "\\t".to_string(),
+            '\u{8}' => "\\b".to_string(),
+            '\u{c}' => "\\f".to_string(),
+            c if c < ' ' => format!("\\u{:04x}", c as u32),
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        writer.write_all(escaped.as_bytes())?;
+        start = i + ch.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])?;
+    writer.write_all(b"\"")?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -875,6 +970,12 @@ mod tests {
             ("random".to_string(), Node::String("value".to_string())),
         ])),
     )]
+    #[case(
+        Node::Object(HashMap::from_iter(vec![(
+            "quoted \"key\"".to_string(),
+            Node::String("C:\\path\n\ttab\r\u{8}\u{c}\u{1}/".to_string()),
+        )])),
+    )]
     fn test_write(#[case] node: Node) {
         let mut buf = BufWriter::new(Vec::new());
         node.write(&mut buf).unwrap();
@@ -887,6 +988,24 @@ mod tests {
         assert_eq!(res, node);
     }
 
+    #[test]
+    fn test_node_accessors() {
+        let input = r#"{"name": "api", "port": 9090, "ratio": 0.5, "tls": false, "routes": []}"#;
+        let tokens = tokenize(Cursor::new(input).lines()).unwrap();
+        let mut iter = tokens.iter().peekable();
+        let node = parse(&mut iter).unwrap().unwrap();
+
+        assert_eq!(node.get("name").and_then(Node::as_str), Some("api"));
+        assert_eq!(node.get("port").and_then(Node::as_i64), Some(9090));
+        assert_eq!(node.get("port").and_then(Node::as_f64), Some(9090.0));
+        assert_eq!(node.get("ratio").and_then(Node::as_f64), Some(0.5));
+        assert_eq!(node.get("tls").and_then(Node::as_bool), Some(false));
+        assert_eq!(node.get("routes").and_then(Node::as_array), Some(&vec![]));
+        assert_eq!(node.get("missing"), None);
+        assert_eq!(node.get("name").and_then(Node::as_i64), None);
+        assert_eq!(node.as_object().map(|m| m.len()), Some(5));
+    }
+
     #[rstest]
     #[case(
         r#"0.4e00669999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999999969999999006"#,
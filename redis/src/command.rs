@@ -11,6 +11,9 @@ pub enum Command {
     Get {
         key: String,
     },
+    MGet {
+        keys: Vec<String>,
+    },
     Incr {
         key: String,
     },
@@ -27,6 +30,19 @@ pub enum Command {
         expire_time: Option<Duration>,
         keep_ttl: bool,
     },
+    PExpire {
+        key: String,
+        expire_time: Duration,
+    },
+
+    EvalSha {
+        sha: String,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+    ScriptLoad {
+        script: String,
+    },
 }
 
 #[derive(Debug)]
@@ -61,11 +77,16 @@ impl Display for Command {
         match self {
             Self::Hello { .. } => f.write_str("HELLO"),
             Self::Get { .. } => f.write_str("GET"),
+            Self::MGet { .. } => f.write_str("MGET"),
             Self::Incr { .. } => f.write_str("INCR"),
             Self::Decr { .. } => f.write_str("DECR"),
 
             Self::Del { .. } => f.write_str("DEL"),
             Self::Set { .. } => f.write_str("SET"),
+            Self::PExpire { .. } => f.write_str("PEXPIRE"),
+
+            Self::EvalSha { .. } => f.write_str("EVALSHA"),
+            Self::ScriptLoad { .. } => f.write_str("SCRIPT"),
         }
     }
 }
@@ -102,10 +123,28 @@ impl TryFrom<Command> for Frame {
                     builder.bulk_string("KEEPTTL");
                 }
             }
-            Command::Del { keys } => {
+            Command::PExpire { key, expire_time } => {
+                builder.bulk_string(key);
+                builder.bulk_string(expire_time.as_millis());
+            }
+            Command::Del { keys } | Command::MGet { keys } => {
+                for k in keys {
+                    builder.bulk_string(k);
+                }
+            }
+            Command::EvalSha { sha, keys, args } => {
+                builder.bulk_string(sha);
+                builder.bulk_string(keys.len());
                 for k in keys {
                     builder.bulk_string(k);
                 }
+                for arg in args {
+                    builder.bulk_string(arg);
+                }
+            }
+            Command::ScriptLoad { script } => {
+                builder.bulk_string("LOAD");
+                builder.bulk_string(script);
             }
         }
 
@@ -119,8 +158,50 @@ pub enum Response {}
 #[cfg(test)]
 mod tests {
 
+    use super::*;
     use rstest::*;
 
+    fn frame(parts: &[&str]) -> Frame {
+        Frame::Array(
+            parts
+                .iter()
+                .map(|part| Frame::BulkString(Some(part.to_string())))
+                .collect(),
+        )
+    }
+
     #[rstest]
-    fn test_frame_from_command() {}
+    #[case(Command::Get { key: "k".to_string() }, frame(&["GET", "k"]))]
+    #[case(
+        Command::Set {
+            key: "k".to_string(),
+            value: "v".to_string(),
+            expire_time: Some(Duration::from_secs(1)),
+            keep_ttl: false,
+        },
+        frame(&["SET", "k", "v", "PX", "1000"])
+    )]
+    #[case(
+        Command::MGet { keys: vec!["k1".to_string(), "k2".to_string()] },
+        frame(&["MGET", "k1", "k2"])
+    )]
+    #[case(
+        Command::PExpire { key: "k".to_string(), expire_time: Duration::from_millis(1500) },
+        frame(&["PEXPIRE", "k", "1500"])
+    )]
+    #[case(
+        Command::EvalSha {
+            sha: "e0e1f9fabfc9d4800c877a703b823ac0578ff8db".to_string(),
+            keys: vec!["k1".to_string(), "k2".to_string()],
+            args: vec!["a".to_string()],
+        },
+        frame(&["EVALSHA", "e0e1f9fabfc9d4800c877a703b823ac0578ff8db", "2", "k1", "k2", "a"])
+    )]
+    #[case(
+        Command::ScriptLoad { script: "return 1".to_string() },
+        frame(&["SCRIPT", "LOAD", "return 1"])
+    )]
+    fn test_frame_from_command(#[case] command: Command, #[case] expected: Frame) {
+        assert_eq!(Frame::try_from(command).unwrap(), expected);
+    }
 }
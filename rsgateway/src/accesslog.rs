@@ -0,0 +1,486 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::{self, BufWriter},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+use http::{date, header::HeaderMap, request::Request, response::Response, statuscode::StatusCode};
+use json::parser::{Node, NumberNode};
+
+use crate::{cache::CacheStatus, tracing::Trace, trie::Captures};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+// headers whose values are replaced in the log by default, as they carry
+// credentials
+pub const REDACTED_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+pub const REDACTED: &str = "[redacted]";
+pub const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+pub const MAX_FILES: usize = 5;
+// lines waiting to be written, further lines are dropped so that a slow sink
+// does not hold requests back
+const BACKLOG: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sink {
+    Stdout,
+    // a file rotated once it reaches its maximum size
+    File(PathBuf),
+}
+
+// AccessLogConfig tells where access log lines are written and what they
+// contain.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessLogConfig {
+    pub sink: Sink,
+    // size a file reaches before it is rotated
+    pub max_size: u64,
+    // rotated files kept, the oldest one is removed
+    pub max_files: usize,
+    // whether the request headers are logged
+    pub headers: bool,
+    // lowercase names of the headers whose values are not logged
+    pub redact: Vec<String>,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            sink: Sink::Stdout,
+            max_size: MAX_FILE_SIZE,
+            max_files: MAX_FILES,
+            headers: true,
+            redact: REDACTED_HEADERS.iter().map(|h| h.to_string()).collect(),
+        }
+    }
+}
+
+// Timings is where the time of a request went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timings {
+    // connecting to the endpoint the response came from
+    pub connect: Option<Duration>,
+    // from sending the request to the endpoint until its response head
+    pub first_byte: Option<Duration>,
+    // from the request head until the response was sent
+    pub total: Duration,
+}
+
+// Entry is what the access log tells about a request.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub id: String,
+    pub time: SystemTime,
+    start: Instant,
+    pub client: SocketAddr,
+    pub method: String,
+    pub host: String,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub route: Option<String>,
+    // values captured by the path of the route
+    pub captures: Captures,
+    pub upstream: Option<String>,
+    // not set when the connection failed before a response was sent
+    pub status: Option<StatusCode>,
+    // set for the routes caching their responses
+    pub cache: Option<CacheStatus>,
+    // lengths of the request and response bodies as sent
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub timings: Timings,
+    // set when spans of the request are exported
+    pub trace: Option<Trace>,
+}
+
+impl Entry {
+    pub fn new(client: SocketAddr, req: &Request) -> Self {
+        let id = match req.parts.headers.raw.get(REQUEST_ID_HEADER) {
+            Some(id) if !id.trim().is_empty() => id.trim().to_string(),
+            _ => format!("{:016x}{:016x}", fastrand::u64(..), fastrand::u64(..)),
+        };
+        Self {
+            id,
+            time: SystemTime::now(),
+            start: Instant::now(),
+            client,
+            method: String::try_from(req.parts.method.clone()).unwrap_or_default(),
+            host: req
+                .parts
+                .headers
+                .raw
+                .get("host")
+                .cloned()
+                .unwrap_or_default(),
+            path: String::try_from(req.parts.url.path.clone()).unwrap_or_default(),
+            headers: req.parts.headers.clone(),
+            route: None,
+            captures: Captures::default(),
+            upstream: None,
+            status: None,
+            cache: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            timings: Timings::default(),
+            trace: None,
+        }
+    }
+
+    // records a response whose body is in memory
+    pub fn responded(&mut self, resp: &Response) {
+        self.status = Some(resp.status);
+        self.bytes_out = resp.body.as_ref().map_or(0, Vec::len);
+    }
+
+    pub fn finish(&mut self) {
+        self.timings.total = self.start.elapsed();
+    }
+
+    pub fn node(&self, config: &AccessLogConfig) -> Node {
+        let string = |s: &str| Node::String(s.to_string());
+        let optional = |s: &Option<String>| s.as_deref().map_or(Node::Null, string);
+        let count = |n: usize| Node::Number(NumberNode::I64(n as i64));
+        let millis = |d: Duration| Node::Number(NumberNode::F64(d.as_secs_f64() * 1000.0));
+
+        let timings = HashMap::from([
+            (
+                "connect_ms".to_string(),
+                self.timings.connect.map_or(Node::Null, millis),
+            ),
+            (
+                "first_byte_ms".to_string(),
+                self.timings.first_byte.map_or(Node::Null, millis),
+            ),
+            ("total_ms".to_string(), millis(self.timings.total)),
+        ]);
+        let status = self.status.map_or(Node::Null, |s| count(s.code() as usize));
+        let cache = self
+            .cache
+            .map_or(Node::Null, |c| Node::String(c.as_str().to_string()));
+
+        let mut fields = HashMap::from([
+            (
+                "timestamp".to_string(),
+                string(&date::format_rfc3339(self.time)),
+            ),
+            ("request_id".to_string(), string(&self.id)),
+            ("client".to_string(), string(&self.client.to_string())),
+            ("method".to_string(), string(&self.method)),
+            ("host".to_string(), string(&self.host)),
+            ("path".to_string(), string(&self.path)),
+            ("route".to_string(), optional(&self.route)),
+            (
+                "captures".to_string(),
+                Node::Object(
+                    self.captures
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), string(v)))
+                        .collect(),
+                ),
+            ),
+            ("upstream".to_string(), optional(&self.upstream)),
+            ("status".to_string(), status),
+            ("cache".to_string(), cache),
+            ("bytes_in".to_string(), count(self.bytes_in)),
+            ("bytes_out".to_string(), count(self.bytes_out)),
+            ("timings".to_string(), Node::Object(timings)),
+        ]);
+        if config.headers {
+            let headers = self
+                .headers
+                .raw
+                .iter()
+                .map(|(k, v)| match config.redact.contains(k) {
+                    true => (k.clone(), string(REDACTED)),
+                    false => (k.clone(), string(v)),
+                })
+                .collect();
+            fields.insert("headers".to_string(), Node::Object(headers));
+        }
+        Node::Object(fields)
+    }
+}
+
+// AccessLog writes a line per request in the background, requests do not
+// wait for their line to be written.
+#[derive(Debug)]
+pub struct AccessLog {
+    config: AccessLogConfig,
+    lines: mpsc::Sender<Vec<u8>>,
+}
+
+impl AccessLog {
+    pub async fn open(config: &AccessLogConfig) -> io::Result<Self> {
+        let (lines, rx) = mpsc::channel(BACKLOG);
+        match &config.sink {
+            Sink::Stdout => {
+                tokio::spawn(write_lines(tokio::io::stdout(), rx));
+            }
+            Sink::File(path) => {
+                let file = RotatingFile::open(path, config.max_size, config.max_files).await?;
+                tokio::spawn(write_rotated(file, rx));
+            }
+        }
+        Ok(Self {
+            config: config.clone(),
+            lines,
+        })
+    }
+
+    pub fn log(&self, entry: &Entry) {
+        let mut writer = BufWriter::new(Vec::new());
+        if entry.node(&self.config).write(&mut writer).is_err() {
+            return;
+        }
+        if let Ok(mut line) = writer.into_inner() {
+            line.push(b'\n');
+            let _ = self.lines.try_send(line);
+        }
+    }
+}
+
+async fn write_lines<W: AsyncWrite + Unpin>(mut writer: W, mut lines: mpsc::Receiver<Vec<u8>>) {
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = writer.write_all(&line).await {
+            eprintln!("access log: {}", e);
+        }
+        let _ = writer.flush().await;
+    }
+}
+
+async fn write_rotated(mut file: RotatingFile, mut lines: mpsc::Receiver<Vec<u8>>) {
+    while let Some(line) = lines.recv().await {
+        if let Err(e) = file.write(&line).await {
+            eprintln!("access log {}: {}", file.path.display(), e);
+        }
+    }
+}
+
+// RotatingFile is a file renamed to <path>.1 once it reaches its maximum
+// size, the file previously at <path>.1 becoming <path>.2 and so on.
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    async fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        let size = file.metadata().await?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    async fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate().await?;
+        }
+        self.file.write_all(line).await?;
+        self.file.flush().await?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> io::Result<()> {
+        // renaming over the last file removes it
+        for i in (1..self.max_files).rev() {
+            match fs::rename(rotated(&self.path, i), rotated(&self.path, i + 1)).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        match self.max_files {
+            0 => fs::remove_file(&self.path).await?,
+            _ => fs::rename(&self.path, rotated(&self.path, 1)).await?,
+        }
+        *self = Self::open(&self.path, self.max_size, self.max_files).await?;
+        Ok(())
+    }
+}
+
+fn rotated(path: &Path, i: usize) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", i));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, str::FromStr};
+
+    use http::{builder::Builder, method::Method, uri::path::Path as UrlPath};
+    use json::parser::{parse, tokenize};
+    use tokio::io::AsyncBufReadExt;
+
+    use super::*;
+
+    fn parsed(line: &[u8]) -> Node {
+        let tokens = tokenize(std::io::BufRead::lines(Cursor::new(line))).unwrap();
+        parse(&mut tokens.iter().peekable()).unwrap().unwrap()
+    }
+
+    fn entry(headers: &[(&str, &str)]) -> Entry {
+        let mut map = HeaderMap::default();
+        for (k, v) in headers {
+            map.raw.insert(k.to_string(), v.to_string());
+        }
+        let req = Builder::new()
+            .method(Method::GET)
+            .path(UrlPath::from_str("/a?q=1").unwrap())
+            .headers(map)
+            .build();
+        Entry::new(SocketAddr::from_str("127.0.0.1:4000").unwrap(), &req)
+    }
+
+    #[test]
+    fn test_entry_node() {
+        let mut entry = entry(&[
+            ("host", "gateway.test:80"),
+            ("authorization", "Bearer secret"),
+            ("x-request-id", "req-1"),
+            ("user-agent", "agent \"quoted\""),
+        ]);
+        entry.route = Some("gateway.test:80/a".to_string());
+        entry.captures = Captures::from([("id", "42")]);
+        entry.status = Some(StatusCode::Ok);
+        entry.bytes_out = 12;
+        entry.timings.connect = Some(Duration::from_micros(1500));
+        entry.finish();
+
+        let mut writer = BufWriter::new(Vec::new());
+        entry
+            .node(&AccessLogConfig::default())
+            .write(&mut writer)
+            .unwrap();
+        let node = parsed(&writer.into_inner().unwrap());
+
+        let field = |key: &str| node.get(key).unwrap_or(&Node::Null);
+        assert_eq!(field("request_id").as_str(), Some("req-1"));
+        assert_eq!(field("client").as_str(), Some("127.0.0.1:4000"));
+        assert_eq!(field("method").as_str(), Some("GET"));
+        assert_eq!(field("host").as_str(), Some("gateway.test:80"));
+        assert_eq!(field("path").as_str(), Some("/a?q=1"));
+        assert_eq!(field("route").as_str(), Some("gateway.test:80/a"));
+        assert_eq!(
+            field("captures").get("id").and_then(Node::as_str),
+            Some("42")
+        );
+        assert_eq!(field("upstream"), &Node::Null);
+        assert_eq!(field("cache"), &Node::Null);
+        assert_eq!(field("status").as_i64(), Some(200));
+        assert_eq!(field("bytes_in").as_i64(), Some(0));
+        assert_eq!(field("bytes_out").as_i64(), Some(12));
+        assert!(field("timestamp").as_str().unwrap().ends_with('Z'));
+
+        let timings = field("timings");
+        assert_eq!(timings.get("connect_ms").and_then(Node::as_f64), Some(1.5));
+        assert_eq!(timings.get("first_byte_ms"), Some(&Node::Null));
+        assert!(timings.get("total_ms").and_then(Node::as_f64).is_some());
+
+        let headers = field("headers");
+        assert_eq!(
+            headers.get("authorization").and_then(Node::as_str),
+            Some(REDACTED)
+        );
+        assert_eq!(
+            headers.get("user-agent").and_then(Node::as_str),
+            Some("agent \"quoted\"")
+        );
+
+        let config = AccessLogConfig {
+            headers: false,
+            ..Default::default()
+        };
+        assert_eq!(entry.node(&config).get("headers"), None);
+    }
+
+    #[test]
+    fn test_entry_generates_request_id() {
+        let (a, b) = (entry(&[]), entry(&[]));
+        assert_eq!(a.id.len(), 32);
+        assert_ne!(a.id, b.id);
+    }
+
+    #[tokio::test]
+    async fn test_rotating_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut file = RotatingFile::open(&path, 10, 2).await.unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write(line.as_bytes()).await.unwrap();
+        }
+
+        let read = |i: usize| {
+            let path = match i {
+                0 => path.clone(),
+                i => rotated(&path, i),
+            };
+            std::fs::read_to_string(path).unwrap()
+        };
+        assert_eq!(read(0), "fourth\n");
+        assert_eq!(read(1), "third\n");
+        assert_eq!(read(2), "second\n");
+        assert!(!rotated(&path, 3).exists());
+    }
+
+    #[tokio::test]
+    async fn test_access_log_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let config = AccessLogConfig {
+            sink: Sink::File(path.clone()),
+            ..Default::default()
+        };
+        let log = AccessLog::open(&config).await.unwrap();
+        log.log(&entry(&[("x-request-id", "one")]));
+        log.log(&entry(&[("x-request-id", "two")]));
+
+        let mut ids = Vec::new();
+        for _ in 0..100 {
+            let file = tokio::io::BufReader::new(File::open(&path).await.unwrap());
+            let mut lines = file.lines();
+            ids.clear();
+            while let Some(line) = lines.next_line().await.unwrap() {
+                let node = parsed(line.as_bytes());
+                ids.push(
+                    node.get("request_id")
+                        .and_then(Node::as_str)
+                        .unwrap()
+                        .to_string(),
+                );
+            }
+            if ids.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(ids, vec!["one", "two"]);
+    }
+}
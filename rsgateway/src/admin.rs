@@ -0,0 +1,506 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    net::SocketAddr,
+    sync::Arc,
+};
+
+use http::{
+    client::Client, header::HeaderKind, method::Method, request::Request, response::Response,
+    statuscode::StatusCode,
+};
+use json::parser::{Node, NumberNode};
+use tokio::{
+    io::BufReader,
+    net::{TcpListener, TcpStream},
+    task::spawn_blocking,
+};
+
+use crate::{
+    error::ConfigError,
+    metrics::{self, Metrics},
+    reload::Reloader,
+    route::MatchType,
+    router::Router,
+    upstream::{Endpoint, Pool},
+};
+
+// AdminConfig tells where the admin listener is bound, and the token its
+// clients send as a bearer token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AdminConfig {
+    pub address: String,
+    pub token: String,
+}
+
+// Admin serves the state of the gateway on a listener of its own, kept apart
+// from the proxied traffic, and lets operators act on it.
+pub struct Admin {
+    socket: TcpListener,
+    gateway: Gateway,
+}
+
+// Gateway is what the admin listener tells about and acts on.
+struct Gateway {
+    token: String,
+    router: Arc<Router>,
+    client: Client,
+    metrics: Arc<Metrics>,
+    // reloads are not available when the config was not loaded from a file
+    reloader: Option<Arc<Reloader>>,
+}
+
+impl Admin {
+    pub async fn bind(
+        config: &AdminConfig,
+        router: Arc<Router>,
+        client: Client,
+        metrics: Arc<Metrics>,
+    ) -> Result<Self, ConfigError> {
+        let socket = TcpListener::bind(&config.address).await?;
+        let gateway = Gateway {
+            token: config.token.clone(),
+            router,
+            client,
+            metrics,
+            reloader: None,
+        };
+        Ok(Self { socket, gateway })
+    }
+
+    pub fn reloader(&mut self, reloader: Arc<Reloader>) {
+        self.gateway.reloader = Some(reloader);
+    }
+
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.socket.local_addr().ok()
+    }
+
+    pub async fn run(self) {
+        let gateway = Arc::new(self.gateway);
+        while let Ok((stream, _)) = self.socket.accept().await {
+            tokio::spawn(serve(stream, gateway.clone()));
+        }
+    }
+}
+
+// answers a single request, then closes the connection
+async fn serve(mut stream: TcpStream, gateway: Arc<Gateway>) {
+    let req = match Request::read(&mut BufReader::new(&mut stream)).await {
+        Ok(req) => req,
+        Err(_) => return,
+    };
+    let mut resp = answer(&req, &gateway).await;
+    resp.headers
+        .raw
+        .insert("connection".to_string(), "close".to_string());
+    let _ = resp.write(&mut stream).await;
+}
+
+async fn answer(req: &Request, gateway: &Gateway) -> Response {
+    if !authorized(req, &gateway.token) {
+        let mut resp = error(StatusCode::Unauthorized, "missing or invalid token");
+        resp.headers
+            .raw
+            .insert("www-authenticate".to_string(), "Bearer".to_string());
+        return resp;
+    }
+
+    let path = req.parts.url.path.raw_path.trim_matches('/');
+    let segments: Vec<&str> = path.split('/').collect();
+    let allowed = match segments.as_slice() {
+        ["routes"] | ["upstreams"] | ["circuit-breakers"] | ["metrics"] => Method::GET,
+        ["upstreams", _, "endpoints", _, "drain" | "undrain"] | ["reload"] => Method::POST,
+        _ => return error(StatusCode::NotFound, "unknown endpoint"),
+    };
+    if req.parts.method != allowed {
+        let mut resp = error(StatusCode::MethodNotAllowed, "method not allowed");
+        resp.headers.raw.insert(
+            "allow".to_string(),
+            String::try_from(allowed).unwrap_or_default(),
+        );
+        return resp;
+    }
+
+    match segments.as_slice() {
+        ["routes"] => json(StatusCode::Ok, &routes(&gateway.router)),
+        ["upstreams"] => json(StatusCode::Ok, &upstreams(&gateway.router)),
+        ["circuit-breakers"] => json(StatusCode::Ok, &circuit_breakers(&gateway.router)),
+        ["metrics"] => {
+            let upstreams = gateway.router.upstreams();
+            let text = gateway.metrics.render(&upstreams, &gateway.client);
+            ok(StatusCode::Ok, metrics::CONTENT_TYPE, text.into_bytes())
+        }
+        ["upstreams", pool, "endpoints", index, action] => {
+            drain(&gateway.router, pool, index, *action == "drain")
+        }
+        ["reload"] => match &gateway.reloader {
+            // reading and parsing the config blocks
+            Some(reloader) => match spawn_blocking({
+                let reloader = reloader.clone();
+                move || reloader.reload()
+            })
+            .await
+            {
+                Ok(Ok(n)) => json(
+                    StatusCode::Ok,
+                    &Node::Object(HashMap::from([(
+                        "routes".to_string(),
+                        Node::Number(NumberNode::I64(n as i64)),
+                    )])),
+                ),
+                Ok(Err(e)) => error(StatusCode::BadRequest, &e.to_string()),
+                Err(_) => error(StatusCode::InternalServerError, "reload failed"),
+            },
+            None => error(
+                StatusCode::ServiceUnavailable,
+                "the config was not loaded from a file",
+            ),
+        },
+        _ => error(StatusCode::NotFound, "unknown endpoint"),
+    }
+}
+
+// whether the request carries the token, compared in constant time so that
+// the time of the comparison does not tell how much of it matched
+fn authorized(req: &Request, token: &str) -> bool {
+    let given = match req.parts.headers.raw.get("authorization") {
+        Some(value) => match value.split_once(' ') {
+            Some((scheme, given)) if scheme.eq_ignore_ascii_case("bearer") => given.trim(),
+            _ => return false,
+        },
+        None => return false,
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn json(status: StatusCode, node: &Node) -> Response {
+    let mut writer = BufWriter::new(Vec::new());
+    if node.write(&mut writer).is_err() || writer.flush().is_err() {
+        return Response::new(StatusCode::InternalServerError);
+    }
+    ok(
+        status,
+        "application/json",
+        writer.into_inner().unwrap_or_default(),
+    )
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    let node = Node::Object(HashMap::from([(
+        "error".to_string(),
+        Node::String(message.to_string()),
+    )]));
+    json(status, &node)
+}
+
+fn ok(status: StatusCode, content_type: &str, body: Vec<u8>) -> Response {
+    let mut resp = Response::new(status);
+    let _ = resp
+        .headers
+        .put("content-length", HeaderKind::ContentLength(body.len()));
+    resp.headers
+        .raw
+        .insert("content-type".to_string(), content_type.to_string());
+    resp.hasbody = true;
+    resp.body = Some(body);
+    resp
+}
+
+// routes of the table in use, sorted by name
+fn routes(router: &Router) -> Node {
+    let trie = router.load();
+    let mut routes = trie.routes();
+    routes.sort_by(|a, b| a.name.cmp(&b.name));
+    let routes = routes
+        .into_iter()
+        .map(|route| {
+            let match_type = match route.match_type {
+                MatchType::Exact => "exact",
+                MatchType::Prefix => "prefix",
+            };
+            Node::Object(HashMap::from([
+                ("name".to_string(), Node::String(route.name.clone())),
+                ("match".to_string(), Node::String(match_type.to_string())),
+                (
+                    "upstream".to_string(),
+                    Node::String(route.upstream.name.clone()),
+                ),
+            ]))
+        })
+        .collect();
+    Node::Array(routes)
+}
+
+fn endpoint(index: usize, endpoint: &Endpoint) -> Node {
+    let health = &endpoint.health;
+    Node::Object(HashMap::from([
+        (
+            "index".to_string(),
+            Node::Number(NumberNode::I64(index as i64)),
+        ),
+        (
+            "url".to_string(),
+            Node::String(String::try_from(endpoint.url.clone()).unwrap_or_default()),
+        ),
+        (
+            "weight".to_string(),
+            Node::Number(NumberNode::I64(endpoint.weight as i64)),
+        ),
+        ("healthy".to_string(), Node::Boolean(health.healthy())),
+        ("ejected".to_string(), Node::Boolean(health.ejected())),
+        ("drained".to_string(), Node::Boolean(health.drained())),
+        ("available".to_string(), Node::Boolean(health.available())),
+        (
+            "outstanding".to_string(),
+            Node::Number(NumberNode::I64(endpoint.outstanding() as i64)),
+        ),
+    ]))
+}
+
+// endpoints and circuit breaker of every pool defined in the config, by pool
+// name
+fn upstreams(router: &Router) -> Node {
+    let mut pools = HashMap::new();
+    for pool in router.upstreams().iter() {
+        let endpoints = pool
+            .endpoints
+            .iter()
+            .enumerate()
+            .map(|(i, e)| endpoint(i, e))
+            .collect();
+        pools.insert(
+            pool.name.clone(),
+            Node::Object(HashMap::from([
+                ("endpoints".to_string(), Node::Array(endpoints)),
+                ("circuit_breaker".to_string(), circuit_breaker(pool)),
+            ])),
+        );
+    }
+    Node::Object(pools)
+}
+
+// drains an endpoint of a pool, or lets it receive requests again
+fn drain(router: &Router, pool: &str, index: &str, drained: bool) -> Response {
+    let upstreams = router.upstreams();
+    let Some(pool) = upstreams.iter().find(|p| p.name == pool) else {
+        return error(StatusCode::NotFound, "unknown upstream");
+    };
+    let Some((i, e)) = index
+        .parse::<usize>()
+        .ok()
+        .and_then(|i| Some((i, pool.endpoints.get(i)?)))
+    else {
+        return error(StatusCode::NotFound, "unknown endpoint");
+    };
+    e.health.drain(drained);
+    eprintln!(
+        "{}: endpoint {} {}",
+        pool.name,
+        String::try_from(e.url.clone()).unwrap_or_default(),
+        if drained { "drained" } else { "undrained" }
+    );
+    json(StatusCode::Ok, &endpoint(i, e))
+}
+
+fn circuit_breaker(pool: &Pool) -> Node {
+    let breaker = match &pool.circuit_breaker {
+        Some(breaker) => breaker,
+        None => return Node::Null,
+    };
+    let (failures, consecutive) = breaker.failures();
+    let state = breaker.state().as_str().to_string();
+    Node::Object(HashMap::from([
+        ("state".to_string(), Node::String(state)),
+        (
+            "failures".to_string(),
+            Node::Number(NumberNode::I64(failures as i64)),
+        ),
+        (
+            "consecutive_failures".to_string(),
+            Node::Number(NumberNode::I64(consecutive as i64)),
+        ),
+    ]))
+}
+
+// state of the circuit breaker of every pool having one, by pool name
+fn circuit_breakers(router: &Router) -> Node {
+    let mut breakers = HashMap::new();
+    for pool in router.upstreams().iter() {
+        if pool.circuit_breaker.is_some() {
+            breakers.insert(pool.name.clone(), circuit_breaker(pool));
+        }
+    }
+    Node::Object(breakers)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, str::FromStr};
+
+    use dns::resolver::DNS_IP_GOOGLE;
+    use tempfile::NamedTempFile;
+
+    use crate::config::Config;
+
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "listeners": [{"address": "localhost:9090"}],
+        "upstreams": {"pool": {"endpoints": [
+            {"url": "http://127.0.0.1:8080/"}, {"url": "http://127.0.0.1:8081/"}
+        ]}},
+        "routes": [
+            {"host": "localhost:9090", "path": "/api", "upstream": "pool"},
+            {"host": "localhost:9090", "path": "/", "upstream": "http://127.0.0.1:8082/"}
+        ]
+    }"#;
+
+    const RELOADED: &str = r#"{
+        "listeners": [{"address": "localhost:9090"}],
+        "routes": [{"host": "localhost:9090", "path": "/", "upstream": "http://127.0.0.1:8082/"}]
+    }"#;
+
+    fn gateway(reloader: Option<PathBuf>) -> Gateway {
+        let config = Config::from_str(CONFIG).unwrap();
+        let router = Arc::new(Router::from_config(&config));
+        let client = Client::new(DNS_IP_GOOGLE);
+        let reloader = reloader
+            .map(|path| Arc::new(Reloader::new(path, router.clone(), client.clone(), &config)));
+        Gateway {
+            token: "secret".to_string(),
+            router,
+            client,
+            metrics: Arc::new(Metrics::default()),
+            reloader,
+        }
+    }
+
+    async fn call(
+        gateway: &Gateway,
+        method: &str,
+        path: &str,
+        token: &str,
+    ) -> (StatusCode, String) {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nhost: admin\r\nauthorization: Bearer {}\r\ncontent-length: 0\r\n\r\n",
+            method, path, token
+        );
+        let req = Request::read(&mut raw.as_bytes()).await.unwrap();
+        let resp = answer(&req, gateway).await;
+        let body = String::from_utf8(resp.body.unwrap_or_default()).unwrap();
+        (resp.status, body)
+    }
+
+    #[tokio::test]
+    async fn test_admin_token() {
+        let gateway = gateway(None);
+        let (status, body) = call(&gateway, "GET", "/routes", "wrong!").await;
+        assert_eq!(status, StatusCode::Unauthorized);
+        assert!(body.contains(r#""error":"missing or invalid token""#));
+
+        let req = Request::read(&mut "GET /routes HTTP/1.1\r\nhost: admin\r\n\r\n".as_bytes())
+            .await
+            .unwrap();
+        let resp = answer(&req, &gateway).await;
+        assert_eq!(resp.status, StatusCode::Unauthorized);
+        assert_eq!(resp.headers.raw["www-authenticate"], "Bearer");
+
+        let (status, _) = call(&gateway, "GET", "/routes", "secret").await;
+        assert_eq!(status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes() {
+        let gateway = gateway(None);
+        let (status, body) = call(&gateway, "GET", "/routes", "secret").await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body.starts_with('['));
+        assert!(body.contains(r#""name":"localhost:9090/api""#));
+        assert!(body.contains(r#""upstream":"pool""#));
+        // sorted by name, the route of the host root first
+        let root = body.find(r#""name":"localhost:9090/""#).unwrap();
+        assert!(root < body.find(r#""name":"localhost:9090/api""#).unwrap());
+
+        let (status, _) = call(&gateway, "POST", "/routes", "secret").await;
+        assert_eq!(status, StatusCode::MethodNotAllowed);
+        let (status, _) = call(&gateway, "GET", "/unknown", "secret").await;
+        assert_eq!(status, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_admin_drain() {
+        let gateway = gateway(None);
+        let (status, body) = call(
+            &gateway,
+            "POST",
+            "/upstreams/pool/endpoints/1/drain",
+            "secret",
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(body.contains(r#""drained":true"#));
+        assert!(body.contains(r#""available":false"#));
+
+        let endpoints = &gateway.router.upstreams()[0].endpoints;
+        assert!(!endpoints[0].health.drained());
+        assert!(endpoints[1].health.drained());
+
+        let (_, body) = call(&gateway, "GET", "/upstreams", "secret").await;
+        assert!(body.contains(r#""drained":true"#));
+        assert!(body.contains(r#""drained":false"#));
+
+        let (status, _) = call(
+            &gateway,
+            "POST",
+            "/upstreams/pool/endpoints/1/undrain",
+            "secret",
+        )
+        .await;
+        assert_eq!(status, StatusCode::Ok);
+        assert!(endpoints[1].health.available());
+
+        for path in [
+            "/upstreams/pool/endpoints/2/drain",
+            "/upstreams/pool/endpoints/x/drain",
+            "/upstreams/other/endpoints/0/drain",
+        ] {
+            let (status, _) = call(&gateway, "POST", path, "secret").await;
+            assert_eq!(status, StatusCode::NotFound);
+        }
+        let (status, _) = call(
+            &gateway,
+            "GET",
+            "/upstreams/pool/endpoints/0/drain",
+            "secret",
+        )
+        .await;
+        assert_eq!(status, StatusCode::MethodNotAllowed);
+    }
+
+    #[tokio::test]
+    async fn test_admin_reload() {
+        let (status, _) = call(&gateway(None), "POST", "/reload", "secret").await;
+        assert_eq!(status, StatusCode::ServiceUnavailable);
+
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), CONFIG).unwrap();
+        let gateway = gateway(Some(file.path().to_path_buf()));
+
+        fs::write(file.path(), r#"{"listeners": [}"#).unwrap();
+        let (status, body) = call(&gateway, "POST", "/reload", "secret").await;
+        assert_eq!(status, StatusCode::BadRequest);
+        assert!(body.contains(r#""error""#));
+
+        fs::write(file.path(), RELOADED).unwrap();
+        let (status, body) = call(&gateway, "POST", "/reload", "secret").await;
+        assert_eq!(status, StatusCode::Ok, "{}", body);
+        assert_eq!(body, r#"{"routes":1}"#);
+        assert_eq!(gateway.router.load().routes().len(), 1);
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+// the rolling window is made of this many buckets, older buckets are dropped
+// as a whole when they leave the window
+const WINDOW_BUCKETS: u32 = 10;
+
+// BreakerConfig tells when the circuit of an upstream opens: after
+// `consecutive_failures` failed requests in a row, or when at least
+// `error_rate_percent` of the requests of the last `window` failed, provided
+// there were `min_requests` of them. The circuit stays open for `cooldown`,
+// then lets `half_open_requests` requests through to probe the upstream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakerConfig {
+    pub consecutive_failures: usize,
+    pub error_rate_percent: usize,
+    pub window: Duration,
+    pub min_requests: usize,
+    pub cooldown: Duration,
+    pub half_open_requests: usize,
+}
+
+impl Default for BreakerConfig {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            error_rate_percent: 50,
+            window: Duration::from_secs(10),
+            min_requests: 20,
+            cooldown: Duration::from_secs(30),
+            half_open_requests: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+impl BreakerState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BreakerState::Closed => "closed",
+            BreakerState::Open => "open",
+            BreakerState::HalfOpen => "half_open",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    start: Instant,
+    requests: usize,
+    failures: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: BreakerState,
+    consecutive_failures: usize,
+    buckets: VecDeque<Bucket>,
+    opened_at: Instant,
+    // probes in flight and succeeded while half open
+    probes: usize,
+    successes: usize,
+    // half open rounds so far, probes of an earlier round are not counted
+    round: usize,
+}
+
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    pub config: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner {
+                state: BreakerState::Closed,
+                consecutive_failures: 0,
+                buckets: VecDeque::new(),
+                opened_at: Instant::now(),
+                probes: 0,
+                successes: 0,
+                round: 0,
+            }),
+        }
+    }
+
+    fn inner(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().expect("breaker lock poisoned")
+    }
+
+    pub fn state(&self) -> BreakerState {
+        let mut inner = self.inner();
+        self.cool_down(&mut inner);
+        inner.state
+    }
+
+    // takes over the state of the breaker it replaces. Probes in flight
+    // report to the old breaker, they are not counted.
+    pub fn inherit(&self, old: &CircuitBreaker) {
+        let (state, consecutive_failures, buckets, opened_at, successes) = {
+            let old = old.inner();
+            let buckets = old.buckets.clone();
+            (
+                old.state,
+                old.consecutive_failures,
+                buckets,
+                old.opened_at,
+                old.successes,
+            )
+        };
+        let mut inner = self.inner();
+        inner.state = state;
+        inner.consecutive_failures = consecutive_failures;
+        inner.buckets = buckets;
+        inner.opened_at = opened_at;
+        inner.probes = 0;
+        inner.successes = successes;
+    }
+
+    // failures counted in the current window and in a row
+    pub fn failures(&self) -> (usize, usize) {
+        let mut inner = self.inner();
+        self.expire(&mut inner);
+        let failures = inner.buckets.iter().map(|b| b.failures).sum();
+        (failures, inner.consecutive_failures)
+    }
+
+    // an open circuit is half open once the cooldown is over
+    fn cool_down(&self, inner: &mut Inner) {
+        if inner.state == BreakerState::Open && inner.opened_at.elapsed() >= self.config.cooldown {
+            inner.state = BreakerState::HalfOpen;
+            inner.probes = 0;
+            inner.successes = 0;
+            inner.round += 1;
+        }
+    }
+
+    fn expire(&self, inner: &mut Inner) {
+        while let Some(bucket) = inner.buckets.front() {
+            if bucket.start.elapsed() < self.config.window {
+                break;
+            }
+            inner.buckets.pop_front();
+        }
+    }
+
+    fn open(&self, inner: &mut Inner) -> Option<BreakerState> {
+        inner.state = BreakerState::Open;
+        inner.opened_at = Instant::now();
+        inner.consecutive_failures = 0;
+        inner.buckets.clear();
+        Some(BreakerState::Open)
+    }
+
+    // lets a request through unless the circuit is open, or half open with
+    // enough probes already in flight
+    pub fn allow(&self) -> Option<Permit<'_>> {
+        let mut inner = self.inner();
+        self.cool_down(&mut inner);
+        match inner.state {
+            BreakerState::Closed => {}
+            BreakerState::Open => return None,
+            BreakerState::HalfOpen => {
+                if inner.probes + inner.successes >= self.config.half_open_requests {
+                    return None;
+                }
+                inner.probes += 1;
+            }
+        }
+        Some(Permit {
+            breaker: self,
+            probe: (inner.state == BreakerState::HalfOpen).then_some(inner.round),
+            done: false,
+        })
+    }
+
+    // records the outcome of a request, returns the new state when it changed
+    fn record(&self, probe: Option<usize>, ok: bool) -> Option<BreakerState> {
+        let mut inner = self.inner();
+        let probe = match probe {
+            Some(round) if round != inner.round => return None,
+            Some(_) => {
+                inner.probes -= 1;
+                true
+            }
+            None => false,
+        };
+
+        match inner.state {
+            BreakerState::HalfOpen if !probe => None,
+            BreakerState::HalfOpen if !ok => self.open(&mut inner),
+            BreakerState::HalfOpen => {
+                inner.successes += 1;
+                if inner.successes < self.config.half_open_requests {
+                    return None;
+                }
+                inner.state = BreakerState::Closed;
+                inner.consecutive_failures = 0;
+                inner.buckets.clear();
+                Some(BreakerState::Closed)
+            }
+            // requests let through before the circuit opened
+            BreakerState::Open => None,
+            BreakerState::Closed => {
+                self.expire(&mut inner);
+                let width = self.config.window / WINDOW_BUCKETS;
+                match inner.buckets.back_mut() {
+                    Some(bucket) if bucket.start.elapsed() < width => {
+                        bucket.requests += 1;
+                        bucket.failures += !ok as usize;
+                    }
+                    _ => inner.buckets.push_back(Bucket {
+                        start: Instant::now(),
+                        requests: 1,
+                        failures: !ok as usize,
+                    }),
+                }
+
+                if ok {
+                    inner.consecutive_failures = 0;
+                    return None;
+                }
+                inner.consecutive_failures += 1;
+                if inner.consecutive_failures >= self.config.consecutive_failures {
+                    return self.open(&mut inner);
+                }
+
+                let requests: usize = inner.buckets.iter().map(|b| b.requests).sum();
+                let failures: usize = inner.buckets.iter().map(|b| b.failures).sum();
+                if requests >= self.config.min_requests
+                    && failures * 100 >= self.config.error_rate_percent * requests
+                {
+                    return self.open(&mut inner);
+                }
+                None
+            }
+        }
+    }
+}
+
+// Permit is a request let through the breaker. Its outcome is recorded with
+// `record`, a permit dropped without outcome, e.g. because the client went
+// away, does not count as a success nor as a failure.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    breaker: &'a CircuitBreaker,
+    // half open round of a probe
+    probe: Option<usize>,
+    done: bool,
+}
+
+impl Permit<'_> {
+    pub fn record(mut self, ok: bool) -> Option<BreakerState> {
+        self.done = true;
+        self.breaker.record(self.probe, ok)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let (Some(round), false) = (self.probe, self.done) {
+            let mut inner = self.breaker.inner();
+            if round == inner.round {
+                inner.probes -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(BreakerConfig {
+            consecutive_failures: 3,
+            error_rate_percent: 50,
+            window: Duration::from_secs(10),
+            min_requests: 6,
+            cooldown: Duration::from_secs(30),
+            half_open_requests: 2,
+        })
+    }
+
+    fn outcome(breaker: &CircuitBreaker, ok: bool) -> Option<BreakerState> {
+        breaker.allow().unwrap().record(ok)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_consecutive_failures() {
+        let breaker = breaker();
+        assert_eq!(outcome(&breaker, false), None);
+        assert_eq!(outcome(&breaker, false), None);
+        assert_eq!(outcome(&breaker, true), None);
+        assert_eq!(outcome(&breaker, false), None);
+        assert_eq!(outcome(&breaker, false), None);
+        assert_eq!(outcome(&breaker, false), Some(BreakerState::Open));
+        assert!(breaker.allow().is_none());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+
+        // only half_open_requests probes go through at once
+        let first = breaker.allow().unwrap();
+        let second = breaker.allow().unwrap();
+        assert!(breaker.allow().is_none());
+        // a probe without outcome gives its slot back
+        drop(second);
+        let second = breaker.allow().unwrap();
+
+        assert_eq!(first.record(true), None);
+        assert_eq!(second.record(true), Some(BreakerState::Closed));
+        assert_eq!(breaker.failures(), (0, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_failed_probe_reopens() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            outcome(&breaker, false);
+        }
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(outcome(&breaker, false), Some(BreakerState::Open));
+        assert!(breaker.allow().is_none());
+
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert_eq!(breaker.state(), BreakerState::Open);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_probe_outlives_round() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            outcome(&breaker, false);
+        }
+        // a probe of each round is still in flight when another one fails
+        let mut slow = Vec::new();
+        for _ in 0..2 {
+            tokio::time::advance(Duration::from_secs(30)).await;
+            slow.push(breaker.allow().unwrap());
+            assert_eq!(outcome(&breaker, false), Some(BreakerState::Open));
+        }
+
+        // they finish in a later round, which does not count them
+        tokio::time::advance(Duration::from_secs(30)).await;
+        let first = breaker.allow().unwrap();
+        assert_eq!(slow.remove(0).record(true), None);
+        drop(slow);
+        let second = breaker.allow().unwrap();
+        assert!(breaker.allow().is_none());
+        assert_eq!(first.record(true), None);
+        assert_eq!(second.record(true), Some(BreakerState::Closed));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_breaker_error_rate() {
+        let breaker = breaker();
+        // failures interleaved with successes never trip the consecutive limit
+        for ok in [false, true, false, true, false] {
+            assert_eq!(outcome(&breaker, ok), None);
+        }
+        assert_eq!(breaker.failures(), (3, 1));
+
+        // the window rolls, old failures are forgotten
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(breaker.failures(), (0, 1));
+        for ok in [true, false, true, false, true] {
+            assert_eq!(outcome(&breaker, ok), None);
+        }
+        // 3 failures out of 6 requests
+        assert_eq!(outcome(&breaker, false), Some(BreakerState::Open));
+    }
+}
@@ -0,0 +1,87 @@
+use std::{net::IpAddr, str::FromStr};
+
+use http::error::frame::FrameError;
+
+// Cidr is a block of addresses, e.g. 10.0.0.0/8 or 2001:db8::/32. An address
+// without prefix length is a block of its own.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // clients connecting over ipv6 with an ipv4 address match ipv4 blocks
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(block) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(block), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(block) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = FrameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr = IpAddr::from_str(addr)?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => match prefix.parse::<u8>() {
+                Ok(n) if n <= max && prefix.bytes().all(|b| b.is_ascii_digit()) => n,
+                _ => {
+                    return Err(FrameError::Invalid {
+                        reason: "prefix length out of range",
+                        subject: "cidr",
+                    })
+                }
+            },
+            None => max,
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[rstest]
+    #[case("10.0.0.0/8", "10.1.2.3", true)]
+    #[case("10.0.0.0/8", "11.0.0.1", false)]
+    #[case("192.168.1.7", "192.168.1.7", true)]
+    #[case("192.168.1.7", "192.168.1.8", false)]
+    #[case("0.0.0.0/0", "203.0.113.9", true)]
+    #[case("10.0.0.0/8", "::ffff:10.0.0.1", true)]
+    #[case("10.0.0.0/8", "::1", false)]
+    #[case("2001:db8::/32", "2001:db8:1::1", true)]
+    #[case("2001:db8::/32", "2001:db9::1", false)]
+    #[case("::/0", "::1", true)]
+    fn test_cidr_contains(#[case] cidr: &str, #[case] ip: &str, #[case] expected: bool) {
+        let cidr = Cidr::from_str(cidr).unwrap();
+        assert_eq!(cidr.contains(IpAddr::from_str(ip).unwrap()), expected);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("10.0.0.0/33")]
+    #[case("10.0.0.0/+8")]
+    #[case("10.0.0/8")]
+    #[case("::1/129")]
+    #[case("localhost")]
+    fn test_cidr_invalid(#[case] input: &str) {
+        assert!(Cidr::from_str(input).is_err());
+    }
+}
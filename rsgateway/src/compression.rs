@@ -0,0 +1,315 @@
+use std::str::FromStr;
+
+use archive::{gzip, zlib};
+use http::{
+    body::Framing,
+    coding::Coding,
+    header::{HeaderKind, HeaderMap},
+    method::Method,
+    response::Response,
+    statuscode::StatusCode,
+};
+
+// smaller responses gain too little to be worth compressing
+pub const MIN_SIZE: usize = 1024;
+// content types compressed by default, those ending with '/' are prefixes
+pub const CONTENT_TYPES: [&str; 5] = [
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+// events are expected as soon as they are sent, an encoder would hold them
+// back until it has a block
+const STREAMED_TYPES: [&str; 1] = ["text/event-stream"];
+
+// adds Accept-Encoding to the vary header of a response whose coding depends
+// on the one the client accepts
+pub fn vary(headers: &mut HeaderMap) {
+    if headers.contains_token("vary", "accept-encoding") || headers.contains_token("vary", "*") {
+        return;
+    }
+    let vary = match headers.raw.get("vary") {
+        Some(vary) if !vary.trim().is_empty() => format!("{}, Accept-Encoding", vary),
+        _ => "Accept-Encoding".to_string(),
+    };
+    headers.raw.insert("vary".to_string(), vary);
+}
+
+// quality the client gives to a coding, 0 when not acceptable
+fn quality(accept_encoding: &str, coding: Coding) -> f32 {
+    let mut any = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+        if name == "*" {
+            any = Some(q);
+        } else if Coding::from_str(name) == Ok(coding) {
+            return q;
+        }
+    }
+    any.unwrap_or(0.0)
+}
+
+// picks the coding the client prefers among the supported ones, gzip when
+// both are as good
+pub fn negotiate(accept_encoding: &str) -> Option<Coding> {
+    let gzip = quality(accept_encoding, Coding::Gzip);
+    let deflate = quality(accept_encoding, Coding::Deflate);
+    if gzip > 0.0 && gzip >= deflate {
+        Some(Coding::Gzip)
+    } else if deflate > 0.0 {
+        Some(Coding::Deflate)
+    } else {
+        None
+    }
+}
+
+// the coding to remove from a response, for clients not accepting it. A
+// client not telling which codings it accepts is sent plain bodies, as it
+// most likely does not expect any.
+pub fn decoding(request: &HeaderMap, headers: &HeaderMap) -> Option<Coding> {
+    let coding = Coding::of(headers)?;
+    match request.raw.get("accept-encoding") {
+        Some(accept_encoding) if quality(accept_encoding, coding) > 0.0 => None,
+        _ => Some(coding),
+    }
+}
+
+// Compression tells which responses of a route are compressed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Compression {
+    // responses with a smaller known length are sent as they are
+    pub min_size: usize,
+    pub content_types: Vec<String>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Self {
+            min_size: MIN_SIZE,
+            content_types: CONTENT_TYPES.iter().map(|t| t.to_string()).collect(),
+        }
+    }
+}
+
+impl Compression {
+    // the coding to apply to a response, if any
+    pub fn coding(
+        &self,
+        request: &HeaderMap,
+        method: &Method,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Coding> {
+        if headers.raw.contains_key("content-encoding")
+            || headers.raw.contains_key("content-range")
+            || headers.contains_token("cache-control", "no-transform")
+            || status == StatusCode::PartialContent
+        {
+            return None;
+        }
+        match Framing::response(method, status, headers).ok()? {
+            Framing::Empty => return None,
+            Framing::Length(n) if n < self.min_size => return None,
+            _ => {}
+        }
+        if !self.compressible(headers.raw.get("content-type")?) {
+            return None;
+        }
+        negotiate(request.raw.get("accept-encoding")?)
+    }
+
+    fn compressible(&self, content_type: &str) -> bool {
+        let media = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if STREAMED_TYPES.contains(&media.as_str()) {
+            return false;
+        }
+        self.content_types.iter().any(|t| match t.ends_with('/') {
+            true => media.starts_with(t.as_str()),
+            false => media == *t,
+        })
+    }
+
+    // compresses a response whose body is in memory
+    pub fn encode(&self, request: &HeaderMap, method: &Method, resp: &mut Response) {
+        let Some(coding) = self.coding(request, method, resp.status, &resp.headers) else {
+            return;
+        };
+        if let Some(body) = resp.body.take() {
+            let body = compress(coding, &body);
+            coding.encode_headers(&mut resp.headers);
+            vary(&mut resp.headers);
+            let _ = resp
+                .headers
+                .put("content-length", HeaderKind::ContentLength(body.len()));
+            resp.body = Some(body);
+        }
+    }
+}
+
+pub fn compress(coding: Coding, body: &[u8]) -> Vec<u8> {
+    let mut encoder = Encoder::new(coding);
+    let mut out = encoder.write(body);
+    out.extend(encoder.finish());
+    out
+}
+
+// Encoder compresses a body relayed one piece at a time.
+#[derive(Debug)]
+pub enum Encoder {
+    Gzip(gzip::Encoder),
+    Deflate(zlib::Encoder),
+}
+
+impl Encoder {
+    pub fn new(coding: Coding) -> Self {
+        match coding {
+            Coding::Gzip => Encoder::Gzip(gzip::Encoder::default()),
+            Coding::Deflate => Encoder::Deflate(zlib::Encoder::new()),
+        }
+    }
+
+    pub fn coding(&self) -> Coding {
+        match self {
+            Encoder::Gzip(_) => Coding::Gzip,
+            Encoder::Deflate(_) => Coding::Deflate,
+        }
+    }
+
+    // returns the encoded bytes available so far
+    pub fn write(&mut self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write(data),
+            Encoder::Deflate(encoder) => encoder.write(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Deflate(encoder) => encoder.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    fn headers(header: &str) -> HeaderMap {
+        let mut headers = HeaderMap::default();
+        for line in header.split("\r\n").filter(|line| !line.is_empty()) {
+            headers.parse(line).unwrap();
+        }
+        headers
+    }
+
+    fn response(header: &str) -> Response {
+        let mut resp = Response::new(StatusCode::Ok);
+        resp.headers = headers(header);
+        resp
+    }
+
+    #[rstest]
+    #[case("gzip", Some(Coding::Gzip))]
+    #[case("deflate", Some(Coding::Deflate))]
+    #[case("deflate, gzip", Some(Coding::Gzip))]
+    #[case("gzip;q=0.5, deflate", Some(Coding::Deflate))]
+    #[case("br, x-gzip;q=0.1", Some(Coding::Gzip))]
+    #[case("*", Some(Coding::Gzip))]
+    #[case("*, gzip;q=0", Some(Coding::Deflate))]
+    #[case("gzip;q=0, deflate;q=0", None)]
+    #[case("identity", None)]
+    #[case("", None)]
+    fn test_negotiate(#[case] accept_encoding: &str, #[case] expected: Option<Coding>) {
+        assert_eq!(negotiate(accept_encoding), expected);
+    }
+
+    #[rstest]
+    #[case("content-type: text/html\r\ncontent-length: 2000", Some(Coding::Gzip))]
+    #[case(
+        "content-type: application/json; charset=utf-8\r\ntransfer-encoding: chunked",
+        Some(Coding::Gzip)
+    )]
+    #[case("content-type: image/png\r\ncontent-length: 2000", None)]
+    #[case("content-type: text/event-stream\r\ntransfer-encoding: chunked", None)]
+    #[case("content-length: 2000", None)]
+    #[case("content-type: text/html\r\ncontent-length: 100", None)]
+    #[case(
+        "content-type: text/html\r\ncontent-length: 2000\r\ncontent-encoding: br",
+        None
+    )]
+    #[case(
+        "content-type: text/html\r\ncontent-length: 2000\r\ncache-control: no-transform",
+        None
+    )]
+    fn test_coding(#[case] header: &str, #[case] expected: Option<Coding>) {
+        let compression = Compression::default();
+        let request = headers("accept-encoding: gzip, deflate");
+        let header = headers(header);
+        let coding = |request: &HeaderMap, method: &Method| {
+            compression.coding(request, method, StatusCode::Ok, &header)
+        };
+        assert_eq!(coding(&request, &Method::GET), expected);
+        assert_eq!(coding(&request, &Method::HEAD), None);
+        assert_eq!(coding(&HeaderMap::default(), &Method::GET), None);
+    }
+
+    #[rstest]
+    #[case("accept-encoding: gzip", "content-encoding: gzip", None)]
+    #[case("accept-encoding: *", "content-encoding: deflate", None)]
+    #[case(
+        "accept-encoding: deflate",
+        "content-encoding: gzip",
+        Some(Coding::Gzip)
+    )]
+    #[case(
+        "accept-encoding: gzip;q=0",
+        "content-encoding: gzip",
+        Some(Coding::Gzip)
+    )]
+    #[case("", "content-encoding: deflate", Some(Coding::Deflate))]
+    #[case("", "content-encoding: br", None)]
+    #[case("", "content-type: text/plain", None)]
+    fn test_decoding(
+        #[case] request: &str,
+        #[case] header: &str,
+        #[case] expected: Option<Coding>,
+    ) {
+        assert_eq!(decoding(&headers(request), &headers(header)), expected);
+    }
+
+    #[test]
+    fn test_encode() {
+        let compression = Compression::default();
+        let request = headers("accept-encoding: deflate");
+        let body = b"compressed ".repeat(200);
+        let mut resp = response("content-type: text/plain\r\netag: \"v1\"\r\nvary: cookie");
+        let _ = resp
+            .headers
+            .put("content-length", HeaderKind::ContentLength(body.len()));
+        resp.body = Some(body.clone());
+
+        compression.encode(&request, &Method::GET, &mut resp);
+        let encoded = resp.body.clone().unwrap();
+        assert_eq!(zlib::decompress(&encoded).unwrap(), body);
+        assert_eq!(resp.headers.raw["content-encoding"], "deflate");
+        assert_eq!(
+            resp.headers.raw["content-length"],
+            encoded.len().to_string()
+        );
+        assert_eq!(resp.headers.raw["vary"], "cookie, Accept-Encoding");
+        assert_eq!(resp.headers.raw["etag"], "W/\"v1\"");
+    }
+}
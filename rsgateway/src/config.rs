@@ -0,0 +1,2133 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, Cursor},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
+
+use http::{
+    method::Method,
+    statuscode::StatusCode,
+    uri::{authority::Authority, url::Url},
+};
+use json::{
+    error::ParserError,
+    parser::{parse, tokenize, Node},
+};
+
+use crate::{
+    accesslog::{AccessLogConfig, Sink},
+    admin::AdminConfig,
+    breaker::BreakerConfig,
+    cache::{CachePolicy, ResponseCache, MAX_ENTRY_SIZE},
+    cidr::Cidr,
+    compression::Compression,
+    error::ConfigError,
+    forwarded::Forwarding,
+    health::{HealthCheck, OutlierDetection},
+    predicate::{Field, Predicates},
+    ratelimit::{Algorithm, LimitKey, RateLimit, Users},
+    retry::{RetryBudget, RetryOn, RetryPolicy},
+    rewrite::{HeaderRules, PathRewrite, Rewrite, Template},
+    route::{MatchType, Route, RouteOptions, Timeouts},
+    store::Store,
+    tracing::TracingConfig,
+    trie::{shape, Segment, Trie, CATCH_ALL},
+    upstream::{Endpoint, HashOn, Pool, Strategy},
+};
+
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+pub const MAX_REQUESTS_PER_CONNECTION: usize = 1000;
+pub const DEFAULT_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Listener {
+    pub address: String,
+    // how long a client connection may stay idle between two requests
+    pub idle_timeout: Duration,
+    // how long a client may take to send a request head once it started
+    pub header_read_timeout: Duration,
+    pub max_requests_per_connection: usize,
+}
+
+impl Listener {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            idle_timeout: IDLE_TIMEOUT,
+            header_read_timeout: HEADER_READ_TIMEOUT,
+            max_requests_per_connection: MAX_REQUESTS_PER_CONNECTION,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RouteConfig {
+    pub host: String,
+    pub path: String,
+    pub route: Route,
+}
+
+impl RouteConfig {
+    // key under which the route is stored in the trie: <host><path>
+    pub fn key(&self) -> String {
+        let mut res = self.host.clone();
+        res.push_str(self.path.trim_end_matches('/'));
+        res
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Config {
+    pub listeners: Vec<Listener>,
+    pub upstreams: Vec<Arc<Pool>>,
+    pub routes: Vec<RouteConfig>,
+    // shared by every route, reloads keep the budget the gateway started with
+    pub retry_budget: Arc<RetryBudget>,
+    // the admin listener is disabled when not set
+    pub admin: Option<AdminConfig>,
+    // address of the redis server holding shared rate limit counters and the
+    // shared tier of the response cache
+    pub redis: Option<String>,
+    // shared by every route, reloads keep the responses cached before them
+    // that fit in the new capacity
+    pub cache: Arc<ResponseCache>,
+    // requests are not logged when not set, reloads keep the log the gateway
+    // started with
+    pub access_log: Option<AccessLogConfig>,
+    // spans are not exported when not set, reloads keep the exporter the
+    // gateway started with
+    pub tracing: Option<TracingConfig>,
+    // reloads keep the forwarding the gateway started with
+    pub forwarding: Forwarding,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let raw = fs::read_to_string(path)?;
+        Config::from_str(&raw)
+    }
+
+    pub fn trie(&self) -> Trie {
+        let mut trie = Trie::new();
+        for route in self.routes.iter() {
+            trie.insert(&route.key(), Some(route.route.clone()));
+        }
+        trie
+    }
+}
+
+impl FromStr for Config {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(Cursor::new(s).lines())?;
+        let mut iter = tokens.iter().peekable();
+
+        let node = match parse(&mut iter)? {
+            Some(node) => node,
+            None => return Err(ConfigError::invalid("$", "config is empty")),
+        };
+
+        if let Some(token) = iter.next() {
+            return Err(ParserError {
+                token: Some(token.clone()),
+                reason: "unexpected token after the end of the config".to_string(),
+            }
+            .into());
+        }
+
+        Config::try_from(&node)
+    }
+}
+
+impl TryFrom<&Node> for Config {
+    type Error = ConfigError;
+
+    fn try_from(node: &Node) -> Result<Self, Self::Error> {
+        let root = Section::new(
+            node,
+            "$".to_string(),
+            &[
+                "listeners",
+                "upstreams",
+                "routes",
+                "retry_budget",
+                "admin",
+                "redis",
+                "cache",
+                "access_log",
+                "tracing",
+                "forwarding",
+            ],
+        )?;
+
+        let mut listeners = Vec::new();
+        for (i, node) in root.required_array("listeners")?.iter().enumerate() {
+            let section = Section::new(
+                node,
+                root.index("listeners", i),
+                &[
+                    "address",
+                    "idle_timeout_ms",
+                    "header_read_timeout_ms",
+                    "max_requests_per_connection",
+                ],
+            )?;
+            let address = section.required_str("address")?;
+            if address.is_empty() {
+                return Err(ConfigError::invalid(
+                    &section.at("address"),
+                    "address should not be empty",
+                ));
+            }
+            let mut listener = Listener::new(address);
+            if let Some(idle_timeout) = section.duration_ms("idle_timeout_ms")? {
+                listener.idle_timeout = idle_timeout;
+            }
+            if let Some(header_read_timeout) = section.duration_ms("header_read_timeout_ms")? {
+                listener.header_read_timeout = header_read_timeout;
+            }
+            if let Some(n) = section.threshold("max_requests_per_connection")? {
+                listener.max_requests_per_connection = n;
+            }
+            listeners.push(listener);
+        }
+
+        if listeners.is_empty() {
+            return Err(ConfigError::invalid(
+                &root.at("listeners"),
+                "at least one listener is required",
+            ));
+        }
+
+        let mut pools = HashMap::new();
+        if let Some(mapping) = root.object("upstreams")? {
+            // sorted so that errors and the order of pools are deterministic
+            let mut names: Vec<&String> = mapping.keys().collect();
+            names.sort();
+            for name in names {
+                let section = Section::new(
+                    &mapping[name],
+                    root.at("upstreams") + "." + name,
+                    &[
+                        "strategy",
+                        "hash_on",
+                        "endpoints",
+                        "health_check",
+                        "outlier_detection",
+                        "circuit_breaker",
+                    ],
+                )?;
+                if name.is_empty() || name.contains("://") {
+                    return Err(ConfigError::invalid(
+                        &section.at,
+                        "upstream name should not be empty or contain '://'",
+                    ));
+                }
+                let pool = Pool::try_from((name.as_str(), &section))?;
+                pools.insert(name.clone(), Arc::new(pool));
+            }
+        }
+
+        let mut routes: Vec<RouteConfig> = Vec::new();
+        for (i, node) in root.array("routes")?.into_iter().flatten().enumerate() {
+            let section = Section::new(
+                node,
+                root.index("routes", i),
+                &[
+                    "host", "path", "match", "upstream", "methods", "headers", "query", "clients",
+                    "priority", "options",
+                ],
+            )?;
+            let mut route = RouteConfig::try_from((&section, &pools))?;
+            // routes sharing a path are named after their position so that
+            // they are limited and reported apart
+            if !route.route.predicates.is_empty() {
+                let name = format!("{}#{}", route.route.name, i);
+                if let Some(limit) = route.route.options.rate_limit.as_mut() {
+                    limit.scope.clone_from(&name);
+                }
+                route.route.name = name;
+            }
+
+            // routes sharing a path are told apart by their predicates
+            let key = shape(&route.key());
+            if routes
+                .iter()
+                .any(|r| shape(&r.key()) == key && r.route.predicates == route.route.predicates)
+            {
+                return Err(ConfigError::invalid(
+                    &root.index("routes", i),
+                    "a route with the same host, path and predicates is already defined",
+                ));
+            }
+            routes.push(route);
+        }
+
+        let mut upstreams: Vec<Arc<Pool>> = pools.into_values().collect();
+        upstreams.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut retry_budget = RetryBudget::default();
+        if let Some(section) = root.section("retry_budget", &["percent", "reserve"])? {
+            let percent = section.usize("percent")?.unwrap_or(retry_budget.percent);
+            if percent > 100 {
+                return Err(ConfigError::invalid(
+                    &section.at("percent"),
+                    "expected a percentage between 0 and 100",
+                ));
+            }
+            let reserve = section.usize("reserve")?.unwrap_or(retry_budget.reserve);
+            retry_budget = RetryBudget::new(percent, reserve);
+        }
+
+        let mut admin = None;
+        if let Some(section) = root.section("admin", &["address", "token"])? {
+            let address = section.required_str("address")?;
+            if address.is_empty() {
+                return Err(ConfigError::invalid(
+                    &section.at("address"),
+                    "address should not be empty",
+                ));
+            }
+            let token = section.required_str("token")?;
+            if token.trim().is_empty() {
+                return Err(ConfigError::invalid(
+                    &section.at("token"),
+                    "token should not be empty",
+                ));
+            }
+            admin = Some(AdminConfig {
+                address: address.to_string(),
+                token: token.to_string(),
+            });
+        }
+
+        let mut redis = None;
+        if let Some(section) = root.section("redis", &["address"])? {
+            let address = section.required_str("address")?;
+            if address.is_empty() {
+                return Err(ConfigError::invalid(
+                    &section.at("address"),
+                    "address should not be empty",
+                ));
+            }
+            redis = Some(address.to_string());
+        }
+        for (i, route) in routes.iter().enumerate() {
+            let options = &route.route.options;
+            let stores = [
+                ("rate_limit", options.rate_limit.as_ref().map(|l| l.store)),
+                ("cache", options.cache.as_ref().map(|c| c.store)),
+            ];
+            for (option, store) in stores {
+                if store == Some(Store::Redis) && redis.is_none() {
+                    return Err(ConfigError::invalid(
+                        &format!("{}.options.{}.store", root.index("routes", i), option),
+                        "the redis store requires a redis server to be configured",
+                    ));
+                }
+            }
+        }
+
+        let mut cache = ResponseCache::default();
+        if let Some(section) = root.section("cache", &["capacity_bytes"])? {
+            if let Some(capacity) = section.threshold("capacity_bytes")? {
+                cache = ResponseCache::new(capacity);
+            }
+        }
+
+        let mut access_log = None;
+        if let Some(section) = root.section(
+            "access_log",
+            &[
+                "sink",
+                "path",
+                "max_bytes",
+                "max_files",
+                "headers",
+                "redact",
+            ],
+        )? {
+            access_log = Some(AccessLogConfig::try_from(&section)?);
+        }
+
+        let mut tracing = None;
+        if let Some(section) = root.section(
+            "tracing",
+            &[
+                "collector",
+                "service_name",
+                "batch_size",
+                "flush_interval_ms",
+            ],
+        )? {
+            tracing = Some(TracingConfig::try_from(&section)?);
+        }
+
+        let mut forwarding = Forwarding::default();
+        if let Some(section) = root.section("forwarding", &["trusted_proxies", "pseudonym"])? {
+            forwarding = Forwarding::try_from(&section)?;
+        }
+
+        Ok(Config {
+            listeners,
+            upstreams,
+            routes,
+            retry_budget: Arc::new(retry_budget),
+            admin,
+            redis,
+            cache: Arc::new(cache),
+            access_log,
+            tracing,
+            forwarding,
+        })
+    }
+}
+
+fn upstream_url(section: &Section, key: &str) -> Result<Url, ConfigError> {
+    match Url::from_str(section.required_str(key)?) {
+        Ok(url) if url.scheme == "http" && url.authority != Authority::Undefined => Ok(url),
+        _ => Err(ConfigError::invalid(
+            &section.at(key),
+            "upstream should be an url of the form http://<host>(:<port>)?/",
+        )),
+    }
+}
+
+impl TryFrom<&Section<'_>> for RetryPolicy {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut policy = RetryPolicy::default();
+        if let Some(n) = section.threshold("attempts")? {
+            policy.attempts = n;
+        }
+        if let Some(nodes) = section.array("on")? {
+            policy.on.clear();
+            for (i, node) in nodes.iter().enumerate() {
+                let on = match node.as_str() {
+                    Some("connect_error") => RetryOn::ConnectError,
+                    Some("reset") => RetryOn::Reset,
+                    _ => {
+                        return Err(ConfigError::invalid(
+                            &section.index("on", i),
+                            "expected one of 'connect_error', 'reset'",
+                        ))
+                    }
+                };
+                policy.on.push(on);
+            }
+        }
+        for (i, node) in section.array("statuses")?.into_iter().flatten().enumerate() {
+            let status = node
+                .as_i64()
+                .and_then(|code| StatusCode::from_str(&code.to_string()).ok());
+            match status {
+                Some(status) => policy.on.push(RetryOn::Status(status)),
+                None => {
+                    return Err(ConfigError::invalid(
+                        &section.index("statuses", i),
+                        "unknown status code",
+                    ))
+                }
+            }
+        }
+        if let Some(d) = section.duration_ms("backoff_ms")? {
+            policy.backoff = d;
+        }
+        if let Some(d) = section.duration_ms("max_backoff_ms")? {
+            policy.max_backoff = d;
+        }
+        if let Some(non_idempotent) = section.bool("non_idempotent")? {
+            policy.non_idempotent = non_idempotent;
+        }
+        Ok(policy)
+    }
+}
+
+impl TryFrom<&Section<'_>> for RateLimit {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let key = match section.str("key")? {
+            None | Some("client_ip") => Some(LimitKey::ClientIp),
+            Some("user") => {
+                let mut users = Users::default();
+                for (user, node) in section.object("users")?.into_iter().flatten() {
+                    match node.as_str() {
+                        Some(password) if !user.is_empty() && !user.contains(':') => {
+                            users.insert(user, password)
+                        }
+                        _ => {
+                            return Err(ConfigError::invalid(
+                                &format!("{}.{}", section.at("users"), user),
+                                "expected a user name without ':' and its password",
+                            ))
+                        }
+                    }
+                }
+                if users == Users::default() {
+                    return Err(ConfigError::invalid(
+                        &section.at("users"),
+                        "users are required to key by user",
+                    ));
+                }
+                Some(LimitKey::User(users))
+            }
+            Some(s) => match s.split_once(':') {
+                Some(("header", name)) if !name.trim().is_empty() => {
+                    Some(LimitKey::Header(name.trim().to_lowercase()))
+                }
+                _ => None,
+            },
+        };
+        let key = match key {
+            Some(key) => key,
+            None => {
+                return Err(ConfigError::invalid(
+                    &section.at("key"),
+                    "key should be one of 'client_ip', 'header:<name>', 'user'",
+                ))
+            }
+        };
+
+        let algorithm = match section.str("algorithm")? {
+            None | Some("token_bucket") => Algorithm::TokenBucket,
+            Some("sliding_window") => Algorithm::SlidingWindow,
+            Some(_) => {
+                return Err(ConfigError::invalid(
+                    &section.at("algorithm"),
+                    "algorithm should be one of 'token_bucket', 'sliding_window'",
+                ))
+            }
+        };
+
+        let store = section.store("store")?;
+
+        section.required("limit")?;
+        let limit = section.threshold("limit")?.expect("checked by required");
+        let window = section
+            .duration_ms("window_ms")?
+            .unwrap_or(DEFAULT_LIMIT_WINDOW);
+        Ok(RateLimit::new(key, algorithm, limit, window).store(store))
+    }
+}
+
+impl TryFrom<&Section<'_>> for Compression {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut compression = Compression::default();
+        if let Some(n) = section.usize("min_size")? {
+            compression.min_size = n;
+        }
+        if let Some(nodes) = section.array("content_types")? {
+            compression.content_types.clear();
+            for (i, node) in nodes.iter().enumerate() {
+                match node.as_str().map(|t| t.trim().to_lowercase()) {
+                    Some(t) if !t.is_empty() => compression.content_types.push(t),
+                    _ => return Err(ConfigError::invalid(
+                        &section.index("content_types", i),
+                        "expected a content type such as 'text/html' or a prefix such as 'text/'",
+                    )),
+                }
+            }
+        }
+        Ok(compression)
+    }
+}
+
+impl TryFrom<&Section<'_>> for AccessLogConfig {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let sink = match section.str("sink")? {
+            None | Some("stdout") => Sink::Stdout,
+            Some("file") => match section.required_str("path")? {
+                "" => {
+                    return Err(ConfigError::invalid(
+                        &section.at("path"),
+                        "path should not be empty",
+                    ))
+                }
+                path => Sink::File(PathBuf::from(path)),
+            },
+            Some(_) => {
+                return Err(ConfigError::invalid(
+                    &section.at("sink"),
+                    "sink should be one of 'stdout', 'file'",
+                ))
+            }
+        };
+        if sink == Sink::Stdout && section.get("path").is_some() {
+            return Err(ConfigError::invalid(
+                &section.at("path"),
+                "path is only used by the 'file' sink",
+            ));
+        }
+        let mut config = AccessLogConfig {
+            sink,
+            ..Default::default()
+        };
+        if let Some(n) = section.threshold("max_bytes")? {
+            config.max_size = n as u64;
+        }
+        if let Some(n) = section.usize("max_files")? {
+            config.max_files = n;
+        }
+        if let Some(headers) = section.bool("headers")? {
+            config.headers = headers;
+        }
+        if let Some(nodes) = section.array("redact")? {
+            config.redact.clear();
+            for (i, node) in nodes.iter().enumerate() {
+                match node.as_str().map(|h| h.trim().to_lowercase()) {
+                    Some(h) if !h.is_empty() => config.redact.push(h),
+                    _ => {
+                        return Err(ConfigError::invalid(
+                            &section.index("redact", i),
+                            "expected a header name",
+                        ))
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+impl TryFrom<&Section<'_>> for TracingConfig {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let collector = match Url::from_str(section.required_str("collector")?) {
+            Ok(url) if url.scheme == "http" && url.authority != Authority::Undefined => url,
+            _ => {
+                return Err(ConfigError::invalid(
+                    &section.at("collector"),
+                    "collector should be an url of the form http://<host>(:<port>)?/<path>",
+                ))
+            }
+        };
+        let mut config = TracingConfig::new(collector);
+        if let Some(name) = section.str("service_name")? {
+            if name.trim().is_empty() {
+                return Err(ConfigError::invalid(
+                    &section.at("service_name"),
+                    "service_name should not be empty",
+                ));
+            }
+            config.service_name = name.to_string();
+        }
+        if let Some(n) = section.threshold("batch_size")? {
+            config.batch_size = n;
+        }
+        if let Some(d) = section.duration_ms("flush_interval_ms")? {
+            config.flush_interval = d;
+        }
+        Ok(config)
+    }
+}
+
+impl TryFrom<&Section<'_>> for Forwarding {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut forwarding = Forwarding::default();
+        for (i, node) in section
+            .array("trusted_proxies")?
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            match node.as_str().map(Cidr::from_str) {
+                Some(Ok(cidr)) => forwarding.trusted.push(cidr),
+                _ => {
+                    return Err(ConfigError::invalid(
+                        &section.index("trusted_proxies", i),
+                        "expected an address or a block such as '10.0.0.0/8'",
+                    ))
+                }
+            }
+        }
+        if let Some(pseudonym) = section.str("pseudonym")? {
+            // the pseudonym is sent as a token of the via header
+            let token = pseudonym
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"-._".contains(&b));
+            if pseudonym.is_empty() || !token {
+                return Err(ConfigError::invalid(
+                    &section.at("pseudonym"),
+                    "pseudonym should only contain letters, digits, '-', '.' or '_'",
+                ));
+            }
+            forwarding.pseudonym = pseudonym.to_string();
+        }
+        Ok(forwarding)
+    }
+}
+
+impl TryFrom<&Section<'_>> for Predicates {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut predicates = Predicates::default();
+        for (i, node) in section.array("methods")?.into_iter().flatten().enumerate() {
+            match node.as_str().map(|m| Method::from_str(&m.to_uppercase())) {
+                Some(Ok(method)) => predicates.methods.push(method),
+                _ => {
+                    return Err(ConfigError::invalid(
+                        &section.index("methods", i),
+                        "expected a method such as 'GET'",
+                    ))
+                }
+            }
+        }
+        let fields = |key| -> Result<Vec<Field>, ConfigError> {
+            let mut res = Vec::new();
+            for (name, node) in section.object(key)?.into_iter().flatten() {
+                let at = format!("{}.{}", section.at(key), name);
+                match (node.as_str(), node.as_bool()) {
+                    (Some(value), _) => res.push(Field::equal(name, value)),
+                    (_, Some(true)) => res.push(Field::present(name)),
+                    _ => {
+                        return Err(ConfigError::invalid(
+                            &at,
+                            "expected the value to equal, or true for any value",
+                        ))
+                    }
+                }
+            }
+            Ok(res)
+        };
+        predicates.headers = fields("headers")?;
+        for field in predicates.headers.iter_mut() {
+            field.name = field.name.to_lowercase();
+        }
+        predicates.query = fields("query")?;
+        // objects are unordered, fields are checked in a stable order
+        for fields in [&mut predicates.headers, &mut predicates.query] {
+            fields.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        for (i, node) in section.array("clients")?.into_iter().flatten().enumerate() {
+            match node.as_str().map(Cidr::from_str) {
+                Some(Ok(cidr)) => predicates.clients.push(cidr),
+                _ => {
+                    return Err(ConfigError::invalid(
+                        &section.index("clients", i),
+                        "expected an address or a block such as '10.0.0.0/8'",
+                    ))
+                }
+            }
+        }
+        Ok(predicates)
+    }
+}
+
+impl TryFrom<&Section<'_>> for Rewrite {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut rewrite = Rewrite::default();
+        let prefix = |key| match section.str(key)? {
+            Some(prefix) if !prefix.starts_with('/') => Err(ConfigError::invalid(
+                &section.at(key),
+                "prefix should start with '/'",
+            )),
+            prefix => Ok(prefix.map(str::to_string)),
+        };
+        let mut paths = Vec::new();
+        if let Some(prefix) = prefix("strip_prefix")? {
+            paths.push(PathRewrite::StripPrefix(prefix));
+        }
+        if let Some(replace) = section.section("replace_prefix", &["prefix", "with"])? {
+            let (prefix, with) = (
+                replace.required_str("prefix")?,
+                replace.required_str("with")?,
+            );
+            for (key, s) in [("prefix", prefix), ("with", with)] {
+                if !s.starts_with('/') {
+                    return Err(ConfigError::invalid(
+                        &replace.at(key),
+                        "prefix should start with '/'",
+                    ));
+                }
+            }
+            paths.push(PathRewrite::ReplacePrefix {
+                prefix: prefix.to_string(),
+                with: with.to_string(),
+            });
+        }
+        if let Some(template) = section.str("path")? {
+            match Template::from_str(template) {
+                Ok(template) => paths.push(PathRewrite::Template(template)),
+                Err(_) => {
+                    return Err(ConfigError::invalid(
+                        &section.at("path"),
+                        "expected a path such as '/v2/{id}/items/{3..}', where {id} is captured by the route path, {n} is the n-th segment of the request path and {n..} the segments from it on",
+                    ))
+                }
+            }
+        }
+        if paths.len() > 1 {
+            return Err(ConfigError::invalid(
+                &section.at,
+                "only one of 'strip_prefix', 'replace_prefix', 'path' may be set",
+            ));
+        }
+        rewrite.path = paths.pop();
+
+        if let Some(host) = section.str("host")? {
+            match Authority::from_str(host) {
+                Ok(_) if !host.is_empty() && !host.contains('/') => {
+                    rewrite.host = Some(host.to_string())
+                }
+                _ => {
+                    return Err(ConfigError::invalid(
+                        &section.at("host"),
+                        "host should be an authority of the form <host>(:<port>)?",
+                    ))
+                }
+            }
+        }
+        for (key, rules) in [
+            ("request_headers", &mut rewrite.request_headers),
+            ("response_headers", &mut rewrite.response_headers),
+        ] {
+            if let Some(headers) = section.section(key, &["add", "set", "remove"])? {
+                *rules = HeaderRules::try_from(&headers)?;
+            }
+        }
+        Ok(rewrite)
+    }
+}
+
+impl TryFrom<&Section<'_>> for HeaderRules {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let name = |at: &str, k: &str| {
+            let token = k
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+            match !k.is_empty() && token {
+                true => Ok(k.to_lowercase()),
+                false => Err(ConfigError::invalid(at, "expected a header name")),
+            }
+        };
+        let headers = |key| -> Result<Vec<(String, String)>, ConfigError> {
+            let mut res = Vec::new();
+            for (k, node) in section.object(key)?.into_iter().flatten() {
+                let at = format!("{}.{}", section.at(key), k);
+                match node.as_str() {
+                    Some(v) if !v.contains(['\r', '\n']) => {
+                        res.push((name(&at, k)?, v.to_string()))
+                    }
+                    _ => {
+                        return Err(ConfigError::invalid(
+                            &at,
+                            "expected a header value on a single line",
+                        ))
+                    }
+                }
+            }
+            // objects are unordered, rules are applied in a stable order
+            res.sort();
+            Ok(res)
+        };
+
+        let mut rules = HeaderRules {
+            add: headers("add")?,
+            set: headers("set")?,
+            remove: Vec::new(),
+        };
+        for (i, node) in section.array("remove")?.into_iter().flatten().enumerate() {
+            let at = section.index("remove", i);
+            match node.as_str() {
+                Some(k) => rules.remove.push(name(&at, k)?),
+                None => return Err(ConfigError::invalid(&at, "expected a header name")),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl TryFrom<&Section<'_>> for Timeouts {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut timeouts = Timeouts::default();
+        if let Some(d) = section.duration_ms("body_read_ms")? {
+            timeouts.body_read = d;
+        }
+        if let Some(d) = section.duration_ms("connect_ms")? {
+            timeouts.connect = d;
+        }
+        if let Some(d) = section.duration_ms("first_byte_ms")? {
+            timeouts.first_byte = d;
+        }
+        timeouts.total = section.duration_ms("total_ms")?;
+        Ok(timeouts)
+    }
+}
+
+impl TryFrom<(&str, &Section<'_>)> for Pool {
+    type Error = ConfigError;
+
+    fn try_from((name, section): (&str, &Section)) -> Result<Self, Self::Error> {
+        let strategy = match section.str("strategy")? {
+            None | Some("round_robin") => Strategy::RoundRobin,
+            Some("weighted_round_robin") => Strategy::WeightedRoundRobin,
+            Some("least_outstanding") => Strategy::LeastOutstanding,
+            Some("random_two_choices") => Strategy::RandomTwoChoices,
+            Some("consistent_hash") => {
+                let on = match section.required_str("hash_on")? {
+                    "client_ip" => Some(HashOn::ClientIp),
+                    s => match s.split_once(':') {
+                        Some(("header", name)) if !name.trim().is_empty() => {
+                            Some(HashOn::Header(name.trim().to_lowercase()))
+                        }
+                        _ => None,
+                    },
+                };
+                match on {
+                    Some(on) => Strategy::ConsistentHash(on),
+                    None => {
+                        return Err(ConfigError::invalid(
+                            &section.at("hash_on"),
+                            "hash_on should be one of 'client_ip', 'header:<name>'",
+                        ))
+                    }
+                }
+            }
+            Some(_) => {
+                return Err(ConfigError::invalid(
+                    &section.at("strategy"),
+                    "strategy should be one of 'round_robin', 'weighted_round_robin', \
+                    'least_outstanding', 'random_two_choices', 'consistent_hash'",
+                ))
+            }
+        };
+
+        if section.get("hash_on").is_some() && !matches!(strategy, Strategy::ConsistentHash(_)) {
+            return Err(ConfigError::invalid(
+                &section.at("hash_on"),
+                "hash_on is only allowed with the 'consistent_hash' strategy",
+            ));
+        }
+
+        let mut endpoints = Vec::new();
+        for (i, node) in section.required_array("endpoints")?.iter().enumerate() {
+            let endpoint = Section::new(node, section.index("endpoints", i), &["url", "weight"])?;
+            let url = upstream_url(&endpoint, "url")?;
+            let weight = endpoint.usize("weight")?.unwrap_or(1);
+            if weight == 0 {
+                return Err(ConfigError::invalid(
+                    &endpoint.at("weight"),
+                    "weight should be greater than 0",
+                ));
+            }
+            endpoints.push(Endpoint::new(url, weight));
+        }
+
+        if endpoints.is_empty() {
+            return Err(ConfigError::invalid(
+                &section.at("endpoints"),
+                "at least one endpoint is required",
+            ));
+        }
+
+        let health_check = match section.section(
+            "health_check",
+            &[
+                "path",
+                "interval_ms",
+                "timeout_ms",
+                "expected_status",
+                "healthy_threshold",
+                "unhealthy_threshold",
+            ],
+        )? {
+            None => None,
+            Some(section) => Some(HealthCheck::try_from(&section)?),
+        };
+
+        let outlier_detection = match section.section(
+            "outlier_detection",
+            &["consecutive_failures", "ejection_time_ms"],
+        )? {
+            None => None,
+            Some(section) => Some(OutlierDetection::try_from(&section)?),
+        };
+
+        let circuit_breaker = match section.section(
+            "circuit_breaker",
+            &[
+                "consecutive_failures",
+                "error_rate_percent",
+                "window_ms",
+                "min_requests",
+                "cooldown_ms",
+                "half_open_requests",
+            ],
+        )? {
+            None => None,
+            Some(section) => Some(BreakerConfig::try_from(&section)?),
+        };
+
+        Ok(Pool::new(name.to_string(), strategy, endpoints)
+            .health_check(health_check)
+            .outlier_detection(outlier_detection)
+            .circuit_breaker(circuit_breaker))
+    }
+}
+
+impl TryFrom<&Section<'_>> for HealthCheck {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut check = HealthCheck::default();
+        if let Some(path) = section.str("path")? {
+            if !path.starts_with('/') || http::uri::path::Path::from_str(path).is_err() {
+                return Err(ConfigError::invalid(
+                    &section.at("path"),
+                    "path should start with '/'",
+                ));
+            }
+            check.path = path.to_string();
+        }
+        if let Some(interval) = section.duration_ms("interval_ms")? {
+            check.interval = interval;
+        }
+        if let Some(timeout) = section.duration_ms("timeout_ms")? {
+            check.timeout = timeout;
+        }
+        if let Some(status) = section.usize("expected_status")? {
+            check.expected_status = match StatusCode::from_str(&status.to_string()) {
+                Ok(status) => status,
+                Err(_) => {
+                    return Err(ConfigError::invalid(
+                        &section.at("expected_status"),
+                        "unknown status code",
+                    ))
+                }
+            };
+        }
+        if let Some(n) = section.threshold("healthy_threshold")? {
+            check.healthy_threshold = n;
+        }
+        if let Some(n) = section.threshold("unhealthy_threshold")? {
+            check.unhealthy_threshold = n;
+        }
+        Ok(check)
+    }
+}
+
+impl TryFrom<&Section<'_>> for OutlierDetection {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut outlier = OutlierDetection::default();
+        if let Some(n) = section.threshold("consecutive_failures")? {
+            outlier.consecutive_failures = n;
+        }
+        if let Some(ejection_time) = section.duration_ms("ejection_time_ms")? {
+            outlier.ejection_time = ejection_time;
+        }
+        Ok(outlier)
+    }
+}
+
+impl TryFrom<&Section<'_>> for BreakerConfig {
+    type Error = ConfigError;
+
+    fn try_from(section: &Section) -> Result<Self, Self::Error> {
+        let mut breaker = BreakerConfig::default();
+        if let Some(n) = section.threshold("consecutive_failures")? {
+            breaker.consecutive_failures = n;
+        }
+        if let Some(percent) = section.threshold("error_rate_percent")? {
+            if percent > 100 {
+                return Err(ConfigError::invalid(
+                    &section.at("error_rate_percent"),
+                    "expected a percentage between 0 and 100",
+                ));
+            }
+            breaker.error_rate_percent = percent;
+        }
+        if let Some(window) = section.duration_ms("window_ms")? {
+            breaker.window = window;
+        }
+        if let Some(n) = section.threshold("min_requests")? {
+            breaker.min_requests = n;
+        }
+        if let Some(cooldown) = section.duration_ms("cooldown_ms")? {
+            breaker.cooldown = cooldown;
+        }
+        if let Some(n) = section.threshold("half_open_requests")? {
+            breaker.half_open_requests = n;
+        }
+        Ok(breaker)
+    }
+}
+
+impl TryFrom<(&Section<'_>, &HashMap<String, Arc<Pool>>)> for RouteConfig {
+    type Error = ConfigError;
+
+    fn try_from(
+        (section, pools): (&Section, &HashMap<String, Arc<Pool>>),
+    ) -> Result<Self, Self::Error> {
+        let host = section.required_str("host")?;
+        if host.is_empty() || host.contains('/') || Authority::from_str(host).is_err() {
+            return Err(ConfigError::invalid(
+                &section.at("host"),
+                "host should be an authority of the form <host>(:<port>)?",
+            ));
+        }
+
+        let path = section.required_str("path")?;
+        if !path.starts_with('/') {
+            return Err(ConfigError::invalid(
+                &section.at("path"),
+                "path should start with '/'",
+            ));
+        }
+
+        // names captured by the path, "{param}" segments and a trailing "**"
+        let mut captures = Vec::new();
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(1).collect();
+        for (i, s) in segments.iter().enumerate() {
+            let reason = match Segment::from(*s) {
+                Segment::Param(name)
+                    if name.starts_with(|c: char| c.is_ascii_digit())
+                        || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') =>
+                {
+                    "param names should only contain letters, digits or '_', and not start with a digit"
+                }
+                Segment::Param(name) if captures.contains(&name) => {
+                    "param names should be unique in a path"
+                }
+                Segment::Param(name) => {
+                    captures.push(name);
+                    continue;
+                }
+                Segment::CatchAll if i + 1 < segments.len() => "'**' should be the last segment",
+                Segment::CatchAll => {
+                    captures.push(CATCH_ALL);
+                    continue;
+                }
+                Segment::Literal(s) if s.contains(['{', '}', '*']) => {
+                    "'{', '}' and '*' should only be used in '{param}', '*' and '**' segments"
+                }
+                _ => continue,
+            };
+            return Err(ConfigError::invalid(&section.at("path"), reason));
+        }
+
+        let match_type = match section.str("match")? {
+            None | Some("prefix") => MatchType::Prefix,
+            Some("exact") => MatchType::Exact,
+            Some(_) => {
+                return Err(ConfigError::invalid(
+                    &section.at("match"),
+                    "match should be one of 'exact', 'prefix'",
+                ))
+            }
+        };
+
+        // upstream is either an url or the name of a pool defined in upstreams
+        let name = section.required_str("upstream")?;
+        let upstream =
+            if name.contains("://") {
+                Arc::new(Pool::single(upstream_url(section, "upstream")?))
+            } else {
+                match pools.get(name) {
+                    Some(pool) => pool.clone(),
+                    None => return Err(ConfigError::invalid(
+                        &section.at("upstream"),
+                        "upstream is neither an url nor the name of a pool defined in upstreams",
+                    )),
+                }
+            };
+
+        let mut options = RouteOptions::default();
+        if let Some(opts) = section.section(
+            "options",
+            &[
+                "preserve_host",
+                "timeouts",
+                "retries",
+                "rate_limit",
+                "cache",
+                "compression",
+                "rewrite",
+            ],
+        )? {
+            if let Some(preserve_host) = opts.bool("preserve_host")? {
+                options.preserve_host = preserve_host;
+            }
+            if let Some(timeouts) = opts.section(
+                "timeouts",
+                &["body_read_ms", "connect_ms", "first_byte_ms", "total_ms"],
+            )? {
+                options.timeouts = Timeouts::try_from(&timeouts)?;
+            }
+            if let Some(retries) = opts.section(
+                "retries",
+                &[
+                    "attempts",
+                    "on",
+                    "statuses",
+                    "backoff_ms",
+                    "max_backoff_ms",
+                    "non_idempotent",
+                ],
+            )? {
+                options.retries = RetryPolicy::try_from(&retries)?;
+            }
+            if let Some(rate_limit) = opts.section(
+                "rate_limit",
+                &["key", "users", "algorithm", "limit", "window_ms", "store"],
+            )? {
+                let mut scope = host.to_string();
+                scope.push_str(path);
+                options.rate_limit = Some(RateLimit::try_from(&rate_limit)?.scope(&scope));
+            }
+            if let Some(cache) =
+                opts.section("cache", &["default_ttl_ms", "max_entry_bytes", "store"])?
+            {
+                options.cache = Some(CachePolicy {
+                    default_ttl: cache.duration_ms("default_ttl_ms")?,
+                    max_entry_size: cache
+                        .threshold("max_entry_bytes")?
+                        .unwrap_or(MAX_ENTRY_SIZE),
+                    store: cache.store("store")?,
+                });
+            }
+            if let Some(compression) =
+                opts.section("compression", &["min_size", "content_types"])?
+            {
+                options.compression = Some(Compression::try_from(&compression)?);
+            }
+            if let Some(rewrite) = opts.section(
+                "rewrite",
+                &[
+                    "strip_prefix",
+                    "replace_prefix",
+                    "path",
+                    "host",
+                    "request_headers",
+                    "response_headers",
+                ],
+            )? {
+                options.rewrite = Rewrite::try_from(&rewrite)?;
+                if let Some(PathRewrite::Template(template)) = &options.rewrite.path {
+                    if template.captures().any(|name| !captures.contains(&name)) {
+                        return Err(ConfigError::invalid(
+                            &rewrite.at("path"),
+                            "path refers to a capture the route path does not define",
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(RouteConfig {
+            host: host.to_string(),
+            path: path.to_string(),
+            route: Route {
+                name: format!("{}{}", host, path),
+                upstream,
+                match_type,
+                predicates: Predicates::try_from(section)?,
+                priority: section.usize("priority")?.unwrap_or_default(),
+                options,
+            },
+        })
+    }
+}
+
+// Section is a json object of the config along with its location, used to
+// report which part of the config failed validation.
+pub struct Section<'a> {
+    pub at: String,
+    mapping: &'a HashMap<String, Node>,
+}
+
+impl<'a> Section<'a> {
+    pub fn new(node: &'a Node, at: String, fields: &[&str]) -> Result<Self, ConfigError> {
+        let mapping = match node.as_object() {
+            Some(mapping) => mapping,
+            None => return Err(ConfigError::invalid(&at, "expected an object")),
+        };
+
+        for k in mapping.keys() {
+            if !fields.contains(&k.as_str()) {
+                let mut at = at.clone();
+                at.push('.');
+                at.push_str(k);
+                return Err(ConfigError::invalid(&at, "unknown field"));
+            }
+        }
+
+        Ok(Self { at, mapping })
+    }
+
+    pub fn at(&self, key: &str) -> String {
+        let mut res = self.at.clone();
+        res.push('.');
+        res.push_str(key);
+        res
+    }
+
+    pub fn index(&self, key: &str, i: usize) -> String {
+        let mut res = self.at(key);
+        res.push('[');
+        res.push_str(&i.to_string());
+        res.push(']');
+        res
+    }
+
+    pub fn get(&self, key: &str) -> Option<&'a Node> {
+        self.mapping.get(key)
+    }
+
+    pub fn required(&self, key: &str) -> Result<&'a Node, ConfigError> {
+        self.get(key)
+            .ok_or_else(|| ConfigError::invalid(&self.at(key), "missing required field"))
+    }
+
+    pub fn str(&self, key: &str) -> Result<Option<&'a str>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => match node.as_str() {
+                Some(s) => Ok(Some(s)),
+                None => Err(ConfigError::invalid(&self.at(key), "expected a string")),
+            },
+        }
+    }
+
+    pub fn store(&self, key: &str) -> Result<Store, ConfigError> {
+        match self.str(key)? {
+            None | Some("local") => Ok(Store::Local),
+            Some("redis") => Ok(Store::Redis),
+            Some(_) => Err(ConfigError::invalid(
+                &self.at(key),
+                "store should be one of 'local', 'redis'",
+            )),
+        }
+    }
+
+    pub fn required_str(&self, key: &str) -> Result<&'a str, ConfigError> {
+        self.required(key)?;
+        Ok(self.str(key)?.unwrap_or_default())
+    }
+
+    pub fn bool(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => match node.as_bool() {
+                Some(b) => Ok(Some(b)),
+                None => Err(ConfigError::invalid(&self.at(key), "expected a boolean")),
+            },
+        }
+    }
+
+    pub fn usize(&self, key: &str) -> Result<Option<usize>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => match node.as_i64() {
+                Some(n) if n >= 0 => Ok(Some(n as usize)),
+                _ => Err(ConfigError::invalid(
+                    &self.at(key),
+                    "expected a positive integer",
+                )),
+            },
+        }
+    }
+
+    pub fn threshold(&self, key: &str) -> Result<Option<usize>, ConfigError> {
+        match self.usize(key)? {
+            Some(0) => Err(ConfigError::invalid(
+                &self.at(key),
+                "expected an integer greater than 0",
+            )),
+            n => Ok(n),
+        }
+    }
+
+    pub fn duration_ms(&self, key: &str) -> Result<Option<Duration>, ConfigError> {
+        match self.threshold(key)? {
+            Some(ms) => Ok(Some(Duration::from_millis(ms as u64))),
+            None => Ok(None),
+        }
+    }
+
+    pub fn object(&self, key: &str) -> Result<Option<&'a HashMap<String, Node>>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => match node.as_object() {
+                Some(mapping) => Ok(Some(mapping)),
+                None => Err(ConfigError::invalid(&self.at(key), "expected an object")),
+            },
+        }
+    }
+
+    pub fn array(&self, key: &str) -> Result<Option<&'a Vec<Node>>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => match node.as_array() {
+                Some(nodes) => Ok(Some(nodes)),
+                None => Err(ConfigError::invalid(&self.at(key), "expected an array")),
+            },
+        }
+    }
+
+    pub fn required_array(&self, key: &str) -> Result<&'a Vec<Node>, ConfigError> {
+        self.required(key)?;
+        Ok(self.array(key)?.expect("checked by required"))
+    }
+
+    pub fn section(&self, key: &str, fields: &[&str]) -> Result<Option<Section<'a>>, ConfigError> {
+        match self.get(key) {
+            None => Ok(None),
+            Some(node) => Ok(Some(Section::new(node, self.at(key), fields)?)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::*;
+
+    #[test]
+    fn test_config_parsing() {
+        let config = Config::from_str(
+            r#"{
+                "redis": {"address": "127.0.0.1:6379"},
+                "cache": {"capacity_bytes": 1048576},
+                "access_log": {
+                    "sink": "file",
+                    "path": "/var/log/rsgateway/access.log",
+                    "max_bytes": 1048576,
+                    "max_files": 3,
+                    "redact": ["Authorization", "x-session"]
+                },
+                "tracing": {
+                    "collector": "http://127.0.0.1:4318/v1/traces",
+                    "batch_size": 64,
+                    "flush_interval_ms": 1000
+                },
+                "forwarding": {"trusted_proxies": ["10.0.0.0/8", "::1"], "pseudonym": "edge"},
+                "listeners": [
+                    {"address": "localhost:9090"},
+                    {
+                        "address": "localhost:9091",
+                        "idle_timeout_ms": 5000,
+                        "header_read_timeout_ms": 2000,
+                        "max_requests_per_connection": 10
+                    }
+                ],
+                "routes": [
+                    {
+                        "host": "localhost:9090",
+                        "path": "/status/",
+                        "match": "exact",
+                        "upstream": "http://httpbin.org:80/",
+                        "options": {
+                            "preserve_host": true,
+                            "timeouts": {"connect_ms": 250, "total_ms": 10000},
+                            "retries": {
+                                "attempts": 3,
+                                "on": ["reset"],
+                                "statuses": [503],
+                                "max_backoff_ms": 100,
+                                "non_idempotent": true
+                            },
+                            "rate_limit": {
+                                "key": "header:X-Api-Key",
+                                "algorithm": "sliding_window",
+                                "limit": 100,
+                                "window_ms": 60000,
+                                "store": "redis"
+                            },
+                            "cache": {"default_ttl_ms": 5000, "store": "redis"},
+                            "compression": {"min_size": 256, "content_types": ["text/", "Application/JSON"]},
+                            "rewrite": {
+                                "path": "/v2/{2..}",
+                                "host": "httpbin.internal",
+                                "request_headers": {
+                                    "add": {"X-Tag": "gateway", "x-env": "prod"},
+                                    "remove": ["Cookie"]
+                                },
+                                "response_headers": {"set": {"server": "rsgateway"}}
+                            }
+                        }
+                    },
+                    {
+                        "host": "localhost:9090",
+                        "path": "/bytes",
+                        "upstream": "http://127.0.0.1:8080/",
+                        "methods": ["get", "HEAD"],
+                        "headers": {"X-Canary": "1", "x-api-key": true},
+                        "query": {"v": "2"},
+                        "clients": ["10.0.0.0/8"],
+                        "priority": 5
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.listeners,
+            vec![
+                Listener::new("localhost:9090"),
+                Listener {
+                    address: "localhost:9091".to_string(),
+                    idle_timeout: Duration::from_secs(5),
+                    header_read_timeout: Duration::from_secs(2),
+                    max_requests_per_connection: 10,
+                }
+            ]
+        );
+        assert_eq!(
+            config.routes,
+            vec![
+                RouteConfig {
+                    host: "localhost:9090".to_string(),
+                    path: "/status/".to_string(),
+                    route: Route {
+                        name: "localhost:9090/status/".to_string(),
+                        upstream: Arc::new(Pool::single(
+                            Url::from_str("http://httpbin.org:80/").unwrap()
+                        )),
+                        match_type: MatchType::Exact,
+                        predicates: Predicates::default(),
+                        priority: 0,
+                        options: RouteOptions {
+                            preserve_host: true,
+                            timeouts: Timeouts {
+                                connect: Duration::from_millis(250),
+                                total: Some(Duration::from_secs(10)),
+                                ..Default::default()
+                            },
+                            retries: RetryPolicy {
+                                attempts: 3,
+                                on: vec![
+                                    RetryOn::Reset,
+                                    RetryOn::Status(StatusCode::ServiceUnavailable)
+                                ],
+                                max_backoff: Duration::from_millis(100),
+                                non_idempotent: true,
+                                ..Default::default()
+                            },
+                            rate_limit: Some(
+                                RateLimit::new(
+                                    LimitKey::Header("x-api-key".to_string()),
+                                    Algorithm::SlidingWindow,
+                                    100,
+                                    Duration::from_secs(60)
+                                )
+                                .store(Store::Redis)
+                                .scope("localhost:9090/status/")
+                            ),
+                            cache: Some(CachePolicy {
+                                default_ttl: Some(Duration::from_secs(5)),
+                                store: Store::Redis,
+                                ..Default::default()
+                            }),
+                            compression: Some(Compression {
+                                min_size: 256,
+                                content_types: vec![
+                                    "text/".to_string(),
+                                    "application/json".to_string()
+                                ],
+                            }),
+                            rewrite: Rewrite {
+                                path: Some(PathRewrite::Template(
+                                    Template::from_str("/v2/{2..}").unwrap()
+                                )),
+                                host: Some("httpbin.internal".to_string()),
+                                request_headers: HeaderRules {
+                                    add: vec![
+                                        ("x-env".to_string(), "prod".to_string()),
+                                        ("x-tag".to_string(), "gateway".to_string()),
+                                    ],
+                                    set: vec![],
+                                    remove: vec!["cookie".to_string()],
+                                },
+                                response_headers: HeaderRules {
+                                    set: vec![("server".to_string(), "rsgateway".to_string())],
+                                    ..Default::default()
+                                },
+                            },
+                        },
+                    },
+                },
+                RouteConfig {
+                    host: "localhost:9090".to_string(),
+                    path: "/bytes".to_string(),
+                    route: Route {
+                        name: "localhost:9090/bytes#1".to_string(),
+                        upstream: Arc::new(Pool::single(
+                            Url::from_str("http://127.0.0.1:8080/").unwrap()
+                        )),
+                        match_type: MatchType::Prefix,
+                        predicates: Predicates {
+                            methods: vec![Method::GET, Method::HEAD],
+                            headers: vec![
+                                Field::present("x-api-key"),
+                                Field::equal("x-canary", "1"),
+                            ],
+                            query: vec![Field::equal("v", "2")],
+                            clients: vec![Cidr::from_str("10.0.0.0/8").unwrap()],
+                        },
+                        priority: 5,
+                        options: RouteOptions::default(),
+                    },
+                },
+            ]
+        );
+
+        assert_eq!(config.redis, Some("127.0.0.1:6379".to_string()));
+        assert_eq!(config.cache.capacity(), 1024 * 1024);
+        assert_eq!(
+            config.access_log,
+            Some(AccessLogConfig {
+                sink: Sink::File(PathBuf::from("/var/log/rsgateway/access.log")),
+                max_size: 1024 * 1024,
+                max_files: 3,
+                headers: true,
+                redact: vec!["authorization".to_string(), "x-session".to_string()],
+            })
+        );
+        assert_eq!(
+            config.tracing,
+            Some(TracingConfig {
+                collector: Url::from_str("http://127.0.0.1:4318/v1/traces").unwrap(),
+                service_name: "rsgateway".to_string(),
+                batch_size: 64,
+                flush_interval: Duration::from_secs(1),
+            })
+        );
+        assert_eq!(
+            config.forwarding,
+            Forwarding {
+                trusted: vec![
+                    Cidr::from_str("10.0.0.0/8").unwrap(),
+                    Cidr::from_str("::1").unwrap()
+                ],
+                pseudonym: "edge".to_string(),
+            }
+        );
+
+        let trie = config.trie();
+        assert_eq!(
+            trie.get("localhost:9090/status"),
+            Some(config.routes[0].route.clone())
+        );
+        assert_eq!(trie.get("localhost:9090/status/200"), None);
+        assert_eq!(
+            trie.get("localhost:9090/bytes/20"),
+            Some(config.routes[1].route.clone())
+        );
+    }
+
+    #[test]
+    fn test_config_upstreams() {
+        let config = Config::from_str(
+            r#"{
+                "listeners": [{"address": "localhost:9090"}],
+                "retry_budget": {"percent": 10},
+                "admin": {"address": "127.0.0.1:9901", "token": "secret"},
+                "upstreams": {
+                    "api": {
+                        "strategy": "weighted_round_robin",
+                        "endpoints": [
+                            {"url": "http://127.0.0.1:8080/", "weight": 3},
+                            {"url": "http://127.0.0.1:8081/"}
+                        ],
+                        "health_check": {
+                            "path": "/healthz",
+                            "interval_ms": 5000,
+                            "expected_status": 204,
+                            "unhealthy_threshold": 2
+                        },
+                        "outlier_detection": {"consecutive_failures": 3},
+                        "circuit_breaker": {"error_rate_percent": 25, "cooldown_ms": 1000}
+                    },
+                    "sessions": {
+                        "strategy": "consistent_hash",
+                        "hash_on": "header:X-Session",
+                        "endpoints": [{"url": "http://127.0.0.1:8082/"}]
+                    }
+                },
+                "routes": [
+                    {"host": "localhost:9090", "path": "/api", "upstream": "api"},
+                    {"host": "localhost:9090", "path": "/v2/api", "upstream": "api"},
+                    {"host": "localhost:9090", "path": "/login", "upstream": "sessions"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.upstreams,
+            vec![
+                Arc::new(
+                    Pool::new(
+                        "api".to_string(),
+                        Strategy::WeightedRoundRobin,
+                        vec![
+                            Endpoint::new(Url::from_str("http://127.0.0.1:8080/").unwrap(), 3),
+                            Endpoint::new(Url::from_str("http://127.0.0.1:8081/").unwrap(), 1),
+                        ]
+                    )
+                    .health_check(Some(HealthCheck {
+                        path: "/healthz".to_string(),
+                        interval: Duration::from_secs(5),
+                        expected_status: StatusCode::NoContent,
+                        unhealthy_threshold: 2,
+                        ..Default::default()
+                    }))
+                    .outlier_detection(Some(OutlierDetection {
+                        consecutive_failures: 3,
+                        ..Default::default()
+                    }))
+                    .circuit_breaker(Some(BreakerConfig {
+                        error_rate_percent: 25,
+                        cooldown: Duration::from_secs(1),
+                        ..Default::default()
+                    }))
+                ),
+                Arc::new(Pool::new(
+                    "sessions".to_string(),
+                    Strategy::ConsistentHash(HashOn::Header("x-session".to_string())),
+                    vec![Endpoint::new(
+                        Url::from_str("http://127.0.0.1:8082/").unwrap(),
+                        1
+                    )]
+                )),
+            ]
+        );
+
+        assert_eq!(*config.retry_budget, RetryBudget::new(10, 10));
+        assert_eq!(
+            config.admin,
+            Some(AdminConfig {
+                address: "127.0.0.1:9901".to_string(),
+                token: "secret".to_string(),
+            })
+        );
+
+        // routes using the same pool share its balancing state
+        assert!(Arc::ptr_eq(
+            &config.routes[0].route.upstream,
+            &config.routes[1].route.upstream
+        ));
+        assert!(Arc::ptr_eq(
+            &config.routes[2].route.upstream,
+            &config.upstreams[1]
+        ));
+    }
+
+    #[test]
+    fn test_config_routes_sharing_path() {
+        let config = Config::from_str(
+            r#"{
+                "redis": {"address": "127.0.0.1:6379"},
+                "listeners": [{"address": "localhost:9090"}],
+                "routes": [
+                    {
+                        "host": "localhost:9090", "path": "/api", "upstream": "http://127.0.0.1:8081/",
+                        "headers": {"x-canary": "1"},
+                        "options": {"rate_limit": {"limit": 10, "store": "redis"}}
+                    },
+                    {
+                        "host": "localhost:9090", "path": "/api", "upstream": "http://127.0.0.1:8080/",
+                        "options": {"rate_limit": {"limit": 10, "store": "redis"}}
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let names: Vec<&str> = config
+            .routes
+            .iter()
+            .map(|r| r.route.name.as_str())
+            .collect();
+        assert_eq!(names, ["localhost:9090/api#0", "localhost:9090/api"]);
+        let scopes: Vec<&str> = config
+            .routes
+            .iter()
+            .filter_map(|r| r.route.options.rate_limit.as_ref())
+            .map(|limit| limit.scope.as_str())
+            .collect();
+        assert_eq!(scopes, names);
+    }
+
+    #[test]
+    fn test_config_load_example() {
+        let config = Config::load("rsgateway.json").unwrap();
+        assert_eq!(config.routes.len(), 3);
+    }
+
+    #[rstest]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}],
+        "routes": [}"#,
+        "syntax error at line 2, column 20: unexpected token"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}]} {}"#,
+        "syntax error at line 1, column 48: unexpected token after the end of the config"
+    )]
+    #[case(r#""#, "invalid config at $: config is empty")]
+    #[case(r#"[]"#, "invalid config at $: expected an object")]
+    #[case(
+        r#"{"listeners": []}"#,
+        "invalid config at $.listeners: at least one listener is required"
+    )]
+    #[case(r#"{"listener": []}"#, "invalid config at $.listener: unknown field")]
+    #[case(
+        r#"{"listeners": [{"address": 9090}]}"#,
+        "invalid config at $.listeners[0].address: expected a string"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: missing required field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "get", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: path should start with '/'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "match": "regex", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].match: match should be one of 'exact', 'prefix'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "ftp://localhost/"}
+        ]}"#,
+        "invalid config at $.routes[0].upstream: upstream should be an url of the form http://<host>(:<port>)?/"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "localhost"}
+        ]}"#,
+        "invalid config at $.routes[0].upstream: upstream is neither an url nor the name of a pool defined in upstreams"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": []}"#,
+        "invalid config at $.upstreams: expected an object"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {"endpoints": []}}}"#,
+        "invalid config at $.upstreams.api.endpoints: at least one endpoint is required"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "strategy": "fastest", "endpoints": [{"url": "http://localhost:80/"}]
+        }}}"#,
+        "invalid config at $.upstreams.api.strategy: strategy should be one of 'round_robin', 'weighted_round_robin', 'least_outstanding', 'random_two_choices', 'consistent_hash'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "strategy": "consistent_hash", "hash_on": "cookie", "endpoints": [{"url": "http://localhost:80/"}]
+        }}}"#,
+        "invalid config at $.upstreams.api.hash_on: hash_on should be one of 'client_ip', 'header:<name>'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "hash_on": "client_ip", "endpoints": [{"url": "http://localhost:80/"}]
+        }}}"#,
+        "invalid config at $.upstreams.api.hash_on: hash_on is only allowed with the 'consistent_hash' strategy"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://localhost:80/", "weight": 0}]
+        }}}"#,
+        "invalid config at $.upstreams.api.endpoints[0].weight: weight should be greater than 0"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://localhost:80/", "weight": -2}]
+        }}}"#,
+        "invalid config at $.upstreams.api.endpoints[0].weight: expected a positive integer"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://localhost:80/"}], "health_check": {"expected_status": 299}
+        }}}"#,
+        "invalid config at $.upstreams.api.health_check.expected_status: unknown status code"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://localhost:80/"}], "health_check": {"interval_ms": 0}
+        }}}"#,
+        "invalid config at $.upstreams.api.health_check.interval_ms: expected an integer greater than 0"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://localhost:80/"}], "outlier_detection": {"failures": 1}
+        }}}"#,
+        "invalid config at $.upstreams.api.outlier_detection.failures: unknown field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"retries": {"on": ["timeout"]}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.retries.on[0]: expected one of 'connect_error', 'reset'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"retries": {"statuses": [502, 599]}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.retries.statuses[1]: unknown status code"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "upstreams": {"api": {
+            "endpoints": [{"url": "http://127.0.0.1:8080/"}], "circuit_breaker": {"error_rate_percent": 120}
+        }}}"#,
+        "invalid config at $.upstreams.api.circuit_breaker.error_rate_percent: expected a percentage between 0 and 100"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rate_limit": {"window_ms": 1000}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rate_limit.limit: missing required field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rate_limit": {"limit": 10, "key": "cookie"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rate_limit.key: key should be one of 'client_ip', 'header:<name>', 'user'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rate_limit": {"limit": 10, "key": "user"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rate_limit.users: users are required to key by user"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rate_limit": {"limit": 10, "key": "user", "users": {"a:b": "c"}}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rate_limit.users.a:b: expected a user name without ':' and its password"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rate_limit": {"limit": 10, "store": "redis"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rate_limit.store: the redis store requires a redis server to be configured"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"cache": {"store": "redis"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.cache.store: the redis store requires a redis server to be configured"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"compression": {"content_types": ["text/", 1]}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.compression.content_types[1]: expected a content type such as 'text/html' or a prefix such as 'text/'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"compression": {"level": 9}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.compression.level: unknown field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rewrite": {"strip_prefix": "/a", "path": "/b"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite: only one of 'strip_prefix', 'replace_prefix', 'path' may be set"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rewrite": {"path": "/v2/{a-b}"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite.path: expected a path such as '/v2/{id}/items/{3..}', where {id} is captured by the route path, {n} is the n-th segment of the request path and {n..} the segments from it on"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/users/{id}", "upstream": "http://localhost:80/", "options": {"rewrite": {"path": "/v2/{name}"}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite.path: path refers to a capture the route path does not define"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/files/**/meta", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: '**' should be the last segment"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/users/{id}/{id}", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: param names should be unique in a path"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/users/{user-id}", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: param names should only contain letters, digits or '_', and not start with a digit"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/users/id-{id}", "upstream": "http://localhost:80/"}
+        ]}"#,
+        "invalid config at $.routes[0].path: '{', '}' and '*' should only be used in '{param}', '*' and '**' segments"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/users/{id}", "upstream": "http://localhost:80/"},
+            {"host": "localhost:9090", "path": "/users/{name}", "upstream": "http://localhost:81/"}
+        ]}"#,
+        "invalid config at $.routes[1]: a route with the same host, path and predicates is already defined"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rewrite": {"replace_prefix": {"prefix": "/a", "with": "b"}}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite.replace_prefix.with: prefix should start with '/'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rewrite": {"request_headers": {"set": {"x a": "1"}}}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite.request_headers.set.x a: expected a header name"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"rewrite": {"response_headers": {"add": {"x-a": "1\r\nx-b: 2"}}}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.rewrite.response_headers.add.x-a: expected a header value on a single line"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "methods": ["GET", "FETCH"]}
+        ]}"#,
+        "invalid config at $.routes[0].methods[1]: expected a method such as 'GET'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "headers": {"x-canary": false}}
+        ]}"#,
+        "invalid config at $.routes[0].headers.x-canary: expected the value to equal, or true for any value"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "clients": ["10.0.0.0/8", "10.0.0.0/33"]}
+        ]}"#,
+        "invalid config at $.routes[0].clients[1]: expected an address or a block such as '10.0.0.0/8'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "priority": -1}
+        ]}"#,
+        "invalid config at $.routes[0].priority: expected a positive integer"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/a", "upstream": "http://localhost:80/", "methods": ["GET"]},
+            {"host": "localhost:9090", "path": "/a", "upstream": "http://localhost:81/", "methods": ["POST"]},
+            {"host": "localhost:9090", "path": "/a", "upstream": "http://localhost:82/", "methods": ["GET"]}
+        ]}"#,
+        "invalid config at $.routes[2]: a route with the same host, path and predicates is already defined"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "tracing": {"collector": "localhost:4318"}}"#,
+        "invalid config at $.tracing.collector: collector should be an url of the form http://<host>(:<port>)?/<path>"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "tracing": {"collector": "http://localhost:4318/v1/traces", "batch_size": 0}}"#,
+        "invalid config at $.tracing.batch_size: expected an integer greater than 0"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "forwarding": {"trusted_proxies": ["10.0.0.0/40"]}}"#,
+        "invalid config at $.forwarding.trusted_proxies[0]: expected an address or a block such as '10.0.0.0/8'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "forwarding": {"pseudonym": "my gateway"}}"#,
+        "invalid config at $.forwarding.pseudonym: pseudonym should only contain letters, digits, '-', '.' or '_'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "access_log": {"sink": "file"}}"#,
+        "invalid config at $.access_log.path: missing required field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "access_log": {"sink": "syslog"}}"#,
+        "invalid config at $.access_log.sink: sink should be one of 'stdout', 'file'"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "access_log": {"path": "access.log"}}"#,
+        "invalid config at $.access_log.path: path is only used by the 'file' sink"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "access_log": {"redact": [""]}}"#,
+        "invalid config at $.access_log.redact[0]: expected a header name"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "admin": {}}"#,
+        "invalid config at $.admin.address: missing required field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "admin": {"address": "localhost:9901"}}"#,
+        "invalid config at $.admin.token: missing required field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "admin": {"address": "localhost:9901", "token": " "}}"#,
+        "invalid config at $.admin.token: token should not be empty"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "retry_budget": {"percent": 150}}"#,
+        "invalid config at $.retry_budget.percent: expected a percentage between 0 and 100"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"timeouts": {"connect_ms": 0}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.timeouts.connect_ms: expected an integer greater than 0"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"timeouts": {"read_ms": 10}}}
+        ]}"#,
+        "invalid config at $.routes[0].options.timeouts.read_ms: unknown field"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/", "upstream": "http://localhost:80/", "options": {"preserve_host": "yes"}}
+        ]}"#,
+        "invalid config at $.routes[0].options.preserve_host: expected a boolean"
+    )]
+    #[case(
+        r#"{"listeners": [{"address": "localhost:9090"}], "routes": [
+            {"host": "localhost:9090", "path": "/a", "upstream": "http://localhost:80/"},
+            {"host": "localhost:9090", "path": "/a/", "upstream": "http://localhost:81/"}
+        ]}"#,
+        "invalid config at $.routes[1]: a route with the same host, path and predicates is already defined"
+    )]
+    fn test_config_parsing_error(#[case] input: &str, #[case] expected: &str) {
+        let err = Config::from_str(input).unwrap_err();
+        assert_eq!(err.to_string(), expected);
+    }
+}
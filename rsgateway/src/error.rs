@@ -0,0 +1,186 @@
+use std::{error::Error, fmt::Display};
+
+use http::{error::frame::FrameError, statuscode::StatusCode};
+use json::error::ParserError;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IOError(std::io::Error),
+    Syntax {
+        line: Option<usize>,
+        column: Option<usize>,
+        reason: String,
+    },
+    Invalid {
+        at: String,
+        reason: String,
+    },
+}
+
+impl ConfigError {
+    pub fn invalid(at: &str, reason: &str) -> Self {
+        Self::Invalid {
+            at: at.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::IOError(e) => write!(f, "unable to read config: {}", e),
+            ConfigError::Syntax {
+                line: Some(line),
+                column: Some(column),
+                reason,
+            } => write!(
+                f,
+                "syntax error at line {}, column {}: {}",
+                line, column, reason
+            ),
+            ConfigError::Syntax { reason, .. } => write!(f, "syntax error: {}", reason),
+            ConfigError::Invalid { at, reason } => {
+                write!(f, "invalid config at {}: {}", at, reason)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(src: std::io::Error) -> Self {
+        Self::IOError(src)
+    }
+}
+
+impl From<ParserError> for ConfigError {
+    fn from(src: ParserError) -> Self {
+        // tokens are 0-indexed, editors are not
+        match src.token {
+            Some(token) => Self::Syntax {
+                line: Some(token.line() + 1),
+                column: Some(token.start() + 1),
+                reason: src.reason,
+            },
+            None => Self::Syntax {
+                line: None,
+                column: None,
+                reason: src.reason,
+            },
+        }
+    }
+}
+
+// Phase is the part of a proxied request a timeout applies to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Phase {
+    HeaderRead,
+    BodyRead,
+    Connect,
+    FirstByte,
+    Total,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let phase = match self {
+            Phase::HeaderRead => "header read",
+            Phase::BodyRead => "body read",
+            Phase::Connect => "upstream connect",
+            Phase::FirstByte => "upstream first byte",
+            Phase::Total => "total",
+        };
+        write!(f, "{}", phase)
+    }
+}
+
+#[derive(Debug)]
+pub enum ProxyError {
+    // the client sent an invalid request or went away
+    Client(FrameError),
+    // no endpoint of the pool is available
+    NoEndpoint,
+    // the circuit breaker of the pool rejects requests
+    CircuitOpen,
+    // the upstream could not be reached
+    Connect(FrameError),
+    // the upstream failed or sent an invalid response
+    Upstream(FrameError),
+    Timeout(Phase),
+}
+
+impl ProxyError {
+    // status answered to the client when the response was not started yet
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::Client(_) => StatusCode::BadRequest,
+            ProxyError::NoEndpoint | ProxyError::CircuitOpen => StatusCode::ServiceUnavailable,
+            ProxyError::Connect(_) | ProxyError::Upstream(_) => StatusCode::BadGateway,
+            ProxyError::Timeout(Phase::HeaderRead | Phase::BodyRead) => StatusCode::RequestTimeout,
+            ProxyError::Timeout(_) => StatusCode::GatewayTimeout,
+        }
+    }
+
+    // whether the error is to be blamed on the upstream endpoint
+    pub fn upstream(&self) -> bool {
+        matches!(
+            self,
+            ProxyError::Connect(_)
+                | ProxyError::Upstream(_)
+                | ProxyError::Timeout(Phase::Connect | Phase::FirstByte)
+        )
+    }
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyError::Client(e) => write!(f, "client error: {:?}", e),
+            ProxyError::NoEndpoint => write!(f, "no endpoint available"),
+            ProxyError::CircuitOpen => write!(f, "circuit breaker open"),
+            ProxyError::Connect(e) => write!(f, "upstream connect error: {:?}", e),
+            ProxyError::Upstream(e) => write!(f, "upstream error: {:?}", e),
+            ProxyError::Timeout(phase) => write!(f, "{} timed out", phase),
+        }
+    }
+}
+
+impl Error for ProxyError {}
+
+// StoreError is a failure of the redis store shared by gateway instances.
+#[derive(Debug)]
+pub enum StoreError {
+    Redis(redis::frame::FrameError),
+    // the store answered with an error or an unexpected reply
+    Reply(String),
+    Timeout,
+    // redis failed recently and is not tried again yet
+    Unavailable,
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StoreError::Redis(e) => write!(f, "redis error: {}", e),
+            StoreError::Reply(reply) => write!(f, "unexpected redis reply: {}", reply),
+            StoreError::Timeout => write!(f, "redis timed out"),
+            StoreError::Unavailable => write!(f, "redis unavailable"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+impl From<redis::frame::FrameError> for StoreError {
+    fn from(src: redis::frame::FrameError) -> Self {
+        Self::Redis(src)
+    }
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(src: std::io::Error) -> Self {
+        Self::Redis(src.into())
+    }
+}
@@ -0,0 +1,213 @@
+use std::net::{IpAddr, SocketAddr};
+
+use http::{header::HeaderMap, standard::Standard};
+
+use crate::{accesslog::REQUEST_ID_HEADER, cidr::Cidr};
+
+pub const PSEUDONYM: &str = "rsgateway";
+// headers only meaningful for a single connection, never forwarded. Trailer
+// is left out as trailers are relayed.
+pub const HOP_BY_HOP_HEADERS: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "upgrade",
+];
+
+// Forwarding tells which clients are trusted to tell about the clients before
+// them, and how the gateway names itself in via headers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Forwarding {
+    // the forwarding headers of other clients are replaced
+    pub trusted: Vec<Cidr>,
+    pub pseudonym: String,
+}
+
+impl Default for Forwarding {
+    fn default() -> Self {
+        Self {
+            trusted: Vec::new(),
+            pseudonym: PSEUDONYM.to_string(),
+        }
+    }
+}
+
+impl Forwarding {
+    pub fn trusts(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    // tells the upstream about the client a request came from: its address,
+    // the host and scheme it asked for, and the id of the request
+    pub fn apply(
+        &self,
+        headers: &mut HeaderMap,
+        client: SocketAddr,
+        standard: &Standard,
+        request_id: &str,
+    ) {
+        let host = headers.raw.get("host").cloned().unwrap_or_default();
+        let ip = client.ip().to_canonical();
+        if !self.trusts(client.ip()) {
+            for k in [
+                "x-forwarded-for",
+                "x-forwarded-proto",
+                "x-forwarded-host",
+                "forwarded",
+            ] {
+                headers.raw.remove(k);
+            }
+        }
+
+        headers.append("x-forwarded-for", &ip.to_string());
+        // listeners only serve plain http
+        headers
+            .raw
+            .entry("x-forwarded-proto".to_string())
+            .or_insert_with(|| "http".to_string());
+        if !host.is_empty() {
+            headers
+                .raw
+                .entry("x-forwarded-host".to_string())
+                .or_insert_with(|| host.clone());
+        }
+
+        let mut element = format!("for={}", node(ip));
+        if !host.is_empty() {
+            element.push_str(";host=");
+            element.push_str(&quoted(&host));
+        }
+        element.push_str(";proto=http");
+        headers.append("forwarded", &element);
+
+        let version = &standard.version;
+        let received = match version.minor {
+            Some(minor) => format!("{}.{} {}", version.major, minor, self.pseudonym),
+            None => format!("{} {}", version.major, self.pseudonym),
+        };
+        headers.append("via", &received);
+
+        headers
+            .raw
+            .insert(REQUEST_ID_HEADER.to_string(), request_id.to_string());
+    }
+}
+
+// removes the headers only meaningful for the connection a message came on,
+// along with the ones its connection header names
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    if let Some(connection) = headers.raw.get("connection").cloned() {
+        for token in connection.split(',') {
+            headers.raw.remove(&token.trim().to_lowercase());
+        }
+    }
+    for k in HOP_BY_HOP_HEADERS {
+        headers.raw.remove(k);
+    }
+}
+
+// node of a forwarded element, ipv6 addresses are bracketed and quoted
+fn node(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("\"[{}]\"", ip),
+    }
+}
+
+// values of forwarded elements which are not tokens are quoted
+fn quoted(v: &str) -> String {
+    let token = v
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    match token {
+        true => v.to_string(),
+        false => format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn headers(raw: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::default();
+        for (k, v) in raw {
+            headers.raw.insert(k.to_string(), v.to_string());
+        }
+        headers
+    }
+
+    const FORWARDED: [(&str, &str); 5] = [
+        ("host", "gateway.test"),
+        ("x-forwarded-for", "203.0.113.7"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "example.com"),
+        ("forwarded", "for=203.0.113.7;proto=https"),
+    ];
+
+    #[test]
+    fn test_forwarding_untrusted() {
+        let mut headers = headers(&FORWARDED);
+        let client = SocketAddr::from_str("192.0.2.1:4000").unwrap();
+        Forwarding::default().apply(&mut headers, client, &Standard::default(), "req-1");
+
+        assert_eq!(headers.raw["x-forwarded-for"], "192.0.2.1");
+        assert_eq!(headers.raw["x-forwarded-proto"], "http");
+        assert_eq!(headers.raw["x-forwarded-host"], "gateway.test");
+        assert_eq!(
+            headers.raw["forwarded"],
+            "for=192.0.2.1;host=gateway.test;proto=http"
+        );
+        assert_eq!(headers.raw["via"], "1.1 rsgateway");
+        assert_eq!(headers.raw["x-request-id"], "req-1");
+    }
+
+    #[test]
+    fn test_forwarding_trusted() {
+        let mut headers = headers(&FORWARDED);
+        headers.raw.insert("via".to_string(), "1.0 cdn".to_string());
+        headers
+            .raw
+            .insert("host".to_string(), "gateway.test:8080".to_string());
+        let forwarding = Forwarding {
+            trusted: vec![Cidr::from_str("2001:db8::/32").unwrap()],
+            pseudonym: "edge".to_string(),
+        };
+        let client = SocketAddr::from_str("[2001:db8::1]:4000").unwrap();
+        forwarding.apply(&mut headers, client, &Standard::default(), "req-1");
+
+        assert_eq!(headers.raw["x-forwarded-for"], "203.0.113.7, 2001:db8::1");
+        assert_eq!(headers.raw["x-forwarded-proto"], "https");
+        assert_eq!(headers.raw["x-forwarded-host"], "example.com");
+        assert_eq!(
+            headers.raw["forwarded"],
+            r#"for=203.0.113.7;proto=https, for="[2001:db8::1]";host="gateway.test:8080";proto=http"#
+        );
+        assert_eq!(headers.raw["via"], "1.0 cdn, 1.1 edge");
+    }
+
+    #[test]
+    fn test_strip_hop_by_hop() {
+        let mut headers = headers(&[
+            ("connection", "keep-alive, X-Custom"),
+            ("keep-alive", "timeout=5"),
+            ("x-custom", "1"),
+            ("te", "trailers"),
+            ("upgrade", "websocket"),
+            ("proxy-authorization", "Basic abc"),
+            ("transfer-encoding", "chunked"),
+            ("accept", "*/*"),
+        ]);
+        strip_hop_by_hop(&mut headers);
+
+        let mut left: Vec<&String> = headers.raw.keys().collect();
+        left.sort();
+        assert_eq!(left, ["accept", "transfer-encoding"]);
+    }
+}
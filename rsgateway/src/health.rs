@@ -0,0 +1,445 @@
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex, Weak},
+    time::Duration,
+};
+
+use tokio::{
+    task::JoinSet,
+    time::{interval, timeout, Instant, MissedTickBehavior},
+};
+
+use http::{
+    builder::Builder, client::Client, method::Method, statuscode::StatusCode, uri::path::Path,
+};
+
+use crate::upstream::{Endpoint, Pool};
+
+// HealthCheck periodically probes every endpoint of a pool, an endpoint is
+// marked unhealthy after `unhealthy_threshold` failed probes in a row and
+// healthy again after `healthy_threshold` successful ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthCheck {
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    pub expected_status: StatusCode,
+    pub healthy_threshold: usize,
+    pub unhealthy_threshold: usize,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            expected_status: StatusCode::Ok,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+// OutlierDetection ejects an endpoint for `ejection_time` once
+// `consecutive_failures` proxied requests in a row failed, either because the
+// endpoint could not be reached or because it answered with a server error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlierDetection {
+    pub consecutive_failures: usize,
+    pub ejection_time: Duration,
+}
+
+impl Default for OutlierDetection {
+    fn default() -> Self {
+        Self {
+            consecutive_failures: 5,
+            ejection_time: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    healthy: bool,
+    successes: usize,
+    failures: usize,
+
+    passive_failures: usize,
+    ejected_until: Option<Instant>,
+
+    drained: bool,
+}
+
+// Health tracks whether an endpoint may receive traffic, as seen by the active
+// probes and by the outcome of proxied requests.
+#[derive(Debug)]
+pub struct Health {
+    state: Mutex<State>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                healthy: true,
+                successes: 0,
+                failures: 0,
+                passive_failures: 0,
+                ejected_until: None,
+                drained: false,
+            }),
+        }
+    }
+}
+
+impl Health {
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("health lock poisoned")
+    }
+
+    pub fn healthy(&self) -> bool {
+        self.state().healthy
+    }
+
+    pub fn ejected(&self) -> bool {
+        let mut state = self.state();
+        match state.ejected_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // ejection is over, the endpoint is let back in
+                state.ejected_until = None;
+                state.passive_failures = 0;
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn available(&self) -> bool {
+        self.healthy() && !self.ejected() && !self.drained()
+    }
+
+    pub fn drained(&self) -> bool {
+        self.state().drained
+    }
+
+    // a drained endpoint is sent no new requests, the ones in flight complete
+    pub fn drain(&self, drained: bool) {
+        self.state().drained = drained;
+    }
+
+    // takes over the state of the health it replaces. The outcome of the
+    // probes is only kept when the endpoint is still probed, nothing would
+    // change it otherwise.
+    pub fn inherit(&self, old: &Health, probed: bool) {
+        let (healthy, successes, failures, passive_failures, ejected_until, drained) = {
+            let old = old.state();
+            (
+                old.healthy,
+                old.successes,
+                old.failures,
+                old.passive_failures,
+                old.ejected_until,
+                old.drained,
+            )
+        };
+        let mut state = self.state();
+        if probed {
+            state.healthy = healthy;
+            state.successes = successes;
+            state.failures = failures;
+        }
+        state.passive_failures = passive_failures;
+        state.ejected_until = ejected_until;
+        state.drained = drained;
+    }
+
+    // records the result of a probe, returns the new health when it changed
+    pub fn probed(&self, ok: bool, check: &HealthCheck) -> Option<bool> {
+        let mut state = self.state();
+        if ok {
+            state.failures = 0;
+            state.successes += 1;
+            if !state.healthy && state.successes >= check.healthy_threshold {
+                state.healthy = true;
+                return Some(true);
+            }
+        } else {
+            state.successes = 0;
+            state.failures += 1;
+            if state.healthy && state.failures >= check.unhealthy_threshold {
+                state.healthy = false;
+                return Some(false);
+            }
+        }
+        None
+    }
+
+    // records the outcome of a proxied request, returns true when it got the
+    // endpoint ejected
+    pub fn observed(&self, ok: bool, outlier: &OutlierDetection) -> bool {
+        let mut state = self.state();
+        if ok {
+            state.passive_failures = 0;
+            return false;
+        }
+
+        state.passive_failures += 1;
+        if state.ejected_until.is_none() && state.passive_failures >= outlier.consecutive_failures {
+            state.ejected_until = Some(Instant::now() + outlier.ejection_time);
+            return true;
+        }
+        false
+    }
+}
+
+pub async fn probe(client: &Client, endpoint: &Endpoint, check: &HealthCheck) -> bool {
+    let path = match Path::from_str(&check.path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let request = Builder::new()
+        .method(Method::GET)
+        .url(endpoint.url.clone())
+        .path(path)
+        .build();
+
+    match timeout(check.timeout, client.perform(request)).await {
+        Ok(Ok(resp)) => resp.status == check.expected_status,
+        _ => false,
+    }
+}
+
+// spawns the active health checks of the pools which have one configured.
+// Checks stop once the pool is dropped, e.g. after a reload replaced it.
+pub fn spawn(client: &Client, pools: &[Arc<Pool>]) {
+    for pool in pools.iter() {
+        if let Some(check) = pool.health_check.clone() {
+            tokio::spawn(run(client.clone(), Arc::downgrade(pool), check));
+        }
+    }
+}
+
+async fn run(client: Client, pool: Weak<Pool>, check: HealthCheck) {
+    let mut ticker = interval(check.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        let pool = match pool.upgrade() {
+            Some(pool) => pool,
+            None => return,
+        };
+
+        let mut set = JoinSet::new();
+        for endpoint in pool.endpoints.iter() {
+            let client = client.clone();
+            let endpoint = endpoint.clone();
+            let check = check.clone();
+            set.spawn(async move {
+                let ok = probe(&client, &endpoint, &check).await;
+                (endpoint, ok)
+            });
+        }
+
+        while let Some(Ok((endpoint, ok))) = set.join_next().await {
+            if let Some(healthy) = endpoint.health.probed(ok, &check) {
+                eprintln!(
+                    "{}: endpoint {} is {}",
+                    pool.name,
+                    String::try_from(endpoint.url.clone()).unwrap_or_default(),
+                    if healthy { "healthy" } else { "unhealthy" }
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use dns::resolver::DNS_IP_LOCAL;
+    use http::{header::HeaderMap, uri::url::Url};
+
+    use super::*;
+    use crate::{
+        testing::FakeUpstream,
+        upstream::{Context, Strategy},
+    };
+
+    fn check() -> HealthCheck {
+        HealthCheck {
+            path: "/health".to_string(),
+            interval: Duration::from_millis(10),
+            timeout: Duration::from_millis(500),
+            expected_status: StatusCode::Ok,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+
+    #[test]
+    fn test_health_thresholds() {
+        let health = Health::default();
+        let check = check();
+
+        assert_eq!(health.probed(false, &check), None);
+        assert_eq!(health.probed(false, &check), None);
+        // a success resets the failure count
+        assert_eq!(health.probed(true, &check), None);
+        assert_eq!(health.probed(false, &check), None);
+        assert_eq!(health.probed(false, &check), None);
+        assert!(health.available());
+        assert_eq!(health.probed(false, &check), Some(false));
+        assert!(!health.available());
+
+        assert_eq!(health.probed(true, &check), None);
+        assert!(!health.available());
+        assert_eq!(health.probed(true, &check), Some(true));
+        assert!(health.available());
+    }
+
+    #[test]
+    fn test_health_inherit() {
+        let old = Health::default();
+        let check = check();
+        for _ in 0..check.unhealthy_threshold {
+            old.probed(false, &check);
+        }
+        old.drain(true);
+
+        let probed = Health::default();
+        probed.inherit(&old, true);
+        assert!(!probed.healthy());
+        assert!(probed.drained());
+
+        // no probe would ever mark it healthy again
+        let unprobed = Health::default();
+        unprobed.inherit(&old, false);
+        assert!(unprobed.healthy());
+        assert!(unprobed.drained());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_outlier_ejection() {
+        let health = Health::default();
+        let outlier = OutlierDetection {
+            consecutive_failures: 2,
+            ejection_time: Duration::from_secs(30),
+        };
+
+        assert!(!health.observed(false, &outlier));
+        assert!(!health.observed(true, &outlier));
+        assert!(!health.observed(false, &outlier));
+        assert!(health.observed(false, &outlier));
+        assert!(!health.available());
+
+        tokio::time::advance(Duration::from_secs(29)).await;
+        assert!(!health.available());
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(health.available());
+
+        // readmitted endpoints start over
+        assert!(!health.observed(false, &outlier));
+        assert!(health.available());
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let upstream = FakeUpstream::start("up").await;
+        let endpoint = Endpoint::new(upstream.url.clone(), 1);
+        let client = Client::new(DNS_IP_LOCAL);
+        assert!(probe(&client, &endpoint, &check()).await);
+
+        upstream.set_status(StatusCode::ServiceUnavailable);
+        assert!(!probe(&client, &endpoint, &check()).await);
+
+        let mut check = check();
+        check.expected_status = StatusCode::ServiceUnavailable;
+        assert!(probe(&client, &endpoint, &check).await);
+
+        let closed = Endpoint::new(Url::from_str(&FakeUpstream::closed()).unwrap(), 1);
+        assert!(!probe(&client, &closed, &check).await);
+    }
+
+    #[tokio::test]
+    async fn test_active_checks_eject_and_readmit() {
+        let up = FakeUpstream::start("0").await;
+        let flaky = FakeUpstream::start("1").await;
+        let pool = Arc::new(
+            Pool::new(
+                "test".to_string(),
+                Strategy::RoundRobin,
+                vec![
+                    Endpoint::new(up.url.clone(), 1),
+                    Endpoint::new(flaky.url.clone(), 1),
+                ],
+            )
+            .health_check(Some(check())),
+        );
+        spawn(&Client::new(DNS_IP_LOCAL), std::slice::from_ref(&pool));
+
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+        let selected = |pool: &Pool| {
+            (0..10)
+                .map(|_| pool.select(&context).unwrap().index)
+                .collect::<Vec<usize>>()
+        };
+
+        flaky.set_status(StatusCode::InternalServerError);
+        while pool.endpoints[1].health.healthy() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(selected(&pool).iter().all(|i| *i == 0));
+
+        flaky.set_status(StatusCode::Ok);
+        while !pool.endpoints[1].health.healthy() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(selected(&pool).contains(&1));
+
+        // every endpoint is down, nothing can be selected
+        up.set_status(StatusCode::InternalServerError);
+        flaky.set_status(StatusCode::InternalServerError);
+        while pool.endpoints.iter().any(|e| e.health.healthy()) {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert!(pool.select(&context).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_passive_ejection() {
+        let up = FakeUpstream::start("0").await;
+        let pool = Pool::new(
+            "test".to_string(),
+            Strategy::RoundRobin,
+            vec![
+                Endpoint::new(up.url.clone(), 1),
+                Endpoint::new(Url::from_str(&FakeUpstream::closed()).unwrap(), 1),
+            ],
+        )
+        .outlier_detection(Some(OutlierDetection {
+            consecutive_failures: 2,
+            ejection_time: Duration::from_secs(60),
+        }));
+
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+        for _ in 0..4 {
+            let lease = pool.select(&context).unwrap();
+            let ok = lease.index == 0;
+            pool.observe(&lease, ok);
+        }
+        assert!(pool.endpoints[1].health.ejected());
+        assert!((0..10).all(|_| pool.select(&context).unwrap().index == 0));
+    }
+}
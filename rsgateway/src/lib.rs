@@ -1,4 +1,25 @@
-#![feature(str_split_remainder)]
+pub mod accesslog;
+pub mod admin;
+pub mod breaker;
+pub mod cache;
+pub mod cidr;
+pub mod compression;
+pub mod config;
+pub mod error;
+pub mod forwarded;
+pub mod health;
+pub mod metrics;
+pub mod predicate;
 pub mod proxy;
+pub mod ratelimit;
+pub mod reload;
+pub mod retry;
+pub mod rewrite;
 pub mod route;
+pub mod router;
+pub mod store;
+#[cfg(test)]
+pub mod testing;
+pub mod tracing;
 pub mod trie;
+pub mod upstream;
@@ -1,7 +1,38 @@
-use rsgateway::proxy::Proxy;
+use std::{env, path::PathBuf, process, sync::Arc};
+
+use rsgateway::{config::Config, proxy::Proxy, reload::Reloader};
+
+const DEFAULT_CONFIG_PATH: &str = "rsgateway.json";
 
 #[tokio::main]
 async fn main() {
-    let proxy = Proxy::new("localhost:9090").await;
+    let path = env::args()
+        .nth(1)
+        .unwrap_or(DEFAULT_CONFIG_PATH.to_string());
+
+    let config = match Config::load(&path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut proxy = match Proxy::from_config(&config).await {
+        Ok(proxy) => proxy,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let reloader = Arc::new(Reloader::new(
+        PathBuf::from(path),
+        proxy.router(),
+        proxy.client(),
+        &config,
+    ));
+    proxy.reloader(reloader.clone());
+    tokio::spawn(reloader.run());
     proxy.run().await;
 }
@@ -0,0 +1,513 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::Duration,
+};
+
+use http::client::Client;
+
+use crate::{accesslog::Entry, upstream::Pool};
+
+// upper bounds of the buckets of durations, in seconds
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+// route label of the requests not matching any route
+pub const UNMATCHED: &str = "unmatched";
+// status label of the requests the gateway could not answer
+pub const UNANSWERED: &str = "none";
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    // counts something in progress until the returned guard is dropped
+    pub fn track(self: &Arc<Self>) -> Tracked {
+        self.inc();
+        Tracked(self.clone())
+    }
+}
+
+pub struct Tracked(Arc<Gauge>);
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+// Histogram counts observations in buckets of values up to a bound.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    // observations of each bucket alone, the last one is unbounded
+    counts: Vec<AtomicU64>,
+    sum: Mutex<f64>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(&DURATION_BUCKETS)
+    }
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Self {
+        Self {
+            bounds: bounds.to_vec(),
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: Mutex::new(0.0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let i = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+        *self.sum.lock().expect("histogram lock poisoned") += value;
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+
+    // cumulative count of each bucket along with its bound, then the sum and
+    // the count of all observations
+    fn snapshot(&self) -> (Vec<(String, u64)>, f64, u64) {
+        let mut buckets = Vec::with_capacity(self.counts.len());
+        let mut total = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            total += count.load(Ordering::Relaxed);
+            let bound = match self.bounds.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            buckets.push((bound, total));
+        }
+        let sum = *self.sum.lock().expect("histogram lock poisoned");
+        (buckets, sum, total)
+    }
+}
+
+// names of the labels of a metric along with their values
+type Labels = Vec<(&'static str, String)>;
+
+// Family is a metric with a value for each combination of its labels.
+#[derive(Debug)]
+pub struct Family<M> {
+    labels: &'static [&'static str],
+    // sorted so that the exposition is stable
+    metrics: RwLock<BTreeMap<Vec<String>, Arc<M>>>,
+}
+
+impl<M: Default> Family<M> {
+    pub fn new(labels: &'static [&'static str]) -> Self {
+        Self {
+            labels,
+            metrics: RwLock::default(),
+        }
+    }
+
+    // the metric of the given label values, in the order of the labels
+    pub fn get(&self, values: &[&str]) -> Arc<M> {
+        let key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        if let Some(metric) = self.metrics.read().expect("family lock poisoned").get(&key) {
+            return metric.clone();
+        }
+        self.metrics
+            .write()
+            .expect("family lock poisoned")
+            .entry(key)
+            .or_default()
+            .clone()
+    }
+
+    fn each(&self) -> Vec<(Labels, Arc<M>)> {
+        self.metrics
+            .read()
+            .expect("family lock poisoned")
+            .iter()
+            .map(|(values, metric)| {
+                let labels = self.labels.iter().copied().zip(values.clone()).collect();
+                (labels, metric.clone())
+            })
+            .collect()
+    }
+}
+
+// Metrics are the measures of the gateway exposed to Prometheus. They live
+// as long as the process, reloads keep counting in the same metrics.
+#[derive(Debug)]
+pub struct Metrics {
+    requests: Family<Counter>,
+    request_duration: Family<Histogram>,
+    upstream_latency: Family<Histogram>,
+    cache: Family<Counter>,
+    rate_limited: Family<Counter>,
+    connections: Arc<Gauge>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            requests: Family::new(&["route", "method", "status"]),
+            request_duration: Family::new(&["route"]),
+            upstream_latency: Family::new(&["upstream"]),
+            cache: Family::new(&["route", "status"]),
+            rate_limited: Family::new(&["route"]),
+            connections: Arc::default(),
+        }
+    }
+}
+
+impl Metrics {
+    // counts a request once it was handled
+    pub fn observe(&self, entry: &Entry) {
+        let route = entry.route.as_deref().unwrap_or(UNMATCHED);
+        let status = entry
+            .status
+            .map_or(UNANSWERED.to_string(), |s| s.code().to_string());
+        self.requests.get(&[route, &entry.method, &status]).inc();
+        self.request_duration
+            .get(&[route])
+            .observe_duration(entry.timings.total);
+        if let Some(cache) = entry.cache {
+            let cache = cache.as_str().to_lowercase();
+            self.cache.get(&[route, &cache]).inc();
+        }
+    }
+
+    // time an upstream took to send a response head
+    pub fn upstream_latency(&self, upstream: &str, latency: Duration) {
+        self.upstream_latency
+            .get(&[upstream])
+            .observe_duration(latency);
+    }
+
+    pub fn rate_limited(&self, route: &str) {
+        self.rate_limited.get(&[route]).inc();
+    }
+
+    // counts a client connection until the guard is dropped
+    pub fn connection(&self) -> Tracked {
+        self.connections.track()
+    }
+
+    // the metrics in the Prometheus text format, along with the state of the
+    // pools in use
+    pub fn render(&self, upstreams: &[Arc<Pool>], client: &Client) -> String {
+        let mut out = Exposition::default();
+
+        out.header(
+            "rsgateway_requests_total",
+            "Requests handled by the gateway.",
+            "counter",
+        );
+        for (labels, counter) in self.requests.each() {
+            out.sample("rsgateway_requests_total", &labels, counter.get());
+        }
+        out.histograms(
+            "rsgateway_request_duration_seconds",
+            "Time from a request head until its response was sent.",
+            &self.request_duration,
+        );
+        out.histograms(
+            "rsgateway_upstream_latency_seconds",
+            "Time from sending a request to an upstream until its response head.",
+            &self.upstream_latency,
+        );
+        out.header(
+            "rsgateway_cache_responses_total",
+            "Responses of routes caching them, by cache status.",
+            "counter",
+        );
+        for (labels, counter) in self.cache.each() {
+            out.sample("rsgateway_cache_responses_total", &labels, counter.get());
+        }
+        out.header(
+            "rsgateway_rate_limited_total",
+            "Requests rejected by a rate limit.",
+            "counter",
+        );
+        for (labels, counter) in self.rate_limited.each() {
+            out.sample("rsgateway_rate_limited_total", &labels, counter.get());
+        }
+        out.header(
+            "rsgateway_active_connections",
+            "Client connections currently open.",
+            "gauge",
+        );
+        out.sample("rsgateway_active_connections", &[], self.connections.get());
+
+        // gauges of the endpoints, sorted by pool then by endpoint
+        let mut endpoints = Vec::new();
+        for pool in upstreams.iter() {
+            for endpoint in pool.endpoints.iter() {
+                let url = String::try_from(endpoint.url.clone()).unwrap_or_default();
+                let labels = vec![("upstream", pool.name.clone()), ("endpoint", url)];
+                endpoints.push((labels, endpoint));
+            }
+        }
+        out.header(
+            "rsgateway_upstream_outstanding_requests",
+            "Requests sent to an endpoint and not answered yet.",
+            "gauge",
+        );
+        for (labels, endpoint) in endpoints.iter() {
+            let name = "rsgateway_upstream_outstanding_requests";
+            out.sample(name, labels, endpoint.outstanding());
+        }
+        out.header(
+            "rsgateway_upstream_idle_connections",
+            "Connections to an endpoint kept open for later requests.",
+            "gauge",
+        );
+        for (labels, endpoint) in endpoints.iter() {
+            let name = "rsgateway_upstream_idle_connections";
+            out.sample(name, labels, client.idle(&endpoint.url.authority));
+        }
+        out.header(
+            "rsgateway_upstream_available",
+            "Whether an endpoint is healthy and not ejected.",
+            "gauge",
+        );
+        for (labels, endpoint) in endpoints.iter() {
+            let name = "rsgateway_upstream_available";
+            out.sample(name, labels, endpoint.health.available() as u8);
+        }
+
+        out.text
+    }
+}
+
+// Exposition writes metrics in the Prometheus text format.
+#[derive(Default)]
+struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        self.text.push_str(&format!("# HELP {} {}\n", name, help));
+        self.text.push_str(&format!("# TYPE {} {}\n", name, kind));
+    }
+
+    fn sample<V: Display>(&mut self, name: &str, labels: &[(&str, String)], value: V) {
+        self.text.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            self.text.push_str(&format!("{{{}}}", labels.join(",")));
+        }
+        self.text.push_str(&format!(" {}\n", value));
+    }
+
+    fn histograms(&mut self, name: &str, help: &str, family: &Family<Histogram>) {
+        self.header(name, help, "histogram");
+        for (labels, histogram) in family.each() {
+            let (buckets, sum, count) = histogram.snapshot();
+            for (bound, n) in buckets {
+                let mut labels = labels.clone();
+                labels.push(("le", bound));
+                self.sample(&format!("{}_bucket", name), &labels, n);
+            }
+            self.sample(&format!("{}_sum", name), &labels, sum);
+            self.sample(&format!("{}_count", name), &labels, count);
+        }
+    }
+}
+
+// label values are quoted, with backslashes, quotes and line feeds escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use dns::resolver::DNS_IP_LOCAL;
+    use http::{builder::Builder, method::Method, statuscode::StatusCode, uri::url::Url};
+
+    use super::*;
+    use crate::cache::CacheStatus;
+
+    fn entry(route: Option<&str>, status: Option<StatusCode>, total_ms: u64) -> Entry {
+        let req = Builder::new().method(Method::GET).build();
+        let mut entry = Entry::new(SocketAddr::from_str("127.0.0.1:4000").unwrap(), &req);
+        entry.route = route.map(String::from);
+        entry.status = status;
+        entry.timings.total = Duration::from_millis(total_ms);
+        entry
+    }
+
+    #[test]
+    fn test_histogram() {
+        let histogram = Histogram::new(&[0.1, 1.0]);
+        for value in [0.05, 0.1, 0.5, 2.0] {
+            histogram.observe(value);
+        }
+        let (buckets, sum, count) = histogram.snapshot();
+        assert_eq!(
+            buckets,
+            vec![
+                ("0.1".to_string(), 2),
+                ("1".to_string(), 3),
+                ("+Inf".to_string(), 4)
+            ]
+        );
+        assert_eq!(sum, 2.65);
+        assert_eq!(count, 4);
+    }
+
+    #[test]
+    fn test_tracked_gauge() {
+        let metrics = Metrics::default();
+        let first = metrics.connection();
+        let second = metrics.connection();
+        assert_eq!(metrics.connections.get(), 2);
+        drop(first);
+        drop(second);
+        assert_eq!(metrics.connections.get(), 0);
+    }
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        let mut hit = entry(Some("example.com:80/a"), Some(StatusCode::Ok), 20);
+        hit.cache = Some(CacheStatus::Hit);
+        metrics.observe(&hit);
+        metrics.observe(&entry(Some("example.com:80/a"), Some(StatusCode::Ok), 300));
+        metrics.observe(&entry(None, Some(StatusCode::NotFound), 1));
+        metrics.observe(&entry(Some("example.com:80/a\"b"), None, 20_000));
+        metrics.upstream_latency("api", Duration::from_millis(250));
+        metrics.rate_limited("example.com:80/a");
+        let _connection = metrics.connection();
+
+        let pool = Pool::single(Url::from_str("http://127.0.0.1:8080/").unwrap());
+        let client = Client::new(DNS_IP_LOCAL);
+        let expected = r#"# HELP rsgateway_requests_total Requests handled by the gateway.
+# TYPE rsgateway_requests_total counter
+rsgateway_requests_total{route="example.com:80/a",method="GET",status="200"} 2
+rsgateway_requests_total{route="example.com:80/a\"b",method="GET",status="none"} 1
+rsgateway_requests_total{route="unmatched",method="GET",status="404"} 1
+# HELP rsgateway_request_duration_seconds Time from a request head until its response was sent.
+# TYPE rsgateway_request_duration_seconds histogram
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.005"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.01"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.025"} 1
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.05"} 1
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.1"} 1
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.25"} 1
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="0.5"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="1"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="2.5"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="5"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="10"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a",le="+Inf"} 2
+rsgateway_request_duration_seconds_sum{route="example.com:80/a"} 0.32
+rsgateway_request_duration_seconds_count{route="example.com:80/a"} 2
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.005"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.01"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.025"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.05"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.1"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.25"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="0.5"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="1"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="2.5"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="5"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="10"} 0
+rsgateway_request_duration_seconds_bucket{route="example.com:80/a\"b",le="+Inf"} 1
+rsgateway_request_duration_seconds_sum{route="example.com:80/a\"b"} 20
+rsgateway_request_duration_seconds_count{route="example.com:80/a\"b"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.005"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.01"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.025"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.05"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.1"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.25"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="0.5"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="1"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="2.5"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="5"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="10"} 1
+rsgateway_request_duration_seconds_bucket{route="unmatched",le="+Inf"} 1
+rsgateway_request_duration_seconds_sum{route="unmatched"} 0.001
+rsgateway_request_duration_seconds_count{route="unmatched"} 1
+# HELP rsgateway_upstream_latency_seconds Time from sending a request to an upstream until its response head.
+# TYPE rsgateway_upstream_latency_seconds histogram
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.005"} 0
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.01"} 0
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.025"} 0
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.05"} 0
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.1"} 0
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.25"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="0.5"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="1"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="2.5"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="5"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="10"} 1
+rsgateway_upstream_latency_seconds_bucket{upstream="api",le="+Inf"} 1
+rsgateway_upstream_latency_seconds_sum{upstream="api"} 0.25
+rsgateway_upstream_latency_seconds_count{upstream="api"} 1
+# HELP rsgateway_cache_responses_total Responses of routes caching them, by cache status.
+# TYPE rsgateway_cache_responses_total counter
+rsgateway_cache_responses_total{route="example.com:80/a",status="hit"} 1
+# HELP rsgateway_rate_limited_total Requests rejected by a rate limit.
+# TYPE rsgateway_rate_limited_total counter
+rsgateway_rate_limited_total{route="example.com:80/a"} 1
+# HELP rsgateway_active_connections Client connections currently open.
+# TYPE rsgateway_active_connections gauge
+rsgateway_active_connections 1
+# HELP rsgateway_upstream_outstanding_requests Requests sent to an endpoint and not answered yet.
+# TYPE rsgateway_upstream_outstanding_requests gauge
+rsgateway_upstream_outstanding_requests{upstream="http://127.0.0.1:8080/",endpoint="http://127.0.0.1:8080/"} 0
+# HELP rsgateway_upstream_idle_connections Connections to an endpoint kept open for later requests.
+# TYPE rsgateway_upstream_idle_connections gauge
+rsgateway_upstream_idle_connections{upstream="http://127.0.0.1:8080/",endpoint="http://127.0.0.1:8080/"} 0
+# HELP rsgateway_upstream_available Whether an endpoint is healthy and not ejected.
+# TYPE rsgateway_upstream_available gauge
+rsgateway_upstream_available{upstream="http://127.0.0.1:8080/",endpoint="http://127.0.0.1:8080/"} 1
+"#;
+        assert_eq!(metrics.render(&[Arc::new(pool)], &client), expected);
+    }
+}
@@ -0,0 +1,140 @@
+use std::net::IpAddr;
+
+use http::{method::Method, request::Request};
+
+use crate::cidr::Cidr;
+
+// Field is a header or query parameter a request should have, with the given
+// value when set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Field {
+    pub fn present(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: None,
+        }
+    }
+
+    pub fn equal(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: Some(value.to_string()),
+        }
+    }
+
+    fn matches<'a>(&self, mut values: impl Iterator<Item = &'a str>) -> bool {
+        match &self.value {
+            Some(expected) => values.any(|v| v == expected),
+            None => values.next().is_some(),
+        }
+    }
+}
+
+// Predicates are what a request should satisfy, besides its host and path,
+// to be sent to a route. Empty predicates accept every request.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Predicates {
+    // any method when empty
+    pub methods: Vec<Method>,
+    pub headers: Vec<Field>,
+    pub query: Vec<Field>,
+    // any client when empty
+    pub clients: Vec<Cidr>,
+}
+
+impl Predicates {
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+            && self.headers.is_empty()
+            && self.query.is_empty()
+            && self.clients.is_empty()
+    }
+
+    pub fn allows(&self, method: &Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method)
+    }
+
+    // whether the request satisfies the predicates other than its method
+    pub fn accepts(&self, req: &Request, client: IpAddr) -> bool {
+        let headers = &req.parts.headers;
+        let query = req.parts.url.path.query.as_ref();
+        (self.clients.is_empty() || self.clients.iter().any(|cidr| cidr.contains(client)))
+            && self.headers.iter().all(|field| {
+                let value = headers.raw.get(&field.name).map(|v| v.trim());
+                field.matches(value.into_iter())
+            })
+            && self.query.iter().all(|field| {
+                let values = query.and_then(|q| q.get(&field.name)).unwrap_or_default();
+                field.matches(values.iter().map(String::as_str))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::{builder::Builder, header::HeaderMap, uri::url::Url};
+
+    use super::*;
+    use rstest::*;
+
+    fn request(method: Method, url: &str, headers: &[(&str, &str)]) -> Request {
+        let mut map = HeaderMap::default();
+        for (k, v) in headers {
+            map.raw.insert(k.to_string(), v.to_string());
+        }
+        Builder::new()
+            .method(method)
+            .url(Url::from_str(url).unwrap())
+            .headers(map)
+            .build()
+    }
+
+    fn predicates() -> Predicates {
+        Predicates {
+            methods: vec![Method::GET, Method::HEAD],
+            headers: vec![Field::equal("x-canary", "1"), Field::present("x-api-key")],
+            query: vec![Field::equal("v", "2"), Field::present("debug")],
+            clients: vec![Cidr::from_str("10.0.0.0/8").unwrap()],
+        }
+    }
+
+    #[rstest]
+    #[case("http://a/?v=2&debug=", &[("x-canary", "1"), ("x-api-key", "k")], "10.1.2.3", true)]
+    #[case("http://a/?v=1&v=2&debug=1", &[("x-canary", " 1 "), ("x-api-key", "")], "10.1.2.3", true)]
+    #[case("http://a/?v=2&debug=", &[("x-canary", "1"), ("x-api-key", "k")], "192.0.2.1", false)]
+    #[case("http://a/?v=2&debug=", &[("x-canary", "0"), ("x-api-key", "k")], "10.1.2.3", false)]
+    #[case("http://a/?v=2&debug=", &[("x-canary", "1")], "10.1.2.3", false)]
+    #[case("http://a/?v=3&debug=", &[("x-canary", "1"), ("x-api-key", "k")], "10.1.2.3", false)]
+    #[case("http://a/?v=2", &[("x-canary", "1"), ("x-api-key", "k")], "10.1.2.3", false)]
+    #[case("http://a/", &[("x-canary", "1"), ("x-api-key", "k")], "10.1.2.3", false)]
+    fn test_predicates_accepts(
+        #[case] url: &str,
+        #[case] headers: &[(&str, &str)],
+        #[case] client: &str,
+        #[case] expected: bool,
+    ) {
+        let req = request(Method::GET, url, headers);
+        let client = IpAddr::from_str(client).unwrap();
+        assert_eq!(predicates().accepts(&req, client), expected);
+    }
+
+    #[test]
+    fn test_predicates_methods() {
+        let predicates = predicates();
+        assert!(predicates.allows(&Method::HEAD));
+        assert!(!predicates.allows(&Method::POST));
+
+        let any = Predicates::default();
+        assert!(any.is_empty());
+        assert!(any.allows(&Method::DELETE));
+        let req = request(Method::POST, "http://a/", &[]);
+        assert!(any.accepts(&req, IpAddr::from_str("::1").unwrap()));
+    }
+}
@@ -1,73 +1,2254 @@
-use std::str::FromStr;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
-use tokio::net::TcpListener;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinSet,
+    time::timeout,
+};
 
 use dns::resolver::DNS_IP_GOOGLE;
-use http::{builder::Builder, client::Client, request::Request, uri::url::Url};
+use http::{
+    body::{BodyReader, BodyWriter, Framing},
+    builder::Builder,
+    client::{Client, Connection},
+    coding::{Coding, Decoder},
+    error::frame::FrameError,
+    header::{HeaderKind, HeaderMap},
+    method::Method,
+    request::Request,
+    response::Response,
+    standard::Standard,
+    statuscode::StatusCode,
+};
 
 use crate::{
-    route::{MatchType, Route},
-    trie::Trie,
+    accesslog::{AccessLog, Entry},
+    admin::Admin,
+    cache::{CacheStatus, Lookup, ResponseCache, Stored, CACHE_STATUS_HEADER},
+    compression::{decoding, vary, Encoder},
+    config::{Config, Listener},
+    error::{ConfigError, Phase, ProxyError},
+    forwarded::{strip_hop_by_hop, Forwarding},
+    health,
+    metrics::Metrics,
+    reload::Reloader,
+    retry::{RetryBudget, MAX_REPLAY_BODY_SIZE},
+    route::{RouteOptions, Timeouts},
+    router::Router,
+    store::{RedisStore, Store},
+    tracing::{Exporter, Trace},
+    trie::{Captures, Match},
+    upstream::{Context, Lease},
 };
 
 pub struct Proxy {
-    listener: TcpListener,
+    listeners: Vec<(TcpListener, Listener)>,
+    admin: Option<Admin>,
+    state: State,
+}
+
+// State is shared by every connection of the gateway.
+#[derive(Clone)]
+struct State {
+    router: Arc<Router>,
+    client: Client,
+    retry_budget: Arc<RetryBudget>,
+    redis: Option<Arc<RedisStore>>,
+    cache: Arc<ResponseCache>,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Arc<Metrics>,
+    exporter: Option<Arc<Exporter>>,
+    forwarding: Arc<Forwarding>,
 }
 
 impl Proxy {
-    pub async fn new(addr: &str) -> Self {
-        Self {
-            listener: TcpListener::bind(addr).await.unwrap(),
-        }
-    }
-
-    pub async fn run(&self) {
-        let mut trie = Trie::new();
-        trie.insert(
-            "localhost:9090/get",
-            Some(Route {
-                url: Url::from_str("http://httpbin.org:80/").unwrap(),
-                match_type: MatchType::Prefix,
-            }),
-        );
-        trie.insert(
-            "localhost:9090/status",
-            Some(Route {
-                url: Url::from_str("http://httpbin.org:80/").unwrap(),
-                match_type: MatchType::Prefix,
-            }),
-        );
-        trie.insert(
-            "localhost:9090/bytes",
-            Some(Route {
-                url: Url::from_str("http://httpbin.org:80/").unwrap(),
-                match_type: MatchType::Prefix,
-            }),
-        );
-        while let Ok((mut inbound, _)) = self.listener.accept().await {
-            let req = Request::parse(&mut inbound).await.unwrap();
-
-            let host = req.parts.url.host().unwrap();
-            match trie.get(&host) {
-                None => println!("request did not match any routes {:?}", host),
-                Some(upstream) => {
-                    tokio::spawn(async move {
-                        let proxied_request = Builder::new()
-                            .method(req.parts.method)
-                            .headers(req.parts.headers)
-                            .url(upstream.url)
-                            .path(req.parts.url.path)
-                            .body(req.body)
-                            .build();
-                        println!("{:?}", proxied_request);
-                        let resp = Client::perform(proxied_request, DNS_IP_GOOGLE)
-                            .await
-                            .unwrap();
-
-                        resp.write(&mut inbound).await.unwrap();
-                    });
+    pub async fn from_config(config: &Config) -> Result<Self, ConfigError> {
+        let mut listeners = Vec::with_capacity(config.listeners.len());
+        for listener in config.listeners.iter() {
+            let socket = TcpListener::bind(&listener.address).await?;
+            listeners.push((socket, listener.clone()));
+        }
+        let client = Client::new(DNS_IP_GOOGLE);
+        health::spawn(&client, &config.upstreams);
+
+        let router = Arc::new(Router::from_config(config));
+        let metrics = Arc::new(Metrics::default());
+        let admin = match &config.admin {
+            Some(admin) => {
+                Some(Admin::bind(admin, router.clone(), client.clone(), metrics.clone()).await?)
+            }
+            None => None,
+        };
+        let access_log = match &config.access_log {
+            Some(access_log) => Some(Arc::new(AccessLog::open(access_log).await?)),
+            None => None,
+        };
+        let exporter = config
+            .tracing
+            .as_ref()
+            .map(|tracing| Arc::new(Exporter::start(tracing, client.clone())));
+
+        Ok(Self {
+            listeners,
+            admin,
+            state: State {
+                router,
+                client,
+                retry_budget: config.retry_budget.clone(),
+                redis: config
+                    .redis
+                    .as_ref()
+                    .map(|address| Arc::new(RedisStore::new(address))),
+                cache: config.cache.clone(),
+                access_log,
+                metrics,
+                exporter,
+                forwarding: Arc::new(config.forwarding.clone()),
+            },
+        })
+    }
+
+    pub fn router(&self) -> Arc<Router> {
+        self.state.router.clone()
+    }
+
+    pub fn client(&self) -> Client {
+        self.state.client.clone()
+    }
+
+    // addresses the listeners are bound to, useful when binding to port 0
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.listeners
+            .iter()
+            .filter_map(|(socket, _)| socket.local_addr().ok())
+            .collect()
+    }
+
+    // lets the admin listener trigger reloads of the config file
+    pub fn reloader(&mut self, reloader: Arc<Reloader>) {
+        if let Some(admin) = self.admin.as_mut() {
+            admin.reloader(reloader);
+        }
+    }
+
+    pub fn admin_addr(&self) -> Option<SocketAddr> {
+        self.admin.as_ref().and_then(|admin| admin.local_addr())
+    }
+
+    pub async fn run(self) {
+        let mut set = JoinSet::new();
+        if let Some(admin) = self.admin {
+            set.spawn(admin.run());
+        }
+        for (socket, listener) in self.listeners {
+            set.spawn(serve(socket, listener, self.state.clone()));
+        }
+        while set.join_next().await.is_some() {}
+    }
+}
+
+async fn serve(socket: TcpListener, listener: Listener, state: State) {
+    while let Ok((inbound, client)) = socket.accept().await {
+        let listener = listener.clone();
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            let _connection = state.metrics.connection();
+            // requests are served one after the other, pipelined requests wait
+            // in the buffer so that their responses are written in order
+            let mut buffer = BufReader::new(inbound);
+            let mut served = 0;
+
+            loop {
+                match timeout(listener.idle_timeout, buffer.fill_buf()).await {
+                    Ok(Ok(buf)) if !buf.is_empty() => {}
+                    // idle for too long, closed by the client or broken
+                    _ => return,
+                }
+
+                let head = timeout(
+                    listener.header_read_timeout,
+                    Request::read_head(&mut buffer),
+                );
+                let req = match head.await {
+                    Ok(Ok(req)) => req,
+                    Err(_) => {
+                        let resp = Response::new(ProxyError::Timeout(Phase::HeaderRead).status());
+                        let _ = respond(resp, false, buffer.get_mut()).await;
+                        return;
+                    }
+                    Ok(Err(_)) => {
+                        let resp = Response::new(StatusCode::BadRequest);
+                        let _ = respond(resp, false, buffer.get_mut()).await;
+                        return;
+                    }
+                };
+                // the body is not read yet, it is relayed while being received
+                let framing = match Framing::request(&req.parts.method, &req.parts.headers) {
+                    Ok(framing) => framing,
+                    Err(_) => {
+                        let resp = Response::new(StatusCode::BadRequest);
+                        let _ = respond(resp, false, buffer.get_mut()).await;
+                        return;
+                    }
+                };
+
+                served += 1;
+                let keep_alive = req.parts.headers.keep_alive(&req.parts.standard)
+                    && served < listener.max_requests_per_connection;
+
+                let mut entry = Entry::new(client, &req);
+                if state.exporter.is_some() {
+                    entry.trace = Some(Trace::start(&req.parts.headers));
+                }
+                let res = handle(&state, req, framing, keep_alive, &mut buffer, &mut entry).await;
+                entry.finish();
+                state.metrics.observe(&entry);
+                if let Some(access_log) = &state.access_log {
+                    access_log.log(&entry);
+                }
+                if let (Some(exporter), Some(trace)) = (&state.exporter, entry.trace.take()) {
+                    exporter.export(trace, &entry);
+                }
+                match res {
+                    Ok(true) => {}
+                    _ => return,
+                }
+            }
+        });
+    }
+}
+
+// writes a response to the client, telling it whether the connection is kept
+async fn respond(
+    mut resp: Response,
+    keep_alive: bool,
+    inbound: &mut TcpStream,
+) -> Result<(), FrameError> {
+    resp.headers.raw.insert(
+        "connection".to_string(),
+        if keep_alive { "keep-alive" } else { "close" }.to_string(),
+    );
+    resp.write(inbound).await
+}
+
+// handles a request whose body is still to be read from the inbound buffer,
+// returns whether the client connection can serve further requests. What
+// happened to the request is recorded in its access log entry.
+async fn handle(
+    state: &State,
+    mut req: Request,
+    framing: Framing,
+    keep_alive: bool,
+    inbound: &mut BufReader<TcpStream>,
+    entry: &mut Entry,
+) -> Result<bool, FrameError> {
+    let client = entry.client;
+    // requests are routed against the table in use when they were received,
+    // a concurrent reload only affects later requests
+    let trie = state.router.load();
+    let host = match req.parts.url.host() {
+        Ok(host) => host,
+        Err(_) => {
+            let keep_alive = keep_alive && framing == Framing::Empty;
+            let resp = Response::new(StatusCode::BadRequest);
+            entry.responded(&resp);
+            respond(resp, keep_alive, inbound.get_mut()).await?;
+            return Ok(keep_alive);
+        }
+    };
+    // methods of the routes matching the request but for its method
+    let mut allowed: Vec<Method> = Vec::new();
+    let found = trie.find(&host, |route| {
+        let predicates = &route.predicates;
+        if !predicates.accepts(&req, client.ip()) {
+            return false;
+        }
+        if !predicates.allows(&req.parts.method) {
+            for method in predicates.methods.iter() {
+                if !allowed.contains(method) {
+                    allowed.push(method.clone());
                 }
             }
+            return false;
+        }
+        true
+    });
+    let Match { route, captures } = match found {
+        Some(found) => found,
+        None => {
+            // a request body left in the buffer hides the next request
+            let keep_alive = keep_alive && framing == Framing::Empty;
+            let mut resp = Response::new(StatusCode::NotFound);
+            if !allowed.is_empty() {
+                resp = Response::new(StatusCode::MethodNotAllowed);
+                resp.headers
+                    .put("allow", HeaderKind::Allow(Some(allowed)))?;
+            }
+            entry.responded(&resp);
+            respond(resp, keep_alive, inbound.get_mut()).await?;
+            return Ok(keep_alive);
         }
+    };
+    entry.route = Some(route.name.clone());
+    entry.captures = captures.clone();
+
+    let options = &route.options;
+    let mut decision = None;
+    if let Some(limit) = &options.rate_limit {
+        let key = limit.key(client.ip(), &req.parts.headers);
+        decision = limit.check(&key, state.redis.as_deref()).await;
+        if let Some(decision) = decision.as_ref().filter(|d| !d.allowed) {
+            let keep_alive = keep_alive && framing == Framing::Empty;
+            let mut resp = Response::new(StatusCode::TooManyRequests);
+            decision.headers(&mut resp.headers);
+            entry.responded(&resp);
+            state.metrics.rate_limited(&route.name);
+            respond(resp, keep_alive, inbound.get_mut()).await?;
+            return Ok(keep_alive);
+        }
+    }
+
+    // fresh responses are answered from the cache, stale ones are revalidated
+    // with the upstream when they have an entity tag. Only the first request
+    // missing a key goes to the upstream, the others wait for its response.
+    let cached = options
+        .cache
+        .as_ref()
+        .zip(ResponseCache::key(&route.name, &req));
+    let headers = req.parts.headers.clone();
+    let method = req.parts.method.clone();
+    let shared = match &cached {
+        Some((policy, _)) if policy.store == Store::Redis => state.redis.clone(),
+        _ => None,
+    };
+    let mut stale = None;
+    let mut _flight = None;
+    if let Some((_, key)) = &cached {
+        let mut waited = false;
+        loop {
+            let mut lookup = state.cache.lookup(key, &headers);
+            if let (false, Some(redis)) = (matches!(lookup, Lookup::Fresh(_)), &shared) {
+                match state.cache.lookup_shared(redis, key, &headers).await {
+                    Lookup::Miss => {}
+                    found => lookup = found,
+                }
+            }
+            match lookup {
+                Lookup::Fresh(stored) => {
+                    let keep_alive = keep_alive && framing == Framing::Empty;
+                    let mut resp = stored.response(CacheStatus::Hit, &headers);
+                    compress(options, &headers, &method, &mut resp);
+                    if let Some(decision) = &decision {
+                        decision.headers(&mut resp.headers);
+                    }
+                    options.rewrite.response(&mut resp.headers);
+                    entry.responded(&resp);
+                    entry.cache = Some(CacheStatus::Hit);
+                    respond(resp, keep_alive, inbound.get_mut()).await?;
+                    return Ok(keep_alive);
+                }
+                Lookup::Stale(stored) => stale = Some(stored),
+                Lookup::Miss => stale = None,
+            }
+
+            // the response of the flight waited for was not stored, the
+            // request goes to the upstream on its own
+            if waited {
+                break;
+            }
+            match state.cache.join(key) {
+                Ok(flight) => {
+                    _flight = Some(flight);
+                    break;
+                }
+                Err(mut done) => {
+                    let _ = done.changed().await;
+                    waited = true;
+                }
+            }
+        }
+        if let Some(etag) = stale.as_ref().and_then(|stale| stale.etag()) {
+            req.parts
+                .headers
+                .raw
+                .insert("if-none-match".to_string(), etag.clone());
+        }
+    }
+
+    // the client connection headers only apply to the client connection
+    strip_hop_by_hop(&mut req.parts.headers);
+    let standard = req.parts.standard.clone();
+    state
+        .forwarding
+        .apply(&mut req.parts.headers, client, &standard, &entry.id);
+    state.retry_budget.deposit();
+
+    let mut exchange = Exchange {
+        upstream: &state.client,
+        framing,
+        timeouts: &options.timeouts,
+        inbound,
+        replay: None,
+        resend: req.parts.method.idempotent() || options.retries.allows(&req.parts.method),
+        responded: false,
+        entry,
+    };
+    let res = within(options.timeouts.total, Phase::Total, async {
+        // a request is only retried when its body can be sent again
+        let mut attempts = 1;
+        if options.retries.allows(&req.parts.method) && exchange.buffer().await? {
+            attempts = options.retries.attempts;
+        }
+
+        let context = Context {
+            client: Some(client.ip()),
+            headers: &req.parts.headers,
+        };
+        let mut tried = Vec::new();
+        let mut attempt = 1;
+        loop {
+            // an open circuit fails the request without trying an endpoint
+            let permit = route.upstream.admit()?;
+            // the lease is held until the response has been relayed
+            let lease = match route.upstream.select_other(&context, &tried) {
+                Some(lease) => lease,
+                None => return Err(ProxyError::NoEndpoint),
+            };
+            tried.push(lease.index);
+            exchange.entry.upstream = String::try_from(lease.endpoint.url.clone()).ok();
+            let mut request = proxied(&req, &lease, options, &captures);
+            if let Some(trace) = &mut exchange.entry.trace {
+                trace.attempt(&mut request.parts.headers);
+            }
+
+            let retry = attempt < attempts;
+            let failure = match exchange.forward(&request).await {
+                Ok((mut conn, mut resp)) => {
+                    if let Some(latency) = exchange.entry.timings.first_byte {
+                        state
+                            .metrics
+                            .upstream_latency(&route.upstream.name, latency);
+                    }
+                    route.upstream.observe(&lease, resp.status.code() < 500);
+                    route.upstream.record(permit, resp.status.code() < 500);
+                    if !(retry
+                        && options.retries.retries_status(resp.status)
+                        && state.retry_budget.withdraw())
+                    {
+                        if let (Some((policy, key)), Some(stale)) = (&cached, &stale) {
+                            let not_modified = resp.status == StatusCode::NotModified;
+                            let served = if not_modified {
+                                let stored =
+                                    state.cache.refresh(key, &headers, stale, &resp, policy);
+                                share(&shared, key, &stored, true);
+                                Some(stored.response(CacheStatus::Stale, &headers))
+                            } else if resp.status.code() >= 500 {
+                                // the stored response is better than an error
+                                Some(stale.response(CacheStatus::Stale, &headers))
+                            } else {
+                                None
+                            };
+                            if let Some(mut served) = served {
+                                compress(options, &headers, &method, &mut served);
+                                // an error response is left unread, its
+                                // connection is closed
+                                if not_modified {
+                                    conn.release(&request.parts, &resp);
+                                }
+                                if let Some(decision) = &decision {
+                                    decision.headers(&mut served.headers);
+                                }
+                                options.rewrite.response(&mut served.headers);
+                                exchange.entry.cache = Some(CacheStatus::Stale);
+                                return exchange.serve(served, keep_alive).await;
+                            }
+                        }
+
+                        // the response is stored as received from the upstream
+                        let mut store = None;
+                        if let Some((policy, key)) = &cached {
+                            if let Some(lifetime) = ResponseCache::storable(&resp, policy) {
+                                store = Some((key, policy.max_entry_size, lifetime));
+                            }
+                        }
+                        let upstream_headers = store.map(|_| resp.headers.clone());
+                        if cached.is_some() {
+                            exchange.entry.cache = Some(CacheStatus::Miss);
+                            resp.headers.raw.insert(
+                                CACHE_STATUS_HEADER.to_string(),
+                                CacheStatus::Miss.as_str().to_string(),
+                            );
+                        }
+                        if let Some(decision) = &decision {
+                            decision.headers(&mut resp.headers);
+                        }
+                        options.rewrite.response(&mut resp.headers);
+
+                        let keep = store.map(|(_, max, _)| max);
+                        // a body the client cannot decode is decoded, then
+                        // possibly encoded again with a coding it accepts
+                        let decoded = decoding(&headers, &resp.headers);
+                        let mut plain = resp.headers.clone();
+                        if let Some(coding) = decoded {
+                            coding.decode_headers(&mut plain);
+                        }
+                        let coding = options
+                            .compression
+                            .as_ref()
+                            .and_then(|c| c.coding(&headers, &method, resp.status, &plain));
+                        let body = exchange
+                            .relay(
+                                &request,
+                                &mut conn,
+                                &resp,
+                                &req.parts.standard,
+                                keep_alive,
+                                Transit::new(keep, decoded, coding),
+                            )
+                            .await?;
+                        conn.release(&request.parts, &resp);
+                        if let (Some((key, _, lifetime)), Some(body), Some(upstream)) =
+                            (store, body, upstream_headers)
+                        {
+                            let stored = state.cache.store(
+                                key,
+                                &headers,
+                                resp.status,
+                                &upstream,
+                                body,
+                                lifetime,
+                            );
+                            share(&shared, key, &stored, false);
+                        }
+                        return Ok(keep_alive);
+                    }
+                    format!("status {}", resp.status.code())
+                }
+                Err(e) => {
+                    // other failures do not tell about the upstream, the
+                    // permit is dropped without outcome
+                    if e.upstream() {
+                        route.upstream.observe(&lease, false);
+                        route.upstream.record(permit, false);
+                    }
+                    if !(retry
+                        && options.retries.retries_error(&e)
+                        && state.retry_budget.withdraw())
+                    {
+                        return Err(e);
+                    }
+                    e.to_string()
+                }
+            };
+
+            eprintln!("{}: retrying after {}", route.upstream.name, failure);
+            tokio::time::sleep(options.retries.backoff(attempt as u32)).await;
+            attempt += 1;
+        }
+    })
+    .await;
+
+    match res {
+        Ok(keep_alive) => Ok(keep_alive),
+        Err(e) => {
+            eprintln!("{}: {}", route.upstream.name, e);
+            // once the response started the client can only be told about
+            // the failure by closing the connection
+            if exchange.responded {
+                return Ok(false);
+            }
+            // the stored response is better than an error
+            let unavailable =
+                e.upstream() || matches!(e, ProxyError::NoEndpoint | ProxyError::CircuitOpen);
+            if let (Some(stale), true) = (&stale, unavailable) {
+                let mut resp = stale.response(CacheStatus::Stale, &headers);
+                compress(options, &headers, &method, &mut resp);
+                if let Some(decision) = &decision {
+                    decision.headers(&mut resp.headers);
+                }
+                exchange.entry.cache = Some(CacheStatus::Stale);
+                return match exchange.serve(resp, keep_alive).await {
+                    Ok(keep_alive) => Ok(keep_alive),
+                    Err(_) => Ok(false),
+                };
+            }
+            let keep_alive = keep_alive && exchange.drained();
+            let resp = Response::new(e.status());
+            exchange.entry.responded(&resp);
+            respond(resp, keep_alive, exchange.inbound.get_mut()).await?;
+            Ok(keep_alive)
+        }
+    }
+}
+
+// builds the request sent to the selected endpoint
+fn proxied(req: &Request, lease: &Lease, options: &RouteOptions, captures: &Captures) -> Request {
+    let mut request = Builder::new()
+        .method(req.parts.method.clone())
+        .headers(req.parts.headers.clone())
+        .url(lease.endpoint.url.clone())
+        .path(req.parts.url.path.clone())
+        .build();
+    if let (true, Some(host)) = (options.preserve_host, req.parts.headers.raw.get("host")) {
+        request
+            .parts
+            .headers
+            .raw
+            .insert("host".to_string(), host.clone());
+    }
+    options.rewrite.request(&mut request, captures);
+    request
+}
+
+// runs a phase of the exchange, failing with a timeout once the limit is over
+async fn within<T, F>(limit: Option<Duration>, phase: Phase, f: F) -> Result<T, ProxyError>
+where
+    F: Future<Output = Result<T, ProxyError>>,
+{
+    match limit {
+        Some(limit) => match timeout(limit, f).await {
+            Ok(res) => res,
+            Err(_) => Err(ProxyError::Timeout(phase)),
+        },
+        None => f.await,
+    }
+}
+
+// Exchange relays a client request to upstreams, and a response back to the
+// client.
+struct Exchange<'a> {
+    upstream: &'a Client,
+    // framing of the request body, still to be read from the inbound buffer
+    framing: Framing,
+    timeouts: &'a Timeouts,
+    inbound: &'a mut BufReader<TcpStream>,
+    // request body kept in memory to be sent again
+    replay: Option<Vec<u8>>,
+    // whether the method allows sending the request again on a new
+    // connection when the upstream did not answer it
+    resend: bool,
+    // whether the response head was written to the client
+    responded: bool,
+    entry: &'a mut Entry,
+}
+
+impl Exchange<'_> {
+    // reads a small request body in memory, so that the request can be sent
+    // several times. Returns whether the request can be sent again.
+    async fn buffer(&mut self) -> Result<bool, ProxyError> {
+        match self.framing {
+            Framing::Empty => Ok(true),
+            Framing::Length(n) if n <= MAX_REPLAY_BODY_SIZE => {
+                let mut body = Vec::with_capacity(n);
+                self.entry.bytes_in = copy(
+                    &mut BodyReader::new(self.framing),
+                    &mut *self.inbound,
+                    &mut BodyWriter::new(self.framing),
+                    &mut body,
+                    self.timeouts.body_read,
+                    true,
+                    &mut Transit::default(),
+                )
+                .await?;
+                self.replay = Some(body);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // whether the request body was read entirely from the inbound buffer
+    fn drained(&self) -> bool {
+        matches!(self.framing, Framing::Empty | Framing::Length(0)) || self.replay.is_some()
+    }
+
+    // sends the request head to the upstream and relays the request body,
+    // then reads the response head. The response body is left on the
+    // returned connection.
+    async fn forward(&mut self, request: &Request) -> Result<(Connection, Response), ProxyError> {
+        let start = SystemTime::now();
+        let res = self.attempt(request).await;
+        if let Some(trace) = &mut self.entry.trace {
+            let status = res.as_ref().ok().map(|(_, resp)| resp.status);
+            trace.responded(&request.parts.url, start, status);
+        }
+        res
+    }
+
+    // same as forward, without recording the span of the request
+    async fn attempt(&mut self, request: &Request) -> Result<(Connection, Response), ProxyError> {
+        let mut conn = self.connect(request, false).await?;
+        let mut start = Instant::now();
+        let sent = self.write(request, &mut conn).await;
+
+        // the upstream may have closed an idle connection, a request it did
+        // not answer is sent again on a new connection when its body was not
+        // read yet
+        if conn.reused() && self.drained() && self.resend {
+            let answered = match sent {
+                Ok(()) => {
+                    within(Some(self.timeouts.first_byte), Phase::FirstByte, async {
+                        Ok(conn.answering().await)
+                    })
+                    .await?
+                }
+                Err(ProxyError::Upstream(_)) => false,
+                Err(e) => return Err(e),
+            };
+            if !answered {
+                drop(conn);
+                conn = self.connect(request, true).await?;
+                start = Instant::now();
+                self.write(request, &mut conn).await?;
+            }
+        } else {
+            sent?;
+        }
+
+        let resp = within(Some(self.timeouts.first_byte), Phase::FirstByte, async {
+            Response::read_head(&mut conn.stream)
+                .await
+                .map_err(ProxyError::Upstream)
+        })
+        .await?;
+        self.entry.timings.first_byte = Some(start.elapsed());
+        Ok((conn, resp))
+    }
+
+    async fn connect(&mut self, request: &Request, new: bool) -> Result<Connection, ProxyError> {
+        let authority = &request.parts.url.authority;
+        let start = Instant::now();
+        let upstream = self.upstream;
+        let conn = within(Some(self.timeouts.connect), Phase::Connect, async {
+            let conn = match new {
+                true => upstream.connect_new(authority).await,
+                false => upstream.connect(authority).await,
+            };
+            conn.map_err(ProxyError::Connect)
+        })
+        .await?;
+        self.entry.timings.connect = Some(start.elapsed());
+        if let (Some(trace), Some(dial)) = (&mut self.entry.trace, conn.dial()) {
+            trace.dialed(dial);
+        }
+        Ok(conn)
+    }
+
+    // sends the request head to the upstream and relays the request body
+    async fn write(&mut self, request: &Request, conn: &mut Connection) -> Result<(), ProxyError> {
+        let stream = conn.stream.get_mut();
+        request
+            .write_head(stream)
+            .await
+            .map_err(ProxyError::Upstream)?;
+        let mut writer = BodyWriter::new(self.framing);
+        match &self.replay {
+            Some(body) => {
+                writer
+                    .write(stream, body)
+                    .await
+                    .map_err(ProxyError::Upstream)?;
+                writer
+                    .finish(stream, None)
+                    .await
+                    .map_err(ProxyError::Upstream)?;
+            }
+            None => {
+                self.entry.bytes_in = copy(
+                    &mut BodyReader::new(self.framing),
+                    &mut *self.inbound,
+                    &mut writer,
+                    stream,
+                    self.timeouts.body_read,
+                    true,
+                    &mut Transit::default(),
+                )
+                .await?
+            }
+        }
+        Ok(())
+    }
+
+    // answers the client with a response whose body is in memory
+    async fn serve(&mut self, resp: Response, keep_alive: bool) -> Result<bool, ProxyError> {
+        let keep_alive = keep_alive && self.drained();
+        self.responded = true;
+        self.entry.responded(&resp);
+        respond(resp, keep_alive, self.inbound.get_mut())
+            .await
+            .map_err(ProxyError::Client)?;
+        Ok(keep_alive)
+    }
+
+    // writes the response head to the client and relays the response body,
+    // decoding and encoding it on the way as the transit tells. Returns the
+    // copy of the body the transit kept, as received from the upstream.
+    async fn relay(
+        &mut self,
+        request: &Request,
+        conn: &mut Connection,
+        resp: &Response,
+        standard: &Standard,
+        keep_alive: bool,
+        mut transit: Transit,
+    ) -> Result<Option<Vec<u8>>, ProxyError> {
+        let body = Framing::response(&request.parts.method, resp.status, &resp.headers)
+            .map_err(ProxyError::Upstream)?;
+        let mut head = Response {
+            standard: resp.standard.clone(),
+            status: resp.status,
+            headers: resp.headers.clone(),
+            hasbody: resp.hasbody,
+            body: None,
+            trailers: None,
+        };
+        if let Some(decoder) = &transit.decoder {
+            decoder.coding().decode_headers(&mut head.headers);
+            vary(&mut head.headers);
+        }
+        if let Some(encoder) = &transit.encoder {
+            encoder.coding().encode_headers(&mut head.headers);
+            vary(&mut head.headers);
+        }
+        // a body ending when the upstream closes the connection, or whose
+        // length changes with its coding, is chunked for clients supporting
+        // it, so that their connection can be kept
+        let unknown =
+            body == Framing::Close || transit.decoder.is_some() || transit.encoder.is_some();
+        let (outbound, keep_alive) = match body {
+            _ if unknown && standard.persistent() => {
+                head.headers
+                    .raw
+                    .insert("transfer-encoding".to_string(), "chunked".to_string());
+                (Framing::Chunked, keep_alive)
+            }
+            _ if unknown => (Framing::Close, false),
+            framing => (framing, keep_alive),
+        };
+        // the upstream connection headers only apply to the upstream connection
+        strip_hop_by_hop(&mut head.headers);
+        head.headers.raw.insert(
+            "connection".to_string(),
+            if keep_alive { "keep-alive" } else { "close" }.to_string(),
+        );
+
+        self.responded = true;
+        self.entry.status = Some(head.status);
+        head.write_head(self.inbound.get_mut())
+            .await
+            .map_err(ProxyError::Client)?;
+        self.entry.bytes_out = copy(
+            &mut BodyReader::new(body),
+            &mut conn.stream,
+            &mut BodyWriter::new(outbound),
+            self.inbound.get_mut(),
+            self.timeouts.body_read,
+            false,
+            &mut transit,
+        )
+        .await?;
+        Ok(transit.kept.body)
+    }
+}
+
+// writes a stored response to redis in the background, the response to the
+// client does not wait for it
+fn share(redis: &Option<Arc<RedisStore>>, key: &str, stored: &Arc<Stored>, refreshed: bool) {
+    if let Some(redis) = redis {
+        let (redis, key, stored) = (redis.clone(), key.to_string(), stored.clone());
+        tokio::spawn(async move {
+            ResponseCache::share(&redis, &key, &stored, refreshed).await;
+        });
+    }
+}
+
+// decodes a response whose body is in memory for clients not accepting its
+// coding, then compresses it for routes compressing their responses
+fn compress(options: &RouteOptions, request: &HeaderMap, method: &Method, resp: &mut Response) {
+    if decoding(request, &resp.headers).is_some() {
+        // a body failing to decode is sent as it is
+        if resp.decode().is_err() {
+            return;
+        }
+        vary(&mut resp.headers);
+    }
+    if let Some(compression) = &options.compression {
+        compression.encode(request, method, resp);
+    }
+}
+
+// Transit is what happens to a body while it is relayed.
+#[derive(Default)]
+struct Transit {
+    // the kept copy is the body as received
+    kept: Kept,
+    decoder: Option<Decoder>,
+    encoder: Option<Encoder>,
+}
+
+impl Transit {
+    fn new(keep: Option<usize>, decoded: Option<Coding>, encoded: Option<Coding>) -> Self {
+        Self {
+            kept: keep.map(Kept::new).unwrap_or_default(),
+            decoder: decoded.map(Decoder::new),
+            encoder: encoded.map(Encoder::new),
+        }
+    }
+
+    // returns the piece of body to send for a piece received
+    fn pass(&mut self, data: Vec<u8>) -> Result<Vec<u8>, FrameError> {
+        self.kept.push(&data);
+        let data = match &mut self.decoder {
+            Some(decoder) => decoder.write(&data)?,
+            None => data,
+        };
+        Ok(match &mut self.encoder {
+            Some(encoder) => encoder.write(&data),
+            None => data,
+        })
+    }
+
+    // returns what is left to send once the body was received entirely
+    fn finish(&mut self) -> Result<Vec<u8>, FrameError> {
+        if let Some(decoder) = self.decoder.take() {
+            decoder.finish()?;
+        }
+        Ok(self.encoder.take().map(Encoder::finish).unwrap_or_default())
+    }
+}
+
+// copy of a body kept while it is relayed, given up once over its limit
+#[derive(Default)]
+struct Kept {
+    body: Option<Vec<u8>>,
+    limit: usize,
+}
+
+impl Kept {
+    fn new(limit: usize) -> Self {
+        Self {
+            body: Some(Vec::new()),
+            limit,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        if let Some(body) = &mut self.body {
+            match body.len() + data.len() > self.limit {
+                true => self.body = None,
+                false => body.extend_from_slice(data),
+            }
+        }
+    }
+}
+
+// turns a failure on one side of the exchange into a proxy error
+type Blame = fn(FrameError) -> ProxyError;
+
+// relays a body one piece at a time, so that a slow receiver slows down the
+// reads from the sender. Each piece must arrive within body_read. Returns the
+// length of the body written.
+async fn copy<R, W>(
+    reader: &mut BodyReader,
+    from: &mut R,
+    writer: &mut BodyWriter,
+    to: &mut W,
+    body_read: Duration,
+    upload: bool,
+    transit: &mut Transit,
+) -> Result<usize, ProxyError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // errors are blamed on the side they happened on
+    let (sender, receiver): (Blame, Blame) = match upload {
+        true => (ProxyError::Client, ProxyError::Upstream),
+        false => (ProxyError::Upstream, ProxyError::Client),
+    };
+
+    let mut written = 0;
+    loop {
+        let data = match timeout(body_read, reader.next(from)).await {
+            Ok(data) => data.map_err(sender)?,
+            Err(_) => return Err(ProxyError::Timeout(Phase::BodyRead)),
+        };
+        match data {
+            Some(data) => {
+                let data = transit.pass(data).map_err(sender)?;
+                written += data.len();
+                writer.write(to, &data).await.map_err(receiver)?
+            }
+            None => break,
+        }
+    }
+    let rest = transit.finish().map_err(sender)?;
+    written += rest.len();
+    writer.write(to, &rest).await.map_err(receiver)?;
+    writer
+        .finish(to, reader.trailers().as_ref())
+        .await
+        .map_err(receiver)?;
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        str::FromStr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use std::io::Cursor;
+
+    use http::trace::TraceContext;
+    use json::parser::{parse, tokenize, Node};
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+    use crate::{
+        accesslog::REDACTED,
+        testing::{FakeCollector, FakeUpstream},
+    };
+    use rstest::*;
+
+    // starts a gateway routing /a and /b to two upstreams named after them
+    async fn gateway(listener: &str) -> (SocketAddr, Vec<FakeUpstream>) {
+        gateway_with(listener, "{}").await
+    }
+
+    // same as gateway, with the given options on both routes
+    async fn gateway_with(listener: &str, options: &str) -> (SocketAddr, Vec<FakeUpstream>) {
+        let upstreams = vec![
+            FakeUpstream::start("a").await,
+            FakeUpstream::start("b").await,
+        ];
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{}],
+                "routes": [
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "options": {}}},
+                    {{"host": "gateway.test:80", "path": "/b", "upstream": "{}", "options": {}}}
+                ]
+            }}"#,
+            listener,
+            String::try_from(upstreams[0].url.clone()).unwrap(),
+            options,
+            String::try_from(upstreams[1].url.clone()).unwrap(),
+            options,
+        ))
+        .unwrap();
+
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+        (addr, upstreams)
+    }
+
+    fn request(path: &str, extra: &str) -> String {
+        format!(
+            "GET {} HTTP/1.1\r\nhost: gateway.test:80\r\n{}\r\n",
+            path, extra
+        )
+    }
+
+    // reads responses until the gateway closes the connection
+    async fn responses(stream: TcpStream) -> Vec<(String, String)> {
+        let mut buffer = BufReader::new(stream);
+        let mut res = Vec::new();
+        while let Ok(resp) = Response::read(&mut buffer).await {
+            let connection = resp.headers.raw.get("connection").cloned().unwrap();
+            let body = String::from_utf8(resp.body.unwrap_or_default()).unwrap();
+            res.push((body, connection));
+        }
+        res
+    }
+
+    fn expected(items: &[(&str, &str)]) -> Vec<(String, String)> {
+        items
+            .iter()
+            .map(|(body, connection)| (body.to_string(), connection.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_keep_alive_pipelining() {
+        let (addr, upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut pipeline = String::new();
+        pipeline.push_str(&request("/a", ""));
+        pipeline.push_str(&request("/b", ""));
+        pipeline.push_str(&request("/unknown", ""));
+        pipeline.push_str(&request("/a", "connection: close\r\n"));
+        // never answered, the connection is closed before
+        pipeline.push_str(&request("/b", ""));
+        stream.write_all(pipeline.as_bytes()).await.unwrap();
+
+        assert_eq!(
+            responses(stream).await,
+            expected(&[
+                ("a", "keep-alive"),
+                ("b", "keep-alive"),
+                ("", "keep-alive"),
+                ("a", "close"),
+            ])
+        );
+        assert_eq!(upstreams[0].hits(), 2);
+        assert_eq!(upstreams[1].hits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_http10() {
+        let (addr, _upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = "GET /a HTTP/1.0\r\nhost: gateway.test:80\r\n\r\n".repeat(2);
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(responses(stream).await, expected(&[("a", "close")]));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = "GET /a HTTP/1.0\r\nhost: gateway.test:80\r\nconnection: keep-alive\r\n\r\n";
+        stream.write_all(req.repeat(2).as_bytes()).await.unwrap();
+        stream.shutdown().await.unwrap();
+        assert_eq!(
+            responses(stream).await,
+            expected(&[("a", "keep-alive"), ("a", "keep-alive")])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_connection_limits() {
+        let (addr, _upstreams) = gateway(
+            r#"{"address": "127.0.0.1:0", "idle_timeout_ms": 50, "max_requests_per_connection": 2}"#,
+        )
+        .await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let pipeline = [request("/a", ""), request("/b", ""), request("/a", "")].concat();
+        stream.write_all(pipeline.as_bytes()).await.unwrap();
+        assert_eq!(
+            responses(stream).await,
+            expected(&[("a", "keep-alive"), ("b", "close")])
+        );
+
+        // an idle connection is closed by the gateway
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(request("/a", "").as_bytes())
+            .await
+            .unwrap();
+        let started = tokio::time::Instant::now();
+        assert_eq!(responses(stream).await, expected(&[("a", "keep-alive")]));
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_response_framing() {
+        let (addr, upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+        upstreams[0].set_response(
+            "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n6\r\n world\r\n0\r\nx-checksum: 42\r\n\r\n",
+        );
+        upstreams[1].set_response("HTTP/1.1 200 OK\r\nconnection: close\r\n\r\nuntil close");
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let pipeline = [request("/a", ""), request("/b", "connection: close\r\n")].concat();
+        stream.write_all(pipeline.as_bytes()).await.unwrap();
+
+        let mut buffer = BufReader::new(stream);
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body, Some(b"hello world".to_vec()));
+        assert_eq!(
+            resp.trailers.unwrap().raw.get("x-checksum"),
+            Some(&"42".to_string())
+        );
+
+        // relayed as it is read, hence chunked for the client
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body, Some(b"until close".to_vec()));
+        assert_eq!(
+            resp.headers.raw.get("transfer-encoding"),
+            Some(&"chunked".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_streams_large_bodies() {
+        let (addr, upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+        let size = http::body::MAX_CHUNK_SIZE * 64 + 3;
+        upstreams[1].set_response(&format!(
+            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}",
+            size,
+            "x".repeat(size)
+        ));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let upload = format!(
+            "POST /a HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: {}\r\n\r\n{}",
+            size,
+            "y".repeat(size)
+        );
+        let chunked = format!(
+            "POST /a HTTP/1.1\r\nhost: gateway.test:80\r\ntransfer-encoding: chunked\r\n\r\n\
+            {:x}\r\n{}\r\n0\r\n\r\n",
+            size,
+            "z".repeat(size)
+        );
+        let (mut reader, mut writer) = stream.split();
+        let mut buffer = BufReader::new(&mut reader);
+
+        writer.write_all(upload.as_bytes()).await.unwrap();
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body, Some(b"a".to_vec()));
+        assert_eq!(upstreams[0].received(), size);
+
+        writer.write_all(chunked.as_bytes()).await.unwrap();
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body, Some(b"a".to_vec()));
+        assert_eq!(upstreams[0].received(), size);
+
+        writer
+            .write_all(request("/b", "").as_bytes())
+            .await
+            .unwrap();
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.body.map(|b| b.len()), Some(size));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rejects_ambiguous_framing() {
+        let (addr, upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"POST /a HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 4\r\n\
+                transfer-encoding: chunked\r\n\r\n0\r\n\r\nGET /b HTTP/1.1\r\nhost: gateway.test:80\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        assert_eq!(status(stream).await, StatusCode::BadRequest);
+        assert_eq!(upstreams[0].hits() + upstreams[1].hits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_missing_host() {
+        let (addr, upstreams) = gateway(r#"{"address": "127.0.0.1:0"}"#).await;
+        let resp = call(addr, "GET /a HTTP/1.1\r\n\r\n").await;
+        assert_eq!(resp.status, StatusCode::BadRequest);
+        assert_eq!(upstreams[0].hits(), 0);
+    }
+
+    // reads the status of the only response sent before the gateway closes
+    // the connection
+    async fn status(stream: TcpStream) -> StatusCode {
+        let mut buffer = BufReader::new(stream);
+        let resp = Response::read(&mut buffer).await.unwrap();
+        assert_eq!(resp.headers.raw.get("connection").unwrap(), "close");
+        assert!(Response::read(&mut buffer).await.is_err());
+        resp.status
+    }
+
+    #[tokio::test]
+    async fn test_proxy_client_timeouts() {
+        let (addr, upstreams) = gateway_with(
+            r#"{"address": "127.0.0.1:0", "header_read_timeout_ms": 50}"#,
+            r#"{"timeouts": {"body_read_ms": 50}}"#,
+        )
+        .await;
+
+        // the head is never completed
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nhost: gateway")
+            .await
+            .unwrap();
+        assert_eq!(status(stream).await, StatusCode::RequestTimeout);
+
+        // the body is shorter than announced
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let req = "POST /a HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 10\r\n\r\nabc";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(status(stream).await, StatusCode::RequestTimeout);
+        assert_eq!(upstreams[0].hits(), 0);
+    }
+
+    #[rstest]
+    #[case(r#"{"timeouts": {"first_byte_ms": 50}}"#)]
+    #[case(r#"{"timeouts": {"total_ms": 50}}"#)]
+    #[tokio::test]
+    async fn test_proxy_upstream_timeouts(#[case] options: &str) {
+        let (addr, upstreams) = gateway_with(r#"{"address": "127.0.0.1:0"}"#, options).await;
+        upstreams[0].set_delay(Duration::from_secs(5));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let started = tokio::time::Instant::now();
+        let req = "POST /a HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 3\r\n\r\nabc";
+        stream.write_all(req.as_bytes()).await.unwrap();
+        assert_eq!(status(stream).await, StatusCode::GatewayTimeout);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_timeout_after_response_started() {
+        // the upstream announces more than it sends, then stalls
+        let stalled = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", stalled.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = stalled.accept().await.unwrap();
+            let _ = Request::read(&mut BufReader::new(&mut stream)).await;
+            let resp = "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nabc";
+            stream.write_all(resp.as_bytes()).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "routes": [{{
+                    "host": "gateway.test:80", "path": "/a", "upstream": "{}",
+                    "options": {{"timeouts": {{"body_read_ms": 50}}}}
+                }}]
+            }}"#,
+            url
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(request("/a", "").as_bytes())
+            .await
+            .unwrap();
+        let started = tokio::time::Instant::now();
+        let mut buffer = BufReader::new(stream);
+        let resp = Response::read_head(&mut buffer).await.unwrap();
+        assert_eq!(resp.status, StatusCode::Ok);
+        // the truncated body can only be noticed by the connection closing
+        let body = BodyReader::new(Framing::Length(10))
+            .collect(&mut buffer)
+            .await;
+        assert!(body.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    // starts a gateway routing / to a round robin pool of the given endpoints
+    async fn pool_gateway(endpoints: &[String], options: &str, budget: &str) -> SocketAddr {
+        let endpoints: Vec<String> = endpoints
+            .iter()
+            .map(|url| format!(r#"{{"url": "{}"}}"#, url))
+            .collect();
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "retry_budget": {},
+                "upstreams": {{"pool": {{"endpoints": [{}]}}}},
+                "routes": [
+                    {{"host": "gateway.test:80", "path": "/", "upstream": "pool", "options": {}}}
+                ]
+            }}"#,
+            budget,
+            endpoints.join(","),
+            options
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+        addr
+    }
+
+    async fn call(addr: SocketAddr, req: &str) -> Response {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(req.as_bytes()).await.unwrap();
+        Response::read(&mut BufReader::new(stream)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retries_another_endpoint() {
+        let failing = FakeUpstream::start("failing").await;
+        failing.set_status(StatusCode::ServiceUnavailable);
+        let up = FakeUpstream::start("up").await;
+        let addr = pool_gateway(
+            &[
+                String::try_from(failing.url.clone()).unwrap(),
+                FakeUpstream::closed(),
+                String::try_from(up.url.clone()).unwrap(),
+            ],
+            r#"{"retries": {"attempts": 3, "statuses": [503], "backoff_ms": 1}}"#,
+            "{}",
+        )
+        .await;
+
+        // whichever endpoint is tried first, the request ends up on the one up
+        for _ in 0..3 {
+            let resp = call(addr, &request("/", "connection: close\r\n")).await;
+            assert_eq!(resp.status, StatusCode::Ok);
+            assert_eq!(resp.body, Some(b"up".to_vec()));
+        }
+        assert_eq!(up.hits(), 3);
+
+        // a small body is replayed on every attempt
+        let hits = up.hits();
+        let req = "PUT / HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 4\r\n\r\nbody";
+        let resp = call(addr, req).await;
+        assert_eq!(resp.body, Some(b"up".to_vec()));
+        assert_eq!(up.hits(), hits + 1);
+        assert_eq!(up.received(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retries_idempotent_only() {
+        let failing = FakeUpstream::start("failing").await;
+        failing.set_status(StatusCode::ServiceUnavailable);
+        let endpoints = [String::try_from(failing.url.clone()).unwrap()];
+        let retries = r#"{"retries": {"attempts": 3, "statuses": [503], "backoff_ms": 1}}"#;
+        let addr = pool_gateway(&endpoints, retries, "{}").await;
+
+        let req = "POST / HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 2\r\n\r\nhi";
+        let resp = call(addr, req).await;
+        assert_eq!(resp.status, StatusCode::ServiceUnavailable);
+        assert_eq!(failing.hits(), 1);
+
+        // the last attempt is relayed as is
+        let resp = call(addr, &request("/", "")).await;
+        assert_eq!(resp.status, StatusCode::ServiceUnavailable);
+        assert_eq!(failing.hits(), 4);
+    }
+
+    // starts an upstream answering the first request on a connection, then
+    // closing it on the next request, in the middle of the response body when
+    // truncated and without answering otherwise. Returns its url and the
+    // number of connections it accepted.
+    async fn closing_upstream(truncated: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(async move {
+                    let mut stream = BufReader::new(stream);
+                    Request::read(&mut stream).await.unwrap();
+                    let resp = b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok";
+                    stream.get_mut().write_all(resp).await.unwrap();
+                    if Request::read(&mut stream).await.is_ok() && truncated {
+                        let resp = b"HTTP/1.1 200 OK\r\ncontent-length: 5\r\n\r\nok";
+                        let _ = stream.get_mut().write_all(resp).await;
+                    }
+                });
+            }
+        });
+        (url, accepted)
+    }
+
+    #[rstest]
+    #[case("GET", false, 2)]
+    #[case("POST", false, 1)]
+    #[case("GET", true, 1)]
+    #[tokio::test]
+    async fn test_proxy_resends_unanswered(
+        #[case] method: &str,
+        #[case] truncated: bool,
+        #[case] accepted: usize,
+    ) {
+        let (url, connections) = closing_upstream(truncated).await;
+        let addr = pool_gateway(&[url], "{}", "{}").await;
+
+        let resp = call(addr, &request("/", "")).await;
+        assert_eq!(resp.body, Some(b"ok".to_vec()));
+
+        // only a request the upstream did not answer at all, and whose method
+        // allows it, is sent again on a new connection
+        let req = format!(
+            "{} / HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 0\r\n\r\n",
+            method
+        );
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(req.as_bytes()).await.unwrap();
+        let resp = Response::read(&mut BufReader::new(stream)).await;
+        assert_eq!(
+            resp.is_ok_and(|resp| resp.body == Some(b"ok".to_vec())),
+            accepted == 2
+        );
+        assert_eq!(connections.load(Ordering::Relaxed), accepted);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_retry_budget() {
+        let failing = FakeUpstream::start("failing").await;
+        failing.set_status(StatusCode::ServiceUnavailable);
+        let endpoints = [String::try_from(failing.url.clone()).unwrap()];
+        let retries = r#"{"retries": {"attempts": 3, "statuses": [503], "backoff_ms": 1}}"#;
+        let addr = pool_gateway(&endpoints, retries, r#"{"percent": 0, "reserve": 3}"#).await;
+
+        for _ in 0..3 {
+            call(addr, &request("/", "")).await;
+        }
+        // 3 requests and the 3 retries of the reserve, nothing is earned back
+        assert_eq!(failing.hits(), 6);
+    }
+
+    async fn breaker_state(admin: SocketAddr) -> String {
+        let resp = call(
+            admin,
+            "GET /circuit-breakers HTTP/1.1\r\nhost: admin\r\nauthorization: Bearer secret\r\n\r\n",
+        )
+        .await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        String::from_utf8(resp.body.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proxy_circuit_breaker() {
+        let upstream = FakeUpstream::start("up").await;
+        upstream.set_status(StatusCode::InternalServerError);
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "admin": {{"address": "127.0.0.1:0", "token": "secret"}},
+                "upstreams": {{"pool": {{
+                    "endpoints": [{{"url": "{}"}}],
+                    "circuit_breaker": {{"consecutive_failures": 2, "cooldown_ms": 200}}
+                }}}},
+                "routes": [{{"host": "gateway.test:80", "path": "/", "upstream": "pool"}}]
+            }}"#,
+            String::try_from(upstream.url.clone()).unwrap()
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        let admin = proxy.admin_addr().unwrap();
+        tokio::spawn(proxy.run());
+
+        assert!(breaker_state(admin).await.contains(r#""state":"closed""#));
+        for _ in 0..2 {
+            let resp = call(addr, &request("/", "")).await;
+            assert_eq!(resp.status, StatusCode::InternalServerError);
+        }
+
+        // the open circuit answers without reaching the upstream
+        let resp = call(addr, &request("/", "")).await;
+        assert_eq!(resp.status, StatusCode::ServiceUnavailable);
+        assert_eq!(upstream.hits(), 2);
+        assert!(breaker_state(admin).await.contains(r#""state":"open""#));
+
+        // once the cooldown is over a probe goes through and closes the circuit
+        upstream.set_status(StatusCode::Ok);
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert!(breaker_state(admin)
+            .await
+            .contains(r#""state":"half_open""#));
+        let resp = call(addr, &request("/", "")).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(upstream.hits(), 3);
+        assert!(breaker_state(admin).await.contains(r#""state":"closed""#));
+
+        let resp = call(
+            admin,
+            "GET /unknown HTTP/1.1\r\nhost: admin\r\nauthorization: Bearer secret\r\n\r\n",
+        )
+        .await;
+        assert_eq!(resp.status, StatusCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rate_limit() {
+        let options =
+            r#"{"rate_limit": {"key": "header:X-Api-Key", "limit": 2, "window_ms": 60000}}"#;
+        let (addr, upstreams) = gateway_with(r#"{"address": "127.0.0.1:0"}"#, options).await;
+
+        for remaining in ["1", "0"] {
+            let resp = call(addr, &request("/a", "x-api-key: k1\r\n")).await;
+            assert_eq!(resp.status, StatusCode::Ok);
+            assert_eq!(resp.headers.raw["ratelimit-limit"], "2");
+            assert_eq!(resp.headers.raw["ratelimit-remaining"], remaining);
+        }
+
+        let resp = call(addr, &request("/a", "x-api-key: k1\r\n")).await;
+        assert_eq!(resp.status, StatusCode::TooManyRequests);
+        assert_eq!(resp.headers.raw["ratelimit-remaining"], "0");
+        assert_eq!(resp.headers.raw["retry-after"], "30");
+        assert_eq!(upstreams[0].hits(), 2);
+
+        // other keys and other routes have limits of their own
+        let resp = call(addr, &request("/a", "x-api-key: k2\r\n")).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        let resp = call(addr, &request("/b", "x-api-key: k1\r\n")).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cache() {
+        let (addr, upstreams) =
+            gateway_with(r#"{"address": "127.0.0.1:0"}"#, r#"{"cache": {}}"#).await;
+        upstreams[0].set_response(
+            "HTTP/1.1 200 OK\r\ncache-control: max-age=60\r\netag: \"a1\"\r\n\
+            content-length: 5\r\n\r\nfresh",
+        );
+
+        let resp = call(addr, &request("/a", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "MISS");
+        let resp = call(addr, &request("/a", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "HIT");
+        assert_eq!(resp.body, Some(b"fresh".to_vec()));
+        let resp = call(addr, &request("/a", "if-none-match: \"a1\"\r\n")).await;
+        assert_eq!(resp.status, StatusCode::NotModified);
+        assert_eq!(upstreams[0].hits(), 1);
+
+        // stale responses are revalidated, and served when the upstream fails
+        upstreams[1].set_response(
+            "HTTP/1.1 200 OK\r\ncache-control: no-cache\r\netag: \"b1\"\r\n\
+            content-length: 5\r\n\r\nstale",
+        );
+        let resp = call(addr, &request("/b", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "MISS");
+        upstreams[1].set_response("HTTP/1.1 304 Not Modified\r\netag: \"b1\"\r\n\r\n");
+        let resp = call(addr, &request("/b", "")).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "STALE");
+        assert_eq!(resp.body, Some(b"stale".to_vec()));
+        upstreams[1].set_response("HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\n\r\n");
+        let resp = call(addr, &request("/b", "")).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "STALE");
+        assert_eq!(upstreams[1].hits(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cache_coalescing() {
+        let (addr, upstreams) =
+            gateway_with(r#"{"address": "127.0.0.1:0"}"#, r#"{"cache": {}}"#).await;
+        upstreams[0].set_response(
+            "HTTP/1.1 200 OK\r\ncache-control: max-age=60\r\ncontent-length: 1\r\n\r\na",
+        );
+        upstreams[0].set_delay(Duration::from_millis(100));
+
+        let calls =
+            (0..5).map(|_| tokio::spawn(async move { call(addr, &request("/a", "")).await }));
+        let mut statuses = Vec::new();
+        for call in calls.collect::<Vec<_>>() {
+            let resp = call.await.unwrap();
+            statuses.push(resp.headers.raw[CACHE_STATUS_HEADER].clone());
+        }
+        statuses.sort();
+        assert_eq!(statuses, ["HIT", "HIT", "HIT", "HIT", "MISS"]);
+        assert_eq!(upstreams[0].hits(), 1);
+
+        // responses not stored do not hold other requests back
+        upstreams[1].set_delay(Duration::from_millis(100));
+        let started = tokio::time::Instant::now();
+        let calls =
+            (0..3).map(|_| tokio::spawn(async move { call(addr, &request("/b", "")).await }));
+        for call in calls.collect::<Vec<_>>() {
+            call.await.unwrap();
+        }
+        assert_eq!(upstreams[1].hits(), 3);
+        assert!(started.elapsed() < Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_compression() {
+        let (addr, upstreams) = gateway_with(
+            r#"{"address": "127.0.0.1:0"}"#,
+            r#"{"cache": {}, "compression": {}}"#,
+        )
+        .await;
+        let body = "compressible ".repeat(200);
+        upstreams[0].set_response(&format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncache-control: max-age=60\r\n\
+            etag: \"c1\"\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+
+        // relayed responses are encoded while being relayed, then chunked
+        let resp = call(addr, &request("/a", "accept-encoding: gzip\r\n")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "MISS");
+        assert_eq!(resp.headers.raw["content-encoding"], "gzip");
+        assert_eq!(resp.headers.raw["transfer-encoding"], "chunked");
+        assert_eq!(resp.headers.raw["vary"], "Accept-Encoding");
+        assert_eq!(resp.headers.raw["etag"], "W/\"c1\"");
+        let encoded = resp.body.unwrap();
+        assert!(encoded.len() < body.len() / 10);
+        assert_eq!(
+            archive::gzip::decompress(&encoded).unwrap(),
+            body.as_bytes()
+        );
+
+        // the cache keeps the response as received, and encodes it per client
+        let resp = call(addr, &request("/a", "accept-encoding: deflate\r\n")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "HIT");
+        assert_eq!(resp.headers.raw["content-encoding"], "deflate");
+        let encoded = resp.body.unwrap();
+        assert_eq!(
+            resp.headers.raw["content-length"],
+            encoded.len().to_string()
+        );
+        assert_eq!(
+            archive::zlib::decompress(&encoded).unwrap(),
+            body.as_bytes()
+        );
+        let resp = call(addr, &request("/a", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "HIT");
+        assert!(!resp.headers.raw.contains_key("content-encoding"));
+        assert_eq!(resp.body, Some(body.clone().into_bytes()));
+        assert_eq!(upstreams[0].hits(), 1);
+
+        // small responses are sent as they are, and http/1.0 clients get the
+        // end of an encoded body by the connection closing
+        upstreams[1].set_response(&format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+        let req = "GET /b HTTP/1.0\r\nhost: gateway.test:80\r\naccept-encoding: gzip\r\n\r\n";
+        let resp = call(addr, req).await;
+        assert_eq!(resp.headers.raw["content-encoding"], "gzip");
+        assert_eq!(resp.headers.raw["connection"], "close");
+        assert!(!resp.headers.raw.contains_key("transfer-encoding"));
+        let encoded = resp.body.unwrap();
+        assert_eq!(
+            archive::gzip::decompress(&encoded).unwrap(),
+            body.as_bytes()
+        );
+        upstreams[1].set_response(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-length: 5\r\n\r\nsmall",
+        );
+        let resp = call(addr, &request("/b", "accept-encoding: gzip\r\n")).await;
+        assert!(!resp.headers.raw.contains_key("content-encoding"));
+        assert_eq!(resp.body, Some(b"small".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_decompression() {
+        let (addr, upstreams) = gateway_with(
+            r#"{"address": "127.0.0.1:0"}"#,
+            r#"{"cache": {}, "compression": {}}"#,
+        )
+        .await;
+        let body = "plain ".repeat(300);
+        let gzipped = archive::gzip::compress(body.as_bytes());
+        let mut raw = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-encoding: gzip\r\n\
+            cache-control: max-age=60\r\ncontent-length: {}\r\n\r\n",
+            gzipped.len()
+        )
+        .into_bytes();
+        raw.extend_from_slice(&gzipped);
+        upstreams[0].set_response_bytes(&raw);
+        upstreams[1].set_response_bytes(&raw);
+
+        // clients not accepting the upstream coding get the body decoded
+        let resp = call(addr, &request("/a", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "MISS");
+        assert!(!resp.headers.raw.contains_key("content-encoding"));
+        assert_eq!(resp.headers.raw["transfer-encoding"], "chunked");
+        assert_eq!(resp.headers.raw["vary"], "Accept-Encoding");
+        assert_eq!(resp.body, Some(body.clone().into_bytes()));
+
+        // or encoded again with a coding they accept
+        let resp = call(addr, &request("/a", "accept-encoding: deflate\r\n")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "HIT");
+        assert_eq!(resp.headers.raw["content-encoding"], "deflate");
+        let encoded = resp.body.unwrap();
+        assert_eq!(
+            archive::zlib::decompress(&encoded).unwrap(),
+            body.as_bytes()
+        );
+        let resp = call(addr, &request("/a", "")).await;
+        assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], "HIT");
+        assert_eq!(resp.headers.raw["content-length"], body.len().to_string());
+        assert_eq!(resp.body, Some(body.clone().into_bytes()));
+        assert_eq!(upstreams[0].hits(), 1);
+
+        // clients accepting it get the body as sent by the upstream
+        let resp = call(addr, &request("/b", "accept-encoding: gzip\r\n")).await;
+        assert_eq!(resp.headers.raw["content-encoding"], "gzip");
+        assert_eq!(
+            resp.headers.raw["content-length"],
+            gzipped.len().to_string()
+        );
+        assert_eq!(resp.body, Some(gzipped));
+    }
+
+    // waits for the access log to have the given number of lines
+    async fn log_lines(path: &std::path::Path, n: usize) -> Vec<Node> {
+        let mut lines = Vec::new();
+        for _ in 0..100 {
+            let raw = std::fs::read_to_string(path).unwrap();
+            lines = raw
+                .lines()
+                .map(|line| {
+                    let tokens = tokenize(std::io::BufRead::lines(Cursor::new(line))).unwrap();
+                    parse(&mut tokens.iter().peekable()).unwrap().unwrap()
+                })
+                .collect();
+            if lines.len() >= n {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_proxy_access_log() {
+        let upstream = FakeUpstream::start("a").await;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "access_log": {{"sink": "file", "path": "{}"}},
+                "routes": [{{"host": "gateway.test:80", "path": "/a", "upstream": "{}"}}]
+            }}"#,
+            path.display(),
+            String::try_from(upstream.url.clone()).unwrap(),
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let req = "POST /a/b?c=d HTTP/1.1\r\nhost: gateway.test:80\r\n\
+            authorization: Bearer secret\r\nx-request-id: req-1\r\ncontent-length: 5\r\n\r\nhello";
+        assert_eq!(call(addr, req).await.status, StatusCode::Ok);
+        assert_eq!(
+            call(addr, &request("/unknown", "")).await.status,
+            StatusCode::NotFound
+        );
+
+        let lines = log_lines(&path, 2).await;
+        let str = |i: usize, key: &str| lines[i].get(key).and_then(Node::as_str);
+        let int = |i: usize, key: &str| lines[i].get(key).and_then(Node::as_i64);
+        let upstream_url = String::try_from(upstream.url.clone()).unwrap();
+        assert_eq!(str(0, "request_id"), Some("req-1"));
+        assert_eq!(str(0, "method"), Some("POST"));
+        assert_eq!(str(0, "host"), Some("gateway.test:80"));
+        assert_eq!(str(0, "path"), Some("/a/b?c=d"));
+        assert_eq!(str(0, "route"), Some("gateway.test:80/a"));
+        assert_eq!(str(0, "upstream"), Some(upstream_url.as_str()));
+        assert_eq!(int(0, "status"), Some(200));
+        assert_eq!(int(0, "bytes_in"), Some(5));
+        assert_eq!(int(0, "bytes_out"), Some(1));
+        assert!(str(0, "client").unwrap().starts_with("127.0.0.1:"));
+        let timings = lines[0].get("timings").unwrap();
+        assert!(timings.get("connect_ms").and_then(Node::as_f64).is_some());
+        assert!(timings
+            .get("first_byte_ms")
+            .and_then(Node::as_f64)
+            .is_some());
+        let headers = lines[0].get("headers").unwrap();
+        assert_eq!(
+            headers.get("authorization").and_then(Node::as_str),
+            Some(REDACTED)
+        );
+
+        assert_eq!(int(1, "status"), Some(404));
+        assert_eq!(lines[1].get("route"), Some(&Node::Null));
+        assert_eq!(lines[1].get("upstream"), Some(&Node::Null));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_rewrite() {
+        let upstream = FakeUpstream::start("a").await;
+        let endpoint = String::try_from(upstream.url.clone()).unwrap();
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "routes": [{{
+                    "host": "gateway.test:80",
+                    "path": "/api",
+                    "upstream": "{}",
+                    "options": {{"rewrite": {{
+                        "replace_prefix": {{"prefix": "/api", "with": "/v2"}},
+                        "host": "backend.internal",
+                        "request_headers": {{"set": {{"x-env": "prod"}}, "remove": ["cookie"]}},
+                        "response_headers": {{"add": {{"x-served-by": "gateway"}}}}
+                    }}}}
+                }}]
+            }}"#,
+            endpoint
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let resp = call(
+            addr,
+            &request("/api/users?page=2", "cookie: session=1\r\nx-env: dev\r\n"),
+        )
+        .await;
+        assert_eq!(resp.status, StatusCode::Ok);
+        assert_eq!(resp.headers.raw["x-served-by"], "gateway");
+        assert_eq!(upstream.path(), "/v2/users?page=2");
+        let headers = upstream.headers().raw;
+        assert_eq!(headers["host"], "backend.internal");
+        assert_eq!(headers["x-env"], "prod");
+        assert!(!headers.contains_key("cookie"));
+        // forwarding headers tell about the host the client asked for
+        assert_eq!(headers["x-forwarded-host"], "gateway.test:80");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_predicates() {
+        let names = ["write", "canary", "v2", "read", "internal"];
+        let mut upstreams = Vec::new();
+        for name in names {
+            upstreams.push(FakeUpstream::start(name).await);
+        }
+        let url = |i: usize| String::try_from(upstreams[i].url.clone()).unwrap();
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "routes": [
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "methods": ["POST"]}},
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "headers": {{"x-canary": "1"}}, "priority": 1}},
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "methods": ["GET"], "query": {{"v": "2"}}}},
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "methods": ["GET"]}},
+                    {{"host": "gateway.test:80", "path": "/b", "upstream": "{}", "clients": ["192.0.2.0/24"]}}
+                ]
+            }}"#,
+            url(0),
+            url(1),
+            url(2),
+            url(3),
+            url(4)
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let send = |method: &str, path: &str, extra: &str| {
+            format!(
+                "{} {} HTTP/1.1\r\nhost: gateway.test:80\r\ncontent-length: 0\r\n{}\r\n",
+                method, path, extra
+            )
+        };
+        for (req, expected) in [
+            (send("GET", "/a", "x-canary: 1\r\n"), "canary"),
+            (send("POST", "/a", "x-canary: 1\r\n"), "canary"),
+            (send("GET", "/a?v=2", ""), "v2"),
+            (send("GET", "/a?v=3", ""), "read"),
+            (send("POST", "/a", ""), "write"),
+        ] {
+            let resp = call(addr, &req).await;
+            assert_eq!(resp.body, Some(expected.as_bytes().to_vec()), "{}", req);
+        }
+
+        let resp = call(addr, &send("DELETE", "/a", "")).await;
+        assert_eq!(resp.status, StatusCode::MethodNotAllowed);
+        assert_eq!(
+            resp.headers.get("allow").unwrap(),
+            HeaderKind::Allow(Some(vec![Method::POST, Method::GET]))
+        );
+
+        let resp = call(addr, &send("GET", "/b", "")).await;
+        assert_eq!(resp.status, StatusCode::NotFound);
+        assert_eq!(upstreams[4].hits(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_cache_predicates() {
+        let stable = FakeUpstream::start("stable").await;
+        let canary = FakeUpstream::start("canary").await;
+        for (upstream, name) in [(&stable, "stable"), (&canary, "canary")] {
+            upstream.set_response(&format!(
+                "HTTP/1.1 200 OK\r\ncache-control: max-age=60\r\ncontent-length: {}\r\n\r\n{}",
+                name.len(),
+                name
+            ));
+        }
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "routes": [
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "headers": {{"x-canary": "1"}}, "options": {{"cache": {{}}}}}},
+                    {{"host": "gateway.test:80", "path": "/a", "upstream": "{}", "options": {{"cache": {{}}}}}}
+                ]
+            }}"#,
+            String::try_from(canary.url.clone()).unwrap(),
+            String::try_from(stable.url.clone()).unwrap(),
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        // each route caches its own response for the path
+        for (extra, expected, status) in [
+            ("", "stable", "MISS"),
+            ("x-canary: 1\r\n", "canary", "MISS"),
+            ("", "stable", "HIT"),
+            ("x-canary: 1\r\n", "canary", "HIT"),
+        ] {
+            let resp = call(addr, &request("/a", extra)).await;
+            assert_eq!(resp.body, Some(expected.as_bytes().to_vec()));
+            assert_eq!(resp.headers.raw[CACHE_STATUS_HEADER], status);
+        }
+        assert_eq!((stable.hits(), canary.hits()), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_path_params() {
+        let users = FakeUpstream::start("users").await;
+        let me = FakeUpstream::start("me").await;
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "routes": [
+                    {{
+                        "host": "gateway.test:80",
+                        "path": "/users/{{id}}/**",
+                        "upstream": "{}",
+                        "options": {{"rewrite": {{"path": "/v2/{{id}}/{{**}}"}}}}
+                    }},
+                    {{"host": "gateway.test:80", "path": "/users/me/**", "upstream": "{}"}}
+                ]
+            }}"#,
+            String::try_from(users.url.clone()).unwrap(),
+            String::try_from(me.url.clone()).unwrap(),
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let resp = call(addr, &request("/users/42/orders/7?full=1", "")).await;
+        assert_eq!(resp.body, Some(b"users".to_vec()));
+        assert_eq!(users.path(), "/v2/42/orders/7?full=1");
+
+        // literal segments take precedence over params
+        let resp = call(addr, &request("/users/me/orders", "")).await;
+        assert_eq!(resp.body, Some(b"me".to_vec()));
+        assert_eq!(me.path(), "/users/me/orders");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_forwarding() {
+        let upstream = FakeUpstream::start("a").await;
+        let endpoint = String::try_from(upstream.url.clone()).unwrap();
+        let route = format!(
+            r#"{{"host": "gateway.test:80", "path": "/a", "upstream": "{}"}}"#,
+            endpoint
+        );
+        let start = |forwarding: &str| {
+            let config = Config::from_str(&format!(
+                r#"{{"listeners": [{{"address": "127.0.0.1:0"}}], "forwarding": {}, "routes": [{}]}}"#,
+                forwarding, route
+            ))
+            .unwrap();
+            async move {
+                let proxy = Proxy::from_config(&config).await.unwrap();
+                let addr = proxy.local_addrs()[0];
+                tokio::spawn(proxy.run());
+                addr
+            }
+        };
+        let extra = "x-forwarded-for: 203.0.113.7\r\nx-request-id: req-1\r\n\
+            te: trailers\r\nconnection: keep-alive, x-secret\r\nx-secret: 1\r\n";
+
+        let addr = start("{}").await;
+        assert_eq!(
+            call(addr, &request("/a", extra)).await.status,
+            StatusCode::Ok
+        );
+        let headers = upstream.headers().raw;
+        assert_eq!(headers["x-forwarded-for"], "127.0.0.1");
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(headers["x-forwarded-host"], "gateway.test:80");
+        assert_eq!(
+            headers["forwarded"],
+            r#"for=127.0.0.1;host="gateway.test:80";proto=http"#
+        );
+        assert_eq!(headers["via"], "1.1 rsgateway");
+        assert_eq!(headers["x-request-id"], "req-1");
+        for k in ["te", "connection", "x-secret"] {
+            assert!(!headers.contains_key(k), "{} was forwarded", k);
+        }
+
+        let addr = start(r#"{"trusted_proxies": ["127.0.0.0/8"]}"#).await;
+        assert_eq!(call(addr, &request("/a", "")).await.status, StatusCode::Ok);
+        // a request id is generated when the client did not send one
+        assert_eq!(upstream.headers().raw["x-request-id"].len(), 32);
+        call(addr, &request("/a", extra)).await;
+        assert_eq!(
+            upstream.headers().raw["x-forwarded-for"],
+            "203.0.113.7, 127.0.0.1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tracing() {
+        let upstream = FakeUpstream::start("a").await;
+        let mut collector = FakeCollector::start().await;
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "tracing": {{"collector": "{}", "batch_size": 3}},
+                "routes": [{{"host": "gateway.test:80", "path": "/a", "upstream": "{}"}}]
+            }}"#,
+            String::try_from(collector.url.clone()).unwrap(),
+            String::try_from(upstream.url.clone()).unwrap(),
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        tokio::spawn(proxy.run());
+
+        let traceparent =
+            "traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01\r\n";
+        let resp = call(addr, &request("/a", traceparent)).await;
+        assert_eq!(resp.status, StatusCode::Ok);
+
+        // the upstream is told about the span of its request, in the same trace
+        let sent = TraceContext::from_headers(&upstream.headers()).unwrap();
+        assert_eq!(sent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_ne!(sent.parent_id, 0x00f067aa0ba902b7);
+
+        let payload = collector.received().await;
+        let resource = &payload.get("resourceSpans").unwrap().as_array().unwrap()[0];
+        let scope = &resource.get("scopeSpans").unwrap().as_array().unwrap()[0];
+        let spans = scope.get("spans").unwrap().as_array().unwrap();
+        let field = |i: usize, key: &str| spans[i].get(key).and_then(Node::as_str).unwrap();
+        let names: Vec<&str> = (0..spans.len()).map(|i| field(i, "name")).collect();
+        // the upstream url holds an address, nothing is resolved
+        assert_eq!(names, ["upstream.connect", "upstream.response", "proxy"]);
+        for i in 0..3 {
+            assert_eq!(field(i, "traceId"), "4bf92f3577b34da6a3ce929d0e0e4736");
+        }
+        assert_eq!(field(2, "parentSpanId"), "00f067aa0ba902b7");
+        assert_eq!(field(0, "parentSpanId"), field(2, "spanId"));
+        assert_eq!(field(1, "parentSpanId"), field(2, "spanId"));
+        assert_eq!(field(1, "spanId"), sent.parent_id_hex());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_metrics() {
+        let upstream = FakeUpstream::start("up").await;
+        let endpoint = String::try_from(upstream.url.clone()).unwrap();
+        let config = Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "127.0.0.1:0"}}],
+                "admin": {{"address": "127.0.0.1:0", "token": "secret"}},
+                "upstreams": {{"pool": {{"endpoints": [{{"url": "{}"}}]}}}},
+                "routes": [{{
+                    "host": "gateway.test:80", "path": "/", "upstream": "pool",
+                    "options": {{"rate_limit": {{"key": "client_ip", "limit": 2, "window_ms": 60000}}}}
+                }}]
+            }}"#,
+            endpoint
+        ))
+        .unwrap();
+        let proxy = Proxy::from_config(&config).await.unwrap();
+        let addr = proxy.local_addrs()[0];
+        let admin = proxy.admin_addr().unwrap();
+        tokio::spawn(proxy.run());
+
+        for status in [StatusCode::Ok, StatusCode::Ok, StatusCode::TooManyRequests] {
+            assert_eq!(call(addr, &request("/", "")).await.status, status);
+        }
+        let unknown = "GET / HTTP/1.1\r\nhost: other.test:80\r\n\r\n";
+        assert_eq!(call(addr, unknown).await.status, StatusCode::NotFound);
+
+        let expected = [
+            r#"rsgateway_requests_total{route="gateway.test:80/",method="GET",status="200"} 2"#
+                .to_string(),
+            r#"rsgateway_requests_total{route="gateway.test:80/",method="GET",status="429"} 1"#
+                .to_string(),
+            r#"rsgateway_requests_total{route="unmatched",method="GET",status="404"} 1"#
+                .to_string(),
+            r#"rsgateway_request_duration_seconds_count{route="gateway.test:80/"} 3"#.to_string(),
+            r#"rsgateway_upstream_latency_seconds_count{upstream="pool"} 2"#.to_string(),
+            r#"rsgateway_rate_limited_total{route="gateway.test:80/"} 1"#.to_string(),
+            format!(
+                r#"rsgateway_upstream_outstanding_requests{{upstream="pool",endpoint="{}"}} 0"#,
+                endpoint
+            ),
+            format!(
+                r#"rsgateway_upstream_available{{upstream="pool",endpoint="{}"}} 1"#,
+                endpoint
+            ),
+        ];
+        // requests are counted right after their response was sent
+        let mut text = String::new();
+        for _ in 0..100 {
+            let resp = call(
+                admin,
+                "GET /metrics HTTP/1.1\r\nhost: admin\r\nauthorization: Bearer secret\r\n\r\n",
+            )
+            .await;
+            assert_eq!(resp.status, StatusCode::Ok);
+            assert_eq!(
+                resp.headers.raw["content-type"],
+                crate::metrics::CONTENT_TYPE
+            );
+            text = String::from_utf8(resp.body.unwrap()).unwrap();
+            if text.contains(&expected[2]) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let lines: Vec<&str> = text.lines().collect();
+        for line in expected.iter() {
+            assert!(lines.contains(&line.as_str()), "{} not in\n{}", line, text);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_copy_reencodes() {
+        let mut from: &[u8] = b"5\r\nhello\r\n6\r\n world\r\n0\r\nx-checksum: 42\r\n\r\nrest";
+        let mut to = Vec::new();
+
+        let mut reader = BodyReader::new(Framing::Chunked);
+        let mut writer = BodyWriter::new(Framing::Chunked);
+        let n = copy(
+            &mut reader,
+            &mut from,
+            &mut writer,
+            &mut to,
+            Duration::from_secs(1),
+            false,
+            &mut Transit::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(n, 11);
+        assert_eq!(
+            to,
+            b"5\r\nhello\r\n6\r\n world\r\n0\r\nx-checksum:42\r\n\r\n".to_vec()
+        );
+        assert_eq!(from, b"rest");
+
+        let mut from: &[u8] = b"5\r\nhello\r\n0\r\n\r\n";
+        let mut to = Vec::new();
+        let mut reader = BodyReader::new(Framing::Chunked);
+        let mut writer = BodyWriter::new(Framing::Length(5));
+        copy(
+            &mut reader,
+            &mut from,
+            &mut writer,
+            &mut to,
+            Duration::from_secs(1),
+            false,
+            &mut Transit::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(to, b"hello".to_vec());
     }
 }
@@ -0,0 +1,247 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::interval,
+};
+
+use http::client::Client;
+
+use crate::{
+    accesslog::AccessLogConfig, admin::AdminConfig, cache::ResponseCache, config::Config,
+    error::ConfigError, forwarded::Forwarding, health, retry::RetryBudget, router::Router,
+    tracing::TracingConfig,
+};
+
+pub const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// Startup holds the sections of the config only read when the gateway starts,
+// reloads changing them have no effect.
+#[derive(Debug, PartialEq)]
+struct Startup {
+    listeners: Vec<String>,
+    admin: Option<AdminConfig>,
+    redis: Option<String>,
+    retry_budget: Arc<RetryBudget>,
+    access_log: Option<AccessLogConfig>,
+    tracing: Option<TracingConfig>,
+    forwarding: Forwarding,
+}
+
+impl From<&Config> for Startup {
+    fn from(config: &Config) -> Self {
+        Self {
+            listeners: config.listeners.iter().map(|l| l.address.clone()).collect(),
+            admin: config.admin.clone(),
+            redis: config.redis.clone(),
+            retry_budget: config.retry_budget.clone(),
+            access_log: config.access_log.clone(),
+            tracing: config.tracing.clone(),
+            forwarding: config.forwarding.clone(),
+        }
+    }
+}
+
+impl Startup {
+    // names of the sections that differ in the other config
+    fn changed(&self, other: &Startup) -> Vec<&'static str> {
+        [
+            ("listeners", self.listeners == other.listeners),
+            ("admin", self.admin == other.admin),
+            ("redis", self.redis == other.redis),
+            ("retry_budget", self.retry_budget == other.retry_budget),
+            ("access_log", self.access_log == other.access_log),
+            ("tracing", self.tracing == other.tracing),
+            ("forwarding", self.forwarding == other.forwarding),
+        ]
+        .into_iter()
+        .filter_map(|(name, same)| (!same).then_some(name))
+        .collect()
+    }
+}
+
+// Reloader rebuilds the routing table from the config file whenever the
+// process receives SIGHUP or the file is modified. A config that fails to
+// load is rejected and the previous table is kept.
+pub struct Reloader {
+    path: PathBuf,
+    router: Arc<Router>,
+    client: Client,
+    // the cache the gateway serves from, resized by reloads
+    cache: Arc<ResponseCache>,
+    startup: Startup,
+    poll_interval: Duration,
+}
+
+impl Reloader {
+    pub fn new(path: PathBuf, router: Arc<Router>, client: Client, config: &Config) -> Self {
+        Self {
+            path,
+            router,
+            client,
+            cache: config.cache.clone(),
+            startup: Startup::from(config),
+            poll_interval: RELOAD_POLL_INTERVAL,
+        }
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        fs::metadata(&self.path).and_then(|m| m.modified()).ok()
+    }
+
+    pub fn reload(&self) -> Result<usize, ConfigError> {
+        let config = Config::load(&self.path)?;
+
+        for section in self.startup.changed(&Startup::from(&config)) {
+            eprintln!(
+                "{}: changes to {} are ignored until the gateway is restarted",
+                self.path.display(),
+                section
+            );
+        }
+
+        self.router.update(&config);
+        self.cache.resize(config.cache.capacity());
+        // checks of the replaced pools stop once their last request is done
+        health::spawn(&self.client, &config.upstreams);
+        Ok(config.routes.len())
+    }
+
+    pub async fn run(self: Arc<Self>) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                eprintln!("unable to listen for SIGHUP, config reload disabled: {}", e);
+                return;
+            }
+        };
+        let mut ticker = interval(self.poll_interval);
+        let mut modified = self.modified();
+
+        loop {
+            tokio::select! {
+                _ = hangup.recv() => {}
+                _ = ticker.tick() => {
+                    let current = self.modified();
+                    if current == modified {
+                        continue;
+                    }
+                    modified = current;
+                }
+            }
+
+            match self.reload() {
+                Ok(n) => eprintln!("{}: reloaded {} routes", self.path.display(), n),
+                Err(e) => eprintln!(
+                    "{}: reload rejected, keeping previous routes: {}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use dns::resolver::DNS_IP_GOOGLE;
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "listeners": [{"address": "localhost:9090"}],
+        "routes": [
+            {"host": "localhost:9090", "path": "/api", "upstream": "http://127.0.0.1:8080/"}
+        ]
+    }"#;
+
+    #[test]
+    fn test_reload() {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), CONFIG).unwrap();
+
+        let config = Config::load(file.path()).unwrap();
+        let router = Arc::new(Router::new(config.trie()));
+        let reloader = Reloader::new(
+            file.path().to_path_buf(),
+            router.clone(),
+            Client::new(DNS_IP_GOOGLE),
+            &config,
+        );
+
+        let upstream = |router: &Router| {
+            router
+                .load()
+                .get("localhost:9090/api/v1")
+                .map(|route| String::try_from(route).unwrap())
+        };
+        assert_eq!(
+            upstream(&router),
+            Some("http://127.0.0.1:8080/".to_string())
+        );
+
+        // invalid config is rejected, previous routes are kept
+        fs::write(
+            file.path(),
+            r#"{"listeners": [{"address": "localhost:9090"}], "routes": [}"#,
+        )
+        .unwrap();
+        assert!(reloader.reload().is_err());
+        assert_eq!(
+            upstream(&router),
+            Some("http://127.0.0.1:8080/".to_string())
+        );
+
+        fs::write(file.path(), CONFIG.replace("8080", "8081")).unwrap();
+        assert_eq!(reloader.reload().unwrap(), 1);
+        assert_eq!(
+            upstream(&router),
+            Some("http://127.0.0.1:8081/".to_string())
+        );
+
+        // the cache the gateway started with takes the new capacity
+        let cached = CONFIG.replace(
+            r#""routes""#,
+            r#""cache": {"capacity_bytes": 1024}, "routes""#,
+        );
+        fs::write(file.path(), cached).unwrap();
+        reloader.reload().unwrap();
+        assert_eq!(config.cache.capacity(), 1024);
+    }
+
+    #[test]
+    fn test_reload_changed_startup_sections() {
+        let startup = Startup::from(&Config::from_str(CONFIG).unwrap());
+        assert_eq!(
+            startup.changed(&Startup::from(&Config::from_str(CONFIG).unwrap())),
+            Vec::<&str>::new()
+        );
+
+        let config = CONFIG.replace(
+            r#""routes""#,
+            r#""cache": {"capacity_bytes": 1024},
+            "retry_budget": {"percent": 50},
+            "access_log": {"sink": "stdout"},
+            "forwarding": {"pseudonym": "edge"},
+            "routes""#,
+        );
+        let config = Config::from_str(&config.replace("9090\"}]", "9091\"}]")).unwrap();
+        assert_eq!(
+            startup.changed(&Startup::from(&config)),
+            ["listeners", "retry_budget", "access_log", "forwarding"]
+        );
+    }
+}
@@ -0,0 +1,218 @@
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use http::{error::frame::FrameError, method::Method, statuscode::StatusCode};
+
+use crate::error::{Phase, ProxyError};
+
+// largest request body kept in memory so that the request can be sent again,
+// requests with a larger or chunked body are never retried
+pub const MAX_REPLAY_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RetryOn {
+    // the upstream could not be reached
+    ConnectError,
+    // the upstream closed or reset the connection before answering
+    Reset,
+    Status(StatusCode),
+}
+
+// RetryPolicy tells when a failed request is sent again, to another endpoint
+// of the pool when there is one. Only idempotent methods are retried unless
+// `non_idempotent` is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    // attempts including the first one, 1 disables retries
+    pub attempts: usize,
+    pub on: Vec<RetryOn>,
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+    pub non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 1,
+            on: vec![RetryOn::ConnectError, RetryOn::Reset],
+            backoff: Duration::from_millis(25),
+            max_backoff: Duration::from_millis(250),
+            non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn allows(&self, method: &Method) -> bool {
+        self.attempts > 1 && (self.non_idempotent || method.idempotent())
+    }
+
+    pub fn retries_error(&self, e: &ProxyError) -> bool {
+        let on = match e {
+            ProxyError::Connect(_) | ProxyError::Timeout(Phase::Connect) => RetryOn::ConnectError,
+            ProxyError::Upstream(FrameError::IOError(_)) => RetryOn::Reset,
+            // closed before the status line
+            ProxyError::Upstream(FrameError::Invalid {
+                subject: "response",
+                ..
+            }) => RetryOn::Reset,
+            _ => return false,
+        };
+        self.on.contains(&on)
+    }
+
+    pub fn retries_status(&self, status: StatusCode) -> bool {
+        self.on.contains(&RetryOn::Status(status))
+    }
+
+    // delay before the nth retry, doubling from `backoff` up to `max_backoff`.
+    // Half of it is random so that clients failing together spread out.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        let delay = self.backoff.saturating_mul(factor).min(self.max_backoff);
+        let half = delay / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+// thousandths of a token, so that requests can deposit a fraction of one
+const MILLI: usize = 1000;
+
+// RetryBudget caps the retries of the whole gateway to a percentage of the
+// requests, so that retries do not pile up on upstreams which are already
+// failing. Up to `reserve` retries are allowed in a burst.
+#[derive(Debug)]
+pub struct RetryBudget {
+    pub percent: usize,
+    pub reserve: usize,
+    balance: AtomicUsize,
+}
+
+impl PartialEq for RetryBudget {
+    fn eq(&self, other: &Self) -> bool {
+        self.percent == other.percent && self.reserve == other.reserve
+    }
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        RetryBudget::new(20, 10)
+    }
+}
+
+impl RetryBudget {
+    pub fn new(percent: usize, reserve: usize) -> Self {
+        Self {
+            percent,
+            reserve,
+            balance: AtomicUsize::new(reserve * MILLI),
+        }
+    }
+
+    // records a request, which earns a fraction of a retry
+    pub fn deposit(&self) {
+        let cap = self.reserve * MILLI;
+        let _ = self
+            .balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| {
+                Some((balance + self.percent * MILLI / 100).min(cap))
+            });
+    }
+
+    // takes a retry from the budget, returns false when it is exhausted
+    pub fn withdraw(&self) -> bool {
+        self.balance
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |balance| {
+                balance.checked_sub(MILLI)
+            })
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(50, 2);
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+
+        // every other request earns a retry
+        budget.deposit();
+        assert!(!budget.withdraw());
+        budget.deposit();
+        assert!(budget.withdraw());
+
+        // the balance never exceeds the reserve
+        for _ in 0..100 {
+            budget.deposit();
+        }
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let policy = RetryPolicy {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..Default::default()
+        };
+        for (retry, max) in [(1, 100), (2, 200), (3, 300), (10, 300)] {
+            let delay = policy.backoff(retry);
+            assert!(delay >= Duration::from_millis(max / 2), "{:?}", delay);
+            assert!(delay <= Duration::from_millis(max), "{:?}", delay);
+        }
+    }
+
+    #[rstest]
+    #[case(Method::GET, false, true)]
+    #[case(Method::PUT, false, true)]
+    #[case(Method::POST, false, false)]
+    #[case(Method::POST, true, true)]
+    fn test_retry_allows(
+        #[case] method: Method,
+        #[case] non_idempotent: bool,
+        #[case] expected: bool,
+    ) {
+        let policy = RetryPolicy {
+            attempts: 2,
+            non_idempotent,
+            ..Default::default()
+        };
+        assert_eq!(policy.allows(&method), expected);
+        assert!(!RetryPolicy::default().allows(&method));
+    }
+
+    #[test]
+    fn test_retry_conditions() {
+        let policy = RetryPolicy {
+            attempts: 3,
+            on: vec![
+                RetryOn::ConnectError,
+                RetryOn::Status(StatusCode::ServiceUnavailable),
+            ],
+            ..Default::default()
+        };
+        let reset = || {
+            ProxyError::Upstream(FrameError::IOError(std::io::Error::from(
+                std::io::ErrorKind::ConnectionReset,
+            )))
+        };
+        assert!(policy.retries_error(&ProxyError::Timeout(Phase::Connect)));
+        assert!(!policy.retries_error(&reset()));
+        assert!(!policy.retries_error(&ProxyError::Timeout(Phase::FirstByte)));
+        assert!(policy.retries_status(StatusCode::ServiceUnavailable));
+        assert!(!policy.retries_status(StatusCode::BadGateway));
+
+        assert!(RetryPolicy::default().retries_error(&reset()));
+    }
+}
@@ -0,0 +1,338 @@
+use std::str::FromStr;
+
+use http::{error::frame::FrameError, header::HeaderMap, request::Request};
+
+use crate::trie::{Captures, CATCH_ALL};
+
+// PathRewrite changes the path a request is sent to the upstream with, the
+// query is kept as received.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathRewrite {
+    // "/api" turns "/api/users" into "/users"
+    StripPrefix(String),
+    // "/api" replaced with "/v2" turns "/api/users" into "/v2/users"
+    ReplacePrefix { prefix: String, with: String },
+    Template(Template),
+}
+
+impl PathRewrite {
+    pub fn apply(&self, path: &str, captures: &Captures) -> String {
+        match self {
+            PathRewrite::StripPrefix(prefix) => match strip(path, prefix) {
+                Some("") => "/".to_string(),
+                Some(rest) => rest.to_string(),
+                None => path.to_string(),
+            },
+            PathRewrite::ReplacePrefix { prefix, with } => match strip(path, prefix) {
+                Some(rest) => {
+                    let mut res = with.trim_end_matches('/').to_string();
+                    res.push_str(rest);
+                    if res.is_empty() {
+                        res.push('/');
+                    }
+                    res
+                }
+                None => path.to_string(),
+            },
+            PathRewrite::Template(template) => template.render(path, captures),
+        }
+    }
+}
+
+// rest of the path after the prefix, which only matches whole segments so
+// that "/api" does not match "/apis"
+fn strip<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix.trim_end_matches('/'))?;
+    match rest.is_empty() || rest.starts_with('/') {
+        true => Some(rest),
+        false => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Literal(String),
+    // segment of the inbound path, from 1
+    Segment(usize),
+    // segments of the inbound path from the given one on
+    Segments(usize),
+    // value captured by a param or the catch-all of the route path
+    Capture(String),
+}
+
+// Template builds a path out of the segments of the inbound one, e.g.
+// "/v2/{2}/items/{3..}" turns "/api/users/42/orders" into
+// "/v2/users/items/42/orders", and out of the values captured by the route
+// path, e.g. "/v2/{id}/{**}" with the route "/users/{id}/**". Segments
+// missing from the inbound path are left empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    // names of the captures the template refers to
+    pub fn captures(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Capture(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn render(&self, path: &str, captures: &Captures) -> String {
+        let segments: Vec<&str> = path.split('/').skip(1).collect();
+        let mut res = String::new();
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(s) => res.push_str(s),
+                Part::Segment(n) => res.push_str(segments.get(n - 1).unwrap_or(&"")),
+                Part::Segments(n) => {
+                    res.push_str(&segments.get(n - 1..).unwrap_or_default().join("/"))
+                }
+                Part::Capture(name) => res.push_str(captures.get(name).unwrap_or_default()),
+            }
+        }
+        res
+    }
+}
+
+impl FromStr for Template {
+    type Err = FrameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason| FrameError::Invalid {
+            reason,
+            subject: "template",
+        };
+        if !s.starts_with('/') {
+            return Err(invalid("template should start with '/'"));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                return Err(invalid("unclosed '{'"));
+            };
+            let placeholder = &rest[start + 1..start + end];
+            let (n, part): (&str, fn(usize) -> Part) = match placeholder.strip_suffix("..") {
+                Some(n) => (n, Part::Segments),
+                None => (placeholder, Part::Segment),
+            };
+            // names start with a letter, so as not to be read as numbers
+            let name = placeholder.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && placeholder
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'_');
+            match n.parse::<usize>() {
+                Ok(i) if i > 0 && n.bytes().all(|b| b.is_ascii_digit()) => parts.push(part(i)),
+                _ if name || placeholder == CATCH_ALL => {
+                    parts.push(Part::Capture(placeholder.to_string()))
+                }
+                _ => {
+                    return Err(invalid(
+                        "expected a segment number such as {1} or {2..}, or a capture such as {id} or {**}",
+                    ))
+                }
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(invalid("unexpected '}'"));
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Self { parts })
+    }
+}
+
+// HeaderRules change the headers of a message: removed ones first, then set
+// ones, then added ones.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HeaderRules {
+    // appended to the values already present
+    pub add: Vec<(String, String)>,
+    // replace the values already present
+    pub set: Vec<(String, String)>,
+    pub remove: Vec<String>,
+}
+
+impl HeaderRules {
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for k in self.remove.iter() {
+            headers.raw.remove(&k.to_lowercase());
+        }
+        for (k, v) in self.set.iter() {
+            headers.raw.insert(k.to_lowercase(), v.clone());
+        }
+        for (k, v) in self.add.iter() {
+            headers.append(k, v);
+        }
+    }
+}
+
+// Rewrite transforms requests on their way to the upstream of a route, and
+// the responses on their way back.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Rewrite {
+    pub path: Option<PathRewrite>,
+    // host header sent to the upstream, instead of its authority
+    pub host: Option<String>,
+    pub request_headers: HeaderRules,
+    pub response_headers: HeaderRules,
+}
+
+impl Rewrite {
+    pub fn request(&self, req: &mut Request, captures: &Captures) {
+        if let Some(rewrite) = &self.path {
+            let path = &mut req.parts.url.path;
+            path.raw_path = rewrite.apply(&path.raw_path, captures);
+        }
+        if let Some(host) = &self.host {
+            req.parts
+                .headers
+                .raw
+                .insert("host".to_string(), host.clone());
+        }
+        self.request_headers.apply(&mut req.parts.headers);
+    }
+
+    pub fn response(&self, headers: &mut HeaderMap) {
+        self.response_headers.apply(headers);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{builder::Builder, uri::url::Url};
+
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::*;
+
+    fn request(url: &str) -> Request {
+        Builder::new().url(Url::from_str(url).unwrap()).build()
+    }
+
+    #[rstest]
+    #[case(PathRewrite::StripPrefix("/api".to_string()), "/api/users", "/users")]
+    #[case(PathRewrite::StripPrefix("/api/".to_string()), "/api", "/")]
+    #[case(PathRewrite::StripPrefix("/api".to_string()), "/apis/users", "/apis/users")]
+    #[case(PathRewrite::ReplacePrefix {
+        prefix: "/api".to_string(),
+        with: "/v2/".to_string(),
+    }, "/api/users", "/v2/users")]
+    #[case(PathRewrite::ReplacePrefix {
+        prefix: "/api".to_string(),
+        with: "/".to_string(),
+    }, "/api", "/")]
+    #[case(PathRewrite::Template(
+        Template::from_str("/v2/{2}/items/{3..}").unwrap()
+    ), "/api/users/42/orders", "/v2/users/items/42/orders")]
+    #[case(PathRewrite::Template(
+        Template::from_str("/{2}/{1}").unwrap()
+    ), "/a", "//a")]
+    #[case(PathRewrite::Template(
+        Template::from_str("/static").unwrap()
+    ), "/a/b", "/static")]
+    fn test_path_rewrite(#[case] rewrite: PathRewrite, #[case] path: &str, #[case] expected: &str) {
+        assert_eq!(rewrite.apply(path, &Captures::default()), expected);
+    }
+
+    #[test]
+    fn test_template_captures() {
+        let template = Template::from_str("/v2/{id}/{**}/{1}").unwrap();
+        assert_eq!(template.captures().collect::<Vec<_>>(), ["id", "**"]);
+
+        let captures = Captures::from([("id", "42"), ("**", "a/b")]);
+        assert_eq!(
+            template.render("/users/42/files/a/b", &captures),
+            "/v2/42/a/b/users"
+        );
+        // captures the route did not make are left empty
+        assert_eq!(
+            template.render("/users", &Captures::default()),
+            "/v2///users"
+        );
+    }
+
+    #[rstest]
+    #[case("v2/{1}")]
+    #[case("/v2/{1")]
+    #[case("/v2/1}")]
+    #[case("/v2/{0}")]
+    #[case("/v2/{a-b}")]
+    #[case("/v2/{*}")]
+    #[case("/v2/{+1}")]
+    #[case("/v2/{..}")]
+    fn test_template_invalid(#[case] input: &str) {
+        assert!(Template::from_str(input).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_request() {
+        let rewrite = Rewrite {
+            path: Some(PathRewrite::StripPrefix("/api".to_string())),
+            host: Some("backend.internal:8080".to_string()),
+            request_headers: HeaderRules {
+                add: vec![("X-Tag".to_string(), "gateway".to_string())],
+                set: vec![("x-env".to_string(), "prod".to_string())],
+                remove: vec!["Cookie".to_string()],
+            },
+            response_headers: HeaderRules::default(),
+        };
+        let mut req = request("http://127.0.0.1:8080/api/users?page=2");
+        req.parts
+            .headers
+            .raw
+            .insert("x-tag".to_string(), "client".to_string());
+        req.parts
+            .headers
+            .raw
+            .insert("x-env".to_string(), "dev".to_string());
+        req.parts
+            .headers
+            .raw
+            .insert("cookie".to_string(), "session=1".to_string());
+        rewrite.request(&mut req, &Captures::default());
+
+        assert_eq!(req.parts.url.path.raw_path, "/users");
+        assert_eq!(req.parts.url.path.query.unwrap().raw(), "page=2");
+        let headers = req.parts.headers.raw;
+        assert_eq!(headers["host"], "backend.internal:8080");
+        assert_eq!(headers["x-tag"], "client, gateway");
+        assert_eq!(headers["x-env"], "prod");
+        assert!(!headers.contains_key("cookie"));
+    }
+
+    #[test]
+    fn test_rewrite_default_keeps_request() {
+        let mut req = request("http://127.0.0.1:8080/api/users");
+        let before = req.parts.clone();
+        Rewrite::default().request(&mut req, &Captures::default());
+        assert_eq!(req.parts, before);
+    }
+
+    #[test]
+    fn test_rewrite_response() {
+        let rewrite = Rewrite {
+            response_headers: HeaderRules {
+                add: vec![("vary".to_string(), "Origin".to_string())],
+                set: vec![],
+                remove: vec!["server".to_string()],
+            },
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::default();
+        headers.parse("Server: nginx").unwrap();
+        headers.parse("Vary: Accept-Encoding").unwrap();
+        rewrite.response(&mut headers);
+
+        assert_eq!(headers.raw.get("server"), None);
+        assert_eq!(headers.raw["vary"], "Accept-Encoding, Origin");
+    }
+}
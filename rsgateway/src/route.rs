@@ -1,4 +1,11 @@
-use http::{error::frame::FrameError, uri::url::Url};
+use std::{sync::Arc, time::Duration};
+
+use http::error::frame::FrameError;
+
+use crate::{
+    cache::CachePolicy, compression::Compression, predicate::Predicates, ratelimit::RateLimit,
+    retry::RetryPolicy, rewrite::Rewrite, upstream::Pool,
+};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum MatchType {
@@ -6,15 +13,67 @@ pub enum MatchType {
     Prefix,
 }
 
+pub const BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+pub const FIRST_BYTE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Timeouts bound each phase of a proxied request, so that a stalled client or
+// upstream does not hold a connection forever.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeouts {
+    // longest wait for the next piece of a body, from the client or upstream
+    pub body_read: Duration,
+    pub connect: Duration,
+    // longest wait for the response head once the request was sent
+    pub first_byte: Duration,
+    // limit of the whole exchange, from the request head to the last byte of
+    // the response, unbounded by default so that long downloads are allowed
+    pub total: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            body_read: BODY_READ_TIMEOUT,
+            connect: CONNECT_TIMEOUT,
+            first_byte: FIRST_BYTE_TIMEOUT,
+            total: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RouteOptions {
+    // forward the client's host header instead of the upstream authority
+    pub preserve_host: bool,
+    pub timeouts: Timeouts,
+    pub retries: RetryPolicy,
+    pub rate_limit: Option<RateLimit>,
+    // responses are only cached for routes with a policy
+    pub cache: Option<CachePolicy>,
+    // responses are only compressed for routes with compression
+    pub compression: Option<Compression>,
+    pub rewrite: Rewrite,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Route {
-    pub url: Url,
+    // host and path the route is defined with, e.g. "example.com:80/api",
+    // and its position among the routes when it has predicates, e.g.
+    // "example.com:80/api#2"
+    pub name: String,
+    pub upstream: Arc<Pool>,
     pub match_type: MatchType,
+    pub predicates: Predicates,
+    // routes sharing a path are tried from the highest priority, then in the
+    // order they are defined
+    pub priority: usize,
+    pub options: RouteOptions,
 }
 
 impl TryFrom<Route> for String {
     type Error = FrameError;
     fn try_from(route: Route) -> Result<Self, Self::Error> {
-        String::try_from(route.url)
+        Ok(route.upstream.name.clone())
     }
 }
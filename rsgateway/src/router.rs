@@ -0,0 +1,198 @@
+use std::sync::{Arc, RwLock};
+
+use crate::{config::Config, ratelimit::RateLimit, route::Route, trie::Trie, upstream::Pool};
+
+// Router holds the routing table currently in use. Readers take a snapshot of
+// the table so that a reload swapping it does not affect in-flight requests.
+#[derive(Debug, Default)]
+pub struct Router {
+    trie: RwLock<Arc<Trie>>,
+    // pools defined in the upstreams of the config the table was built from
+    upstreams: RwLock<Arc<Vec<Arc<Pool>>>>,
+}
+
+impl Router {
+    pub fn new(trie: Trie) -> Self {
+        Self {
+            trie: RwLock::new(Arc::new(trie)),
+            upstreams: RwLock::default(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let router = Router::default();
+        router.update(config);
+        router
+    }
+
+    pub fn load(&self) -> Arc<Trie> {
+        self.trie.read().expect("router lock poisoned").clone()
+    }
+
+    pub fn store(&self, trie: Trie) {
+        *self.trie.write().expect("router lock poisoned") = Arc::new(trie);
+    }
+
+    pub fn upstreams(&self) -> Arc<Vec<Arc<Pool>>> {
+        self.upstreams.read().expect("router lock poisoned").clone()
+    }
+
+    // swaps the table and the pools for the ones of the given config. The
+    // pools and rate limits left unchanged keep their state: the health of
+    // the endpoints, the circuit breakers and the local counters.
+    pub fn update(&self, config: &Config) {
+        let (previous, upstreams) = (self.load(), self.upstreams());
+        let previous_pools = pools(upstreams.iter(), previous.routes());
+        let pools = pools(
+            config.upstreams.iter(),
+            config.routes.iter().map(|route| &route.route),
+        );
+        for pool in pools.iter() {
+            if let Some(old) = previous_pools.iter().find(|old| old.name == pool.name) {
+                pool.inherit(old);
+            }
+        }
+
+        let limits: Vec<&RateLimit> = previous
+            .routes()
+            .into_iter()
+            .filter_map(|route| route.options.rate_limit.as_ref())
+            .collect();
+        let mut trie = Trie::new();
+        for route in config.routes.iter() {
+            let mut new = route.route.clone();
+            if let Some(limit) = new.options.rate_limit.as_mut() {
+                if let Some(old) = limits.iter().find(|old| **old == limit) {
+                    limit.inherit(old);
+                }
+            }
+            trie.insert(&route.key(), Some(new));
+        }
+
+        *self.upstreams.write().expect("router lock poisoned") = Arc::new(config.upstreams.clone());
+        self.store(trie);
+    }
+}
+
+// pools defined in the upstreams and the ones given inline by routes
+fn pools<'a>(
+    upstreams: impl Iterator<Item = &'a Arc<Pool>>,
+    routes: impl IntoIterator<Item = &'a Route>,
+) -> Vec<&'a Arc<Pool>> {
+    let mut res: Vec<&Arc<Pool>> = upstreams.collect();
+    for route in routes {
+        if !res.iter().any(|pool| Arc::ptr_eq(pool, &route.upstream)) {
+            res.push(&route.upstream);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use http::uri::url::Url;
+
+    use super::*;
+    use crate::{
+        breaker::BreakerState,
+        predicate::Predicates,
+        route::{MatchType, Route, RouteOptions},
+        upstream::Pool,
+    };
+
+    fn route(url: &str) -> Option<Route> {
+        Some(Route {
+            name: "localhost:9090/api".to_string(),
+            upstream: Arc::new(Pool::single(Url::from_str(url).unwrap())),
+            match_type: MatchType::Prefix,
+            predicates: Predicates::default(),
+            priority: 0,
+            options: RouteOptions::default(),
+        })
+    }
+
+    fn config(urls: &str, limit: usize) -> Config {
+        Config::from_str(&format!(
+            r#"{{
+                "listeners": [{{"address": "localhost:9090"}}],
+                "upstreams": {{"pool": {{
+                    "endpoints": [{}],
+                    "health_check": {{"unhealthy_threshold": 1}},
+                    "outlier_detection": {{"consecutive_failures": 1}},
+                    "circuit_breaker": {{"consecutive_failures": 1}}
+                }}}},
+                "routes": [
+                    {{"host": "localhost:9090", "path": "/api", "upstream": "pool",
+                      "options": {{"rate_limit": {{"limit": {}, "window_ms": 60000}}}}}}
+                ]
+            }}"#,
+            urls, limit
+        ))
+        .unwrap()
+    }
+
+    async fn allowed(router: &Router) -> bool {
+        let route = router.load().get("localhost:9090/api").unwrap();
+        let limit = route.options.rate_limit.unwrap();
+        limit.check("k", None).await.unwrap().allowed
+    }
+
+    #[tokio::test]
+    async fn test_router_update_keeps_state() {
+        let router = Router::from_config(&config(
+            r#"{"url": "http://127.0.0.1:8080/"}, {"url": "http://127.0.0.1:8081/"}"#,
+            1,
+        ));
+        let pool = router.upstreams()[0].clone();
+        let check = pool.health_check.clone().unwrap();
+        let outlier = pool.outlier_detection.clone().unwrap();
+        pool.endpoints[0].health.drain(true);
+        pool.endpoints[0].health.probed(false, &check);
+        pool.endpoints[1].health.observed(false, &outlier);
+        pool.record(pool.admit().unwrap(), false);
+        assert!(allowed(&router).await);
+
+        // endpoints are matched by url, whatever their order
+        router.update(&config(
+            r#"{"url": "http://127.0.0.1:8082/"}, {"url": "http://127.0.0.1:8081/"},
+               {"url": "http://127.0.0.1:8080/"}"#,
+            1,
+        ));
+        let pool = router.upstreams()[0].clone();
+        assert!(pool.endpoints[0].health.available());
+        assert!(pool.endpoints[1].health.ejected());
+        assert!(pool.endpoints[2].health.drained());
+        assert!(!pool.endpoints[2].health.healthy());
+        let breaker = pool.circuit_breaker.as_ref().unwrap();
+        assert_eq!(breaker.state(), BreakerState::Open);
+        assert!(!allowed(&router).await);
+
+        // a changed limit starts counting again
+        router.update(&config(r#"{"url": "http://127.0.0.1:8080/"}"#, 2));
+        assert!(allowed(&router).await);
+    }
+
+    #[test]
+    fn test_router_swap_keeps_snapshots() {
+        let mut trie = Trie::new();
+        trie.insert("localhost:9090/api", route("http://127.0.0.1:8080/"));
+        let router = Router::new(trie);
+
+        let before = router.load();
+
+        let mut trie = Trie::new();
+        trie.insert("localhost:9090/api", route("http://127.0.0.1:8081/"));
+        router.store(trie);
+
+        assert_eq!(
+            before.get("localhost:9090/api/v1"),
+            route("http://127.0.0.1:8080/")
+        );
+        assert_eq!(
+            router.load().get("localhost:9090/api/v1"),
+            route("http://127.0.0.1:8081/")
+        );
+    }
+}
@@ -0,0 +1,319 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use redis::{client::Client, command::Command, frame::Frame};
+use tokio::{net::TcpStream, sync::Semaphore, time::Instant};
+
+use crate::error::StoreError;
+
+// longest wait for redis, past it the gateway goes on without redis
+pub const REDIS_TIMEOUT: Duration = Duration::from_millis(500);
+// most connections open to redis at once, calls wait for one past it
+pub const REDIS_POOL_SIZE: usize = 8;
+// wait before trying redis again once it failed, doubled on each failure in
+// a row up to REDIS_MAX_BACKOFF
+pub const REDIS_BACKOFF: Duration = Duration::from_millis(100);
+pub const REDIS_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+// Store tells where the state of a feature lives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Store {
+    // state of this gateway instance only
+    Local,
+    // state shared by every gateway instance using the same redis
+    Redis,
+}
+
+// RedisStore is the redis server shared by the gateway instances, reached
+// over a small pool of connections opened when needed. Once redis could not
+// be reached or did not answer in time, calls fail without trying it until
+// a backoff elapsed.
+#[derive(Debug)]
+pub struct RedisStore {
+    pub address: String,
+    // connections not in use
+    idle: Mutex<Vec<Connection>>,
+    // one per connection in use
+    permits: Semaphore,
+    down: Mutex<Option<Down>>,
+}
+
+#[derive(Debug)]
+struct Down {
+    // calls fail until then
+    until: Instant,
+    backoff: Duration,
+}
+
+struct Connection {
+    client: Client,
+    // sha of the scripts loaded on the server, by script
+    shas: HashMap<&'static str, String>,
+}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("connection")
+            .field("shas", &self.shas)
+            .finish()
+    }
+}
+
+impl Connection {
+    async fn command(&mut self, command: Command) -> Result<Frame, StoreError> {
+        match self.client.do_command(command).await? {
+            Some(Frame::SimpleError(e)) => Err(StoreError::Reply(e)),
+            Some(frame) => Ok(frame),
+            None => Err(StoreError::Redis(redis::frame::FrameError::ConnectionError)),
+        }
+    }
+
+    // runs a script by its sha, loading it first when the server lacks it
+    async fn eval(
+        &mut self,
+        script: &'static str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    ) -> Result<Frame, StoreError> {
+        if let Some(sha) = self.shas.get(script).cloned() {
+            let command = Command::EvalSha {
+                sha,
+                keys: keys.clone(),
+                args: args.clone(),
+            };
+            match self.command(command).await {
+                Err(StoreError::Reply(e)) if e.starts_with("NOSCRIPT") => {}
+                res => return res,
+            }
+        }
+
+        let command = Command::ScriptLoad {
+            script: script.to_string(),
+        };
+        match self.command(command).await? {
+            Frame::BulkString(Some(sha)) => self.shas.insert(script, sha.clone()),
+            frame => return Err(StoreError::Reply(format!("{:?}", frame))),
+        };
+        let sha = self.shas[script].clone();
+        self.command(Command::EvalSha { sha, keys, args }).await
+    }
+}
+
+// what is run over the connection
+enum Call {
+    Command(Command),
+    Eval {
+        script: &'static str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    },
+}
+
+impl RedisStore {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            idle: Mutex::new(Vec::new()),
+            permits: Semaphore::new(REDIS_POOL_SIZE),
+            down: Mutex::new(None),
+        }
+    }
+
+    pub async fn command(&self, command: Command) -> Result<Frame, StoreError> {
+        self.call(Call::Command(command)).await
+    }
+
+    pub async fn eval(
+        &self,
+        script: &'static str,
+        keys: Vec<String>,
+        args: Vec<String>,
+    ) -> Result<Frame, StoreError> {
+        self.call(Call::Eval { script, keys, args }).await
+    }
+
+    fn idle(&self) -> MutexGuard<'_, Vec<Connection>> {
+        self.idle.lock().expect("redis pool lock poisoned")
+    }
+
+    fn down(&self) -> MutexGuard<'_, Option<Down>> {
+        self.down.lock().expect("redis state lock poisoned")
+    }
+
+    async fn call(&self, call: Call) -> Result<Frame, StoreError> {
+        self.admit()?;
+        let res = tokio::time::timeout(REDIS_TIMEOUT, async {
+            let _permit = self.permits.acquire().await.expect("never closed");
+            let idle = self.idle().pop();
+            let mut conn = match idle {
+                Some(conn) => conn,
+                None => Connection {
+                    client: Client::new(TcpStream::connect(&self.address).await?),
+                    shas: HashMap::new(),
+                },
+            };
+            let res = match call {
+                Call::Command(command) => conn.command(command).await,
+                Call::Eval { script, keys, args } => conn.eval(script, keys, args).await,
+            };
+            // a connection in an unknown state is not used again
+            match res {
+                Ok(_) | Err(StoreError::Reply(_)) => self.idle().push(conn),
+                Err(_) => {}
+            }
+            res
+        })
+        .await
+        .unwrap_or(Err(StoreError::Timeout));
+
+        match &res {
+            Ok(_) | Err(StoreError::Reply(_)) => *self.down() = None,
+            Err(_) => self.failed(),
+        }
+        res
+    }
+
+    // fails the call while redis is down. Once the backoff elapsed, a single
+    // call tries redis again, the others keep failing until it is done.
+    fn admit(&self) -> Result<(), StoreError> {
+        let mut down = self.down();
+        match down.as_mut() {
+            None => Ok(()),
+            Some(down) if Instant::now() < down.until => Err(StoreError::Unavailable),
+            Some(down) => {
+                down.until = Instant::now() + REDIS_TIMEOUT;
+                Ok(())
+            }
+        }
+    }
+
+    fn failed(&self) {
+        let mut down = self.down();
+        let backoff = match down.as_ref() {
+            Some(down) => (down.backoff * 2).min(REDIS_MAX_BACKOFF),
+            None => REDIS_BACKOFF,
+        };
+        *down = Some(Down {
+            until: Instant::now() + backoff,
+            backoff,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    // starts a redis answering 1 to every command after the delay, or closing
+    // the connection while failing. Returns its address and the number of
+    // connections it accepted.
+    async fn fake_redis(delay: Duration, failing: Arc<AtomicBool>) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                counter.fetch_add(1, Ordering::Relaxed);
+                let failing = failing.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    while let Ok(1..) = stream.read(&mut buf).await {
+                        if failing.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        tokio::time::sleep(delay).await;
+                        if stream.write_all(b":1\r\n").await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (address, accepted)
+    }
+
+    fn incr() -> Command {
+        Command::Incr {
+            key: "k".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redis_store_pool() {
+        let failing = Arc::new(AtomicBool::new(false));
+        let (address, accepted) = fake_redis(Duration::from_millis(100), failing).await;
+        let redis = Arc::new(RedisStore::new(&address));
+
+        // calls run side by side on connections of their own
+        let start = Instant::now();
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..REDIS_POOL_SIZE {
+            let redis = redis.clone();
+            set.spawn(async move { redis.command(incr()).await.unwrap() });
+        }
+        while let Some(res) = set.join_next().await {
+            assert_eq!(res.unwrap(), Frame::Integer(1));
+        }
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(accepted.load(Ordering::Relaxed), REDIS_POOL_SIZE);
+
+        // and are kept for later calls
+        redis.command(incr()).await.unwrap();
+        assert_eq!(accepted.load(Ordering::Relaxed), REDIS_POOL_SIZE);
+        assert_eq!(redis.idle().len(), REDIS_POOL_SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_redis_store_backoff() {
+        let failing = Arc::new(AtomicBool::new(true));
+        let (address, accepted) = fake_redis(Duration::ZERO, failing.clone()).await;
+        let redis = RedisStore::new(&address);
+
+        assert!(matches!(
+            redis.command(incr()).await,
+            Err(StoreError::Redis(_))
+        ));
+        failing.store(false, Ordering::Relaxed);
+
+        // redis is not tried again until the backoff elapsed
+        assert!(matches!(
+            redis.command(incr()).await,
+            Err(StoreError::Unavailable)
+        ));
+        assert_eq!(accepted.load(Ordering::Relaxed), 1);
+
+        tokio::time::sleep(REDIS_BACKOFF).await;
+        assert_eq!(redis.command(incr()).await.unwrap(), Frame::Integer(1));
+        assert_eq!(redis.command(incr()).await.unwrap(), Frame::Integer(1));
+        assert_eq!(accepted.load(Ordering::Relaxed), 2);
+
+        // unreachable redis, the backoff doubles on each failure in a row
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let redis = RedisStore::new(&closed.local_addr().unwrap().to_string());
+        drop(closed);
+        for backoff in [REDIS_BACKOFF, REDIS_BACKOFF * 2] {
+            if let Some(down) = redis.down().as_mut() {
+                down.until = Instant::now();
+            }
+            assert!(matches!(
+                redis.command(incr()).await,
+                Err(StoreError::Redis(_))
+            ));
+            assert_eq!(redis.down().as_ref().unwrap().backoff, backoff);
+        }
+    }
+}
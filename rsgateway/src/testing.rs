@@ -0,0 +1,214 @@
+use std::{
+    io::{BufRead, Cursor},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU16, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use http::{header::HeaderMap, request::Request, statuscode::StatusCode, uri::url::Url};
+use json::parser::{parse, tokenize, Node};
+use tokio::{
+    io::{AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+
+// FakeUpstream is an in-process http server answering every request with
+// its name as body, used to observe where the gateway sends requests.
+pub struct FakeUpstream {
+    pub url: Url,
+
+    hits: Arc<AtomicUsize>,
+    body: Arc<AtomicUsize>,
+    headers: Arc<Mutex<HeaderMap>>,
+    path: Arc<Mutex<String>>,
+    status: Arc<AtomicU16>,
+    raw: Arc<Mutex<Option<Vec<u8>>>>,
+    delay: Arc<Mutex<Duration>>,
+    handle: JoinHandle<()>,
+}
+
+impl FakeUpstream {
+    pub async fn start(name: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let url = Url::from_str(&format!("http://{}/", addr)).unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let body = Arc::new(AtomicUsize::new(0));
+        let headers = Arc::new(Mutex::new(HeaderMap::default()));
+        let path = Arc::new(Mutex::new(String::new()));
+        let status = Arc::new(AtomicU16::new(StatusCode::Ok.code()));
+        let raw: Arc<Mutex<Option<Vec<u8>>>> = Arc::new(Mutex::new(None));
+        let delay = Arc::new(Mutex::new(Duration::ZERO));
+
+        let name = name.to_string();
+        let counter = hits.clone();
+        let size = body.clone();
+        let last = headers.clone();
+        let target = path.clone();
+        let code = status.clone();
+        let fixed = raw.clone();
+        let stall = delay.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let name = name.clone();
+                let counter = counter.clone();
+                let received = size.clone();
+                let last = last.clone();
+                let target = target.clone();
+                let code = code.load(Ordering::Relaxed);
+                let fixed = fixed.lock().unwrap().clone();
+                let stall = *stall.lock().unwrap();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(&mut stream);
+                    let req = match Request::read(&mut reader).await {
+                        Ok(req) => req,
+                        Err(_) => return,
+                    };
+                    counter.fetch_add(1, Ordering::Relaxed);
+                    *last.lock().unwrap() = req.parts.headers.clone();
+                    *target.lock().unwrap() =
+                        String::try_from(req.parts.url.path.clone()).unwrap_or_default();
+                    let len = req.body.map(|body| body.len()).unwrap_or_default();
+                    received.store(len, Ordering::Relaxed);
+                    tokio::time::sleep(stall).await;
+
+                    let resp = fixed.unwrap_or_else(|| {
+                        format!(
+                            "HTTP/1.1 {} X\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                            code,
+                            name.len(),
+                            name
+                        )
+                        .into_bytes()
+                    });
+                    let _ = stream.write_all(&resp).await;
+                });
+            }
+        });
+
+        Self {
+            url,
+            hits,
+            body,
+            headers,
+            path,
+            status,
+            raw,
+            delay,
+            handle,
+        }
+    }
+
+    // url on which nothing listens, connections to it are refused
+    pub fn closed() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    // length of the body of the last request received
+    pub fn received(&self) -> usize {
+        self.body.load(Ordering::Relaxed)
+    }
+
+    // headers of the last request received
+    pub fn headers(&self) -> HeaderMap {
+        self.headers.lock().unwrap().clone()
+    }
+
+    // path and query of the last request received
+    pub fn path(&self) -> String {
+        self.path.lock().unwrap().clone()
+    }
+
+    // answers with the given raw response, and closes the connection after it
+    pub fn set_response(&self, raw: &str) {
+        self.set_response_bytes(raw.as_bytes());
+    }
+
+    // same as set_response, for responses whose body is not text
+    pub fn set_response_bytes(&self, raw: &[u8]) {
+        *self.raw.lock().unwrap() = Some(raw.to_vec());
+    }
+
+    // waits before answering, to simulate a slow upstream
+    pub fn set_delay(&self, delay: Duration) {
+        *self.delay.lock().unwrap() = delay;
+    }
+
+    pub fn set_status(&self, status: StatusCode) {
+        self.status.store(status.code(), Ordering::Relaxed);
+    }
+}
+
+impl Drop for FakeUpstream {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// FakeCollector is an in-process OTLP/HTTP collector handing out the json
+// bodies it receives.
+pub struct FakeCollector {
+    pub url: Url,
+
+    bodies: mpsc::Receiver<Node>,
+    handle: JoinHandle<()>,
+}
+
+impl FakeCollector {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let url = Url::from_str(&format!("http://{}/v1/traces", addr)).unwrap();
+        let (tx, bodies) = mpsc::channel(16);
+
+        let handle = tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let req = match Request::read(&mut BufReader::new(&mut stream)).await {
+                    Ok(req) => req,
+                    Err(_) => continue,
+                };
+                let body = req.body.unwrap_or_default();
+                let node = tokenize(Cursor::new(body).lines())
+                    .ok()
+                    .and_then(|tokens| parse(&mut tokens.iter().peekable()).ok()?);
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                    .await;
+                if let (Some(node), "/v1/traces") = (node, req.parts.url.path.raw_path.as_str()) {
+                    let _ = tx.send(node).await;
+                }
+            }
+        });
+
+        Self {
+            url,
+            bodies,
+            handle,
+        }
+    }
+
+    // body of the next export received
+    pub async fn received(&mut self) -> Node {
+        timeout(Duration::from_secs(2), self.bodies.recv())
+            .await
+            .expect("nothing exported")
+            .unwrap()
+    }
+}
+
+impl Drop for FakeCollector {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
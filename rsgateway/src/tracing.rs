@@ -0,0 +1,588 @@
+use std::{
+    collections::HashMap,
+    io::{BufWriter, Write},
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{sync::mpsc, time::interval};
+
+use http::{
+    builder::Builder,
+    client::{Client, Dial},
+    header::{HeaderKind, HeaderMap},
+    method::Method,
+    statuscode::StatusCode,
+    trace::{self, TraceContext},
+    uri::url::Url,
+};
+use json::parser::{Node, NumberNode};
+
+use crate::accesslog::Entry;
+
+pub const SERVICE_NAME: &str = "rsgateway";
+pub const BATCH_SIZE: usize = 512;
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+// spans waiting to be exported, further spans are dropped so that a slow
+// collector does not hold requests back
+const BACKLOG: usize = 4096;
+
+// TracingConfig tells where spans are exported and how often.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TracingConfig {
+    // OTLP/HTTP traces endpoint of the collector, e.g.
+    // http://127.0.0.1:4318/v1/traces
+    pub collector: Url,
+    pub service_name: String,
+    // spans sent at once, a full batch is sent without waiting
+    pub batch_size: usize,
+    // how long spans wait for their batch to fill up
+    pub flush_interval: Duration,
+}
+
+impl TracingConfig {
+    pub fn new(collector: Url) -> Self {
+        Self {
+            collector,
+            service_name: SERVICE_NAME.to_string(),
+            batch_size: BATCH_SIZE,
+            flush_interval: FLUSH_INTERVAL,
+        }
+    }
+}
+
+// values are the ones of the OTLP protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Int(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub trace_id: u128,
+    pub span_id: u64,
+    // not set for the span starting a trace
+    pub parent_id: Option<u64>,
+    pub name: &'static str,
+    pub kind: SpanKind,
+    pub time: Range<SystemTime>,
+    pub attributes: Vec<(&'static str, Value)>,
+    pub error: bool,
+}
+
+impl Span {
+    fn node(&self) -> Node {
+        let string = |s: String| Node::String(s);
+        let nanos = |t: SystemTime| {
+            let since = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+            string(since.as_nanos().to_string())
+        };
+        let attributes = self
+            .attributes
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::String(s) => ("stringValue", string(s.clone())),
+                    // 64 bits integers are strings in the json mapping of protobuf
+                    Value::Int(n) => ("intValue", string(n.to_string())),
+                };
+                Node::Object(HashMap::from([
+                    ("key".to_string(), string(key.to_string())),
+                    (
+                        "value".to_string(),
+                        Node::Object(HashMap::from([(value.0.to_string(), value.1)])),
+                    ),
+                ]))
+            })
+            .collect();
+        // 2 is the error status code, 0 leaves it unset
+        let status = if self.error { 2 } else { 0 };
+
+        let mut fields = HashMap::from([
+            (
+                "traceId".to_string(),
+                string(format!("{:032x}", self.trace_id)),
+            ),
+            (
+                "spanId".to_string(),
+                string(format!("{:016x}", self.span_id)),
+            ),
+            ("name".to_string(), string(self.name.to_string())),
+            (
+                "kind".to_string(),
+                Node::Number(NumberNode::I64(self.kind as i64)),
+            ),
+            ("startTimeUnixNano".to_string(), nanos(self.time.start)),
+            ("endTimeUnixNano".to_string(), nanos(self.time.end)),
+            ("attributes".to_string(), Node::Array(attributes)),
+            (
+                "status".to_string(),
+                Node::Object(HashMap::from([(
+                    "code".to_string(),
+                    Node::Number(NumberNode::I64(status)),
+                )])),
+            ),
+        ]);
+        if let Some(parent_id) = self.parent_id {
+            fields.insert(
+                "parentSpanId".to_string(),
+                string(format!("{:016x}", parent_id)),
+            );
+        }
+        Node::Object(fields)
+    }
+}
+
+// Trace gathers the spans of a request going through the gateway. The
+// request span is the parent of a span per DNS lookup, connection and
+// response of the upstreams tried.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    // the parent id of the context is the id of the request span
+    pub context: TraceContext,
+    // span of the client the request came from
+    parent_id: Option<u64>,
+    start: SystemTime,
+    // span of the upstream request in flight, whose id was sent upstream
+    attempt: Option<u64>,
+    spans: Vec<Span>,
+}
+
+impl Trace {
+    // continues the trace of the request, or starts a new one when the client
+    // did not send a valid traceparent
+    pub fn start(headers: &HeaderMap) -> Self {
+        let (context, parent_id) = match TraceContext::from_headers(headers) {
+            Some(parent) => (parent.child(), Some(parent.parent_id)),
+            None => (TraceContext::new(true), None),
+        };
+        Self {
+            context,
+            parent_id,
+            start: SystemTime::now(),
+            attempt: None,
+            spans: Vec::new(),
+        }
+    }
+
+    fn span(
+        &self,
+        span_id: u64,
+        name: &'static str,
+        kind: SpanKind,
+        time: Range<SystemTime>,
+    ) -> Span {
+        Span {
+            trace_id: self.context.trace_id,
+            span_id,
+            parent_id: Some(self.context.parent_id),
+            name,
+            kind,
+            time,
+            attributes: Vec::new(),
+            error: false,
+        }
+    }
+
+    // starts the span of a request to an upstream, telling the upstream about
+    // it in the request headers
+    pub fn attempt(&mut self, headers: &mut HeaderMap) {
+        let context = self.context.child();
+        context.inject(headers);
+        self.attempt = Some(context.parent_id);
+    }
+
+    // records the opening of a new upstream connection
+    pub fn dialed(&mut self, dial: &Dial) {
+        if let Some(lookup) = &dial.lookup {
+            let span = self.span(
+                trace::span_id(),
+                "dns.lookup",
+                SpanKind::Internal,
+                lookup.clone(),
+            );
+            self.spans.push(span);
+        }
+        let span = self.span(
+            trace::span_id(),
+            "upstream.connect",
+            SpanKind::Internal,
+            dial.connect.clone(),
+        );
+        self.spans.push(span);
+    }
+
+    // ends the span of the request to an upstream, the status is not set when
+    // no response was received
+    pub fn responded(&mut self, url: &Url, start: SystemTime, status: Option<StatusCode>) {
+        let span_id = self.attempt.take().unwrap_or_else(trace::span_id);
+        let mut span = self.span(
+            span_id,
+            "upstream.response",
+            SpanKind::Client,
+            start..SystemTime::now(),
+        );
+        span.attributes.push((
+            "url.full",
+            Value::String(String::try_from(url.clone()).unwrap_or_default()),
+        ));
+        if let Some(status) = status {
+            span.attributes.push((
+                "http.response.status_code",
+                Value::Int(status.code() as i64),
+            ));
+        }
+        span.error = status.is_none_or(|s| s.code() >= 500);
+        self.spans.push(span);
+    }
+
+    // ends the request span, returns every span of the request
+    pub fn finish(mut self, entry: &Entry) -> Vec<Span> {
+        let mut span = Span {
+            parent_id: self.parent_id,
+            ..self.span(
+                self.context.parent_id,
+                "proxy",
+                SpanKind::Server,
+                self.start..SystemTime::now(),
+            )
+        };
+        span.attributes.extend([
+            ("http.request.method", Value::String(entry.method.clone())),
+            ("server.address", Value::String(entry.host.clone())),
+            ("url.path", Value::String(entry.path.clone())),
+            (
+                "client.address",
+                Value::String(entry.client.ip().to_string()),
+            ),
+        ]);
+        if let Some(route) = &entry.route {
+            span.attributes
+                .push(("http.route", Value::String(route.clone())));
+        }
+        if let Some(status) = entry.status {
+            span.attributes.push((
+                "http.response.status_code",
+                Value::Int(status.code() as i64),
+            ));
+        }
+        span.error = entry.status.is_none_or(|s| s.code() >= 500);
+        self.spans.push(span);
+        self.spans
+    }
+}
+
+// Exporter sends spans to an OTLP/HTTP collector in the background, in
+// batches. Requests do not wait for their spans to be sent.
+#[derive(Debug)]
+pub struct Exporter {
+    spans: mpsc::Sender<Span>,
+}
+
+impl Exporter {
+    pub fn start(config: &TracingConfig, client: Client) -> Self {
+        let (spans, rx) = mpsc::channel(BACKLOG);
+        tokio::spawn(export(config.clone(), client, rx));
+        Self { spans }
+    }
+
+    // exports the spans of a request, unless its client asked for the trace
+    // not to be sampled
+    pub fn export(&self, trace: Trace, entry: &Entry) {
+        if !trace.context.sampled() {
+            return;
+        }
+        for span in trace.finish(entry) {
+            let _ = self.spans.try_send(span);
+        }
+    }
+}
+
+async fn export(config: TracingConfig, client: Client, mut spans: mpsc::Receiver<Span>) {
+    let mut ticker = interval(config.flush_interval);
+    let mut batch = Vec::new();
+    loop {
+        tokio::select! {
+            span = spans.recv() => match span {
+                Some(span) => {
+                    batch.push(span);
+                    if batch.len() < config.batch_size {
+                        continue;
+                    }
+                }
+                None => {
+                    send(&config, &client, batch).await;
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+        }
+        send(&config, &client, std::mem::take(&mut batch)).await;
+    }
+}
+
+// the spans of a batch as an OTLP ExportTraceServiceRequest
+pub fn payload(service_name: &str, spans: &[Span]) -> Node {
+    let resource = Node::Object(HashMap::from([(
+        "attributes".to_string(),
+        Node::Array(vec![Node::Object(HashMap::from([
+            ("key".to_string(), Node::String("service.name".to_string())),
+            (
+                "value".to_string(),
+                Node::Object(HashMap::from([(
+                    "stringValue".to_string(),
+                    Node::String(service_name.to_string()),
+                )])),
+            ),
+        ]))]),
+    )]));
+    let scope = Node::Object(HashMap::from([
+        (
+            "scope".to_string(),
+            Node::Object(HashMap::from([(
+                "name".to_string(),
+                Node::String(SERVICE_NAME.to_string()),
+            )])),
+        ),
+        (
+            "spans".to_string(),
+            Node::Array(spans.iter().map(Span::node).collect()),
+        ),
+    ]));
+    Node::Object(HashMap::from([(
+        "resourceSpans".to_string(),
+        Node::Array(vec![Node::Object(HashMap::from([
+            ("resource".to_string(), resource),
+            ("scopeSpans".to_string(), Node::Array(vec![scope])),
+        ]))]),
+    )]))
+}
+
+async fn send(config: &TracingConfig, client: &Client, spans: Vec<Span>) {
+    if spans.is_empty() {
+        return;
+    }
+    let mut writer = BufWriter::new(Vec::new());
+    if payload(&config.service_name, &spans)
+        .write(&mut writer)
+        .is_err()
+        || writer.flush().is_err()
+    {
+        return;
+    }
+    let body = writer.into_inner().unwrap_or_default();
+
+    let mut request = Builder::new()
+        .method(Method::POST)
+        .url(config.collector.clone())
+        .build();
+    let headers = &mut request.parts.headers;
+    let _ = headers.put("content-length", HeaderKind::ContentLength(body.len()));
+    headers
+        .raw
+        .insert("content-type".to_string(), "application/json".to_string());
+    request.body = Some(body);
+
+    let collector = String::try_from(config.collector.clone()).unwrap_or_default();
+    match client.perform(request).await {
+        Ok(resp) if resp.status.code() < 300 => {}
+        Ok(resp) => eprintln!(
+            "tracing {}: {} spans rejected with status {}",
+            collector,
+            spans.len(),
+            resp.status.code()
+        ),
+        Err(e) => eprintln!(
+            "tracing {}: {} spans dropped: {}",
+            collector,
+            spans.len(),
+            e
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, str::FromStr};
+
+    use dns::resolver::DNS_IP_LOCAL;
+    use http::uri::path::Path as UrlPath;
+
+    use super::*;
+    use crate::testing::FakeCollector;
+
+    fn exported(payload: &Node) -> &Vec<Node> {
+        let resource = &payload.get("resourceSpans").unwrap().as_array().unwrap()[0];
+        let scope = &resource.get("scopeSpans").unwrap().as_array().unwrap()[0];
+        scope.get("spans").unwrap().as_array().unwrap()
+    }
+
+    fn field<'a>(span: &'a Node, key: &str) -> &'a str {
+        span.get(key).and_then(Node::as_str).unwrap_or_default()
+    }
+
+    fn entry(headers: HeaderMap) -> Entry {
+        let req = Builder::new()
+            .method(Method::GET)
+            .path(UrlPath::from_str("/a").unwrap())
+            .headers(headers)
+            .build();
+        Entry::new(SocketAddr::from_str("127.0.0.1:4000").unwrap(), &req)
+    }
+
+    #[test]
+    fn test_trace_spans() {
+        let mut headers = HeaderMap::default();
+        headers.raw.insert(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        );
+        let mut trace = Trace::start(&headers);
+        assert_eq!(trace.context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+
+        let mut upstream = HeaderMap::default();
+        trace.attempt(&mut upstream);
+        let sent = TraceContext::from_headers(&upstream).unwrap();
+        assert_eq!(sent.trace_id, trace.context.trace_id);
+
+        let now = SystemTime::now();
+        trace.dialed(&Dial {
+            lookup: Some(now..now),
+            connect: now..now,
+        });
+        let url = Url::from_str("http://127.0.0.1:8080/").unwrap();
+        trace.responded(&url, now, Some(StatusCode::Ok));
+
+        let mut entry = entry(headers);
+        entry.status = Some(StatusCode::BadGateway);
+        let spans = trace.clone().finish(&entry);
+        let names: Vec<&str> = spans.iter().map(|s| s.name).collect();
+        assert_eq!(
+            names,
+            [
+                "dns.lookup",
+                "upstream.connect",
+                "upstream.response",
+                "proxy"
+            ]
+        );
+
+        let request = &spans[3];
+        assert_eq!(request.kind, SpanKind::Server);
+        assert_eq!(request.span_id, trace.context.parent_id);
+        assert_eq!(request.parent_id, Some(0x00f067aa0ba902b7));
+        assert!(request.error);
+        for span in &spans[..3] {
+            assert_eq!(span.trace_id, request.trace_id);
+            assert_eq!(span.parent_id, Some(request.span_id));
+        }
+        // the upstream was told about the span of its request
+        assert_eq!(spans[2].span_id, sent.parent_id);
+        assert_eq!(spans[2].kind, SpanKind::Client);
+        assert!(!spans[2].error);
+
+        // a request without a valid traceparent starts a trace
+        let spans = Trace::start(&HeaderMap::default()).finish(&entry);
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].parent_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_exporter_batches() {
+        let mut collector = FakeCollector::start().await;
+        let config = TracingConfig {
+            batch_size: 3,
+            flush_interval: Duration::from_secs(60),
+            service_name: "gateway".to_string(),
+            ..TracingConfig::new(collector.url.clone())
+        };
+        let exporter = Exporter::start(&config, Client::new(DNS_IP_LOCAL));
+
+        // a full batch is sent right away
+        let mut trace = Trace::start(&HeaderMap::default());
+        trace.dialed(&Dial {
+            lookup: None,
+            connect: SystemTime::now()..SystemTime::now(),
+        });
+        let url = Url::from_str("http://127.0.0.1:8080/").unwrap();
+        trace.responded(&url, SystemTime::now(), None);
+        let trace_id = trace.context.trace_id_hex();
+        let mut entry = entry(HeaderMap::default());
+        entry.status = Some(StatusCode::Ok);
+        exporter.export(trace, &entry);
+
+        let payload = collector.received().await;
+        let spans = exported(&payload);
+        assert_eq!(spans.len(), 3);
+        for span in spans.iter() {
+            assert_eq!(field(span, "traceId"), trace_id);
+        }
+        let proxy = &spans[2];
+        assert_eq!(field(proxy, "name"), "proxy");
+        assert_eq!(proxy.get("kind").and_then(Node::as_i64), Some(2));
+        assert_eq!(proxy.get("parentSpanId"), None);
+        assert_eq!(field(&spans[0], "parentSpanId"), field(proxy, "spanId"));
+        let status = |span: &Node| span.get("status").and_then(|s| s.get("code")?.as_i64());
+        assert_eq!(status(proxy), Some(0));
+        assert_eq!(status(&spans[1]), Some(2));
+        assert!(field(proxy, "startTimeUnixNano").parse::<u128>().unwrap() > 0);
+        let attributes = proxy.get("attributes").unwrap().as_array().unwrap();
+        assert!(attributes.iter().any(|a| {
+            field(a, "key") == "http.response.status_code"
+                && a.get("value").map(|v| field(v, "intValue")) == Some("200")
+        }));
+
+        // spans of an unsampled trace are not exported
+        let mut headers = HeaderMap::default();
+        headers.raw.insert(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00".to_string(),
+        );
+        exporter.export(Trace::start(&headers), &entry);
+
+        // the rest is sent once the exporter stops
+        exporter.export(Trace::start(&HeaderMap::default()), &entry);
+        drop(exporter);
+        let payload = collector.received().await;
+        let spans = exported(&payload);
+        assert_eq!(spans.len(), 1);
+        assert_ne!(field(&spans[0], "traceId"), trace_id);
+    }
+
+    #[tokio::test]
+    async fn test_exporter_flush_interval() {
+        let mut collector = FakeCollector::start().await;
+        let config = TracingConfig {
+            flush_interval: Duration::from_millis(20),
+            ..TracingConfig::new(collector.url.clone())
+        };
+        let exporter = Exporter::start(&config, Client::new(DNS_IP_LOCAL));
+        exporter.export(
+            Trace::start(&HeaderMap::default()),
+            &entry(HeaderMap::default()),
+        );
+
+        let payload = collector.received().await;
+        assert_eq!(exported(&payload).len(), 1);
+        let payload = super::payload("gateway", &[]);
+        let resource = &payload.get("resourceSpans").unwrap().as_array().unwrap()[0];
+        let attributes = resource
+            .get("resource")
+            .and_then(|r| r.get("attributes"))
+            .and_then(Node::as_array)
+            .unwrap();
+        assert_eq!(field(&attributes[0], "key"), "service.name");
+    }
+}
@@ -2,40 +2,181 @@ use std::collections::HashMap;
 
 use crate::route::{MatchType, Route};
 
-#[derive(Debug)]
+// name under which the rest of the path matched by a trailing "**" segment is
+// captured
+pub const CATCH_ALL: &str = "**";
+
+// Segment is a part of a route path between two '/': a literal, a "{param}"
+// capturing the segment of the request, a "*" matching any single segment,
+// or a trailing "**" matching the rest of the path.
+#[derive(Debug, PartialEq)]
+pub enum Segment<'a> {
+    Literal(&'a str),
+    Param(&'a str),
+    Wildcard,
+    CatchAll,
+}
+
+impl<'a> From<&'a str> for Segment<'a> {
+    fn from(s: &'a str) -> Self {
+        match s {
+            "*" => Segment::Wildcard,
+            "**" => Segment::CatchAll,
+            _ => match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) if !name.is_empty() => Segment::Param(name),
+                _ => Segment::Literal(s),
+            },
+        }
+    }
+}
+
+// Captures are the values of the params and catch-all of a route path, in the
+// order they appear in it
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Captures(Vec<(String, String)>);
+
+impl Captures {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl<const N: usize> From<[(&str, &str); N]> for Captures {
+    fn from(captures: [(&str, &str); N]) -> Self {
+        Self(
+            captures
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        )
+    }
+}
+
+// Match is a route along with the values its path captured from a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub route: Route,
+    pub captures: Captures,
+}
+
+// a route along with the names of the params and catch-all on its path
+type Stored = (Route, Vec<String>);
+
+// Accept tells whether a route whose path matches a request accepts it
+type Accept<'a> = dyn FnMut(&Route) -> bool + 'a;
+
+#[derive(Debug, Default)]
 pub struct Node {
-    upstream: Option<Route>,
+    // routes ending at the node, from the highest priority
+    routes: Vec<Stored>,
     children: HashMap<String, NodeRef>,
+    param: Option<NodeRef>,
+    wildcard: Option<NodeRef>,
+    catch_all: Option<NodeRef>,
 }
 
 type NodeRef = Box<Node>;
 
 impl Node {
     pub fn insert(&mut self, prefix: &str, upstream: Option<Route>) {
-        match prefix.split_once('/') {
-            Some((left, right)) => match self.children.get_mut(left) {
-                Some(entry) => {
-                    entry.insert(right, upstream);
+        let mut captures = Vec::new();
+        let mut cur = self;
+        for s in prefix.split('/') {
+            cur = match Segment::from(s) {
+                Segment::Literal(s) => cur.children.entry(s.to_string()).or_default(),
+                Segment::Param(name) => {
+                    captures.push(name.to_string());
+                    cur.param.get_or_insert_default()
                 }
-                None => {
-                    let mut child = Node {
-                        upstream: None,
-                        children: HashMap::new(),
-                    };
-                    child.insert(right, upstream);
-                    self.children.insert(left.to_string(), child.into());
+                Segment::Wildcard => cur.wildcard.get_or_insert_default(),
+                Segment::CatchAll => {
+                    captures.push(CATCH_ALL.to_string());
+                    cur.catch_all.get_or_insert_default()
                 }
-            },
-            _ => {
-                let child = Node {
-                    upstream,
-                    children: HashMap::new(),
-                };
-                self.children
-                    .entry(prefix.to_string())
-                    .or_insert(child.into());
+            };
+        }
+        // routes of the same priority are kept in the order they were added
+        if let Some(route) = upstream {
+            let i = cur
+                .routes
+                .iter()
+                .take_while(|(r, _)| r.priority >= route.priority)
+                .count();
+            cur.routes.insert(i, (route, captures));
+        }
+    }
+
+    // route matching the segments. Literal segments are tried first, then
+    // params, then wildcards, then a catch-all, and last the routes of the
+    // node itself matching by prefix. Captured values are pushed along the
+    // way.
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        values: &mut Vec<String>,
+        accept: &mut Accept,
+    ) -> Option<&'a Stored> {
+        let Some((first, rest)) = segments.split_first() else {
+            if let Some(found) = self.accepted(accept, false) {
+                return Some(found);
+            }
+            let found = self.catch_all.as_ref()?.accepted(accept, false)?;
+            values.push(String::new());
+            return Some(found);
+        };
+
+        if let Some(found) = self
+            .children
+            .get(*first)
+            .and_then(|child| child.find(rest, values, accept))
+        {
+            return Some(found);
+        }
+        // params and wildcards do not match empty segments
+        if !first.is_empty() {
+            if let Some(param) = &self.param {
+                values.push(first.to_string());
+                if let Some(found) = param.find(rest, values, accept) {
+                    return Some(found);
+                }
+                values.pop();
             }
+            if let Some(found) = self
+                .wildcard
+                .as_ref()
+                .and_then(|w| w.find(rest, values, accept))
+            {
+                return Some(found);
+            }
+        }
+        if let Some(found) = self
+            .catch_all
+            .as_ref()
+            .and_then(|c| c.accepted(accept, false))
+        {
+            values.push(segments.join("/"));
+            return Some(found);
         }
+        self.accepted(accept, true)
+    }
+
+    // first route of the node accepting the request
+    fn accepted(&self, accept: &mut Accept, prefix: bool) -> Option<&Stored> {
+        self.routes
+            .iter()
+            .filter(|(route, _)| !prefix || route.match_type == MatchType::Prefix)
+            .find(|(route, _)| accept(route))
     }
 }
 
@@ -53,11 +194,7 @@ impl Default for Trie {
 impl Trie {
     pub fn new() -> Self {
         Self {
-            root: Node {
-                upstream: None,
-                children: HashMap::new(),
-            }
-            .into(),
+            root: Node::default().into(),
         }
     }
 
@@ -65,61 +202,84 @@ impl Trie {
         self.root.insert(prefix, upstream);
     }
 
+    // routes of the table, in no particular order
+    pub fn routes(&self) -> Vec<&Route> {
+        let mut res = Vec::new();
+        let mut stack = vec![self.root.as_ref()];
+        while let Some(node) = stack.pop() {
+            res.extend(node.routes.iter().map(|(route, _)| route));
+            stack.extend(node.children.values().map(|child| child.as_ref()));
+            stack.extend(
+                [&node.param, &node.wildcard, &node.catch_all]
+                    .into_iter()
+                    .flatten()
+                    .map(|child| child.as_ref()),
+            );
+        }
+        res
+    }
+
     pub fn get(&self, path: &str) -> Option<Route> {
-        let mut cur = &self.root;
+        self.lookup(path).map(|m| m.route)
+    }
+
+    // route matching a path, along with the values captured by its params
+    pub fn lookup(&self, path: &str) -> Option<Match> {
+        self.find(path, |_| true)
+    }
+
+    // route matching a path and accepted by the given function. The
+    // function is called with the routes whose path matches, in the order
+    // they are tried.
+    pub fn find(&self, path: &str, mut accept: impl FnMut(&Route) -> bool) -> Option<Match> {
         let path = match path.split_once('?') {
             Some((left, _)) => left,
             None => path,
         };
-        let mut split = path.split('/');
-
-        for p in split.by_ref() {
-            match cur.children.get(p) {
-                Some(child) => {
-                    cur = child;
-                    continue;
-                }
-                None => {
-                    // no longer matches & split.remainder != None
-                    if let Some(upstream) = &cur.upstream {
-                        match upstream.match_type {
-                            MatchType::Exact => return None,
-                            MatchType::Prefix => return cur.upstream.clone(),
-                        }
-                    }
-                }
-            }
-        }
-
-        match &cur.upstream {
-            Some(upstream) => {
-                // leaf node
-                match upstream.match_type {
-                    MatchType::Exact if split.remainder().is_some() => None,
-                    _ => Some(upstream.clone()),
-                }
-            }
-            None => {
-                // internal node
-                None
-            }
-        }
+        let segments: Vec<&str> = path.split('/').collect();
+        let mut values = Vec::new();
+        let (route, names) = self.root.find(&segments, &mut values, &mut accept)?;
+        Some(Match {
+            route: route.clone(),
+            captures: Captures(names.iter().cloned().zip(values).collect()),
+        })
     }
 }
 
+// key of a route path regardless of the names of its params, two routes with
+// the same shape would be stored in the same node
+pub fn shape(prefix: &str) -> String {
+    prefix
+        .split('/')
+        .map(|s| match Segment::from(s) {
+            Segment::Param(_) => "{}",
+            _ => s,
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
+    use std::{str::FromStr, sync::Arc};
 
     use http::uri::url::Url;
 
     use super::*;
+    use crate::{predicate::Predicates, route::RouteOptions, upstream::Pool};
+    use rstest::*;
 
     #[test]
     fn test_trie_basic_prefixs() {
         let upstream = Some(Route {
-            url: Url::from_str("http://httpbin.org:9090/").unwrap(),
+            name: "localhost:9090/".to_string(),
+            upstream: Arc::new(Pool::single(
+                Url::from_str("http://httpbin.org:9090/").unwrap(),
+            )),
             match_type: MatchType::Prefix,
+            predicates: Predicates::default(),
+            priority: 0,
+            options: RouteOptions::default(),
         });
         let mut trie = Trie::new();
         trie.insert("localhost:9090/api/v1", upstream.clone());
@@ -132,8 +292,14 @@ mod tests {
     #[test]
     fn test_trie_multiple_path() {
         let upstream = Some(Route {
-            url: Url::from_str("http://httpbin.org:9090/").unwrap(),
+            name: "localhost:9090/".to_string(),
+            upstream: Arc::new(Pool::single(
+                Url::from_str("http://httpbin.org:9090/").unwrap(),
+            )),
             match_type: MatchType::Exact,
+            predicates: Predicates::default(),
+            priority: 0,
+            options: RouteOptions::default(),
         });
         let mut trie = Trie::new();
         trie.insert("localhost:9090/status", upstream.clone());
@@ -144,5 +310,122 @@ mod tests {
         assert_eq!(trie.get("localhost:9090/status/205"), upstream);
         assert_eq!(trie.get("localhost:9090/bytes/205"), None);
         assert_eq!(trie.get("localhost:9090/bytes"), upstream);
+        assert_eq!(trie.routes().len(), 3);
+        assert!(Trie::new().routes().is_empty());
+
+        // a route on the parent of an existing one is kept
+        trie.insert("localhost:9090", upstream.clone());
+        assert_eq!(trie.get("localhost:9090"), upstream);
+        assert_eq!(trie.routes().len(), 4);
+    }
+
+    fn route(name: &str, match_type: MatchType) -> Route {
+        Route {
+            name: name.to_string(),
+            upstream: Arc::new(Pool::single(
+                Url::from_str("http://httpbin.org:9090/").unwrap(),
+            )),
+            match_type,
+            predicates: Predicates::default(),
+            priority: 0,
+            options: RouteOptions::default(),
+        }
+    }
+
+    fn patterns() -> Trie {
+        let mut trie = Trie::new();
+        for (name, match_type) in [
+            ("h/users/me", MatchType::Exact),
+            ("h/users/{id}", MatchType::Exact),
+            ("h/users/{id}/orders/{order}", MatchType::Exact),
+            ("h/users/*/avatar", MatchType::Exact),
+            ("h/files/**", MatchType::Exact),
+            ("h/files/{name}/meta", MatchType::Exact),
+            ("h/api", MatchType::Prefix),
+            ("h/api/{version}/status", MatchType::Exact),
+        ] {
+            trie.insert(name, Some(route(name, match_type)));
+        }
+        trie
+    }
+
+    #[rstest]
+    #[case("h/users/me", Some(("h/users/me", vec![])))]
+    #[case("h/users/42", Some(("h/users/{id}", vec![("id", "42")])))]
+    #[case("h/users/42?page=1", Some(("h/users/{id}", vec![("id", "42")])))]
+    #[case("h/users/me/orders/7", Some((
+        "h/users/{id}/orders/{order}",
+        vec![("id", "me"), ("order", "7")],
+    )))]
+    #[case("h/users/42/avatar", Some(("h/users/*/avatar", vec![])))]
+    #[case("h/users/", None)]
+    #[case("h/users/42/orders", None)]
+    #[case("h/files/a/b.txt", Some(("h/files/**", vec![("**", "a/b.txt")])))]
+    #[case("h/files", Some(("h/files/**", vec![("**", "")])))]
+    #[case("h/files/a/meta", Some(("h/files/{name}/meta", vec![("name", "a")])))]
+    #[case("h/files/a/meta/x", Some(("h/files/**", vec![("**", "a/meta/x")])))]
+    #[case("h/api/v1/status", Some(("h/api/{version}/status", vec![("version", "v1")])))]
+    // a prefix route catches what its longer routes do not match
+    #[case("h/api/v1/other", Some(("h/api", vec![])))]
+    #[case("h/other/users/me", None)]
+    fn test_trie_patterns(#[case] path: &str, #[case] expected: Option<(&str, Vec<(&str, &str)>)>) {
+        let found = patterns().lookup(path);
+        let found = found
+            .as_ref()
+            .map(|m| (m.route.name.as_str(), m.captures.iter().collect()));
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn test_trie_pattern_routes() {
+        let trie = patterns();
+        assert_eq!(trie.routes().len(), 8);
+
+        // routes of the same shape are stored in the same node, the first is
+        // tried first
+        let mut trie = Trie::new();
+        trie.insert("h/u/{id}", Some(route("first", MatchType::Exact)));
+        trie.insert("h/u/{name}", Some(route("second", MatchType::Exact)));
+        let found = trie.lookup("h/u/1").unwrap();
+        assert_eq!(found.route.name, "first");
+        assert_eq!(found.captures, Captures::from([("id", "1")]));
+        assert_eq!(shape("h/u/{id}"), shape("h/u/{name}"));
+        assert_ne!(shape("h/u/{id}"), shape("h/u/*"));
+    }
+
+    #[test]
+    fn test_trie_find() {
+        let mut trie = Trie::new();
+        for (name, priority) in [("low", 0), ("high", 2), ("other", 0), ("mid", 1)] {
+            let route = Route {
+                priority,
+                ..route(name, MatchType::Prefix)
+            };
+            trie.insert("h/a/{id}", Some(route));
+        }
+        trie.insert("h/a/*/b", Some(route("wildcard", MatchType::Exact)));
+
+        // routes sharing a path are tried from the highest priority, then in
+        // the order they were added
+        let mut tried = Vec::new();
+        let found = trie.find("h/a/1/b", |route| {
+            tried.push(route.name.clone());
+            route.name == "other"
+        });
+        assert_eq!(tried, ["high", "mid", "low", "other"]);
+        let found = found.unwrap();
+        assert_eq!(found.route.name, "other");
+        assert_eq!(found.captures, Captures::from([("id", "1")]));
+
+        // the wildcard is tried once the params refused the request
+        let found = trie
+            .find("h/a/1/b", |route| route.name == "wildcard")
+            .unwrap();
+        assert_eq!(found.route.name, "wildcard");
+        assert!(found.captures.is_empty());
+
+        // a path whose routes all refuse the request has no match
+        assert_eq!(trie.find("h/a/1", |_| false), None);
+        assert_eq!(trie.routes().len(), 5);
     }
 }
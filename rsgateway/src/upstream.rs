@@ -0,0 +1,628 @@
+use std::{
+    fmt::Debug,
+    hash::{DefaultHasher, Hash, Hasher},
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use http::{header::HeaderMap, uri::url::Url};
+
+use crate::{
+    breaker::{BreakerConfig, CircuitBreaker, Permit},
+    error::ProxyError,
+    health::{Health, HealthCheck, OutlierDetection},
+};
+
+// number of points placed on the hash ring for each unit of weight
+const VIRTUAL_NODES: usize = 100;
+
+#[derive(Debug)]
+pub struct Endpoint {
+    pub url: Url,
+    pub weight: usize,
+    pub health: Health,
+
+    outstanding: AtomicUsize,
+}
+
+impl Endpoint {
+    pub fn new(url: Url, weight: usize) -> Self {
+        Self {
+            url,
+            weight,
+            health: Health::default(),
+            outstanding: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.load(Ordering::Relaxed)
+    }
+}
+
+// Lease marks a request as outstanding on an endpoint until it is dropped.
+#[derive(Debug)]
+pub struct Lease {
+    pub index: usize,
+    pub endpoint: Arc<Endpoint>,
+}
+
+impl Lease {
+    fn new(index: usize, endpoint: Arc<Endpoint>) -> Self {
+        endpoint.outstanding.fetch_add(1, Ordering::Relaxed);
+        Self { index, endpoint }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.endpoint.outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Context holds the parts of a request a balancer may select an endpoint on.
+pub struct Context<'a> {
+    pub client: Option<IpAddr>,
+    pub headers: &'a HeaderMap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HashOn {
+    ClientIp,
+    Header(String),
+}
+
+impl HashOn {
+    fn key(&self, context: &Context) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        match self {
+            HashOn::ClientIp => context.client?.hash(&mut hasher),
+            HashOn::Header(name) => context.headers.raw.get(name)?.hash(&mut hasher),
+        }
+        Some(hasher.finish())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    RoundRobin,
+    WeightedRoundRobin,
+    LeastOutstanding,
+    RandomTwoChoices,
+    ConsistentHash(HashOn),
+}
+
+impl Strategy {
+    pub fn balancer(&self, endpoints: &[Arc<Endpoint>]) -> Box<dyn Balancer> {
+        match self {
+            Strategy::RoundRobin => Box::new(RoundRobin::default()),
+            Strategy::WeightedRoundRobin => Box::new(WeightedRoundRobin::new(endpoints)),
+            Strategy::LeastOutstanding => Box::new(LeastOutstanding::default()),
+            Strategy::RandomTwoChoices => Box::new(RandomTwoChoices {}),
+            Strategy::ConsistentHash(on) => Box::new(ConsistentHash::new(endpoints, on.clone())),
+        }
+    }
+}
+
+// Balancer picks the index of the endpoint a request should be sent to among
+// the ones for which `eligible` holds.
+pub trait Balancer: Debug + Send + Sync {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        context: &Context,
+    ) -> Option<usize>;
+}
+
+#[derive(Debug, Default)]
+pub struct RoundRobin {
+    next: AtomicUsize,
+}
+
+impl Balancer for RoundRobin {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        _context: &Context,
+    ) -> Option<usize> {
+        let n = endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..n).map(|i| (start + i) % n).find(|i| eligible(*i))
+    }
+}
+
+// WeightedRoundRobin is the smooth weighted round robin used by nginx, which
+// interleaves endpoints instead of sending bursts to the heaviest one.
+#[derive(Debug)]
+pub struct WeightedRoundRobin {
+    current: Mutex<Vec<i64>>,
+}
+
+impl WeightedRoundRobin {
+    pub fn new(endpoints: &[Arc<Endpoint>]) -> Self {
+        Self {
+            current: Mutex::new(vec![0; endpoints.len()]),
+        }
+    }
+}
+
+impl Balancer for WeightedRoundRobin {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        _context: &Context,
+    ) -> Option<usize> {
+        let mut current = self.current.lock().expect("balancer lock poisoned");
+        let mut total: i64 = 0;
+        let mut best: Option<usize> = None;
+
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            if !eligible(i) {
+                continue;
+            }
+            let weight = endpoint.weight as i64;
+            current[i] += weight;
+            total += weight;
+            if best.is_none_or(|b| current[i] > current[b]) {
+                best = Some(i);
+            }
+        }
+
+        if let Some(b) = best {
+            current[b] -= total;
+        }
+        best
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LeastOutstanding {
+    next: AtomicUsize,
+}
+
+impl Balancer for LeastOutstanding {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        _context: &Context,
+    ) -> Option<usize> {
+        // rotate the starting point so that ties are spread evenly
+        let n = endpoints.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..n)
+            .map(|i| (start + i) % n)
+            .filter(|i| eligible(*i))
+            .min_by_key(|i| endpoints[*i].outstanding())
+    }
+}
+
+// RandomTwoChoices samples two endpoints and keeps the least loaded one,
+// which avoids the herding of least outstanding without a global scan.
+#[derive(Debug)]
+pub struct RandomTwoChoices {}
+
+impl Balancer for RandomTwoChoices {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        _context: &Context,
+    ) -> Option<usize> {
+        let candidates: Vec<usize> = (0..endpoints.len()).filter(|i| eligible(*i)).collect();
+        match candidates.len() {
+            0 => None,
+            1 => Some(candidates[0]),
+            n => {
+                let a = fastrand::usize(..n);
+                let b = (a + 1 + fastrand::usize(..n - 1)) % n;
+                let (a, b) = (candidates[a], candidates[b]);
+                if endpoints[b].outstanding() < endpoints[a].outstanding() {
+                    Some(b)
+                } else {
+                    Some(a)
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConsistentHash {
+    on: HashOn,
+    ring: Vec<(u64, usize)>,
+}
+
+impl ConsistentHash {
+    pub fn new(endpoints: &[Arc<Endpoint>], on: HashOn) -> Self {
+        let mut ring = Vec::new();
+        for (i, endpoint) in endpoints.iter().enumerate() {
+            let key = String::try_from(endpoint.url.authority.clone()).unwrap_or_default();
+            for replica in 0..endpoint.weight * VIRTUAL_NODES {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                replica.hash(&mut hasher);
+                ring.push((hasher.finish(), i));
+            }
+        }
+        ring.sort_unstable();
+        Self { on, ring }
+    }
+}
+
+impl Balancer for ConsistentHash {
+    fn select(
+        &self,
+        endpoints: &[Arc<Endpoint>],
+        eligible: &dyn Fn(usize) -> bool,
+        context: &Context,
+    ) -> Option<usize> {
+        let key = match self.on.key(context) {
+            Some(key) => key,
+            // requests without a key are spread randomly
+            None => fastrand::u64(..),
+        };
+
+        // walk the ring clockwise from the key until an eligible endpoint is
+        // found, so that removing an endpoint only remaps its own keys
+        let start = self.ring.partition_point(|(point, _)| *point < key);
+        let n = self.ring.len();
+        (0..n)
+            .map(|i| self.ring[(start + i) % n].1)
+            .find(|i| eligible(*i) && *i < endpoints.len())
+    }
+}
+
+#[derive(Debug)]
+pub struct Pool {
+    pub name: String,
+    pub strategy: Strategy,
+    pub endpoints: Vec<Arc<Endpoint>>,
+    pub health_check: Option<HealthCheck>,
+    pub outlier_detection: Option<OutlierDetection>,
+    pub circuit_breaker: Option<CircuitBreaker>,
+
+    balancer: Box<dyn Balancer>,
+}
+
+impl PartialEq for Pool {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.strategy == other.strategy
+            && self.health_check == other.health_check
+            && self.outlier_detection == other.outlier_detection
+            && self.circuit_breaker.as_ref().map(|b| &b.config)
+                == other.circuit_breaker.as_ref().map(|b| &b.config)
+            && self.endpoints.len() == other.endpoints.len()
+            && self
+                .endpoints
+                .iter()
+                .zip(other.endpoints.iter())
+                .all(|(a, b)| a.url == b.url && a.weight == b.weight)
+    }
+}
+
+impl Pool {
+    pub fn new(name: String, strategy: Strategy, endpoints: Vec<Endpoint>) -> Self {
+        let endpoints: Vec<Arc<Endpoint>> = endpoints.into_iter().map(Arc::new).collect();
+        Self {
+            name,
+            balancer: strategy.balancer(&endpoints),
+            strategy,
+            endpoints,
+            health_check: None,
+            outlier_detection: None,
+            circuit_breaker: None,
+        }
+    }
+
+    pub fn health_check(mut self, health_check: Option<HealthCheck>) -> Self {
+        self.health_check = health_check;
+        self
+    }
+
+    pub fn outlier_detection(mut self, outlier_detection: Option<OutlierDetection>) -> Self {
+        self.outlier_detection = outlier_detection;
+        self
+    }
+
+    pub fn circuit_breaker(mut self, config: Option<BreakerConfig>) -> Self {
+        self.circuit_breaker = config.map(CircuitBreaker::new);
+        self
+    }
+
+    // takes over the state of the pool it replaces: the health of the
+    // endpoints with the same url, and the state of the circuit breaker
+    pub fn inherit(&self, old: &Pool) {
+        for endpoint in self.endpoints.iter() {
+            if let Some(previous) = old.endpoints.iter().find(|e| e.url == endpoint.url) {
+                endpoint
+                    .health
+                    .inherit(&previous.health, self.health_check.is_some());
+            }
+        }
+        if let (Some(breaker), Some(previous)) = (&self.circuit_breaker, &old.circuit_breaker) {
+            breaker.inherit(previous);
+        }
+    }
+
+    // pool of a route whose upstream is given inline as an url
+    pub fn single(url: Url) -> Self {
+        let name = String::try_from(url.clone()).unwrap_or_default();
+        Pool::new(name, Strategy::RoundRobin, vec![Endpoint::new(url, 1)])
+    }
+
+    pub fn select(&self, context: &Context) -> Option<Lease> {
+        let eligible = |i: usize| self.endpoints[i].health.available();
+        let index = self.balancer.select(&self.endpoints, &eligible, context)?;
+        Some(Lease::new(index, self.endpoints[index].clone()))
+    }
+
+    // selects an endpoint other than the ones already tried, retries go to the
+    // endpoints tried before only when no other is available
+    pub fn select_other(&self, context: &Context, tried: &[usize]) -> Option<Lease> {
+        let eligible = |i: usize| !tried.contains(&i) && self.endpoints[i].health.available();
+        match self.balancer.select(&self.endpoints, &eligible, context) {
+            Some(index) => Some(Lease::new(index, self.endpoints[index].clone())),
+            None => self.select(context),
+        }
+    }
+
+    // lets a request through the circuit breaker of the pool, requests fail
+    // right away while the circuit is open
+    pub fn admit(&self) -> Result<Option<Permit<'_>>, ProxyError> {
+        match &self.circuit_breaker {
+            None => Ok(None),
+            Some(breaker) => match breaker.allow() {
+                Some(permit) => Ok(Some(permit)),
+                None => Err(ProxyError::CircuitOpen),
+            },
+        }
+    }
+
+    // feeds the outcome of a request let through to the circuit breaker
+    pub fn record(&self, permit: Option<Permit>, ok: bool) {
+        if let Some(state) = permit.and_then(|permit| permit.record(ok)) {
+            eprintln!("{}: circuit breaker {}", self.name, state.as_str());
+        }
+    }
+
+    // feeds the outcome of a proxied request to the outlier detection
+    pub fn observe(&self, lease: &Lease, ok: bool) {
+        if let Some(outlier) = &self.outlier_detection {
+            if lease.endpoint.health.observed(ok, outlier) {
+                eprintln!(
+                    "{}: endpoint {} ejected for {:?}",
+                    self.name,
+                    String::try_from(lease.endpoint.url.clone()).unwrap_or_default(),
+                    outlier.ejection_time
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
+
+    use dns::resolver::DNS_IP_LOCAL;
+    use http::{builder::Builder, client::Client, method::Method};
+
+    use super::*;
+    use crate::testing::FakeUpstream;
+
+    async fn upstreams(n: usize) -> Vec<FakeUpstream> {
+        let mut res = Vec::with_capacity(n);
+        for i in 0..n {
+            res.push(FakeUpstream::start(&i.to_string()).await);
+        }
+        res
+    }
+
+    fn pool(strategy: Strategy, upstreams: &[FakeUpstream], weights: &[usize]) -> Pool {
+        let endpoints = upstreams
+            .iter()
+            .zip(weights)
+            .map(|(u, w)| Endpoint::new(u.url.clone(), *w))
+            .collect();
+        Pool::new("test".to_string(), strategy, endpoints)
+    }
+
+    // sends a request to the endpoint selected by the pool and returns the
+    // index of the upstream which answered it
+    async fn call(pool: &Pool, context: &Context<'_>) -> usize {
+        let lease = pool.select(context).unwrap();
+        let request = Builder::new()
+            .method(Method::GET)
+            .url(lease.endpoint.url.clone())
+            .build();
+        let resp = Client::new(DNS_IP_LOCAL).perform(request).await.unwrap();
+        String::from_utf8(resp.body.unwrap())
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    async fn spread(pool: &Pool, n: usize) -> Vec<usize> {
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+        let mut res = vec![0; pool.endpoints.len()];
+        for _ in 0..n {
+            res[call(pool, &context).await] += 1;
+        }
+        res
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let upstreams = upstreams(3).await;
+        let pool = pool(Strategy::RoundRobin, &upstreams, &[1, 1, 1]);
+        assert_eq!(spread(&pool, 9).await, vec![3, 3, 3]);
+        assert!(upstreams.iter().all(|u| u.hits() == 3));
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin() {
+        let upstreams = upstreams(3).await;
+        let pool = pool(Strategy::WeightedRoundRobin, &upstreams, &[5, 1, 2]);
+        assert_eq!(spread(&pool, 16).await, vec![10, 2, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_weighted_round_robin_is_smooth() {
+        let endpoints = vec![
+            Endpoint::new(Url::from_str("http://127.0.0.1:1/").unwrap(), 5),
+            Endpoint::new(Url::from_str("http://127.0.0.1:2/").unwrap(), 1),
+            Endpoint::new(Url::from_str("http://127.0.0.1:3/").unwrap(), 1),
+        ];
+        let pool = Pool::new("test".to_string(), Strategy::WeightedRoundRobin, endpoints);
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+        let order: Vec<usize> = (0..7)
+            .map(|_| pool.select(&context).unwrap().index)
+            .collect();
+        assert_eq!(order, vec![0, 0, 1, 0, 2, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_least_outstanding() {
+        let upstreams = upstreams(3).await;
+        let pool = pool(Strategy::LeastOutstanding, &upstreams, &[1, 1, 1]);
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+
+        // two long running requests keep their endpoints busy
+        let first = pool.select(&context).unwrap();
+        let second = pool.select(&context).unwrap();
+        assert_ne!(first.index, second.index);
+
+        let idle = 3 - first.index - second.index;
+        let mut res = [0; 3];
+        for _ in 0..6 {
+            res[call(&pool, &context).await] += 1;
+        }
+        assert_eq!(res[idle], 6);
+
+        drop(first);
+        drop(second);
+        assert_eq!(spread(&pool, 6).await, vec![2, 2, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_random_two_choices() {
+        let upstreams = upstreams(2).await;
+        let pool = pool(Strategy::RandomTwoChoices, &upstreams, &[1, 1]);
+        let headers = HeaderMap::default();
+        let context = Context {
+            client: None,
+            headers: &headers,
+        };
+
+        // with two endpoints both are always sampled, the busy one is avoided
+        let busy = pool.select(&context).unwrap();
+        let mut res = [0; 2];
+        for _ in 0..10 {
+            res[call(&pool, &context).await] += 1;
+        }
+        assert_eq!(res[busy.index], 0);
+        drop(busy);
+
+        let upstreams = self::upstreams(4).await;
+        let pool = self::pool(Strategy::RandomTwoChoices, &upstreams, &[1, 1, 1, 1]);
+        let res = spread(&pool, 200).await;
+        assert!(res.iter().all(|n| *n > 0), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_consistent_hash_header() {
+        let upstreams = upstreams(3).await;
+        let pool = pool(
+            Strategy::ConsistentHash(HashOn::Header("x-api-key".to_string())),
+            &upstreams,
+            &[1, 1, 1],
+        );
+
+        let mut assigned: HashMap<String, usize> = HashMap::new();
+        let mut res = vec![0; 3];
+        for round in 0..2 {
+            for key in 0..30 {
+                let mut headers = HeaderMap::default();
+                headers.parse(&format!("x-api-key: key-{}", key)).unwrap();
+                let context = Context {
+                    client: None,
+                    headers: &headers,
+                };
+                let index = call(&pool, &context).await;
+                res[index] += 1;
+
+                let expected = *assigned.entry(key.to_string()).or_insert(index);
+                assert_eq!(expected, index, "key-{} moved on round {}", key, round);
+            }
+        }
+        assert!(res.iter().all(|n| *n > 0), "{:?}", res);
+    }
+
+    #[tokio::test]
+    async fn test_consistent_hash_client_ip() {
+        let upstreams = upstreams(3).await;
+        let pool = pool(
+            Strategy::ConsistentHash(HashOn::ClientIp),
+            &upstreams,
+            &[1, 1, 1],
+        );
+        let headers = HeaderMap::default();
+
+        for ip in ["10.0.0.1", "10.0.0.2", "192.168.1.20"] {
+            let context = Context {
+                client: Some(IpAddr::from_str(ip).unwrap()),
+                headers: &headers,
+            };
+            let first = call(&pool, &context).await;
+            for _ in 0..5 {
+                assert_eq!(call(&pool, &context).await, first);
+            }
+        }
+    }
+
+    #[test]
+    fn test_consistent_hash_remaps_only_removed_endpoint() {
+        let endpoints: Vec<Arc<Endpoint>> = (1..=4)
+            .map(|port| {
+                let url = Url::from_str(&format!("http://127.0.0.1:{}/", port)).unwrap();
+                Arc::new(Endpoint::new(url, 1))
+            })
+            .collect();
+        let balancer = ConsistentHash::new(&endpoints, HashOn::ClientIp);
+        let headers = HeaderMap::default();
+
+        for i in 0..=255 {
+            let context = Context {
+                client: Some(IpAddr::from([10, 0, 0, i])),
+                headers: &headers,
+            };
+            let before = balancer.select(&endpoints, &|_| true, &context).unwrap();
+            let after = balancer.select(&endpoints, &|j| j != 2, &context).unwrap();
+            if before != 2 {
+                assert_eq!(before, after);
+            } else {
+                assert_ne!(after, 2);
+            }
+        }
+    }
+}